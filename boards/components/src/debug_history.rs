@@ -0,0 +1,43 @@
+//! Component for DebugHistory, the retained copy of kernel debug output.
+//!
+//! This provides one `Component`, `DebugHistoryComponent`, which keeps the
+//! most recent output of `debug!()` in the provided buffer so it can be
+//! replayed later, for example with the process console's `dmesg` command.
+//! Once the buffer is full the oldest output is overwritten.
+//!
+//! The history only records output written after it is set, so finalize this
+//! component before anything is printed, and before the process console
+//! component so that the console can find it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let buf = static_init!([u8; 1024], [0; 1024]);
+//! DebugHistoryComponent::new(buf).finalize(());
+//! ```
+
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct DebugHistoryComponent {
+    buffer: &'static mut [u8],
+}
+
+impl DebugHistoryComponent {
+    pub fn new(buffer: &'static mut [u8]) -> Self {
+        Self { buffer }
+    }
+}
+
+impl Component for DebugHistoryComponent {
+    type StaticInput = ();
+    type Output = ();
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let debug_history = static_init!(
+            kernel::debug::DebugHistory,
+            kernel::debug::DebugHistory::new(self.buffer)
+        );
+        kernel::debug::set_debug_history(debug_history);
+    }
+}
@@ -15,6 +15,7 @@ pub mod cdc;
 pub mod console;
 pub mod crc;
 pub mod ctap;
+pub mod debug_history;
 pub mod debug_queue;
 pub mod debug_writer;
 pub mod digest;
@@ -9,6 +9,12 @@
 //! ```rust
 //! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
 //! ```
+//!
+//! Passing `Some(secret)` instead of `None` as the last argument to `new()`
+//! starts the console locked until `unlock <secret>` is entered.
+//!
+//! If the board has set up a `DebugHistoryComponent` before finalizing this
+//! component, the console's `dmesg` command replays it.
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
@@ -49,6 +55,7 @@ pub struct ProcessConsoleComponent<A: 'static + Alarm<'static>> {
     alarm_mux: &'static MuxAlarm<'static, A>,
     _alarm: PhantomData<A>,
     process_printer: &'static dyn ProcessPrinter,
+    secret: Option<&'static [u8]>,
 }
 
 impl<A: 'static + Alarm<'static>> ProcessConsoleComponent<A> {
@@ -57,6 +64,7 @@ impl<A: 'static + Alarm<'static>> ProcessConsoleComponent<A> {
         uart_mux: &'static MuxUart,
         alarm_mux: &'static MuxAlarm<'static, A>,
         process_printer: &'static dyn ProcessPrinter,
+        secret: Option<&'static [u8]>,
     ) -> ProcessConsoleComponent<A> {
         ProcessConsoleComponent {
             board_kernel: board_kernel,
@@ -64,6 +72,7 @@ impl<A: 'static + Alarm<'static>> ProcessConsoleComponent<A> {
             alarm_mux: alarm_mux,
             _alarm: PhantomData,
             process_printer,
+            secret,
         }
     }
 }
@@ -84,6 +93,7 @@ extern "C" {
 
 pub struct Capability;
 unsafe impl capabilities::ProcessManagementCapability for Capability {}
+unsafe impl capabilities::DebugHistoryCapability for Capability {}
 
 impl<A: 'static + Alarm<'static>> Component for ProcessConsoleComponent<A> {
     type StaticInput = (
@@ -132,6 +142,8 @@ impl<A: 'static + Alarm<'static>> Component for ProcessConsoleComponent<A> {
                 &mut process_console::COMMAND_BUF,
                 self.board_kernel,
                 kernel_addresses,
+                self.secret,
+                kernel::debug::debug_history(&Capability),
                 Capability,
             )
         );
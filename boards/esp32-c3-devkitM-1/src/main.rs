@@ -279,6 +279,7 @@ unsafe fn setup() -> (
         uart_mux,
         mux_alarm,
         process_printer,
+        None,
     )
     .finalize(components::process_console_component_helper!(
         esp32_c3::timg::TimG
@@ -308,6 +308,7 @@ pub unsafe fn main() {
         uart_mux,
         mux_alarm,
         process_printer,
+        None,
     )
     .finalize(components::process_console_component_helper!(
         sam4l::ast::Ast<'static>
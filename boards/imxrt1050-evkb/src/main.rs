@@ -492,6 +492,7 @@ pub unsafe fn main() {
         lpuart_mux,
         mux_alarm,
         process_printer,
+        None,
     )
     .finalize(components::process_console_component_helper!(
         imxrt1050::gpt::Gpt1
@@ -489,6 +489,7 @@ pub unsafe fn main() {
         uart_mux,
         mux_alarm,
         process_printer,
+        None,
     )
     .finalize(components::process_console_component_helper!(
         litex_vexriscv::timer::LiteXAlarm<
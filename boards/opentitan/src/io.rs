@@ -25,7 +25,7 @@ impl IoWrite for Writer {
         // during panic.
         earlgrey::uart::Uart::new(
             earlgrey::uart::UART0_BASE,
-            earlgrey::chip_config::CONFIG.peripheral_freq,
+            earlgrey::chip_config::CONFIG.uart_freq,
         )
         .transmit_sync(buf);
     }
@@ -44,6 +44,7 @@ pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
     let first_led_pin = &mut earlgrey::gpio::GpioPin::new(
         earlgrey::gpio::GPIO0_BASE,
         earlgrey::gpio::PADCTRL_BASE,
+        earlgrey::gpio::PINMUX_BASE,
         earlgrey::gpio::pins::pin7,
     );
     first_led_pin.make_output();
@@ -63,6 +63,8 @@ static mut PLATFORM: Option<&'static EarlGrey> = None;
 // Test access to main loop capability
 #[cfg(test)]
 static mut MAIN_CAP: Option<&dyn kernel::capabilities::MainLoopCapability> = None;
+// Test access to the hardware timer
+static mut TIMER: Option<&'static earlgrey::timer::RvTimer<'static>> = None;
 // Test access to alarm
 static mut ALARM: Option<&'static MuxAlarm<'static, earlgrey::timer::RvTimer<'static>>> = None;
 // Test access to TicKV
@@ -76,10 +78,22 @@ static mut TICKV: Option<
 // Test access to AES CCM
 static mut AES: Option<&virtual_aes_ccm::VirtualAES128CCM<'static, earlgrey::aes::Aes<'static>>> =
     None;
+// Test access to the console UART mux
+#[cfg(test)]
+static mut UART_MUX: Option<&'static capsules::virtual_uart::MuxUart<'static>> = None;
 // Test access to SipHash
 static mut SIPHASH: Option<&capsules::sip_hash::SipHasher24<'static>> = None;
 // Test access to RSA
-static mut RSA_HARDWARE: Option<&lowrisc::rsa::OtbnRsa<'static>> = None;
+static mut RSA_HARDWARE: Option<
+    &lowrisc::rsa::OtbnRsa<'static, VirtualMuxAlarm<'static, earlgrey::timer::RvTimer<'static>>>,
+> = None;
+// Test access to ECDSA
+static mut ECDSA_HARDWARE: Option<
+    &lowrisc::ecdsa::OtbnEcdsaP256<
+        'static,
+        VirtualMuxAlarm<'static, earlgrey::timer::RvTimer<'static>>,
+    >,
+> = None;
 
 // Test access to a software SHA256
 #[cfg(test)]
@@ -232,7 +246,7 @@ unsafe fn setup() -> (
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 6], Default::default());
+        static_init!([DynamicDeferredCallClientState; 9], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -258,6 +272,10 @@ unsafe fn setup() -> (
         dynamic_deferred_caller,
     )
     .finalize(());
+    #[cfg(test)]
+    {
+        UART_MUX = Some(uart_mux);
+    }
 
     // LEDs
     // Start with half on and half off
@@ -293,6 +311,8 @@ unsafe fn setup() -> (
     let hardware_alarm = static_init!(earlgrey::timer::RvTimer, earlgrey::timer::RvTimer::new());
     hardware_alarm.setup();
 
+    TIMER = Some(hardware_alarm);
+
     // Create a shared virtualization mux layer on top of a single hardware
     // alarm.
     let mux_alarm = static_init!(
@@ -337,6 +357,10 @@ unsafe fn setup() -> (
         earlgrey::chip::EarlGrey::new(peripherals, hardware_alarm)
     );
     CHIP = Some(chip);
+    // Include the peripheral registers in the panic output
+    chip.set_peripheral_dump(peripherals);
+    // Let console output make it out before a software reset
+    chip.set_reset_flush(&peripherals.uart0);
 
     // Need to enable all interrupts for Tock Kernel
     chip.enable_plic_interrupts();
@@ -447,6 +471,21 @@ unsafe fn setup() -> (
     peripherals.aes.initialise(
         dynamic_deferred_caller.register(&peripherals.aes).unwrap(), // Unwrap fail = dynamic deferred caller out of slots
     );
+    peripherals.spi_host0.initialise_deferred_call(
+        dynamic_deferred_caller
+            .register(&peripherals.spi_host0)
+            .unwrap(), // Unwrap fail = dynamic deferred caller out of slots
+    );
+    peripherals.spi_host1.initialise_deferred_call(
+        dynamic_deferred_caller
+            .register(&peripherals.spi_host1)
+            .unwrap(), // Unwrap fail = dynamic deferred caller out of slots
+    );
+    peripherals.gpio_port.initialise_deferred_call(
+        dynamic_deferred_caller
+            .register(&peripherals.gpio_port)
+            .unwrap(), // Unwrap fail = dynamic deferred caller out of slots
+    );
 
     let process_printer =
         components::process_printer::ProcessPrinterTextComponent::new().finalize(());
@@ -465,6 +504,9 @@ unsafe fn setup() -> (
     }
 
     // Flash
+    if peripherals.flash_ctrl.wait_for_ready().is_err() {
+        debug!("Flash controller didn't finish initialising");
+    }
     let flash_ctrl_read_buf = static_init!(
         [u8; lowrisc::flash_ctrl::PAGE_SIZE],
         [0; lowrisc::flash_ctrl::PAGE_SIZE]
@@ -549,10 +591,11 @@ unsafe fn setup() -> (
         capsules::tickv::TicKVKeyType,
     ));
 
-    let mux_otbn = crate::otbn::AccelMuxComponent::new(&peripherals.otbn)
-        .finalize(otbn_mux_component_helper!(1024));
+    let mux_otbn = crate::otbn::AccelMuxComponent::new(&peripherals.otbn, mux_alarm)
+        .finalize(otbn_mux_component_helper!(earlgrey::timer::RvTimer));
 
-    let otbn = OtbnComponent::new(&mux_otbn).finalize(crate::otbn_component_helper!());
+    let otbn = OtbnComponent::new(&mux_otbn)
+        .finalize(crate::otbn_component_helper!(earlgrey::timer::RvTimer));
 
     let otbn_rsa_internal_buf = static_init!([u8; 512], [0; 512]);
 
@@ -567,7 +610,7 @@ unsafe fn setup() -> (
         )
     {
         let rsa_hardware = static_init!(
-            lowrisc::rsa::OtbnRsa<'static>,
+            lowrisc::rsa::OtbnRsa<'static, VirtualMuxAlarm<'static, earlgrey::timer::RvTimer>>,
             lowrisc::rsa::OtbnRsa::new(
                 otbn,
                 lowrisc::rsa::AppAddresses {
@@ -579,12 +622,48 @@ unsafe fn setup() -> (
                 otbn_rsa_internal_buf,
             )
         );
-        peripherals.otbn.set_client(rsa_hardware);
+        otbn.set_client(rsa_hardware);
         RSA_HARDWARE = Some(rsa_hardware);
     } else {
         debug!("Unable to find otbn-rsa, disabling RSA support");
     }
 
+    // Use the OTBN to create an ECDSA P-256 engine
+    if let Ok((ecdsa_imem_start, ecdsa_imem_length, ecdsa_dmem_start, ecdsa_dmem_length)) =
+        crate::otbn::find_app(
+            "otbn-ecdsa-p256",
+            core::slice::from_raw_parts(
+                &_sapps as *const u8,
+                &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+            ),
+        )
+    {
+        let otbn_ecdsa = OtbnComponent::new(&mux_otbn)
+            .finalize(crate::otbn_component_helper!(earlgrey::timer::RvTimer));
+        let otbn_ecdsa_internal_buf = static_init!([u8; 64], [0; 64]);
+
+        let ecdsa_hardware = static_init!(
+            lowrisc::ecdsa::OtbnEcdsaP256<
+                'static,
+                VirtualMuxAlarm<'static, earlgrey::timer::RvTimer>,
+            >,
+            lowrisc::ecdsa::OtbnEcdsaP256::new(
+                otbn_ecdsa,
+                lowrisc::rsa::AppAddresses {
+                    imem_start: ecdsa_imem_start,
+                    imem_size: ecdsa_imem_length,
+                    dmem_start: ecdsa_dmem_start,
+                    dmem_size: ecdsa_dmem_length
+                },
+                otbn_ecdsa_internal_buf,
+            )
+        );
+        otbn_ecdsa.set_client(ecdsa_hardware);
+        ECDSA_HARDWARE = Some(ecdsa_hardware);
+    } else {
+        debug!("Unable to find otbn-ecdsa-p256, disabling ECDSA support");
+    }
+
     // Convert hardware RNG to the Random interface.
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
@@ -3,8 +3,8 @@
 //! Usage
 //! -----
 //! ```rust
-//!     let _mux_otbn = crate::otbn::AccelMuxComponent::new(&peripherals.otbn)
-//!         .finalize(otbn_mux_component_helper!());
+//!     let _mux_otbn = crate::otbn::AccelMuxComponent::new(&peripherals.otbn, mux_alarm)
+//!         .finalize(otbn_mux_component_helper!(earlgrey::timer::RvTimer));
 //!
 //!     peripherals.otbn.initialise(
 //!         dynamic_deferred_caller
@@ -13,8 +13,10 @@
 //!     );
 //! ```
 
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::component::Component;
+use kernel::hil::time::Alarm;
 use kernel::static_init_half;
 use lowrisc::otbn::Otbn;
 use lowrisc::virtual_otbn::{MuxAccel, VirtualMuxAccel};
@@ -22,30 +24,53 @@ use lowrisc::virtual_otbn::{MuxAccel, VirtualMuxAccel};
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! otbn_mux_component_helper {
-    ($T:expr $(,)?) => {{
+    ($A:ty $(,)?) => {{
+        use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
         use lowrisc::virtual_otbn::MuxAccel;
-        static mut BUF1: MaybeUninit<MuxAccel<'static>> = MaybeUninit::uninit();
-        &mut BUF1
+        static mut BUF1: MaybeUninit<MuxAccel<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
     }};
 }
 
-pub struct AccelMuxComponent {
+pub struct AccelMuxComponent<A: 'static + Alarm<'static>> {
     otbn: &'static Otbn<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
 }
 
-impl AccelMuxComponent {
-    pub fn new(otbn: &'static Otbn<'static>) -> AccelMuxComponent {
-        AccelMuxComponent { otbn }
+impl<A: 'static + Alarm<'static>> AccelMuxComponent<A> {
+    pub fn new(
+        otbn: &'static Otbn<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> AccelMuxComponent<A> {
+        AccelMuxComponent { otbn, alarm_mux }
     }
 }
 
-impl Component for AccelMuxComponent {
-    type StaticInput = &'static mut MaybeUninit<MuxAccel<'static>>;
-    type Output = &'static MuxAccel<'static>;
+impl<A: 'static + Alarm<'static>> Component for AccelMuxComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<MuxAccel<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+    );
+    type Output = &'static MuxAccel<'static, VirtualMuxAlarm<'static, A>>;
 
     unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
-        let mux_otbn = static_init_half!(s, MuxAccel<'static>, MuxAccel::new(self.otbn));
+        let timeout_alarm = static_init_half!(
+            s.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        timeout_alarm.setup();
+
+        let mux_otbn = static_init_half!(
+            s.0,
+            MuxAccel<'static, VirtualMuxAlarm<'static, A>>,
+            MuxAccel::new(self.otbn, timeout_alarm)
+        );
+        self.otbn.set_client(mux_otbn);
+        timeout_alarm.set_alarm_client(mux_otbn);
 
         mux_otbn
     }
@@ -54,35 +79,41 @@ impl Component for AccelMuxComponent {
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! otbn_component_helper {
-    ($(,)?) => {{
+    ($A:ty $(,)?) => {{
+        use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
         use lowrisc::virtual_otbn::VirtualMuxAccel;
-        static mut BUF1: MaybeUninit<VirtualMuxAccel<'static>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAccel<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
         &mut BUF1
     }};
 }
 
-pub struct OtbnComponent {
-    mux_otbn: &'static MuxAccel<'static>,
+pub struct OtbnComponent<A: 'static + Alarm<'static>> {
+    mux_otbn: &'static MuxAccel<'static, VirtualMuxAlarm<'static, A>>,
 }
 
-impl OtbnComponent {
-    pub fn new(mux_otbn: &'static MuxAccel<'static>) -> OtbnComponent {
+impl<A: 'static + Alarm<'static>> OtbnComponent<A> {
+    pub fn new(
+        mux_otbn: &'static MuxAccel<'static, VirtualMuxAlarm<'static, A>>,
+    ) -> OtbnComponent<A> {
         OtbnComponent { mux_otbn }
     }
 }
 
-impl Component for OtbnComponent {
-    type StaticInput = &'static mut MaybeUninit<VirtualMuxAccel<'static>>;
+impl<A: 'static + Alarm<'static>> Component for OtbnComponent<A> {
+    type StaticInput =
+        &'static mut MaybeUninit<VirtualMuxAccel<'static, VirtualMuxAlarm<'static, A>>>;
 
-    type Output = &'static VirtualMuxAccel<'static>;
+    type Output = &'static VirtualMuxAccel<'static, VirtualMuxAlarm<'static, A>>;
 
     unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
         let virtual_otbn_user = static_init_half!(
             s,
-            VirtualMuxAccel<'static>,
+            VirtualMuxAccel<'static, VirtualMuxAlarm<'static, A>>,
             VirtualMuxAccel::new(self.mux_otbn)
         );
+        virtual_otbn_user.setup();
 
         virtual_otbn_user
     }
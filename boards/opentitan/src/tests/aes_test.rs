@@ -2,13 +2,18 @@
 
 use crate::tests::run_kernel_op;
 use crate::{AES, PERIPHERALS};
+use capsules::aes_gcm;
 use capsules::test::aes::{TestAes128Cbc, TestAes128Ctr, TestAes128Ecb};
 use capsules::test::aes_ccm::Test;
+use capsules::test::aes_gcm::Test as TestGcm;
 use capsules::virtual_aes_ccm;
-use earlgrey::aes::Aes;
+use core::cell::Cell;
+use earlgrey::aes::{Aes, KeySize, KEY_WRAP_OVERHEAD};
 use kernel::debug;
-use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE};
+use kernel::hil::symmetric_encryption::{Client, AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE};
 use kernel::static_init;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
 
 #[test_case]
 fn run_aes128_ccm() {
@@ -39,6 +44,59 @@ unsafe fn static_init_test_ccm(
     )
 }
 
+#[test_case]
+fn run_aes128_gcm() {
+    debug!("check run AES128 GCM... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let aes = AES.unwrap();
+
+        let gcm = static_init_gcm(&aes);
+        AES128::set_client(aes, gcm);
+
+        let t = static_init_test_gcm(gcm);
+        kernel::hil::symmetric_encryption::AES128GCM::set_client(gcm, t);
+
+        t.run();
+    }
+    run_kernel_op(10000);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+unsafe fn static_init_gcm(
+    aes: &'static virtual_aes_ccm::VirtualAES128CCM<'static, Aes>,
+) -> &'static aes_gcm::Aes128Gcm<'static, virtual_aes_ccm::VirtualAES128CCM<'static, Aes<'static>>>
+{
+    let crypt_buf = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
+
+    static_init!(
+        aes_gcm::Aes128Gcm<'static, virtual_aes_ccm::VirtualAES128CCM<'static, Aes>>,
+        aes_gcm::Aes128Gcm::new(aes, crypt_buf)
+    )
+}
+
+unsafe fn static_init_test_gcm(
+    gcm: &'static aes_gcm::Aes128Gcm<
+        'static,
+        virtual_aes_ccm::VirtualAES128CCM<'static, Aes<'static>>,
+    >,
+) -> &'static TestGcm<
+    'static,
+    aes_gcm::Aes128Gcm<'static, virtual_aes_ccm::VirtualAES128CCM<'static, Aes<'static>>>,
+> {
+    let buf = static_init!([u8; 7 * AES128_BLOCK_SIZE], [0; 7 * AES128_BLOCK_SIZE]);
+
+    static_init!(
+        TestGcm<
+            'static,
+            aes_gcm::Aes128Gcm<'static, virtual_aes_ccm::VirtualAES128CCM<'static, Aes>>,
+        >,
+        TestGcm::new(gcm, buf)
+    )
+}
+
 #[test_case]
 fn run_aes128_ecb() {
     debug!("check run AES128 ECB... ");
@@ -133,3 +191,151 @@ unsafe fn static_init_test_ctr(aes: &'static Aes) -> &'static TestAes128Ctr<'sta
         TestAes128Ctr::new(aes, key, iv, source, data)
     )
 }
+
+/// FIPS-197 appendix C.3, AES-256 encryption of a single block
+const KEY_256: [u8; 32] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+];
+#[cfg(feature = "hardware_tests")]
+const PTXT_256: [u8; AES128_BLOCK_SIZE] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+#[cfg(feature = "hardware_tests")]
+const CTXT_256: [u8; AES128_BLOCK_SIZE] = [
+    0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89,
+];
+
+struct Aes256Callback {
+    done: Cell<bool>,
+    data: TakeCell<'static, [u8]>,
+}
+
+impl<'a> Client<'a> for Aes256Callback {
+    fn crypt_done(&'a self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        self.done.set(true);
+        self.data.replace(dest);
+    }
+}
+
+#[test_case]
+fn run_aes256_ecb() {
+    debug!("check run AES256 ECB... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let aes = &perf.aes;
+
+    let cb = unsafe {
+        let data = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+        static_init!(
+            Aes256Callback,
+            Aes256Callback {
+                done: Cell::new(false),
+                data: TakeCell::new(data),
+            }
+        )
+    };
+    aes.set_client(cb);
+
+    // The key has to match the requested size
+    assert_eq!(
+        aes.set_key_with_size(&KEY_256[..AES128_KEY_SIZE], KeySize::Aes256),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        aes.set_key_with_size(&KEY_256, KeySize::Aes192),
+        Err(ErrorCode::INVAL)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use kernel::hil::symmetric_encryption::AES128ECB;
+
+        aes.enable();
+        assert_eq!(aes.set_mode_aes128ecb(true), Ok(()));
+        assert_eq!(aes.set_key_with_size(&KEY_256, KeySize::Aes256), Ok(()));
+
+        let data = cb.data.take().unwrap();
+        data.copy_from_slice(&PTXT_256);
+        assert!(aes.crypt(None, data, 0, AES128_BLOCK_SIZE).is_none());
+        run_kernel_op(1000);
+
+        assert!(cb.done.get());
+        cb.data.map(|data| assert_eq!(data[..], CTXT_256[..]));
+
+        // Leave the block set up for the 128-bit tests
+        assert_eq!(aes.set_key(&KEY_256[..AES128_KEY_SIZE]), Ok(()));
+        aes.disable();
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// RFC 3394 section 4.1, wrap 128 bits of key data with a 128-bit KEK
+const KW_KEK: [u8; AES128_KEY_SIZE] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+];
+const KW_KEY: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+#[cfg(feature = "hardware_tests")]
+const KW_WRAPPED: [u8; 16 + KEY_WRAP_OVERHEAD] = [
+    0x1f, 0xa6, 0x8b, 0x0a, 0x81, 0x12, 0xb4, 0x47, 0xae, 0xf3, 0x4b, 0xd8, 0xfb, 0x5a, 0x7b, 0x82,
+    0x9d, 0x3e, 0x86, 0x23, 0x71, 0xd2, 0xcf, 0xe5,
+];
+
+#[test_case]
+fn run_aes_key_wrap() {
+    debug!("check run AES key wrap... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let aes = &perf.aes;
+
+    let mut wrapped = [0; 16 + KEY_WRAP_OVERHEAD];
+    let mut key = [0; 16];
+
+    // The wrapped form is exactly one semiblock longer than the key
+    assert_eq!(
+        aes.key_wrap(&KW_KEK, KeySize::Aes128, &KW_KEY, &mut wrapped[..16]),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        aes.key_unwrap(&KW_KEK, KeySize::Aes128, &wrapped, &mut key[..8]),
+        Err(ErrorCode::INVAL)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        aes.enable();
+
+        assert_eq!(
+            aes.key_wrap(&KW_KEK, KeySize::Aes128, &KW_KEY, &mut wrapped),
+            Ok(())
+        );
+        assert_eq!(wrapped, KW_WRAPPED);
+        run_kernel_op(100);
+
+        assert_eq!(
+            aes.key_unwrap(&KW_KEK, KeySize::Aes128, &wrapped, &mut key),
+            Ok(())
+        );
+        assert_eq!(key, KW_KEY);
+        run_kernel_op(100);
+
+        // Tampering with the wrapped key is detected
+        wrapped[10] ^= 0x01;
+        assert_eq!(
+            aes.key_unwrap(&KW_KEK, KeySize::Aes128, &wrapped, &mut key),
+            Err(ErrorCode::FAIL)
+        );
+        assert_eq!(key, [0; 16]);
+
+        aes.disable();
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -3,9 +3,28 @@
 use crate::tests::run_kernel_op;
 use crate::PERIPHERALS;
 use capsules::test::rng::TestEntropy32;
+use core::cell::Cell;
 use kernel::debug;
-use kernel::hil::entropy::Entropy32;
+use kernel::hil::entropy::{Client32, Continue, Entropy32};
 use kernel::static_init;
+use kernel::ErrorCode;
+use lowrisc::csrng::{NUM_INSTANCES, SW_INSTANCE};
+
+struct CsrngCallback {
+    requests: Cell<usize>,
+}
+
+impl Client32 for CsrngCallback {
+    fn entropy_available(
+        &self,
+        _entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        assert_eq!(error, Ok(()));
+        self.requests.set(self.requests.get() + 1);
+        Continue::Done
+    }
+}
 
 #[test_case]
 fn run_csrng_entropy32() {
@@ -26,3 +45,225 @@ fn run_csrng_entropy32() {
     debug!("    [ok]");
     run_kernel_op(100);
 }
+
+#[test_case]
+fn csrng_health_status() {
+    debug!("check CSRNG health status... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let status = perf.rng.health_status();
+
+    assert_eq!(status.hw_exceptions, 0);
+    assert_eq!(status.fatal_errors, 0);
+    assert_eq!(status.cmd_error, false);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn csrng_instance_exception() {
+    debug!("check CSRNG instance exceptions... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let rng = &perf.rng;
+
+    for instance in 0..SW_INSTANCE {
+        assert_eq!(rng.instance_exception(instance), Ok(false));
+    }
+    assert_eq!(rng.instance_exception(SW_INSTANCE), Err(ErrorCode::INVAL));
+    assert_eq!(rng.instance_exception(NUM_INSTANCES), Err(ErrorCode::INVAL));
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn csrng_instantiate_once() {
+    debug!("check CSRNG instantiates once... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let perf = PERIPHERALS.unwrap();
+        let rng = &perf.rng;
+
+        let cb = static_init!(
+            CsrngCallback,
+            CsrngCallback {
+                requests: Cell::new(0)
+            }
+        );
+        rng.set_client(cb);
+
+        // Start from an uninstantiated DRBG
+        assert_eq!(rng.cancel(), Ok(()));
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            let count = rng.instantiate_count();
+
+            assert_eq!(rng.get(), Ok(()));
+            run_kernel_op(10000);
+            assert_eq!(cb.requests.get(), 1);
+
+            assert_eq!(rng.get(), Ok(()));
+            run_kernel_op(10000);
+            assert_eq!(cb.requests.get(), 2);
+
+            assert_eq!(rng.instantiate_count(), count + 1);
+
+            // Cancelling uninstantiates, so the next request instantiates again
+            assert_eq!(rng.cancel(), Ok(()));
+            assert_eq!(rng.get(), Ok(()));
+            run_kernel_op(10000);
+            assert_eq!(rng.instantiate_count(), count + 2);
+        }
+    }
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[cfg(feature = "csrng_test_seed")]
+struct SeededCallback {
+    words: Cell<[u32; 8]>,
+    requests: Cell<usize>,
+}
+
+#[cfg(feature = "csrng_test_seed")]
+impl Client32 for SeededCallback {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        assert_eq!(error, Ok(()));
+        let mut words = [0; 8];
+        for (w, e) in words.iter_mut().zip(entropy) {
+            *w = e;
+        }
+        self.words.set(words);
+        self.requests.set(self.requests.get() + 1);
+        Continue::Done
+    }
+}
+
+/// The same test seed gives the same output, and a different one doesn't
+#[cfg(feature = "csrng_test_seed")]
+#[test_case]
+fn csrng_test_seed() {
+    debug!("check CSRNG test seed is repeatable... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let perf = PERIPHERALS.unwrap();
+        let rng = &perf.rng;
+
+        let cb = static_init!(
+            SeededCallback,
+            SeededCallback {
+                words: Cell::new([0; 8]),
+                requests: Cell::new(0),
+            }
+        );
+        rng.set_client(cb);
+
+        assert_eq!(
+            rng.set_test_seed(&[0; lowrisc::csrng::SEED_WORDS + 1]),
+            Err(ErrorCode::SIZE)
+        );
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            let mut outputs = [[0; 8]; 3];
+            for (out, seed) in outputs.iter_mut().zip([1, 1, 2]) {
+                assert_eq!(rng.set_test_seed(&[seed, 0x5EED]), Ok(()));
+                assert_eq!(rng.get(), Ok(()));
+                run_kernel_op(10000);
+                *out = cb.words.get();
+            }
+
+            assert_eq!(cb.requests.get(), 3);
+            assert_eq!(outputs[0], outputs[1]);
+            assert_ne!(outputs[0], outputs[2]);
+        }
+
+        // Don't leave the RNG predictable for later tests
+        rng.clear_test_seed();
+        assert_eq!(rng.cancel(), Ok(()));
+    }
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Clearing the client mid-request leaves the CSRNG usable by a new client
+#[test_case]
+fn csrng_clear_client() {
+    debug!("check CSRNG clear client... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let perf = PERIPHERALS.unwrap();
+        let rng = &perf.rng;
+
+        let old = static_init!(
+            CsrngCallback,
+            CsrngCallback {
+                requests: Cell::new(0)
+            }
+        );
+        let new = static_init!(
+            CsrngCallback,
+            CsrngCallback {
+                requests: Cell::new(0)
+            }
+        );
+
+        rng.set_client(old);
+
+        #[cfg(feature = "hardware_tests")]
+        assert_eq!(rng.get(), Ok(()));
+        assert_eq!(rng.clear_client(), Ok(()));
+        run_kernel_op(10000);
+        assert_eq!(old.requests.get(), 0);
+
+        rng.set_client(new);
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            assert_eq!(rng.get(), Ok(()));
+            run_kernel_op(10000);
+            assert_eq!(new.requests.get(), 1);
+            assert_eq!(old.requests.get(), 0);
+        }
+    }
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// `fill_bytes()` writes exactly the requested bytes, including a partial
+/// last word
+#[test_case]
+fn csrng_fill_bytes() {
+    debug!("check CSRNG fill_bytes... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let rng = &perf.rng;
+
+    let mut buf = [0xA5; 16];
+    assert_eq!(rng.fill_bytes(&mut buf[..0]), Ok(()));
+    assert_eq!(buf, [0xA5; 16]);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(rng.fill_bytes(&mut buf[..7]), Ok(()));
+        assert!(buf[7..].iter().all(|b| *b == 0xA5));
+        // 56 random bits all matching the fill is vanishingly unlikely
+        assert!(buf[..7].iter().any(|b| *b != 0xA5));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -0,0 +1,37 @@
+//! Test that a locked CSRNG is reported as such
+//!
+//! `REGWEN` can't be set again until reset, and OTBN takes its entropy from
+//! the CSRNG, so this module is declared after every other test that uses
+//! either of them.
+
+use crate::tests::run_kernel_op;
+use kernel::debug;
+
+/// A locked `REGWEN` is reported as `RESERVE`, not as a hardware failure.
+#[test_case]
+fn csrng_locked_regwen() {
+    debug!("check CSRNG reports a locked REGWEN... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use crate::PERIPHERALS;
+        use kernel::hil::entropy::Entropy32;
+        use kernel::ErrorCode;
+
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let rng = &perf.rng;
+
+        assert_eq!(rng.cancel(), Ok(()));
+
+        rng.lock();
+        assert!(rng.is_locked());
+
+        let mut out = [0u8; 4];
+        assert_eq!(rng.fill_bytes(&mut out), Err(ErrorCode::RESERVE));
+        assert_eq!(rng.get(), Err(ErrorCode::RESERVE));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
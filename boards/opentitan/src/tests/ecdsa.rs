@@ -0,0 +1,207 @@
+//! Test ECDSA P-256 on the OTBN
+//!
+//! The known answer vector is the P-256, SHA-256, "sample" vector from
+//! RFC 6979 appendix A.2.5.
+
+use crate::tests::run_kernel_op;
+use crate::ECDSA_HARDWARE;
+use crate::PERIPHERALS;
+use core::cell::Cell;
+use kernel::{debug, ErrorCode};
+use lowrisc::ecdsa::Client;
+
+/// SHA-256 of "sample"
+static HASH: [u8; 32] = [
+    0xaf, 0x2b, 0xdb, 0xe1, 0xaa, 0x9b, 0x6e, 0xc1, 0xe2, 0xad, 0xe1, 0xd6, 0x94, 0xf4, 0x1f, 0xc7,
+    0x1a, 0x83, 0x1d, 0x02, 0x68, 0xe9, 0x89, 0x15, 0x62, 0x11, 0x3d, 0x8a, 0x62, 0xad, 0xd1, 0xbf,
+];
+
+static PRIV_KEY: [u8; 32] = [
+    0xc9, 0xaf, 0xa9, 0xd8, 0x45, 0xba, 0x75, 0x16, 0x6b, 0x5c, 0x21, 0x57, 0x67, 0xb1, 0xd6, 0x93,
+    0x4e, 0x50, 0xc3, 0xdb, 0x36, 0xe8, 0x9b, 0x12, 0x7b, 0x8a, 0x62, 0x2b, 0x12, 0x0f, 0x67, 0x21,
+];
+
+static PUB_KEY: [u8; 64] = [
+    // x
+    0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6, 0x35, 0x6d, 0x68,
+    0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62, 0x2e, 0x60, 0xf2, 0x9f, 0xb6,
+    // y
+    0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4, 0x1a, 0xe9, 0xe9, 0x56, 0x28, 0xbc, 0x64,
+    0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f, 0x51, 0x77, 0xa3, 0xc2, 0x94, 0xd4, 0x46, 0x22, 0x99,
+];
+
+static SIGNATURE: [u8; 64] = [
+    // r
+    0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e, 0x81, 0xd6,
+    0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8, 0x4e, 0xaf, 0x37, 0x16,
+    // s
+    0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36, 0xc7, 0xa1, 0xb6, 0xe2, 0x9f, 0x65,
+    0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06, 0x4d, 0xc4, 0xab, 0x2f, 0x84, 0x3a, 0xcd, 0xa8,
+];
+
+/// `SIGNATURE` with the last byte of `r` changed
+static BAD_SIGNATURE: [u8; 64] = [
+    // r
+    0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e, 0x81, 0xd6,
+    0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8, 0x4e, 0xaf, 0x37, 0x17,
+    // s
+    0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36, 0xc7, 0xa1, 0xb6, 0xe2, 0x9f, 0x65,
+    0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06, 0x4d, 0xc4, 0xab, 0x2f, 0x84, 0x3a, 0xcd, 0xa8,
+];
+
+static mut SIGN_DEST: [u8; 64] = [0; 64];
+
+struct EcdsaTestCallback {
+    sign_done: Cell<bool>,
+    verify_result: Cell<Option<Result<bool, ErrorCode>>>,
+}
+
+unsafe impl Sync for EcdsaTestCallback {}
+
+impl EcdsaTestCallback {
+    const fn new() -> Self {
+        EcdsaTestCallback {
+            sign_done: Cell::new(false),
+            verify_result: Cell::new(None),
+        }
+    }
+
+    fn reset(&self) {
+        self.sign_done.set(false);
+        self.verify_result.set(None);
+    }
+}
+
+impl<'a> Client<'a> for EcdsaTestCallback {
+    fn sign_done(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        _hash: &'static [u8],
+        _private_key: &'static [u8],
+        _signature: &'static mut [u8],
+    ) {
+        assert_eq!(result, Ok(()));
+        self.sign_done.set(true);
+    }
+
+    fn verify_done(
+        &'a self,
+        result: Result<bool, ErrorCode>,
+        _hash: &'static [u8],
+        _public_key: &'static [u8],
+        _signature: &'static [u8],
+    ) {
+        self.verify_result.set(Some(result));
+    }
+}
+
+static CALLBACK: EcdsaTestCallback = EcdsaTestCallback::new();
+
+#[test_case]
+fn ecdsa_p256_check_size() {
+    if let Some(ecdsa) = unsafe { ECDSA_HARDWARE } {
+        debug!("check ecdsa p256 operand sizes... ");
+        run_kernel_op(100);
+
+        match ecdsa.ecdsa_p256_verify(&HASH[..31], &PUB_KEY, &SIGNATURE) {
+            Err((ErrorCode::SIZE, _, _, _)) => {}
+            _ => panic!("short hash was not rejected"),
+        }
+        match ecdsa.ecdsa_p256_verify(&HASH, &PUB_KEY[..32], &SIGNATURE) {
+            Err((ErrorCode::SIZE, _, _, _)) => {}
+            _ => panic!("short public key was not rejected"),
+        }
+
+        debug!("    [ok]");
+        run_kernel_op(100);
+    } else {
+        debug!("Not running ECDSA tests");
+    }
+}
+
+#[test_case]
+fn ecdsa_p256_verify_known_answer() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let otbn = &perf.otbn;
+    if let Some(ecdsa) = unsafe { ECDSA_HARDWARE } {
+        debug!("check ecdsa p256 verify... ");
+        run_kernel_op(100);
+
+        // Possibly overridden by other tests
+        otbn.set_client(ecdsa);
+        ecdsa.set_client(&CALLBACK);
+
+        CALLBACK.reset();
+        if ecdsa
+            .ecdsa_p256_verify(&HASH, &PUB_KEY, &SIGNATURE)
+            .is_err()
+        {
+            panic!("verify failed");
+        }
+        run_kernel_op(120000);
+        assert_eq!(CALLBACK.verify_result.get(), Some(Ok(true)));
+        ecdsa.clear_data();
+
+        CALLBACK.reset();
+        if ecdsa
+            .ecdsa_p256_verify(&HASH, &PUB_KEY, &BAD_SIGNATURE)
+            .is_err()
+        {
+            panic!("verify failed");
+        }
+        run_kernel_op(120000);
+        assert_eq!(CALLBACK.verify_result.get(), Some(Ok(false)));
+        ecdsa.clear_data();
+
+        debug!("    [ok]");
+        run_kernel_op(100);
+    } else {
+        debug!("Not running ECDSA tests");
+    }
+}
+
+/// Signatures use a random nonce, so check a new signature by verifying it
+#[test_case]
+fn ecdsa_p256_sign_and_verify() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let otbn = &perf.otbn;
+    if let Some(ecdsa) = unsafe { ECDSA_HARDWARE } {
+        debug!("check ecdsa p256 sign... ");
+        run_kernel_op(100);
+
+        // Possibly overridden by other tests
+        otbn.set_client(ecdsa);
+        ecdsa.set_client(&CALLBACK);
+
+        CALLBACK.reset();
+        unsafe {
+            if ecdsa
+                .ecdsa_p256_sign(&HASH, &PRIV_KEY, &mut SIGN_DEST)
+                .is_err()
+            {
+                panic!("sign failed");
+            }
+        }
+        run_kernel_op(120000);
+        assert_eq!(CALLBACK.sign_done.get(), true);
+        ecdsa.clear_data();
+
+        CALLBACK.reset();
+        unsafe {
+            if ecdsa
+                .ecdsa_p256_verify(&HASH, &PUB_KEY, &SIGN_DEST)
+                .is_err()
+            {
+                panic!("verify failed");
+            }
+        }
+        run_kernel_op(120000);
+        assert_eq!(CALLBACK.verify_result.get(), Some(Ok(true)));
+        ecdsa.clear_data();
+
+        debug!("    [ok]");
+        run_kernel_op(100);
+    } else {
+        debug!("Not running ECDSA tests");
+    }
+}
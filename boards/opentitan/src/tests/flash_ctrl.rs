@@ -0,0 +1,1001 @@
+//! Test the flash controller driver directly
+
+use crate::tests::run_kernel_op;
+use crate::PERIPHERALS;
+use core::cell::Cell;
+use kernel::debug;
+#[cfg(feature = "hardware_tests")]
+use kernel::hil::flash::Flash;
+use kernel::static_init;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+#[cfg(feature = "hardware_tests")]
+use lowrisc::flash_ctrl::FLASH_PAGE_SIZE;
+use lowrisc::flash_ctrl::{
+    FaultClient, FlashBank, FlashMPAdvConfig, FlashRegion, LowRiscPage, ReadPagesClient,
+    VerifiedWriteClient, WriteProgressClient, BANK_SIZE, PAGE_SIZE,
+};
+
+/// A page at the start of the flash storage area, 0x2009_0000 to
+/// 0x2010_0000 in layout.ld and test_layout.ld. That is clear of the kernel,
+/// apps and TicKV, so the tests are free to erase and program it and the few
+/// pages after it.
+pub(super) const TEST_PAGE: usize = 0x2009_0000 / PAGE_SIZE;
+
+struct ReadPagesCallback {
+    result: Cell<Option<Result<(), ErrorCode>>>,
+    buf: TakeCell<'static, [u8]>,
+}
+
+impl ReadPagesClient for ReadPagesCallback {
+    fn read_pages_complete(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.result.set(Some(result));
+        self.buf.replace(buf);
+    }
+}
+
+struct WriteProgressCallback {
+    calls: Cell<usize>,
+    last: Cell<usize>,
+}
+
+impl WriteProgressClient for WriteProgressCallback {
+    fn write_progress(&self, bytes_written: usize, total: usize) {
+        assert_eq!(total, PAGE_SIZE);
+        assert!(bytes_written >= self.last.get());
+        assert!(bytes_written <= total);
+        self.calls.set(self.calls.get() + 1);
+        self.last.set(bytes_written);
+    }
+}
+
+struct VerifiedWriteCallback {
+    result: Cell<Option<Result<(), ErrorCode>>>,
+    buf: TakeCell<'static, LowRiscPage>,
+}
+
+impl VerifiedWriteClient for VerifiedWriteCallback {
+    fn write_verified_complete(
+        &self,
+        buf: &'static mut LowRiscPage,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.result.set(Some(result));
+        self.buf.replace(buf);
+    }
+}
+
+struct FaultCallback {
+    calls: Cell<usize>,
+}
+
+impl FaultClient for FaultCallback {
+    fn fault_detected(&self, _fault_status: u32) {
+        self.calls.set(self.calls.get() + 1);
+    }
+}
+
+/// Issue two back-to-back writes, the second must be rejected while the
+/// first is still in flight and the buffer handed back.
+#[test_case]
+fn flash_ctrl_busy() {
+    debug!("check flash_ctrl rejects concurrent operations... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        let page1 = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        let page2 = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        assert!(flash_ctrl.write_page(TEST_PAGE, page1).is_ok());
+
+        match flash_ctrl.write_page(TEST_PAGE + 1, page2) {
+            Err((ErrorCode::BUSY, _buf)) => {}
+            _ => panic!("second write was not rejected"),
+        }
+
+        // Let the first write complete so the controller is idle again
+        run_kernel_op(1000);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A read issued while a write is still feeding the program FIFO must be
+/// rejected, rather than interleaving with the write.
+#[test_case]
+fn flash_ctrl_read_during_write() {
+    debug!("check flash_ctrl rejects reads during a write... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        let write_page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        let read_page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        assert!(flash_ctrl.write_page(TEST_PAGE, write_page).is_ok());
+
+        match flash_ctrl.read_page(TEST_PAGE, read_page) {
+            Err((ErrorCode::BUSY, _buf)) => {}
+            _ => panic!("read during write was not rejected"),
+        }
+
+        // Let the write complete so the controller is idle again
+        run_kernel_op(1000);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A bank erase can't be started while a page erase is still in flight.
+#[test_case]
+fn flash_ctrl_bank_erase_busy() {
+    debug!("check flash_ctrl rejects bank erase during page erase... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        assert_eq!(
+            flash_ctrl.erase_bank(FlashBank::BANK1),
+            Err(ErrorCode::BUSY)
+        );
+
+        // Let the page erase complete so the controller is idle again
+        run_kernel_op(1000);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn flash_ctrl_geometry() {
+    debug!("check flash_ctrl geometry... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let geometry = perf.flash_ctrl.geometry();
+
+    assert_eq!(geometry.page_size, PAGE_SIZE);
+    assert_eq!(geometry.page_size, LowRiscPage::default().0.len());
+    assert_eq!(geometry.page_size * geometry.pages_per_bank, BANK_SIZE);
+    assert_eq!(geometry.num_banks, 2);
+    assert_eq!(geometry.page_size % geometry.word_size, 0);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// The controller has long since initialised by the time the tests run
+#[test_case]
+fn flash_ctrl_ready() {
+    debug!("check flash_ctrl is ready... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    assert!(flash_ctrl.is_ready());
+    assert_eq!(flash_ctrl.wait_for_ready(), Ok(()));
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A healthy controller has no faults, so neither checking for them nor an
+/// operation's interrupts call the fault client
+#[test_case]
+fn flash_ctrl_fault_status() {
+    debug!("check flash_ctrl fault status... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+    let cb = unsafe {
+        static_init!(
+            FaultCallback,
+            FaultCallback {
+                calls: Cell::new(0)
+            }
+        )
+    };
+    flash_ctrl.set_fault_client(cb);
+
+    assert_eq!(flash_ctrl.fault_status(), 0);
+    assert_eq!(flash_ctrl.check_faults(), 0);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.check_faults(), 0);
+        assert_eq!(cb.calls.get(), 0);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Reading the operation status doesn't change it
+#[test_case]
+fn flash_ctrl_op_status() {
+    debug!("check flash_ctrl op status... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let status = flash_ctrl.op_status();
+    assert_eq!(flash_ctrl.op_status(), status);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+
+        let status = flash_ctrl.op_status();
+        assert_eq!(flash_ctrl.op_status(), status);
+        assert_eq!(status, (true, false));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// In strict mode a write over a page that hasn't been erased is rejected
+/// and the buffer handed back, while a write to an erased page goes ahead.
+#[test_case]
+fn flash_ctrl_strict_write() {
+    debug!("check flash_ctrl strict write rejects unerased pages... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    flash_ctrl.set_strict_write(true);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        let page2 = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        // Pages outside the flash aren't scanned
+        let page = match flash_ctrl.write_page(usize::MAX, page) {
+            Err((ErrorCode::INVAL, buf)) => buf,
+            _ => panic!("write outside the flash was not rejected"),
+        };
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+
+        // The page is erased, so the write is allowed
+        assert!(flash_ctrl.write_page(TEST_PAGE, page).is_ok());
+        run_kernel_op(1000);
+
+        // The page now holds zeros, writing it again must be rejected
+        match flash_ctrl.write_page(TEST_PAGE, page2) {
+            Err((ErrorCode::INVAL, _buf)) => {}
+            _ => panic!("write to unerased page was not rejected"),
+        }
+
+        // A rejected write must leave the controller idle
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+    }
+
+    flash_ctrl.set_strict_write(false);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Reads into a buffer that isn't a whole number of pages are rejected, and
+/// a multi-page read fills the buffer page by page with one callback.
+#[test_case]
+fn flash_ctrl_read_pages() {
+    debug!("check flash_ctrl multi-page reads... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let cb = unsafe {
+        static_init!(
+            ReadPagesCallback,
+            ReadPagesCallback {
+                result: Cell::new(None),
+                buf: TakeCell::empty(),
+            }
+        )
+    };
+    flash_ctrl.set_read_pages_client(cb);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let partial = unsafe { static_init!([u8; PAGE_SIZE + 4], [0; PAGE_SIZE + 4]) };
+        match flash_ctrl.read_pages(TEST_PAGE, partial) {
+            Err((ErrorCode::SIZE, _buf)) => {}
+            _ => panic!("partial page read was not rejected"),
+        }
+
+        let buf = unsafe { static_init!([u8; PAGE_SIZE * 2], [0; PAGE_SIZE * 2]) };
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE + 1), Ok(()));
+        run_kernel_op(1000);
+
+        assert!(flash_ctrl.read_pages(TEST_PAGE, buf).is_ok());
+        run_kernel_op(2000);
+
+        assert_eq!(cb.result.get(), Some(Ok(())));
+        let buf = cb.buf.take().unwrap();
+        assert!(buf.iter().all(|b| *b == 0xFF));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Info partition accesses are bounds checked against the info type and the
+/// number of pages of that type, the buffer is handed back on error.
+#[test_case]
+fn flash_ctrl_info_bounds() {
+    debug!("check flash_ctrl info partition bounds... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use lowrisc::flash_ctrl::INFO_PAGES;
+
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        // There is no info3
+        let page = match flash_ctrl.read_info_page(FlashBank::BANK1, INFO_PAGES.len(), 0, page) {
+            Err((ErrorCode::INVAL, buf)) => buf,
+            _ => panic!("read of info3 was not rejected"),
+        };
+
+        // One past the end of info1
+        let last = INFO_PAGES[1] * FLASH_PAGE_SIZE / PAGE_SIZE;
+        match flash_ctrl.write_info_page(FlashBank::BANK0, 1, last, page) {
+            Err((ErrorCode::INVAL, _buf)) => {}
+            _ => panic!("write past the end of info1 was not rejected"),
+        }
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A write reports its progress as the program FIFO drains, never going
+/// backwards or past the page size.
+#[test_case]
+fn flash_ctrl_write_progress() {
+    debug!("check flash_ctrl write progress... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let cb = unsafe {
+        static_init!(
+            WriteProgressCallback,
+            WriteProgressCallback {
+                calls: Cell::new(0),
+                last: Cell::new(0),
+            }
+        )
+    };
+    flash_ctrl.set_write_progress_client(cb);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        assert!(flash_ctrl.write_page(TEST_PAGE, page).is_ok());
+        run_kernel_op(1000);
+
+        assert!(cb.calls.get() > 0);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A verified write reports success when the page reads back as written,
+/// and FAIL when it doesn't. Programming can only clear bits, so writing
+/// ones over zeros without an erase leaves the page different.
+#[test_case]
+fn flash_ctrl_write_verified() {
+    debug!("check flash_ctrl verified writes... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let cb = unsafe {
+        static_init!(
+            VerifiedWriteCallback,
+            VerifiedWriteCallback {
+                result: Cell::new(None),
+                buf: TakeCell::empty(),
+            }
+        )
+    };
+    flash_ctrl.set_verified_write_client(cb);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        let page = match flash_ctrl.write_page_verified(TEST_PAGE, page) {
+            Err((ErrorCode::NOMEM, buf)) => buf,
+            _ => panic!("verified write without a verify buffer was not rejected"),
+        };
+
+        let verify = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        flash_ctrl.set_verify_buffer(verify);
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+
+        page.0.iter_mut().for_each(|b| *b = 0x00);
+        assert!(flash_ctrl.write_page_verified(TEST_PAGE, page).is_ok());
+        run_kernel_op(2000);
+        assert_eq!(cb.result.take(), Some(Ok(())));
+
+        let page = cb.buf.take().unwrap();
+        page.0.iter_mut().for_each(|b| *b = 0xFF);
+        assert!(flash_ctrl.write_page_verified(TEST_PAGE, page).is_ok());
+        run_kernel_op(2000);
+        assert_eq!(cb.result.take(), Some(Err(ErrorCode::FAIL)));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A full page read with both the watermark and FIFO full interrupts
+/// draining the read FIFO returns every word exactly once, in order.
+#[test_case]
+fn flash_ctrl_read_full_page() {
+    debug!("check flash_ctrl full page read... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let cb = unsafe {
+        static_init!(
+            ReadPagesCallback,
+            ReadPagesCallback {
+                result: Cell::new(None),
+                buf: TakeCell::empty(),
+            }
+        )
+    };
+    flash_ctrl.set_read_pages_client(cb);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        let buf = unsafe { static_init!([u8; PAGE_SIZE], [0; PAGE_SIZE]) };
+
+        // Every word is different, so a lost or repeated word shows up
+        for (i, b) in page.0.iter_mut().enumerate() {
+            *b = (i ^ (i >> 8)) as u8;
+        }
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        assert!(flash_ctrl.write_page(TEST_PAGE, page).is_ok());
+        run_kernel_op(1000);
+
+        assert!(flash_ctrl.read_pages(TEST_PAGE, buf).is_ok());
+        run_kernel_op(2000);
+
+        assert_eq!(cb.result.get(), Some(Ok(())));
+        let buf = cb.buf.take().unwrap();
+        for (i, b) in buf.iter().enumerate() {
+            assert_eq!(*b, (i ^ (i >> 8)) as u8);
+        }
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// The blank check reports an erased page as erased and a written one as
+/// not, without changing either.
+#[test_case]
+fn flash_ctrl_blank_check() {
+    debug!("check flash_ctrl blank check... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    assert_eq!(
+        flash_ctrl.is_region_erased(TEST_PAGE, 0),
+        Err(ErrorCode::INVAL)
+    );
+    // Outside the flash, including page numbers that overflow
+    assert_eq!(flash_ctrl.is_page_erased(0), Err(ErrorCode::INVAL));
+    assert_eq!(
+        flash_ctrl.is_region_erased(TEST_PAGE, BANK_SIZE),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        flash_ctrl.is_region_erased(usize::MAX / PAGE_SIZE, 2),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(flash_ctrl.is_page_erased(usize::MAX), Err(ErrorCode::INVAL));
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE), Err(ErrorCode::BUSY));
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE + 1), Ok(()));
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE), Ok(true));
+        assert_eq!(flash_ctrl.is_region_erased(TEST_PAGE, 2), Ok(true));
+
+        // A single cleared bit is enough for the page to not be blank
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+        page.0.iter_mut().for_each(|b| *b = 0xFF);
+        page.0[PAGE_SIZE - 1] = 0xFE;
+        assert!(flash_ctrl.write_page(TEST_PAGE + 1, page).is_ok());
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE), Ok(true));
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE + 1), Ok(false));
+        assert_eq!(flash_ctrl.is_region_erased(TEST_PAGE, 2), Ok(false));
+
+        // Checking doesn't modify the page
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE), Ok(true));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Region permissions changed after a snapshot are put back by restoring it
+#[test_case]
+fn flash_ctrl_region_snapshot() {
+    debug!("check flash_ctrl region snapshot/restore... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let snapshot = flash_ctrl.snapshot_regions();
+    // Nothing has changed, so there is nothing to restore
+    assert_eq!(flash_ctrl.restore_regions(&snapshot), Ok(()));
+
+    let too_big = FlashMPAdvConfig {
+        base: 0x100,
+        ..snapshot[7]
+    };
+    if !flash_ctrl.is_region_locked(FlashRegion::REGION7) {
+        assert_eq!(
+            flash_ctrl.set_adv_region_perms(FlashRegion::REGION7, &too_big),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let relaxed = FlashMPAdvConfig {
+            enabled: true,
+            base: 0,
+            size: 1,
+            read_en: true,
+            prog_en: true,
+            erase_en: true,
+            ..FlashMPAdvConfig::default()
+        };
+        assert_eq!(
+            flash_ctrl.set_adv_region_perms(FlashRegion::REGION7, &relaxed),
+            Ok(())
+        );
+        assert_eq!(
+            flash_ctrl.read_adv_region_perms(FlashRegion::REGION7),
+            relaxed
+        );
+
+        assert_eq!(flash_ctrl.restore_regions(&snapshot), Ok(()));
+        assert_eq!(flash_ctrl.snapshot_regions(), snapshot);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Region numbers convert to regions up to the last one, and a number past
+/// it is refused instead of indexing past the region registers.
+#[test_case]
+fn flash_ctrl_region_from_usize() {
+    debug!("check flash_ctrl region numbers... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    for (n, region) in FlashRegion::ALL.iter().enumerate() {
+        assert_eq!(FlashRegion::from_usize(n), Some(*region));
+    }
+    assert_eq!(FlashRegion::from_usize(7), Some(FlashRegion::REGION7));
+    assert_eq!(FlashRegion::from_usize(8), None);
+
+    let region = FlashRegion::from_usize(7).unwrap();
+    assert_eq!(
+        flash_ctrl.read_adv_region_perms(region),
+        flash_ctrl.snapshot_regions()[7]
+    );
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Scrambling and ECC can't be turned on for a data region that holds data
+#[test_case]
+fn flash_ctrl_data_security() {
+    debug!("check flash_ctrl data partition scramble/ECC... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        // Leaving them as they are is always allowed
+        assert_eq!(
+            flash_ctrl.set_data_partition_security(&FlashMPAdvConfig::default()),
+            Ok(())
+        );
+
+        // Any operation configures the data partition region, after which
+        // its place in flash can be read back
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        let orig = flash_ctrl.read_adv_region_perms(FlashRegion::REGION0);
+        let region_addr = 0x2000_0000 + orig.base * FLASH_PAGE_SIZE;
+
+        // Make sure the region holds data
+        assert_eq!(flash_ctrl.erase_page(region_addr / PAGE_SIZE), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(flash_ctrl.write_words(region_addr, &[0x0123_4567]), Ok(()));
+
+        let secure = FlashMPAdvConfig {
+            scramble_en: true,
+            ecc_en: true,
+            ..FlashMPAdvConfig::default()
+        };
+        let result = flash_ctrl.set_data_partition_security(&secure);
+        let cfg = flash_ctrl.read_adv_region_perms(FlashRegion::REGION0);
+
+        // Put the region back before checking, so a failure doesn't leave
+        // scrambling and ECC on for the later tests
+        assert_eq!(flash_ctrl.erase_page(region_addr / PAGE_SIZE), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(flash_ctrl.set_data_partition_security(&orig), Ok(()));
+        assert_eq!(flash_ctrl.read_adv_region_perms(FlashRegion::REGION0), orig);
+
+        assert_eq!(result, Err(ErrorCode::INVAL));
+        assert_eq!(
+            (cfg.scramble_en, cfg.ecc_en),
+            (orig.scramble_en, orig.ecc_en)
+        );
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A read issued while a page erase is suspended completes first, then the
+/// erase is resumed and finishes.
+#[test_case]
+fn flash_ctrl_erase_suspend() {
+    debug!("check flash_ctrl read during suspended erase... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    // Nothing to suspend
+    assert_eq!(flash_ctrl.suspend_erase(), Err(ErrorCode::INVAL));
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        // Nothing to resume
+        assert_eq!(flash_ctrl.resume_erase(), Err(ErrorCode::INVAL));
+
+        let cb = unsafe {
+            static_init!(
+                ReadPagesCallback,
+                ReadPagesCallback {
+                    result: Cell::new(None),
+                    buf: TakeCell::empty(),
+                }
+            )
+        };
+        flash_ctrl.set_read_pages_client(cb);
+        let buf = unsafe { static_init!([u8; PAGE_SIZE], [0; PAGE_SIZE]) };
+        let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+
+        // Make sure the erase has something to do
+        page.0.iter_mut().for_each(|b| *b = 0);
+        assert!(flash_ctrl.write_page(TEST_PAGE, page).is_ok());
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        assert_eq!(flash_ctrl.suspend_erase(), Ok(()));
+        assert_eq!(flash_ctrl.suspend_erase(), Err(ErrorCode::ALREADY));
+        run_kernel_op(100);
+
+        // The erase may have finished before the suspend took effect
+        if flash_ctrl.is_erase_suspended() {
+            let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+            match flash_ctrl.write_page(TEST_PAGE + 1, page) {
+                Err((ErrorCode::BUSY, _page)) => {}
+                _ => panic!("write during a suspended erase was not rejected"),
+            }
+
+            assert!(flash_ctrl.read_pages(TEST_PAGE + 1, buf).is_ok());
+            run_kernel_op(1000);
+            assert_eq!(cb.result.get(), Some(Ok(())));
+        }
+
+        // The erase has been resumed and has completed
+        run_kernel_op(1000);
+        assert!(!flash_ctrl.is_erase_suspended());
+        assert_eq!(flash_ctrl.is_page_erased(TEST_PAGE), Ok(true));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Words are programmed across a program window boundary without touching
+/// their neighbours, and can't be programmed again until erased.
+#[test_case]
+fn flash_ctrl_write_words() {
+    debug!("check flash_ctrl word writes... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use lowrisc::flash_ctrl::WORD_SIZE;
+
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        // Straddle the boundary between the first two program windows
+        let addr = TEST_PAGE * PAGE_SIZE + PAGE_SIZE - 2 * WORD_SIZE;
+        let words = [0x0123_4567, 0x89AB_CDEF, 0xFEDC_BA98, 0x7654_3210];
+
+        assert_eq!(flash_ctrl.write_words(addr, &[]), Err(ErrorCode::INVAL));
+        assert_eq!(
+            flash_ctrl.write_words(addr + 1, &words),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            flash_ctrl.write_words(0x1000, &words),
+            Err(ErrorCode::INVAL)
+        );
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        assert_eq!(flash_ctrl.write_words(addr, &words), Err(ErrorCode::BUSY));
+        run_kernel_op(1000);
+
+        assert_eq!(flash_ctrl.write_words(addr, &words), Ok(()));
+        for (i, &word) in words.iter().enumerate() {
+            let ptr = (addr + i * WORD_SIZE) as *const u32;
+            assert_eq!(unsafe { core::ptr::read_volatile(ptr) }, word);
+        }
+
+        // The words either side are still erased
+        let before = (addr - WORD_SIZE) as *const u32;
+        let after = (addr + words.len() * WORD_SIZE) as *const u32;
+        assert_eq!(unsafe { core::ptr::read_volatile(before) }, 0xFFFF_FFFF);
+        assert_eq!(unsafe { core::ptr::read_volatile(after) }, 0xFFFF_FFFF);
+
+        // Programming can only clear bits
+        assert_eq!(flash_ctrl.write_words(addr, &words), Err(ErrorCode::INVAL));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A region of several physical pages is erased by one request
+#[test_case]
+fn flash_ctrl_erase_region() {
+    debug!("check flash_ctrl region erase... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        const PAGES_PER_FLASH_PAGE: usize = FLASH_PAGE_SIZE / PAGE_SIZE;
+        const REGION_PAGES: usize = 4 * PAGES_PER_FLASH_PAGE;
+
+        assert_eq!(flash_ctrl.erase_region(TEST_PAGE, 0), Err(ErrorCode::INVAL));
+        assert_eq!(
+            flash_ctrl.erase_region(TEST_PAGE + 1, REGION_PAGES),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            flash_ctrl.erase_region(TEST_PAGE, REGION_PAGES - 1),
+            Err(ErrorCode::INVAL)
+        );
+
+        // Leave something to erase in each physical page
+        for i in 0..4 {
+            let page = unsafe { static_init!(LowRiscPage, LowRiscPage::default()) };
+            let page_number = TEST_PAGE + i * PAGES_PER_FLASH_PAGE;
+            assert!(flash_ctrl.write_page(page_number, page).is_ok());
+            run_kernel_op(1000);
+        }
+        assert_eq!(
+            flash_ctrl.is_region_erased(TEST_PAGE, REGION_PAGES),
+            Ok(false)
+        );
+
+        assert_eq!(flash_ctrl.erase_region(TEST_PAGE, REGION_PAGES), Ok(()));
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Err(ErrorCode::BUSY));
+        run_kernel_op(5000);
+
+        assert_eq!(
+            flash_ctrl.is_region_erased(TEST_PAGE, REGION_PAGES),
+            Ok(true)
+        );
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Disabling flash is refused while an operation is in flight. Actually
+/// disabling it would stop the kernel running from flash, so that can only
+/// be checked by a standalone image that resets afterwards.
+#[test_case]
+fn flash_ctrl_disable_busy() {
+    debug!("check flash_ctrl refuses to disable during an erase... ");
+    run_kernel_op(100);
+
+    // DIS reads as zero on QEMU, which counts as disabled
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let flash_ctrl = &perf.flash_ctrl;
+
+        assert!(!flash_ctrl.is_flash_disabled());
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        assert_eq!(flash_ctrl.disable_flash(), Err(ErrorCode::BUSY));
+        assert!(!flash_ctrl.is_flash_disabled());
+
+        // Let the page erase complete so the controller is idle again
+        run_kernel_op(1000);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Completed erases are counted against their bank once counting is enabled
+#[test_case]
+fn flash_ctrl_erase_count() {
+    debug!("check flash_ctrl erase counting... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    flash_ctrl.set_erase_count(FlashBank::BANK1, 10);
+    flash_ctrl.set_erase_count(FlashBank::BANK0, 0);
+    assert_eq!(flash_ctrl.erase_count(FlashBank::BANK1), 10);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        // Off by default
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(flash_ctrl.erase_count(FlashBank::BANK1), 10);
+
+        flash_ctrl.set_erase_counting(true);
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(flash_ctrl.erase_count(FlashBank::BANK1), 11);
+        assert_eq!(flash_ctrl.erase_count(FlashBank::BANK0), 0);
+
+        // Each physical page of a region counts
+        const PAGES_PER_FLASH_PAGE: usize = FLASH_PAGE_SIZE / PAGE_SIZE;
+        assert_eq!(
+            flash_ctrl.erase_region(TEST_PAGE, 2 * PAGES_PER_FLASH_PAGE),
+            Ok(())
+        );
+        run_kernel_op(2000);
+        assert_eq!(flash_ctrl.erase_count(FlashBank::BANK1), 13);
+    }
+
+    flash_ctrl.set_erase_counting(false);
+    flash_ctrl.set_erase_count(FlashBank::BANK1, 0);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Locking a region needs its current configuration confirmed, and once
+/// locked the region refuses any further change until reset.
+#[test_case]
+fn flash_ctrl_region_lock() {
+    debug!("check flash_ctrl region lock... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let flash_ctrl = &perf.flash_ctrl;
+
+    let cfg = flash_ctrl.read_adv_region_perms(FlashRegion::REGION7);
+    if !flash_ctrl.is_region_locked(FlashRegion::REGION7) {
+        let other = FlashMPAdvConfig {
+            read_en: !cfg.read_en,
+            ..cfg
+        };
+        assert_eq!(
+            flash_ctrl.lock_region_cfg_confirmed(FlashRegion::REGION7, &other),
+            Err(ErrorCode::INVAL)
+        );
+        assert!(!flash_ctrl.is_region_locked(FlashRegion::REGION7));
+    }
+
+    // Locked until reset, this is the last test to use REGION7
+    #[cfg(feature = "hardware_tests")]
+    {
+        if !flash_ctrl.is_region_locked(FlashRegion::REGION7) {
+            assert_eq!(
+                flash_ctrl.lock_region_cfg_confirmed(FlashRegion::REGION7, &cfg),
+                Ok(cfg)
+            );
+        }
+        assert!(flash_ctrl.is_region_locked(FlashRegion::REGION7));
+        assert!(flash_ctrl.is_any_region_locked());
+
+        assert_eq!(
+            flash_ctrl.lock_region_cfg_confirmed(FlashRegion::REGION7, &cfg),
+            Err(ErrorCode::ALREADY)
+        );
+        assert_eq!(
+            flash_ctrl.set_adv_region_perms(FlashRegion::REGION7, &cfg),
+            Err(ErrorCode::RESERVE)
+        );
+        assert_eq!(flash_ctrl.read_adv_region_perms(FlashRegion::REGION7), cfg);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
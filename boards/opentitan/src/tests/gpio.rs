@@ -0,0 +1,247 @@
+use crate::tests::run_kernel_op;
+use crate::PERIPHERALS;
+use earlgrey::gpio::{GpioBus, NUM_PINS};
+use kernel::debug;
+use kernel::ErrorCode;
+
+/// A bus can only be made from pins that exist
+#[test_case]
+fn gpio_bus_range() {
+    debug!("check GPIO bus range validation... ");
+    run_kernel_op(100);
+
+    assert_eq!(GpioBus::new(0, 0).err(), Some(ErrorCode::INVAL));
+    assert_eq!(GpioBus::new(NUM_PINS, 1).err(), Some(ErrorCode::INVAL));
+    assert_eq!(GpioBus::new(25, 8).err(), Some(ErrorCode::INVAL));
+
+    assert_eq!(GpioBus::new(24, 8).map(|bus| bus.width()), Ok(8));
+    assert_eq!(
+        GpioBus::new(0, NUM_PINS).map(|bus| bus.width()),
+        Ok(NUM_PINS)
+    );
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Drive the LEDs as an 8-bit bus and read the value back from the pads
+#[cfg(feature = "hardware_tests")]
+#[test_case]
+fn gpio_bus_write_read() {
+    use kernel::hil::gpio::Configure;
+
+    debug!("check GPIO bus write and read... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let bus = GpioBus::new(8, 8).unwrap();
+
+    for pin in 8..16 {
+        perf.gpio_port[pin].make_output();
+    }
+    bus.set_direction(true);
+
+    for value in [0xA5, 0x5A, 0xFF, 0x00] {
+        bus.write(value);
+        assert_eq!(bus.read(), value);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+struct GpioCallback {
+    fired: core::cell::Cell<usize>,
+}
+
+impl kernel::hil::gpio::Client for GpioCallback {
+    fn fired(&self) {
+        self.fired.set(self.fired.get() + 1);
+    }
+}
+
+/// Interrupts on two pins at once both reach their clients
+#[cfg(feature = "hardware_tests")]
+#[test_case]
+fn gpio_interrupt_two_pins() {
+    use kernel::hil::gpio::{Configure, Interrupt, InterruptEdge, Output};
+    use kernel::static_init;
+
+    debug!("check GPIO interrupts on two pins... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let pins = [&perf.gpio_port[8], &perf.gpio_port[9]];
+    let callbacks = unsafe {
+        [
+            &*static_init!(
+                GpioCallback,
+                GpioCallback {
+                    fired: core::cell::Cell::new(0)
+                }
+            ),
+            &*static_init!(
+                GpioCallback,
+                GpioCallback {
+                    fired: core::cell::Cell::new(0)
+                }
+            ),
+        ]
+    };
+
+    for (pin, cb) in pins.iter().zip(callbacks.iter()) {
+        pin.make_output();
+        pin.make_input();
+        pin.clear();
+        pin.set_client(*cb);
+        pin.enable_interrupts(InterruptEdge::RisingEdge);
+    }
+
+    // Raise both before either interrupt is handled
+    pins[0].set();
+    pins[1].set();
+    run_kernel_op(1000);
+
+    for (pin, cb) in pins.iter().zip(callbacks.iter()) {
+        assert_eq!(cb.fired.get(), 1);
+        pin.disable_interrupts();
+        pin.clear();
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A client is only registered for a pin that exists, and only sees that
+/// pin's interrupts. An interrupt on a pin without a client is cleared.
+#[test_case]
+fn gpio_set_client() {
+    use kernel::static_init;
+
+    debug!("check GPIO per pin clients... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let cb = unsafe {
+        static_init!(
+            GpioCallback,
+            GpioCallback {
+                fired: core::cell::Cell::new(0)
+            }
+        )
+    };
+
+    assert_eq!(
+        perf.gpio_port.set_client(NUM_PINS, cb),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(perf.gpio_port.set_client(8, cb), Ok(()));
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use kernel::hil::gpio::{Configure, Interrupt, InterruptEdge, Output};
+
+        // Pin 9 has no client
+        let pins = [&perf.gpio_port[8], &perf.gpio_port[9]];
+        for pin in pins.iter() {
+            pin.make_output();
+            pin.make_input();
+            pin.clear();
+            pin.enable_interrupts(InterruptEdge::RisingEdge);
+        }
+
+        pins[1].set();
+        run_kernel_op(1000);
+        assert_eq!(cb.fired.get(), 0);
+        assert!(!pins[1].is_pending());
+
+        pins[0].set();
+        run_kernel_op(1000);
+        assert_eq!(cb.fired.get(), 1);
+
+        for pin in pins.iter() {
+            pin.disable_interrupts();
+            pin.clear();
+        }
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// With deferral on the interrupt handler only records the edge, and every
+/// pin that fired has its client called once from the deferred call
+#[test_case]
+fn gpio_deferred_interrupts() {
+    debug!("check GPIO deferred interrupts... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+
+    assert_eq!(
+        perf.gpio_port.set_deferred(NUM_PINS, true),
+        Err(ErrorCode::INVAL)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use kernel::hil::gpio::{Configure, Interrupt, InterruptEdge, Output};
+        use kernel::static_init;
+
+        let pins = [&perf.gpio_port[8], &perf.gpio_port[9]];
+        let callbacks = unsafe {
+            [
+                &*static_init!(
+                    GpioCallback,
+                    GpioCallback {
+                        fired: core::cell::Cell::new(0)
+                    }
+                ),
+                &*static_init!(
+                    GpioCallback,
+                    GpioCallback {
+                        fired: core::cell::Cell::new(0)
+                    }
+                ),
+            ]
+        };
+
+        for (n, (pin, cb)) in pins.iter().zip(callbacks.iter()).enumerate() {
+            assert_eq!(perf.gpio_port.set_deferred(8 + n, true), Ok(()));
+            pin.make_output();
+            pin.make_input();
+            pin.clear();
+            pin.set_client(*cb);
+            pin.enable_interrupts(InterruptEdge::RisingEdge);
+        }
+
+        // Handle both edges here as the ISR would, the clients are only
+        // called once the deferred call runs
+        pins[0].set();
+        pins[1].set();
+        perf.gpio_port.handle_interrupt(8);
+        perf.gpio_port.handle_interrupt(9);
+        assert_eq!(callbacks[0].fired.get(), 0);
+        assert_eq!(callbacks[1].fired.get(), 0);
+
+        run_kernel_op(1000);
+        assert_eq!(callbacks[0].fired.get(), 1);
+        assert_eq!(callbacks[1].fired.get(), 1);
+
+        // Without deferral the client is called from the handler
+        assert_eq!(perf.gpio_port.set_deferred(8, false), Ok(()));
+        pins[0].clear();
+        pins[0].set();
+        perf.gpio_port.handle_interrupt(8);
+        assert_eq!(callbacks[0].fired.get(), 2);
+
+        for (n, pin) in pins.iter().enumerate() {
+            pin.disable_interrupts();
+            pin.clear();
+            assert_eq!(perf.gpio_port.set_deferred(8 + n, false), Ok(()));
+        }
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -1,14 +1,19 @@
+use crate::tests::flash_ctrl::TEST_PAGE;
 use crate::tests::run_kernel_op;
 use crate::PERIPHERALS;
 use core::cell::Cell;
 #[allow(unused_imports)] // Can be unused if software only test
 use kernel::hil::digest::DigestData;
-use kernel::hil::digest::{self, Digest, DigestVerify, HmacSha256};
+use kernel::hil::digest::{self, Digest, DigestVerify, HmacSha256, HmacSha512, Sha256, Sha384};
+#[allow(unused_imports)] // Can be unused if software only test
+use kernel::hil::flash::Flash;
 use kernel::static_init;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::leasable_buffer::LeasableBuffer;
 use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
 use kernel::{debug, ErrorCode};
+use lowrisc::flash_verify::FlashVerifier;
+use lowrisc::hmac::DigestMode;
 
 static KEY: [u8; 32] = [0xA1; 32];
 
@@ -89,6 +94,43 @@ unsafe fn static_init_test_cb() -> &'static HmacTestCallback {
     )
 }
 
+/// Only the SHA-256 modes are accepted, the others are refused up front
+#[test_case]
+fn hmac_check_modes() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    debug!("check hmac supported modes... ");
+    run_kernel_op(100);
+
+    assert_eq!(
+        hmac.supported_modes(),
+        &[DigestMode::Sha256, DigestMode::HmacSha256]
+    );
+
+    for mode in [
+        DigestMode::Sha384,
+        DigestMode::Sha512,
+        DigestMode::HmacSha384,
+        DigestMode::HmacSha512,
+    ] {
+        assert_eq!(hmac.set_mode(mode, &KEY), Err(ErrorCode::NOSUPPORT));
+    }
+    assert_eq!(hmac.set_mode_sha384(), Err(ErrorCode::NOSUPPORT));
+    assert_eq!(hmac.set_mode_hmacsha512(&KEY), Err(ErrorCode::NOSUPPORT));
+
+    assert_eq!(hmac.set_mode(DigestMode::Sha256, &[]), Ok(()));
+    assert_eq!(hmac.set_mode(DigestMode::HmacSha256, &KEY), Ok(()));
+    assert_eq!(
+        hmac.set_mode(DigestMode::HmacSha256, &[0; 33]),
+        Err(ErrorCode::NOSUPPORT)
+    );
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
 #[test_case]
 fn hmac_check_load_binary() {
     let perf = unsafe { PERIPHERALS.unwrap() };
@@ -150,3 +192,286 @@ fn hmac_check_verify() {
     debug!("    [ok]");
     run_kernel_op(100);
 }
+
+/// Known answer tests for messages that don't fill the last FIFO word.
+/// The digests are HMAC-SHA256 with `KEY` of "Hello, world!" (13 bytes)
+/// and "a" (1 byte).
+#[test_case]
+fn hmac_check_verify_unaligned() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    let callbacks = unsafe {
+        let input_13 = static_init!([u8; 13], *b"Hello, world!");
+        let digest_13 = static_init!(
+            [u8; 32],
+            [
+                0x76, 0x0b, 0x61, 0xa2, 0x4f, 0x0a, 0x7e, 0xda, 0x8e, 0x2b, 0x8c, 0x3c, 0xd3, 0x7e,
+                0xca, 0x9f, 0x76, 0xb0, 0xd1, 0xbf, 0x24, 0x82, 0x6d, 0x46, 0x6d, 0xa8, 0x3f, 0xcb,
+                0x39, 0x1d, 0xfe, 0x50,
+            ]
+        );
+        let input_1 = static_init!([u8; 1], *b"a");
+        let digest_1 = static_init!(
+            [u8; 32],
+            [
+                0xce, 0x19, 0xc3, 0x5a, 0xce, 0x52, 0x90, 0x13, 0x41, 0x9b, 0x9a, 0xf5, 0x0b, 0xf3,
+                0xc1, 0x09, 0x81, 0xc1, 0xc1, 0x9d, 0x96, 0x9b, 0x62, 0xac, 0x3e, 0xdc, 0x7c, 0x1b,
+                0xd2, 0xd3, 0x61, 0x1b,
+            ]
+        );
+
+        [
+            &*static_init!(HmacTestCallback, HmacTestCallback::new(input_13, digest_13)),
+            &*static_init!(HmacTestCallback, HmacTestCallback::new(input_1, digest_1)),
+        ]
+    };
+
+    for callback in callbacks {
+        let _buf = LeasableMutableBuffer::new(callback.input_buffer.take().unwrap());
+
+        debug!("check hmac verify {} byte message... ", _buf.len());
+        run_kernel_op(100);
+
+        hmac.set_client(callback);
+        callback.reset();
+        assert_eq!(hmac.set_mode_hmacsha256(&KEY), Ok(()));
+
+        #[cfg(feature = "hardware_tests")]
+        assert_eq!(hmac.add_mut_data(_buf), Ok(()));
+
+        run_kernel_op(1000);
+        #[cfg(feature = "hardware_tests")]
+        assert_eq!(callback.add_mut_data_done.get(), true);
+        callback.reset();
+
+        assert_eq!(hmac.verify(callback.digest_buffer.take().unwrap()), Ok(()));
+
+        run_kernel_op(1000);
+        #[cfg(feature = "hardware_tests")]
+        assert_eq!(callback.verification_done.get(), true);
+
+        run_kernel_op(100);
+        debug!("    [ok]");
+        run_kernel_op(100);
+    }
+}
+
+/// Auto-wipe is off by default, so a key set once is used for every hash
+/// until it is changed
+#[test_case]
+fn hmac_check_key_reuse() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    let callback = unsafe { static_init_test_cb() };
+
+    debug!("check hmac key reused for two hashes... ");
+    run_kernel_op(100);
+
+    hmac.set_client(callback);
+    assert_eq!(hmac.set_mode_hmacsha256(&KEY), Ok(()));
+
+    // The buffers only come back from the callbacks on hardware
+    #[cfg(feature = "hardware_tests")]
+    for _ in 0..2 {
+        let buf = LeasableMutableBuffer::new(callback.input_buffer.take().unwrap());
+        callback.reset();
+
+        assert_eq!(hmac.add_mut_data(buf), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(callback.add_mut_data_done.get(), true);
+
+        // The callback asserts the digest matches
+        assert_eq!(hmac.verify(callback.digest_buffer.take().unwrap()), Ok(()));
+        run_kernel_op(1000);
+        assert_eq!(callback.verification_done.get(), true);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn hmac_check_context() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    debug!("check hmac save/restore context... ");
+    run_kernel_op(100);
+
+    assert_eq!(hmac.set_mode_sha256(), Ok(()));
+
+    // Nothing has been hashed, so this is on a block boundary
+    let context = hmac.save_context().unwrap();
+    assert_eq!(context.msg_length, 0);
+
+    // The hardware can't be loaded with a saved state
+    assert_eq!(hmac.restore_context(&context), Err(ErrorCode::NOSUPPORT));
+
+    hmac.clear_data();
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// With auto-wipe enabled, after a keyed hash completes the key and digest
+/// are wiped, so the digest registers read back zero.
+#[test_case]
+fn hmac_check_wipe() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    let callback = unsafe { static_init_test_cb() };
+
+    let _buf = LeasableMutableBuffer::new(callback.input_buffer.take().unwrap());
+
+    debug!("check hmac wipe after verify... ");
+    run_kernel_op(100);
+
+    hmac.set_auto_wipe(true);
+    hmac.set_client(callback);
+    callback.reset();
+    assert_eq!(hmac.set_mode_hmacsha256(&KEY), Ok(()));
+
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(hmac.add_mut_data(_buf), Ok(()));
+
+    run_kernel_op(1000);
+    callback.reset();
+
+    assert_eq!(hmac.verify(callback.digest_buffer.take().unwrap()), Ok(()));
+
+    run_kernel_op(1000);
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(callback.verification_done.get(), true);
+        assert!(hmac.is_wiped());
+    }
+
+    // An explicit wipe also clears a key that was never used
+    assert_eq!(hmac.set_mode_hmacsha256(&KEY), Ok(()));
+    hmac.wipe();
+    #[cfg(feature = "hardware_tests")]
+    assert!(hmac.is_wiped());
+
+    hmac.set_auto_wipe(false);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Data added from a borrowed slice hashes the same as the static buffer
+/// used by the other tests, even when it is added in pieces.
+#[test_case]
+fn hmac_check_add_data_sync() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let hmac = &perf.hmac;
+
+    let callback = unsafe { static_init_test_cb() };
+
+    debug!("check hmac add data sync... ");
+    run_kernel_op(100);
+
+    hmac.set_client(callback);
+    callback.reset();
+    assert_eq!(hmac.set_mode_hmacsha256(&KEY), Ok(()));
+
+    let data = [32u8; 32];
+    assert_eq!(hmac.add_data_sync(&data[..13]), Ok(()));
+    assert_eq!(hmac.add_data_sync(&data[13..]), Ok(()));
+
+    assert_eq!(hmac.verify(callback.digest_buffer.take().unwrap()), Ok(()));
+    // A hash is in progress
+    assert_eq!(hmac.add_data_sync(&data), Err(ErrorCode::BUSY));
+
+    run_kernel_op(1000);
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(callback.verification_done.get(), true);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Hash a page holding a known pattern straight from flash. The pattern
+/// counts up through the bytes, so feeding them in any other order gives a
+/// different digest.
+#[test_case]
+fn hmac_check_verify_flash_region() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let verifier = FlashVerifier::new(&perf.flash_ctrl, &perf.hmac);
+
+    debug!("check hmac verify flash region... ");
+    run_kernel_op(100);
+
+    // SHA-256 of the bytes 0x00 to 0x3f
+    #[allow(unused)] // Can be unused if software only test
+    let expected = [
+        0xfd, 0xea, 0xb9, 0xac, 0xf3, 0x71, 0x03, 0x62, 0xbd, 0x26, 0x58, 0xcd, 0xc9, 0xa2, 0x9e,
+        0x8f, 0x9c, 0x75, 0x7f, 0xcf, 0x98, 0x11, 0x60, 0x3a, 0x8c, 0x44, 0x7c, 0xd1, 0xd9, 0x15,
+        0x11, 0x08,
+    ];
+
+    assert_eq!(
+        verifier.verify_flash_region(TEST_PAGE, 0, &[0; 32]),
+        Err(ErrorCode::INVAL)
+    );
+    // Below the start of flash
+    assert_eq!(
+        verifier.verify_flash_region(0, 1, &[0; 32]),
+        Err(ErrorCode::INVAL)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use lowrisc::flash_ctrl::PAGE_SIZE;
+
+        let flash_ctrl = &perf.flash_ctrl;
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+
+        let mut words = [0; PAGE_SIZE / 4];
+        for (i, word) in words.iter_mut().enumerate() {
+            let b = (i * 4) as u8;
+            *word = u32::from_le_bytes([b, b + 1, b + 2, b + 3]);
+        }
+        assert_eq!(
+            flash_ctrl.write_words(TEST_PAGE * PAGE_SIZE, &words),
+            Ok(())
+        );
+
+        assert_eq!(
+            verifier.verify_flash_region(TEST_PAGE, 1, &expected),
+            Ok(true)
+        );
+
+        let mut wrong = expected;
+        wrong[31] ^= 1;
+        assert_eq!(
+            verifier.verify_flash_region(TEST_PAGE, 1, &wrong),
+            Ok(false)
+        );
+
+        // The pattern followed by an erased page
+        let expected = [
+            0xc5, 0xe8, 0x83, 0xbc, 0x98, 0xab, 0x6e, 0x90, 0xae, 0x64, 0x1f, 0x9d, 0x18, 0x48,
+            0x09, 0xf8, 0x55, 0x80, 0x31, 0x87, 0xef, 0x45, 0x14, 0x27, 0x83, 0x27, 0x77, 0x86,
+            0xdd, 0x66, 0xbe, 0x4a,
+        ];
+        assert_eq!(
+            verifier.verify_flash_region(TEST_PAGE, 2, &expected),
+            Ok(true)
+        );
+
+        assert_eq!(flash_ctrl.erase_page(TEST_PAGE), Ok(()));
+        run_kernel_op(1000);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
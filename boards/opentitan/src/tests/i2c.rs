@@ -0,0 +1,31 @@
+//! Test the I2C bus speed selection
+
+use crate::tests::run_kernel_op;
+use crate::PERIPHERALS;
+use kernel::debug;
+use lowrisc::i2c::I2cSpeed;
+
+#[test_case]
+fn i2c_set_speed() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let i2c = &perf.i2c0;
+
+    debug!("check i2c0 speed selection... ");
+    run_kernel_op(100);
+
+    assert_eq!(i2c.set_speed(I2cSpeed::Standard), Ok(()));
+    assert_eq!(i2c.get_speed(), I2cSpeed::Standard);
+
+    // The FPGA peripheral clock is fast enough for all modes
+    #[cfg(feature = "hardware_tests")]
+    {
+        assert_eq!(i2c.set_speed(I2cSpeed::FastPlus), Ok(()));
+        assert_eq!(i2c.get_speed(), I2cSpeed::FastPlus);
+        assert_eq!(i2c.set_speed(I2cSpeed::Fast), Ok(()));
+        assert_eq!(i2c.get_speed(), I2cSpeed::Fast);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
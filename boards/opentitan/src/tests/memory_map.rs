@@ -0,0 +1,33 @@
+//! Test that the peripheral base addresses are consistent
+
+use crate::tests::run_kernel_op;
+use earlgrey::memory_map::peripheral_regions;
+use kernel::debug;
+
+/// No two peripherals' register blocks may overlap, if they do one of the
+/// base addresses has been transcribed wrongly.
+#[test_case]
+fn memory_map_disjoint() {
+    debug!("check peripheral regions don't overlap... ");
+    run_kernel_op(100);
+
+    let regions = peripheral_regions();
+    for (i, a) in regions.iter().enumerate() {
+        assert!(a.size > 0, "{} is empty", a.name);
+        for b in regions[i + 1..].iter() {
+            assert!(
+                !a.overlaps(b),
+                "{} [{:#x}, {:#x}) overlaps {} [{:#x}, {:#x})",
+                a.name,
+                a.start,
+                a.end(),
+                b.name,
+                b.start,
+                b.end()
+            );
+        }
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -47,7 +47,12 @@ fn trivial_assertion() {
 
 mod aes_test;
 mod csrng;
+mod ecdsa;
+mod flash_ctrl;
+mod gpio;
 mod hmac;
+mod i2c;
+mod memory_map;
 mod multi_alarm;
 mod otbn;
 mod rsa;
@@ -56,3 +61,9 @@ mod sha256soft_test; // Test software SHA capsule
 mod sip_hash;
 mod spi_host;
 mod tickv_test;
+mod timer;
+mod uart;
+
+// Locks the CSRNG until reset, so it has to come after every test that uses
+// the CSRNG or OTBN
+mod csrng_lock;
@@ -1,10 +1,14 @@
 use crate::tests::run_kernel_op;
-use crate::PERIPHERALS;
+use crate::{ALARM, PERIPHERALS};
+use capsules::virtual_alarm::VirtualMuxAlarm;
 use core::cell::Cell;
+use earlgrey::timer::RvTimer;
+use kernel::hil::time::Alarm;
 use kernel::static_init;
 use kernel::utilities::cells::TakeCell;
 use kernel::{debug, ErrorCode};
 use lowrisc::otbn::Client;
+use lowrisc::virtual_otbn::{MuxAccel, VirtualMuxAccel};
 
 static MODULUS: [u8; 256] = [
     0xf9, 0x90, 0xc7, 0x94, 0xcf, 0x96, 0xd3, 0x12, 0x6f, 0x16, 0xa6, 0x50, 0x5d, 0xcb, 0xe9, 0x29,
@@ -170,3 +174,160 @@ fn otbn_run_rsa_binary() {
         run_kernel_op(100);
     }
 }
+
+#[test_case]
+fn otbn_wipe() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let otbn = &perf.otbn;
+    let pattern: [u8; 32] = [0xA5; 32];
+    let mut readback: [u8; 32] = [0; 32];
+
+    debug!("check otbn secure wipe...");
+    run_kernel_op(100);
+
+    assert!(otbn.is_idle());
+    assert_eq!(otbn.load_data(0x400, &pattern), Ok(()));
+    assert_eq!(otbn.read_data(0x400, &mut readback), Ok(()));
+    assert_eq!(readback, pattern);
+
+    // DMEM is overwritten with random data, so the pattern must be gone
+    assert_eq!(otbn.wipe(), Ok(()));
+    assert!(otbn.is_idle());
+    assert_eq!(otbn.read_data(0x400, &mut readback), Ok(()));
+    assert_ne!(readback, pattern);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+type TestMuxAlarm = VirtualMuxAlarm<'static, RvTimer<'static>>;
+
+struct OtbnTimeoutCallback {
+    result: Cell<Option<Result<(), ErrorCode>>>,
+    output_buf: TakeCell<'static, [u8]>,
+}
+
+unsafe impl Sync for OtbnTimeoutCallback {}
+
+impl<'a> Client<'a> for OtbnTimeoutCallback {
+    fn op_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+        self.result.set(Some(result));
+        self.output_buf.replace(output);
+    }
+}
+
+unsafe fn static_init_timeout_cb() -> &'static OtbnTimeoutCallback {
+    let output_buf = static_init!([u8; 256], [0; 256]);
+
+    static_init!(
+        OtbnTimeoutCallback,
+        OtbnTimeoutCallback {
+            result: Cell::new(None),
+            output_buf: TakeCell::new(output_buf),
+        }
+    )
+}
+
+/// Load the RSA binary and encryption arguments through `user`
+fn load_rsa(
+    user: &VirtualMuxAccel<'static, TestMuxAlarm>,
+    imem: &[u8],
+    dmem: &[u8],
+) -> Result<(), ErrorCode> {
+    let mut source: [u8; 256] = [0; 256];
+    source[0..14].copy_from_slice(b"OTBN is great!");
+
+    user.load_binary(imem)?;
+    user.load_data(0, dmem)?;
+    user.load_data(0, &[1, 0, 0, 0])?;
+    user.load_data(4, &[(MODULUS.len() / 32) as u8, 0, 0, 0])?;
+    user.load_data(0x20, &MODULUS)?;
+    user.load_data(0x420, &source)
+}
+
+#[test_case]
+fn otbn_timeout() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+
+    debug!("check otbn timeout...");
+
+    if let Ok((imem_start, imem_length, dmem_start, dmem_length)) = unsafe {
+        crate::otbn::find_app(
+            "otbn-rsa",
+            core::slice::from_raw_parts(
+                &_sapps as *const u8,
+                &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+            ),
+        )
+    } {
+        let imem = unsafe { core::slice::from_raw_parts(imem_start as *const u8, imem_length) };
+        let dmem = unsafe { core::slice::from_raw_parts(dmem_start as *const u8, dmem_length) };
+
+        let (slow, waiting) = unsafe {
+            let alarm = static_init!(TestMuxAlarm, VirtualMuxAlarm::new(ALARM.unwrap()));
+            alarm.setup();
+            let mux = static_init!(
+                MuxAccel<'static, TestMuxAlarm>,
+                MuxAccel::new(&perf.otbn, alarm)
+            );
+            alarm.set_alarm_client(mux);
+            perf.otbn.set_client(mux);
+
+            let slow: &'static VirtualMuxAccel<'static, TestMuxAlarm> = static_init!(
+                VirtualMuxAccel<'static, TestMuxAlarm>,
+                VirtualMuxAccel::new(mux)
+            );
+            slow.setup();
+            let waiting: &'static VirtualMuxAccel<'static, TestMuxAlarm> = static_init!(
+                VirtualMuxAccel<'static, TestMuxAlarm>,
+                VirtualMuxAccel::new(mux)
+            );
+            waiting.setup();
+            (slow, waiting)
+        };
+        let slow_cb = unsafe { static_init_timeout_cb() };
+        let waiting_cb = unsafe { static_init_timeout_cb() };
+        slow.set_client(slow_cb);
+        waiting.set_client(waiting_cb);
+
+        // A zero timeout expires before the RSA operation can complete
+        assert_eq!(load_rsa(slow, imem, dmem), Ok(()));
+        let output = slow_cb.output_buf.take().unwrap();
+        assert!(slow.run_with_timeout(0x420, output, Some(0)).is_ok());
+        assert!(slow.is_claimed());
+        assert!(!waiting.is_claimed());
+        assert!(!waiting.is_available());
+        assert_eq!(load_rsa(waiting, imem, dmem), Err(ErrorCode::BUSY));
+
+        run_kernel_op(1000);
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            assert_eq!(slow_cb.result.get(), Some(Err(ErrorCode::CANCEL)));
+
+            // The aborted program still has to finish before OTBN is free
+            let mut ret = load_rsa(waiting, imem, dmem);
+            while ret == Err(ErrorCode::BUSY) {
+                run_kernel_op(1000);
+                ret = load_rsa(waiting, imem, dmem);
+            }
+            assert_eq!(ret, Ok(()));
+
+            let output = waiting_cb.output_buf.take().unwrap();
+            assert!(waiting.run(0x420, output).is_ok());
+            run_kernel_op(10000);
+
+            assert_eq!(waiting_cb.result.get(), Some(Ok(())));
+            waiting_cb.output_buf.map(|output| {
+                assert_eq!(output[0..256], EXPECTING);
+            });
+            waiting.clear_data();
+        }
+
+        debug!("    [ok]");
+        run_kernel_op(100);
+    } else {
+        debug!("    [FAIL] No OTBN binary");
+        run_kernel_op(100);
+    }
+}
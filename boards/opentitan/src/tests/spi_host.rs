@@ -1,12 +1,17 @@
 use crate::tests::run_kernel_op;
 use crate::PERIPHERALS;
 use core::cell::Cell;
-#[allow(unused_imports)]
+use earlgrey::chip_config::CONFIG;
 use kernel::hil::spi::{ClockPhase, ClockPolarity};
 use kernel::hil::spi::{SpiMaster, SpiMasterClient};
 use kernel::static_init;
 use kernel::utilities::cells::TakeCell;
 use kernel::{debug, ErrorCode};
+use lowrisc::spi_host::{
+    pack_fifo_word, unpack_fifo_word, ByteOrder, SegmentDirection, SegmentSpeed, SpiHost,
+    SpiHostConfig, SpiReadyClient, SpiSegment, SpiSegmentClient, SpiVerifyClient, CSN_TIMING_MAX,
+    MAX_DUMMY_CYCLES, NUM_CHIP_SELECTS, SPI_HOST_FIFO_DEPTH,
+};
 
 struct SpiHostCallback {
     transfer_done: Cell<bool>,
@@ -165,6 +170,496 @@ fn spi_host_transfer_partial() {
     run_kernel_op(100);
 }
 
+/// Tests transfers either side of the TXFIFO size. 256 bytes must complete
+/// with a single fill, 257 bytes needs a second 1 byte chunk and 512 bytes
+/// two full chunks.
+#[test_case]
+fn spi_host_transfer_fifo_boundary() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe { static_init_test_partial_cb() };
+
+    spi_host.set_client(cb);
+
+    for len in [256, 257, 512] {
+        debug!("[SPI] Setup spi_host0 {} byte transfer... ", len);
+        run_kernel_op(100);
+
+        cb.reset();
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            let tx = cb.tx_data.take().unwrap();
+            let rx = cb.rx_data.take().unwrap();
+            cb.tx_len.set(len);
+
+            //Set SPI_HOST0 Configs
+            spi_host.specify_chip_select(0).ok();
+            spi_host.set_rate(100000).ok();
+            spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+            spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+            assert_eq!(spi_host.read_write_bytes(tx, Some(rx), len), Ok(()));
+            run_kernel_op(5000);
+
+            assert_eq!(cb.transfer_done.get(), true);
+        }
+        run_kernel_op(100);
+        debug!("    [ok]");
+        run_kernel_op(100);
+    }
+}
+
+/// Tests that coalescing takes fewer interrupts for the same multi-command
+/// transfer and still reports completion
+#[test_case]
+fn spi_host_coalescing() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe { static_init_test_partial_cb() };
+
+    debug!("[SPI] Check spi_host0 interrupt coalescing... ");
+    run_kernel_op(100);
+
+    spi_host.set_client(cb);
+
+    assert_eq!(
+        spi_host.set_coalescing(true, SPI_HOST_FIFO_DEPTH as u8 + 1),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(perf.spi_host1.set_coalescing(true, 0), Err(ErrorCode::OFF));
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+
+        let mut isrs = [0; 2];
+        for (coalesce, count) in [false, true].iter().zip(isrs.iter_mut()) {
+            assert_eq!(spi_host.set_coalescing(*coalesce, 0), Ok(()));
+            cb.reset();
+
+            let tx = cb.tx_data.take().unwrap();
+            let rx = cb.rx_data.take().unwrap();
+            cb.tx_len.set(512);
+
+            let before = spi_host.interrupt_count();
+            assert_eq!(spi_host.read_write_bytes(tx, Some(rx), 512), Ok(()));
+            run_kernel_op(5000);
+            assert_eq!(cb.transfer_done.get(), true);
+            *count = spi_host.interrupt_count().wrapping_sub(before);
+        }
+
+        debug!("    interrupts: {} normal, {} coalesced", isrs[0], isrs[1]);
+        assert!(isrs[1] < isrs[0]);
+    }
+
+    assert_eq!(spi_host.set_coalescing(false, 0), Ok(()));
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+struct ReadyCallback {
+    calls: Cell<usize>,
+    /// The transfer's done callback had run and the driver was idle
+    in_order: Cell<bool>,
+    spi_host: &'static SpiHost,
+    transfer: &'static SpiHostCallback,
+}
+
+impl SpiReadyClient for ReadyCallback {
+    fn ready(&self) {
+        self.calls.set(self.calls.get() + 1);
+        self.in_order
+            .set(self.transfer.transfer_done.get() && !self.spi_host.is_busy());
+    }
+}
+
+/// Tests that the ready client is only called once a transfer has fully
+/// completed, after the transfer's own done callback.
+#[test_case]
+fn spi_host_ready_callback() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe { static_init_test_cb() };
+    let ready = unsafe {
+        static_init!(
+            ReadyCallback,
+            ReadyCallback {
+                calls: Cell::new(0),
+                in_order: Cell::new(false),
+                spi_host,
+                transfer: cb,
+            }
+        )
+    };
+
+    debug!("[SPI] Check spi_host0 ready callback... ");
+    run_kernel_op(100);
+
+    spi_host.set_client(cb);
+    spi_host.set_ready_client(ready);
+    // Nothing has completed yet
+    run_kernel_op(100);
+    assert_eq!(ready.calls.get(), 0);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        cb.reset();
+
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        cb.tx_len.set(tx.len());
+        let len = tx.len();
+        assert_eq!(spi_host.read_write_bytes(tx, Some(rx), len), Ok(()));
+        assert_eq!(ready.calls.get(), 0);
+
+        run_kernel_op(5000);
+        assert_eq!(cb.transfer_done.get(), true);
+        assert_eq!(ready.calls.get(), 1);
+        assert_eq!(ready.in_order.get(), true);
+
+        // Nothing more once idle
+        run_kernel_op(1000);
+        assert_eq!(ready.calls.get(), 1);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that a transfer after a failed one sends the whole buffer, rather
+/// than picking up from offsets left behind by the failure.
+#[cfg(feature = "hardware_tests")]
+#[test_case]
+fn spi_host_transfer_after_error() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Setup spi_host0 transfer after error... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let cb = static_init_test_partial_cb();
+        let failing = static_init!(
+            RecoverCallback,
+            RecoverCallback {
+                rc: Cell::new(None),
+                tx_data: TakeCell::empty(),
+                rx_data: TakeCell::empty(),
+            }
+        );
+
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+        spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+        // Fail a transfer once its first chunk has gone out, leaving the
+        // offsets part way through the buffer
+        spi_host.set_client(failing);
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        let interrupts = spi_host.interrupt_count();
+        assert_eq!(spi_host.read_write_bytes(tx, Some(rx), 513), Ok(()));
+        assert!((0..5000).any(|_| {
+            run_kernel_op(1);
+            spi_host.interrupt_count() != interrupts
+        }));
+        assert_eq!(failing.rc.get(), None);
+        assert!(spi_host.is_busy());
+
+        assert_eq!(spi_host.recover(), Ok(()));
+        assert_eq!(failing.rc.get(), Some(Err(ErrorCode::CANCEL)));
+        cb.tx_data.replace(failing.tx_data.take().unwrap());
+        cb.rx_data.replace(failing.rx_data.take().unwrap());
+
+        // The next transfer sends, and reports, the whole buffer
+        spi_host.set_client(cb);
+        cb.reset();
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        cb.tx_len.set(513);
+        assert_eq!(spi_host.read_write_bytes(tx, Some(rx), 513), Ok(()));
+        run_kernel_op(10000);
+
+        // The callback checks all 513 bytes were sent
+        assert_eq!(cb.transfer_done.get(), true);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that only implemented chip selects can be selected, and that the
+/// rate is kept for the selected chip select
+#[test_case]
+fn spi_host_chip_select() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 chip select... ");
+    run_kernel_op(100);
+
+    assert_eq!(
+        spi_host.specify_chip_select(NUM_CHIP_SELECTS as u32),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(spi_host.specify_chip_select(0), Ok(()));
+    assert_eq!(spi_host.set_rate(100000), Ok(100000));
+    assert_eq!(spi_host.get_rate(), 100000);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that the mode set after `init()` is what the getters report, and
+/// that changing it while a transfer is running leaves that transfer alone
+/// and takes effect for the next one.
+#[test_case]
+fn spi_host_mode_applied() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 mode is applied... ");
+    run_kernel_op(100);
+
+    assert_eq!(spi_host.specify_chip_select(0), Ok(()));
+    assert_eq!(spi_host.set_phase(ClockPhase::SampleTrailing), Ok(()));
+    assert_eq!(spi_host.set_polarity(ClockPolarity::IdleHigh), Ok(()));
+    assert_eq!(spi_host.get_phase(), ClockPhase::SampleTrailing);
+    assert_eq!(spi_host.get_polarity(), ClockPolarity::IdleHigh);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let cb = unsafe { static_init_test_cb() };
+        spi_host.set_client(cb);
+        cb.reset();
+
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        cb.tx_len.set(tx.len());
+        assert_eq!(
+            spi_host.read_write_bytes(tx, Some(rx), cb.tx_len.get()),
+            Ok(())
+        );
+
+        // Stored now, applied once the transfer is done
+        assert_eq!(spi_host.set_phase(ClockPhase::SampleLeading), Ok(()));
+        assert_eq!(spi_host.get_phase(), ClockPhase::SampleLeading);
+        run_kernel_op(5000);
+        assert_eq!(cb.transfer_done.get(), true);
+
+        cb.reset();
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        cb.tx_len.set(tx.len());
+        assert_eq!(
+            spi_host.read_write_bytes(tx, Some(rx), cb.tx_len.get()),
+            Ok(())
+        );
+        run_kernel_op(5000);
+        assert_eq!(cb.transfer_done.get(), true);
+    }
+
+    // Back to the mode the other tests use
+    assert_eq!(spi_host.set_phase(ClockPhase::SampleLeading), Ok(()));
+    assert_eq!(spi_host.set_polarity(ClockPolarity::IdleLow), Ok(()));
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that the lowest and highest rates are accepted and that anything
+/// outside them is rejected rather than truncated
+#[test_case]
+fn spi_host_rate_limits() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 rate limits... ");
+    run_kernel_op(100);
+
+    let min = spi_host.min_rate();
+    let max = spi_host.max_rate();
+    // Derived from the SPI host's own clock, not the other peripherals'
+    assert_eq!(max, CONFIG.spi_host_freq / 2);
+
+    assert_eq!(spi_host.set_rate(min), Ok(min));
+    assert_eq!(spi_host.set_rate(min - 1), Err(ErrorCode::INVAL));
+    assert_eq!(spi_host.get_rate(), min);
+    assert_eq!(spi_host.set_rate(0), Err(ErrorCode::INVAL));
+
+    assert_eq!(spi_host.set_rate(max), Ok(max));
+    assert_eq!(spi_host.set_rate(max + 1), Err(ErrorCode::NOSUPPORT));
+    assert_eq!(spi_host.get_rate(), max);
+
+    assert_eq!(spi_host.set_rate(100000), Ok(100000));
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that an idle host reports an empty TX FIFO and nothing to read
+#[test_case]
+fn spi_host_fifo_levels() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 FIFO levels... ");
+    run_kernel_op(100);
+
+    assert_eq!(spi_host.tx_fifo_space(), SPI_HOST_FIFO_DEPTH * 4);
+    assert_eq!(spi_host.rx_fifo_level(), 0);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that an uninitialized host refuses to do anything, spi_host1 isn't
+/// used by the board so is never initialized
+#[test_case]
+fn spi_host_uninitialized() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+
+    debug!("[SPI] Check spi_host1 before init... ");
+    run_kernel_op(100);
+
+    assert!(perf.spi_host0.is_initialized());
+
+    let spi_host = &perf.spi_host1;
+    assert!(!spi_host.is_initialized());
+    assert_eq!(spi_host.specify_chip_select(0), Err(ErrorCode::OFF));
+    assert_eq!(spi_host.set_rate(100000), Err(ErrorCode::OFF));
+    assert_eq!(spi_host.get_rate(), 0);
+    assert_eq!(
+        spi_host.set_polarity(ClockPolarity::IdleLow),
+        Err(ErrorCode::OFF)
+    );
+    assert_eq!(
+        spi_host.set_phase(ClockPhase::SampleLeading),
+        Err(ErrorCode::OFF)
+    );
+    assert_eq!(spi_host.set_cs_timing(0, 0, 0), Err(ErrorCode::OFF));
+
+    let tx = unsafe { static_init!([u8; 4], [0; 4]) };
+    let rx = unsafe { static_init!([u8; 4], [0; 4]) };
+    match spi_host.read_write_bytes(tx, Some(rx), 4) {
+        Err((ErrorCode::OFF, _tx, _rx)) => {}
+        _ => panic!("transfer before init was not rejected"),
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that `configure()` rejects any invalid field without changing the
+/// current configuration, and applies a valid one
+#[test_case]
+fn spi_host_configure() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 configure... ");
+    run_kernel_op(100);
+
+    let cfg = SpiHostConfig {
+        chip_select: 0,
+        rate: 100000,
+        polarity: ClockPolarity::IdleHigh,
+        phase: ClockPhase::SampleTrailing,
+        csn_lead: 1,
+        csn_trail: 2,
+        csn_idle: CSN_TIMING_MAX,
+    };
+
+    let bad_cs = SpiHostConfig {
+        chip_select: NUM_CHIP_SELECTS as u32,
+        ..cfg
+    };
+    let bad_rate = SpiHostConfig { rate: 0, ..cfg };
+    let bad_timing = SpiHostConfig {
+        csn_lead: CSN_TIMING_MAX + 1,
+        rate: 200000,
+        ..cfg
+    };
+    assert_eq!(spi_host.configure(bad_cs), Err(ErrorCode::INVAL));
+    assert_eq!(spi_host.configure(bad_rate), Err(ErrorCode::INVAL));
+    assert_eq!(spi_host.configure(bad_timing), Err(ErrorCode::INVAL));
+
+    assert_eq!(spi_host.configure(cfg), Ok(()));
+    assert_eq!(spi_host.get_cs_timing(), (1, 2, CSN_TIMING_MAX));
+    assert_eq!(spi_host.get_rate(), 100000);
+    assert_eq!(spi_host.get_polarity(), ClockPolarity::IdleHigh);
+    assert_eq!(spi_host.get_phase(), ClockPhase::SampleTrailing);
+
+    // A rejected configuration leaves the rate alone
+    assert_eq!(spi_host.configure(bad_timing), Err(ErrorCode::INVAL));
+    assert_eq!(spi_host.get_rate(), 100000);
+
+    // Back to the defaults the other tests expect
+    assert_eq!(
+        spi_host.configure(SpiHostConfig {
+            polarity: ClockPolarity::IdleLow,
+            phase: ClockPhase::SampleLeading,
+            csn_lead: 0,
+            csn_trail: 0,
+            csn_idle: 0,
+            ..cfg
+        }),
+        Ok(())
+    );
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that CS timings must fit in their 3 bit fields, and that a
+/// rejected timing leaves the current one in place
+#[test_case]
+fn spi_host_cs_timing() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 CS timing... ");
+    run_kernel_op(100);
+
+    assert_eq!(spi_host.specify_chip_select(0), Ok(()));
+    assert_eq!(spi_host.set_cs_timing(3, 4, 5), Ok(()));
+    assert_eq!(spi_host.get_cs_timing(), (3, 4, 5));
+
+    assert_eq!(
+        spi_host.set_cs_timing(CSN_TIMING_MAX + 1, 0, 0),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        spi_host.set_cs_timing(0, 0, CSN_TIMING_MAX + 1),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(spi_host.get_cs_timing(), (3, 4, 5));
+
+    assert_eq!(spi_host.set_cs_timing(0, 0, 0), Ok(()));
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
 /// Tests two single transfers that do not exceed the TXFIFO
 /// The second test, is to ensure that the driver is left in a clean state
 /// after a transfer (reset internal offsets and counts etc...)
@@ -228,3 +723,554 @@ fn spi_host_transfer_single() {
     debug!("    [ok]");
     run_kernel_op(100);
 }
+
+/// Tests transfers in both byte orders. With a 0x01020304 word as the first
+/// 4 bytes of the buffer, the wire should see `04 03 02 01` in little-endian
+/// order and `01 02 03 04` in big-endian order.
+#[test_case]
+fn spi_host_transfer_byte_order() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe { static_init_test_cb() };
+
+    debug!("[SPI] Setup spi_host0 byte order... ");
+    run_kernel_op(100);
+    spi_host.set_client(cb);
+
+    cb.tx_data.map(|tx| {
+        tx[0..4].copy_from_slice(&0x01020304u32.to_le_bytes());
+    });
+
+    assert_eq!(
+        perf.spi_host1.set_byte_order(ByteOrder::BigEndian),
+        Err(ErrorCode::OFF)
+    );
+    assert_eq!(perf.spi_host1.get_byte_order(), ByteOrder::LittleEndian);
+
+    // Partial words can't be sent in big-endian order
+    assert_eq!(spi_host.set_byte_order(ByteOrder::BigEndian), Ok(()));
+    let tx = cb.tx_data.take().unwrap();
+    let rx = cb.rx_data.take().unwrap();
+    match spi_host.read_write_bytes(tx, Some(rx), 3) {
+        Err((ErrorCode::INVAL, tx, rx)) => {
+            cb.tx_data.replace(tx);
+            cb.rx_data.replace(rx.unwrap());
+        }
+        _ => panic!("partial word was not rejected"),
+    }
+
+    #[cfg(feature = "hardware_tests")]
+    for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+        cb.reset();
+        assert_eq!(spi_host.set_byte_order(order), Ok(()));
+
+        let tx = cb.tx_data.take().unwrap();
+        let rx = cb.rx_data.take().unwrap();
+        cb.tx_len.set(tx.len());
+
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+        spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+        assert_eq!(
+            spi_host.read_write_bytes(tx, Some(rx), cb.tx_len.get()),
+            Ok(())
+        );
+        assert_eq!(
+            spi_host.set_byte_order(ByteOrder::LittleEndian),
+            Err(ErrorCode::BUSY)
+        );
+        run_kernel_op(5000);
+
+        assert_eq!(cb.transfer_done.get(), true);
+    }
+
+    assert_eq!(spi_host.set_byte_order(ByteOrder::LittleEndian), Ok(()));
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Tests that FIFO words are packed with the first buffer byte in the bits
+/// shifted out first, and that unpacking a received word puts every byte
+/// back where it came from, for a multi-word pattern in both orders.
+#[test_case]
+fn spi_host_word_packing() {
+    debug!("[SPI] Check spi_host word packing... ");
+    run_kernel_op(100);
+
+    assert_eq!(
+        pack_fifo_word([0x01, 0x02, 0x03, 0x04], ByteOrder::LittleEndian),
+        0x04030201
+    );
+    assert_eq!(
+        pack_fifo_word([0x01, 0x02, 0x03, 0x04], ByteOrder::BigEndian),
+        0x01020304
+    );
+
+    let pattern = [0x11, 0x22, 0x33, 0x44, 0xA5, 0x5A, 0x0F, 0xF0];
+    for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+        let mut received = [0; 8];
+        for (tx, rx) in pattern.chunks_exact(4).zip(received.chunks_exact_mut(4)) {
+            let word = pack_fifo_word([tx[0], tx[1], tx[2], tx[3]], order);
+            rx.copy_from_slice(&unpack_fifo_word(word, order));
+        }
+        assert_eq!(received, pattern);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+struct SpiSegmentCallback {
+    transfer_done: Cell<bool>,
+    segments: TakeCell<'static, [SpiSegment]>,
+}
+
+impl SpiSegmentClient for SpiSegmentCallback {
+    fn transfer_segments_done(
+        &self,
+        segments: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    ) {
+        assert_eq!(status, Ok(()));
+
+        //All buffers must come back
+        assert!(segments[0].tx_buf.is_some());
+        assert!(segments[1].tx_buf.is_some());
+        assert!(segments[2].rx_buf.is_some());
+
+        self.segments.replace(segments);
+        self.transfer_done.set(true);
+    }
+}
+
+/// Tests a flash style read: a command byte and a 3 byte address followed
+/// by reading back data, all with CS held.
+#[test_case]
+fn spi_host_transfer_segments() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe {
+        let cmd = static_init!([u8; 1], [0x03]);
+        let addr = static_init!([u8; 3], [0x00, 0x10, 0x00]);
+        let data = static_init!([u8; 300], [0; 300]);
+        let segments = static_init!(
+            [SpiSegment; 3],
+            [
+                SpiSegment {
+                    direction: SegmentDirection::TxOnly,
+                    speed: SegmentSpeed::Standard,
+                    tx_buf: Some(cmd),
+                    rx_buf: None,
+                    len: 1,
+                },
+                SpiSegment {
+                    direction: SegmentDirection::TxOnly,
+                    speed: SegmentSpeed::Standard,
+                    tx_buf: Some(addr),
+                    rx_buf: None,
+                    len: 3,
+                },
+                SpiSegment {
+                    direction: SegmentDirection::RxOnly,
+                    speed: SegmentSpeed::Standard,
+                    tx_buf: None,
+                    rx_buf: Some(data),
+                    len: 300,
+                },
+            ]
+        );
+        static_init!(
+            SpiSegmentCallback,
+            SpiSegmentCallback {
+                transfer_done: Cell::new(false),
+                segments: TakeCell::new(segments),
+            }
+        )
+    };
+
+    debug!("[SPI] Setup spi_host0 segment transfer... ");
+    run_kernel_op(100);
+    spi_host.set_segment_client(cb);
+
+    // Bidirectional segments can only use a single lane
+    let segments = cb.segments.take().unwrap();
+    segments[2].direction = SegmentDirection::Bidirectional;
+    segments[2].speed = SegmentSpeed::Quad;
+    match spi_host.transfer_segments(segments) {
+        Err((ErrorCode::INVAL, segments)) => {
+            segments[2].direction = SegmentDirection::RxOnly;
+            segments[2].speed = SegmentSpeed::Standard;
+            cb.segments.replace(segments);
+        }
+        _ => panic!("invalid segment was not rejected"),
+    }
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+        spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+        let segments = cb.segments.take().unwrap();
+        assert!(spi_host.transfer_segments(segments).is_ok());
+        run_kernel_op(5000);
+
+        assert_eq!(cb.transfer_done.get(), true);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+struct DummyCallback {
+    transfer_done: Cell<bool>,
+    segments: TakeCell<'static, [SpiSegment]>,
+}
+
+impl SpiSegmentClient for DummyCallback {
+    fn transfer_segments_done(
+        &self,
+        segments: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    ) {
+        assert_eq!(status, Ok(()));
+        self.segments.replace(segments);
+        self.transfer_done.set(true);
+    }
+}
+
+/// Tests a fast read style transaction with dummy cycles between the
+/// address and the data, and that the command is seen running in the status
+/// register until the dummy cycles and data have been clocked.
+#[test_case]
+fn spi_host_transfer_dummy_cycles() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe {
+        let cmd = static_init!([u8; 4], [0x0B, 0x00, 0x10, 0x00]);
+        let data = static_init!([u8; 8], [0; 8]);
+        let segments = static_init!(
+            [SpiSegment; 3],
+            [
+                SpiSegment {
+                    direction: SegmentDirection::TxOnly,
+                    speed: SegmentSpeed::Standard,
+                    tx_buf: Some(cmd),
+                    rx_buf: None,
+                    len: 4,
+                },
+                SpiSegment::dummy(MAX_DUMMY_CYCLES + 1),
+                SpiSegment {
+                    direction: SegmentDirection::RxOnly,
+                    speed: SegmentSpeed::Standard,
+                    tx_buf: None,
+                    rx_buf: Some(data),
+                    len: 8,
+                },
+            ]
+        );
+        static_init!(
+            DummyCallback,
+            DummyCallback {
+                transfer_done: Cell::new(false),
+                segments: TakeCell::new(segments),
+            }
+        )
+    };
+
+    debug!("[SPI] Setup spi_host0 dummy cycle transfer... ");
+    run_kernel_op(100);
+    spi_host.set_segment_client(cb);
+
+    // The cycle count has to fit in COMMAND.LEN
+    let segments = cb.segments.take().unwrap();
+    match spi_host.transfer_segments(segments) {
+        Err((ErrorCode::INVAL, segments)) => {
+            segments[1] = SpiSegment::dummy(8);
+            cb.segments.replace(segments);
+        }
+        _ => panic!("too many dummy cycles were not rejected"),
+    }
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+
+        let segments = cb.segments.take().unwrap();
+        assert!(spi_host.transfer_segments(segments).is_ok());
+        // At 100 kHz the 104 cycles take about a millisecond
+        assert!(spi_host.is_active());
+        run_kernel_op(5000);
+
+        assert_eq!(cb.transfer_done.get(), true);
+        assert!(!spi_host.is_active());
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+struct SpiVerifyCallback {
+    status: Cell<Option<Result<(), ErrorCode>>>,
+    write: TakeCell<'static, [SpiSegment]>,
+    read: TakeCell<'static, [SpiSegment]>,
+}
+
+impl SpiVerifyClient for SpiVerifyCallback {
+    fn transfer_verified_done(
+        &self,
+        write: &'static mut [SpiSegment],
+        read: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    ) {
+        self.write.replace(write);
+        self.read.replace(read);
+        self.status.set(Some(status));
+    }
+}
+
+/// Tests a write followed by a read back. Nothing on the bus returns the
+/// pattern written, so the comparison must report the mismatch.
+#[test_case]
+fn spi_host_transfer_verified() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    let cb = unsafe {
+        // No stuck-at bus level reads back as this pattern
+        let data = static_init!([u8; 4], [0x00, 0xFF, 0x5A, 0xA5]);
+        let read_back = static_init!([u8; 4], [0; 4]);
+        let write = static_init!(
+            [SpiSegment; 1],
+            [SpiSegment {
+                direction: SegmentDirection::TxOnly,
+                speed: SegmentSpeed::Standard,
+                tx_buf: Some(data),
+                rx_buf: None,
+                len: 4,
+            }]
+        );
+        let read = static_init!(
+            [SpiSegment; 1],
+            [SpiSegment {
+                direction: SegmentDirection::RxOnly,
+                speed: SegmentSpeed::Standard,
+                tx_buf: None,
+                rx_buf: Some(read_back),
+                len: 4,
+            }]
+        );
+        static_init!(
+            SpiVerifyCallback,
+            SpiVerifyCallback {
+                status: Cell::new(None),
+                write: TakeCell::new(write),
+                read: TakeCell::new(read),
+            }
+        )
+    };
+
+    debug!("[SPI] Setup spi_host0 verified transfer... ");
+    run_kernel_op(100);
+    spi_host.set_verify_client(cb);
+
+    // The read back must be the same length as the data written
+    let write = cb.write.take().unwrap();
+    let read = cb.read.take().unwrap();
+    read[0].len = 3;
+    match spi_host.transfer_segments_verified(write, read) {
+        Err((ErrorCode::INVAL, write, read)) => {
+            read[0].len = 4;
+            cb.write.replace(write);
+            cb.read.replace(read);
+        }
+        _ => panic!("mismatched lengths were not rejected"),
+    }
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+        spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+        let write = cb.write.take().unwrap();
+        let read = cb.read.take().unwrap();
+        assert!(spi_host.transfer_segments_verified(write, read).is_ok());
+        run_kernel_op(5000);
+
+        assert_eq!(cb.status.get(), Some(Err(ErrorCode::FAIL)));
+        assert!(cb.write.is_some());
+        assert!(cb.read.is_some());
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// A blocking transfer needs no client and returns once the data has been
+/// received, the same length checks apply as for an async transfer.
+#[test_case]
+fn spi_host_transfer_sync() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 blocking transfer... ");
+    run_kernel_op(100);
+
+    let tx: [u8; 4] = [0x9F, 0, 0, 0];
+    let mut rx: [u8; 4] = [0; 4];
+    let mut short: [u8; 2] = [0; 2];
+
+    assert_eq!(
+        perf.spi_host1.transfer_sync(&tx, &mut rx),
+        Err(ErrorCode::OFF)
+    );
+    assert_eq!(spi_host.transfer_sync(&[], &mut rx), Err(ErrorCode::SIZE));
+    assert_eq!(
+        spi_host.transfer_sync(&tx, &mut short),
+        Err(ErrorCode::SIZE)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+
+        assert_eq!(spi_host.transfer_sync(&tx, &mut rx), Ok(()));
+        // Nothing is left queued for the async path
+        assert!(!spi_host.is_busy());
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn spi_host_toggle_cs() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 chip select pulse... ");
+    run_kernel_op(100);
+
+    assert_eq!(perf.spi_host1.toggle_cs(0), Err(ErrorCode::OFF));
+    assert_eq!(
+        spi_host.toggle_cs(NUM_CHIP_SELECTS as u32),
+        Err(ErrorCode::INVAL)
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+
+        assert_eq!(spi_host.toggle_cs(0), Ok(()));
+        // The command has finished and CS has been released
+        assert!(!spi_host.is_active());
+        assert!(!spi_host.is_busy());
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[cfg(feature = "hardware_tests")]
+struct RecoverCallback {
+    rc: Cell<Option<Result<(), ErrorCode>>>,
+    tx_data: TakeCell<'static, [u8]>,
+    rx_data: TakeCell<'static, [u8]>,
+}
+
+#[cfg(feature = "hardware_tests")]
+impl SpiMasterClient for RecoverCallback {
+    fn read_write_done(
+        &self,
+        tx_data: &'static mut [u8],
+        rx_done: Option<&'static mut [u8]>,
+        _tx_len: usize,
+        rc: Result<(), ErrorCode>,
+    ) {
+        self.rc.set(Some(rc));
+        self.tx_data.replace(tx_data);
+        rx_done.map(|rx| self.rx_data.replace(rx));
+    }
+}
+
+/// Recovering part way through a transfer hands the buffers back with
+/// `CANCEL` and leaves the host usable for the next transfer
+#[test_case]
+fn spi_host_recover() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let spi_host = &perf.spi_host0;
+
+    debug!("[SPI] Check spi_host0 recover... ");
+    run_kernel_op(100);
+
+    assert_eq!(perf.spi_host1.recover(), Err(ErrorCode::OFF));
+    // Nothing in flight, nothing to hand back
+    assert_eq!(spi_host.recover(), Ok(()));
+
+    #[cfg(feature = "hardware_tests")]
+    unsafe {
+        let partial = static_init_test_partial_cb();
+        let cb = static_init!(
+            RecoverCallback,
+            RecoverCallback {
+                rc: Cell::new(None),
+                tx_data: TakeCell::empty(),
+                rx_data: TakeCell::empty(),
+            }
+        );
+        spi_host.set_client(cb);
+
+        spi_host.specify_chip_select(0).ok();
+        spi_host.set_rate(100000).ok();
+        spi_host.set_polarity(ClockPolarity::IdleLow).ok();
+        spi_host.set_phase(ClockPhase::SampleLeading).ok();
+
+        let tx = partial.tx_data.take().unwrap();
+        let rx = partial.rx_data.take().unwrap();
+        assert_eq!(spi_host.read_write_bytes(tx, Some(rx), 513), Ok(()));
+
+        assert_eq!(spi_host.recover(), Ok(()));
+        assert_eq!(cb.rc.get(), Some(Err(ErrorCode::CANCEL)));
+        assert!(cb.tx_data.is_some());
+        assert!(cb.rx_data.is_some());
+        assert!(!spi_host.is_busy());
+        assert!(!spi_host.is_active());
+
+        // Nothing arrives late for the abandoned transfer
+        cb.rc.set(None);
+        run_kernel_op(5000);
+        assert_eq!(cb.rc.get(), None);
+
+        let fresh = static_init_test_cb();
+        spi_host.set_client(fresh);
+        fresh.reset();
+        let tx = fresh.tx_data.take().unwrap();
+        let rx = fresh.rx_data.take().unwrap();
+        fresh.tx_len.set(tx.len());
+        assert_eq!(
+            spi_host.read_write_bytes(tx, Some(rx), fresh.tx_len.get()),
+            Ok(())
+        );
+        run_kernel_op(5000);
+        assert_eq!(fresh.transfer_done.get(), true);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -0,0 +1,85 @@
+//! Test the RvTimer busy-wait delay.
+
+use crate::tests::run_kernel_op;
+use crate::TIMER;
+use earlgrey::timer::{alarm_compare, ticks_to_us};
+use kernel::debug;
+use kernel::hil::time::{Ticks, Time};
+
+#[test_case]
+fn timer_delay_us() {
+    debug!("check timer delay_us... ");
+    run_kernel_op(100);
+
+    let timer = unsafe { TIMER.unwrap() };
+
+    let start = timer.now();
+    timer.delay_us(1000);
+    let elapsed = timer.now().wrapping_sub(start).into_u64();
+
+    // 1000us is 10 ticks of the 10KHz timer, plus the extra tick waited to
+    // cover starting part way through a tick.
+    assert!(elapsed >= 10);
+    assert!(elapsed <= 12);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn timer_ticks_to_us() {
+    debug!("check timer ticks_to_us... ");
+    run_kernel_op(100);
+
+    // Both configs divide down to exactly 10KHz, 100us per tick
+    for cpu_freq in [10_000_000, 500_000] {
+        assert_eq!(ticks_to_us(0, cpu_freq), 0);
+        assert_eq!(ticks_to_us(1, cpu_freq), 100);
+        assert_eq!(ticks_to_us(10_000, cpu_freq), 1_000_000);
+        assert_eq!(
+            ticks_to_us(cpu_freq as u64 + 3, cpu_freq),
+            (cpu_freq as u64 + 3) * 100
+        );
+        assert_eq!(ticks_to_us(u64::MAX, cpu_freq), u64::MAX);
+    }
+
+    // 12.345MHz has 1234 cycles per tick, 99.96..us
+    assert_eq!(ticks_to_us(10_000, 12_345_000), 999_594);
+
+    let timer = unsafe { TIMER.unwrap() };
+    let start = timer.timestamp_us();
+    timer.delay_us(1000);
+    assert!(timer.timestamp_us() - start >= 1000);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn timer_alarm_compare() {
+    debug!("check timer alarm compare values... ");
+    run_kernel_op(100);
+
+    // Just past and exactly now fire straight away
+    assert_eq!(alarm_compare(111, 100, 10), 111);
+    assert_eq!(alarm_compare(110, 100, 10), 110);
+    assert_eq!(alarm_compare(100, 100, 0), 100);
+    // Near now is left as requested
+    assert_eq!(alarm_compare(109, 100, 10), 110);
+    // Carries into the high word rather than truncating to 32 bits
+    assert_eq!(alarm_compare(0xFFFF_FFF0, 0xFFFF_FFF0, 0x20), 0x1_0000_0010);
+    // Far future stays in the future instead of wrapping round
+    assert_eq!(
+        alarm_compare(u64::MAX - 10, u64::MAX - 10, 100),
+        u64::MAX - 1
+    );
+    assert_eq!(alarm_compare(5, 0, u64::MAX), u64::MAX - 1);
+    // The reference can be behind now as long as the alarm isn't
+    assert_eq!(alarm_compare(u64::MAX - 4, u64::MAX - 5, 3), u64::MAX - 2);
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
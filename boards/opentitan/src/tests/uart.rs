@@ -0,0 +1,234 @@
+use crate::tests::run_kernel_op;
+#[allow(unused_imports)]
+use crate::PERIPHERALS;
+use kernel::debug;
+use lowrisc::uart::multidrop_parity_odd;
+
+/// The parity selected for a frame always leaves the 9th bit as requested
+#[test_case]
+fn uart_multidrop_parity() {
+    debug!("check uart multidrop parity selection... ");
+    run_kernel_op(100);
+
+    // No ones, even parity leaves the parity bit clear
+    assert!(!multidrop_parity_odd(0x00, false));
+    assert!(multidrop_parity_odd(0x00, true));
+    // One one, even parity sets the parity bit
+    assert!(multidrop_parity_odd(0x01, false));
+    assert!(!multidrop_parity_odd(0x01, true));
+    // Four ones
+    assert!(!multidrop_parity_odd(0x5A, false));
+    assert!(multidrop_parity_odd(0x5A, true));
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// An address-marked frame and a data frame survive a round trip through
+/// system loopback with the 9th bit intact.
+#[test_case]
+fn uart_multidrop_loopback() {
+    debug!("check uart multidrop loopback... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use kernel::ErrorCode;
+
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let uart = &perf.uart0;
+
+        assert_eq!(uart.transmit_9bit_sync(0x12, true), Err(ErrorCode::OFF));
+
+        // Nothing else can use the console while it is looped back
+        assert_eq!(uart.flush_tx(), Ok(()));
+        uart.set_system_loopback(true);
+        assert_eq!(uart.set_multidrop(true), Ok(()));
+
+        for &(data, address) in [(0x12, true), (0x12, false), (0x5B, true), (0x5B, false)].iter() {
+            assert_eq!(uart.transmit_9bit_sync(data, address), Ok(()));
+            assert_eq!(uart.receive_9bit_sync(), Ok((data, address)));
+        }
+        // Nothing more was sent, the wait gives up
+        assert_eq!(uart.receive_9bit_sync(), Err(ErrorCode::BUSY));
+
+        assert_eq!(uart.set_multidrop(false), Ok(()));
+        uart.set_system_loopback(false);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// The TX and RX paths pass the loopback self test, and the test leaves the
+/// console working afterwards.
+#[test_case]
+fn uart_self_test() {
+    debug!("check uart loopback self test... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let uart = &perf.uart0;
+
+        assert_eq!(uart.self_test(), Ok(()));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Changing the baud rate keeps the UART working at both rates, checked by
+/// looping bytes back at each rate, and out of range rates are refused.
+#[test_case]
+fn uart_set_baud_rate() {
+    debug!("check uart baud rate change... ");
+    run_kernel_op(100);
+
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let uart = &perf.uart0;
+
+    assert_eq!(uart.set_baud_rate(0), Err(kernel::ErrorCode::INVAL));
+    // Needs a divisor wider than 16 bits
+    assert_eq!(uart.set_baud_rate(u32::MAX), Err(kernel::ErrorCode::INVAL));
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        use earlgrey::uart::UART0_BAUDRATE;
+
+        // The console is unreadable until the rate is restored, so nothing
+        // can be printed in between
+        assert_eq!(uart.set_baud_rate(UART0_BAUDRATE / 2), Ok(()));
+        assert_eq!(uart.self_test(), Ok(()));
+        assert_eq!(uart.set_baud_rate(UART0_BAUDRATE), Ok(()));
+        assert_eq!(uart.self_test(), Ok(()));
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+/// Filling the RX FIFO past its depth through loopback is counted as an
+/// overrun, and the FIFO levels report what is queued.
+#[test_case]
+fn uart_rx_overrun_count() {
+    debug!("check uart overrun counter... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    {
+        let perf = unsafe { PERIPHERALS.unwrap() };
+        let uart = &perf.uart0;
+
+        // Nothing else can use the console while it is looped back
+        assert_eq!(uart.flush_tx(), Ok(()));
+        assert_eq!(uart.tx_fifo_level(), 0);
+        let overruns = uart.rx_overrun_count();
+
+        uart.set_system_loopback(true);
+        uart.transmit_sync(&[0x5A; 128]);
+        assert_eq!(uart.flush_tx(), Ok(()));
+        uart.set_system_loopback(false);
+        assert!(uart.rx_fifo_level() > 0);
+        run_kernel_op(100);
+
+        assert!(uart.rx_overrun_count() > overruns);
+
+        // Drop the looped back bytes, this resets the RX FIFO
+        assert_eq!(uart.self_test(), Ok(()));
+        assert_eq!(uart.rx_fifo_level(), 0);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[cfg(feature = "hardware_tests")]
+struct ReceiveTimeoutCallback {
+    len: core::cell::Cell<Option<usize>>,
+    error: core::cell::Cell<kernel::hil::uart::Error>,
+    buffer: kernel::utilities::cells::TakeCell<'static, [u8]>,
+}
+
+#[cfg(feature = "hardware_tests")]
+impl kernel::hil::uart::ReceiveClient for ReceiveTimeoutCallback {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        _rval: Result<(), kernel::ErrorCode>,
+        error: kernel::hil::uart::Error,
+    ) {
+        self.len.set(Some(rx_len));
+        self.error.set(error);
+        self.buffer.replace(rx_buffer);
+    }
+}
+
+/// With nothing arriving, the timeout returns the buffer with no data
+#[test_case]
+fn uart_receive_timeout() {
+    debug!("check uart receive with timeout... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    unsafe {
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use earlgrey::timer::RvTimer;
+        use earlgrey::uart::UartReceiveTimeout;
+        use kernel::hil::time::Alarm;
+        use kernel::hil::uart::{Error, Receive};
+        use kernel::static_init;
+        use kernel::utilities::cells::TakeCell;
+        use kernel::ErrorCode;
+
+        let perf = PERIPHERALS.unwrap();
+        let uart = &perf.uart0;
+        let mux = crate::UART_MUX.unwrap();
+
+        let alarm = static_init!(
+            VirtualMuxAlarm<'static, RvTimer<'static>>,
+            VirtualMuxAlarm::new(crate::ALARM.unwrap())
+        );
+        alarm.setup();
+
+        let timeout = static_init!(
+            UartReceiveTimeout<'static, VirtualMuxAlarm<'static, RvTimer<'static>>>,
+            UartReceiveTimeout::new(uart, alarm)
+        );
+        let cb = static_init!(
+            ReceiveTimeoutCallback,
+            ReceiveTimeoutCallback {
+                len: core::cell::Cell::new(None),
+                error: core::cell::Cell::new(Error::None),
+                buffer: TakeCell::empty(),
+            }
+        );
+        let buf = static_init!([u8; 8], [0; 8]);
+
+        // Borrow the console's receive from the UART mux, it is handed
+        // back below
+        uart.set_receive_client(timeout);
+        alarm.set_alarm_client(timeout);
+        timeout.set_client(cb);
+
+        match timeout.receive_with_timeout(buf, 8, 10) {
+            Ok(()) => {
+                run_kernel_op(10000);
+
+                assert_eq!(cb.len.get(), Some(0));
+                assert_eq!(cb.error.get(), Error::Aborted);
+                assert!(cb.buffer.is_some());
+            }
+            // The console already has a receive outstanding, leave it be
+            Err((ErrorCode::BUSY, _)) => debug!("    console is receiving, skipped"),
+            Err((e, _)) => panic!("receive_with_timeout failed: {:?}", e),
+        }
+
+        uart.set_receive_client(mux);
+    }
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -0,0 +1,399 @@
+//! Implements AES-GCM authenticated encryption/decryption (NIST SP 800-38D)
+//! using an underlying AES-ECB and AES-CTR implementation.
+//!
+//! Most AES hardware supported by Tock, including the EarlGrey AES block,
+//! has no GHASH support. The block cipher operations are done in hardware
+//! and GHASH is computed in software:
+//!
+//! ```text
+//! crypt_buf: [ - 1 blk - | -------- PData/CData -------- ]
+//! aes_ecb:    \_________/                                    H = E(K, 0)
+//! aes_ctr:    \_________________________________________/    starting at J0
+//! ```
+//!
+//! After CTR mode the first block holds E(K, J0), which masks the tag, and
+//! the rest holds the encrypted/decrypted message. The tag is then
+//! GHASH(H, AAD, CData) XOR E(K, J0).
+//!
+//! The client buffer holds the AAD, the message and then the 16 byte tag:
+//!
+//! ```text
+//! buf: [ -------- AAD -------- | -------- PData/CData -------- | -- tag -- ]
+//! ```
+//!
+//! When encrypting the message is replaced by the ciphertext and the tag is
+//! written after it. When decrypting the tag is checked, in constant time,
+//! before anything is written back, so if the tag doesn't match `buf` still
+//! holds the ciphertext.
+//!
+//! IVs of 12 bytes are used directly as recommended by NIST. Shorter IVs are
+//! hashed to form J0, in which case the hardware CTR counter must not carry
+//! out of the low 32 bits during the message (`NOSUPPORT` is returned if it
+//! would).
+//!
+//! Usage
+//! -----
+//!
+//! The GCM capsule has to be the client of the underlying AES, which can be
+//! a `VirtualAES128CCM` to share the hardware with CCM users.
+//!
+//! ```rust
+//! # use capsules::aes_gcm;
+//! # use kernel::hil::symmetric_encryption::{AES128, AES128GCM, AES128_BLOCK_SIZE};
+//! # use kernel::static_init;
+//! const CRYPT_SIZE: usize = 7 * AES128_BLOCK_SIZE;
+//! let crypt_buf = static_init!([u8; CRYPT_SIZE], [0x00; CRYPT_SIZE]);
+//! let gcm = static_init!(
+//!     aes_gcm::Aes128Gcm<'static, VirtualAES128CCM<'static, Aes<'static>>>,
+//!     aes_gcm::Aes128Gcm::new(ccm_client, crypt_buf)
+//! );
+//! AES128::set_client(ccm_client, gcm);
+//! AES128GCM::set_client(gcm, client);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The longest IV accepted by `set_iv()`
+pub const GCM_IV_MAX_LENGTH: usize = 12;
+
+/// The length of the authentication tag following the message
+pub const GCM_TAG_LENGTH: usize = AES128_BLOCK_SIZE;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GCMState {
+    Idle,
+    /// Computing the hash subkey H
+    HashKey,
+    /// Running CTR mode over E(K, J0) and the message
+    Encrypt,
+}
+
+/// Multiply `x` and `y` in GF(2^128) as defined for GHASH
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+
+    let mut z = 0;
+    let mut v = y;
+    for i in 0..128 {
+        if (x >> (127 - i)) & 1 == 1 {
+            z ^= v;
+        }
+        v = if v & 1 == 1 { (v >> 1) ^ R } else { v >> 1 };
+    }
+    z
+}
+
+/// Absorb `data`, zero padded to a whole number of blocks, into `y`
+fn ghash_update(h: u128, mut y: u128, data: &[u8]) -> u128 {
+    for chunk in data.chunks(AES128_BLOCK_SIZE) {
+        let mut block = [0u8; AES128_BLOCK_SIZE];
+        block[..chunk.len()].copy_from_slice(chunk);
+        y = gf128_mul(y ^ u128::from_be_bytes(block), h);
+    }
+    y
+}
+
+/// GHASH(H, A, C) including the final length block
+fn ghash(h: u128, aad: &[u8], ciphertext: &[u8]) -> u128 {
+    let mut y = ghash_update(h, 0, aad);
+    y = ghash_update(h, y, ciphertext);
+
+    let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+    gf128_mul(y ^ lengths, h)
+}
+
+pub struct Aes128Gcm<'a, A: AES128<'a> + AES128Ctr + AES128ECB> {
+    aes: &'a A,
+
+    crypt_buf: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn symmetric_encryption::GCMClient>,
+
+    state: Cell<GCMState>,
+    encrypting: Cell<bool>,
+
+    buf: TakeCell<'static, [u8]>,
+    pos: Cell<(usize, usize, usize)>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    iv: Cell<[u8; GCM_IV_MAX_LENGTH]>,
+    iv_len: Cell<usize>,
+    hash_key: Cell<u128>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128ECB> Aes128Gcm<'a, A> {
+    /// `crypt_buf` must hold one block more than the longest message,
+    /// rounded up to a whole number of blocks.
+    pub fn new(aes: &'a A, crypt_buf: &'static mut [u8]) -> Aes128Gcm<'a, A> {
+        Aes128Gcm {
+            aes: aes,
+            crypt_buf: TakeCell::new(crypt_buf),
+            client: OptionalCell::empty(),
+            state: Cell::new(GCMState::Idle),
+            encrypting: Cell::new(false),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0)),
+            key: Cell::new(Default::default()),
+            iv: Cell::new(Default::default()),
+            iv_len: Cell::new(0),
+            hash_key: Cell::new(0),
+        }
+    }
+
+    /// The pre-counter block J0 for the current IV
+    fn pre_counter(&self) -> u128 {
+        let iv = self.iv.get();
+        let iv_len = self.iv_len.get();
+
+        if iv_len == GCM_IV_MAX_LENGTH {
+            let mut j0 = [0u8; AES128_BLOCK_SIZE];
+            j0[..GCM_IV_MAX_LENGTH].copy_from_slice(&iv);
+            j0[AES128_BLOCK_SIZE - 1] = 1;
+            u128::from_be_bytes(j0)
+        } else {
+            ghash(self.hash_key.get(), &[], &iv[..iv_len])
+        }
+    }
+
+    fn start_hash_key(&self) -> Result<(), ErrorCode> {
+        self.aes.set_mode_aes128ecb(true)?;
+        self.aes.set_key(&self.key.get())?;
+
+        let crypt_buf = self.crypt_buf.take().ok_or(ErrorCode::NOMEM)?;
+        crypt_buf[..AES128_BLOCK_SIZE]
+            .iter_mut()
+            .for_each(|b| *b = 0);
+
+        self.aes.start_message();
+        match self.aes.crypt(None, crypt_buf, 0, AES128_BLOCK_SIZE) {
+            None => {
+                self.state.set(GCMState::HashKey);
+                Ok(())
+            }
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res
+            }
+        }
+    }
+
+    fn start_encrypt(&self) -> Result<(), ErrorCode> {
+        let (_, m_off, m_len) = self.pos.get();
+        let blocks = (m_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE;
+        let j0 = self.pre_counter();
+
+        // GCM only increments the low 32 bits of the counter, the hardware
+        // increments all 128.
+        if (j0 as u32) as u64 + blocks as u64 > u32::MAX as u64 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        self.aes.set_mode_aes128ctr(self.encrypting.get())?;
+        self.aes.set_key(&self.key.get())?;
+        self.aes.set_iv(&j0.to_be_bytes())?;
+
+        let crypt_buf = self.crypt_buf.take().ok_or(ErrorCode::NOMEM)?;
+        let end = AES128_BLOCK_SIZE + blocks * AES128_BLOCK_SIZE;
+
+        // Block 0 becomes E(K, J0), followed by the zero padded message
+        crypt_buf[..end].iter_mut().for_each(|b| *b = 0);
+        self.buf.map(|buf| {
+            crypt_buf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]
+                .copy_from_slice(&buf[m_off..m_off + m_len]);
+        });
+
+        self.aes.start_message();
+        match self.aes.crypt(None, crypt_buf, 0, end) {
+            None => {
+                self.state.set(GCMState::Encrypt);
+                Ok(())
+            }
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res
+            }
+        }
+    }
+
+    fn end_gcm(&self) {
+        let (aad_off, m_off, m_len) = self.pos.get();
+        let encrypting = self.encrypting.get();
+
+        let tag_valid = self.buf.map_or(false, |buf| {
+            self.crypt_buf.map_or(false, |cbuf| {
+                let message = &cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len];
+                let mut mask = [0u8; AES128_BLOCK_SIZE];
+                mask.copy_from_slice(&cbuf[..AES128_BLOCK_SIZE]);
+
+                // The tag always covers the ciphertext
+                let ciphertext = if encrypting {
+                    message
+                } else {
+                    &buf[m_off..m_off + m_len]
+                };
+                let tag = (ghash(self.hash_key.get(), &buf[aad_off..m_off], ciphertext)
+                    ^ u128::from_be_bytes(mask))
+                .to_be_bytes();
+
+                let m_end = m_off + m_len;
+                if encrypting {
+                    buf[m_off..m_end].copy_from_slice(message);
+                    buf[m_end..m_end + GCM_TAG_LENGTH].copy_from_slice(&tag);
+                    true
+                } else {
+                    // Compare every byte so the time taken doesn't depend on
+                    // where the tags differ
+                    let diff = buf[m_end..m_end + GCM_TAG_LENGTH]
+                        .iter()
+                        .zip(tag.iter())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+                    if diff == 0 {
+                        buf[m_off..m_end].copy_from_slice(message);
+                    }
+                    diff == 0
+                }
+            })
+        });
+
+        // Don't leave plaintext or the tag mask behind
+        self.crypt_buf
+            .map(|cbuf| cbuf.iter_mut().for_each(|b| *b = 0));
+
+        self.finish(Ok(()), tag_valid);
+    }
+
+    fn finish(&self, res: Result<(), ErrorCode>, tag_valid: bool) {
+        self.state.set(GCMState::Idle);
+        self.buf.take().map(|buf| {
+            self.client.map(move |client| {
+                client.crypt_done(buf, res, tag_valid);
+            });
+        });
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128ECB> symmetric_encryption::AES128GCM<'a>
+    for Aes128Gcm<'a, A>
+{
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::GCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() != AES128_KEY_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.state.get() != GCMState::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let mut new_key = [0u8; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        Ok(())
+    }
+
+    fn set_iv(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        if nonce.len() == 0 || nonce.len() > GCM_IV_MAX_LENGTH {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.state.get() != GCMState::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let mut new_iv = [0u8; GCM_IV_MAX_LENGTH];
+        new_iv[..nonce.len()].copy_from_slice(nonce);
+        self.iv.set(new_iv);
+        self.iv_len.set(nonce.len());
+        Ok(())
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        aad_offset: usize,
+        message_offset: usize,
+        message_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != GCMState::Idle {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        if self.iv_len.get() == 0 {
+            return Err((ErrorCode::INVAL, buf));
+        }
+        if aad_offset > message_offset || message_offset + message_len + GCM_TAG_LENGTH > buf.len()
+        {
+            return Err((ErrorCode::SIZE, buf));
+        }
+        let blocks = (message_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE;
+        if self.crypt_buf.map_or(0, |cbuf| cbuf.len()) < (blocks + 1) * AES128_BLOCK_SIZE {
+            return Err((ErrorCode::SIZE, buf));
+        }
+
+        self.encrypting.set(encrypting);
+        self.pos.set((aad_offset, message_offset, message_len));
+        self.buf.replace(buf);
+
+        match self.start_hash_key() {
+            Ok(()) => Ok(()),
+            Err(e) => Err((e, self.buf.take().unwrap())),
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128ECB> symmetric_encryption::Client<'a>
+    for Aes128Gcm<'a, A>
+{
+    fn crypt_done(&self, _: Option<&'static mut [u8]>, crypt_buf: &'static mut [u8]) {
+        self.crypt_buf.replace(crypt_buf);
+        match self.state.get() {
+            GCMState::Idle => {}
+            GCMState::HashKey => {
+                self.crypt_buf.map(|cbuf| {
+                    let mut h = [0u8; AES128_BLOCK_SIZE];
+                    h.copy_from_slice(&cbuf[..AES128_BLOCK_SIZE]);
+                    self.hash_key.set(u128::from_be_bytes(h));
+                });
+
+                if let Err(e) = self.start_encrypt() {
+                    self.finish(Err(e), false);
+                }
+            }
+            GCMState::Encrypt => self.end_gcm(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NIST GCM specification test case 2: K = 0, P = 0^128
+    #[test]
+    fn ghash_known_answer() {
+        let h = 0x66e94bd4ef8a2c3b884cfa59ca342b2e;
+        let c = 0x0388dace60b6a392f328c2b971b2fe78u128.to_be_bytes();
+
+        assert_eq!(ghash(h, &[], &c), 0xf38cbb1ad69223dcc3457ae5b6b0f885);
+    }
+
+    #[test]
+    fn ghash_partial_block() {
+        let h = 0x66e94bd4ef8a2c3b884cfa59ca342b2e;
+        let c = 0x0388dace60b6a392f328c2b971b2fe78u128.to_be_bytes();
+
+        // A short final block is hashed as if zero padded, only the length
+        // block differs
+        let mut padded = [0u8; AES128_BLOCK_SIZE];
+        padded[..5].copy_from_slice(&c[..5]);
+        let y = gf128_mul(u128::from_be_bytes(padded), h);
+        assert_eq!(ghash(h, &[], &c[..5]), gf128_mul(y ^ 40, h));
+    }
+}
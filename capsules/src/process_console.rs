@@ -15,6 +15,27 @@
 //!  - 'panic' causes the kernel to run the panic handler
 //!  - 'process n' prints the memory map of process with name n
 //!  - 'kernel' prints the kernel memory map
+//!  - 'dmesg' prints the kernel debug output retained since boot
+//!  - 'unlock s' unlocks the console if s matches the board secret
+//!  - 'lock' locks the console again
+//!
+//! Commands must be given in full, anything else is reported as an unknown
+//! command along with the list of valid commands.
+//!
+//! ### Authentication
+//!
+//! A board can pass a secret to `ProcessConsole::new`. If it does, the console
+//! starts locked and only `help` and `status` are accepted until the user
+//! enters `unlock <secret>`. The secret is compared in constant time. If no
+//! secret is provided the console is always unlocked.
+//!
+//! ### `dmesg`
+//!
+//! A board can keep a copy of recent kernel debug output with
+//! `components::debug_history::DebugHistoryComponent` and pass it to
+//! `ProcessConsole::new`. `dmesg` then replays it, oldest first, which shows
+//! messages printed before the console was connected. Once the history is
+//! full the oldest output is overwritten and is no longer shown.
 //!
 //! ### `list` Command Fields:
 //!
@@ -53,6 +74,7 @@
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
 //!                  kernel,
+//!                  None,
 //!                  Capability));
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
 //!
@@ -112,6 +134,7 @@ use core::fmt;
 use core::fmt::write;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
+use kernel::debug::DebugHistory;
 use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::TakeCell;
 use kernel::ProcessId;
@@ -133,6 +156,9 @@ pub static mut QUEUE_BUF: [u8; 300] = [0; 300];
 /// Since reads are byte-by-byte, to properly echo what's typed,
 /// we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
+/// Number of bytes of the kernel debug history sent per write by `dmesg`.
+const DMESG_CHUNK_SIZE: usize = 128;
+
 /// Commands can be up to 32 bytes long: since commands themselves are 4-5
 /// characters, limiting arguments to 25 bytes or so seems fine for now.
 pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
@@ -157,6 +183,12 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    /// Replaying the kernel debug history from `position` up to, but not
+    /// including, `end`.
+    Dmesg {
+        position: usize,
+        end: usize,
+    },
 }
 
 impl Default for WriterState {
@@ -165,6 +197,61 @@ impl Default for WriterState {
     }
 }
 
+/// The commands understood by the console. The first whitespace separated
+/// word of a line must match one of these exactly.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+enum Command {
+    Help,
+    Status,
+    List,
+    Stop,
+    Start,
+    Fault,
+    Process,
+    Kernel,
+    Dmesg,
+    Lock,
+    Unlock,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "help" => Some(Command::Help),
+            "status" => Some(Command::Status),
+            "list" => Some(Command::List),
+            "stop" => Some(Command::Stop),
+            "start" => Some(Command::Start),
+            "fault" => Some(Command::Fault),
+            "process" => Some(Command::Process),
+            "kernel" => Some(Command::Kernel),
+            "dmesg" => Some(Command::Dmesg),
+            "lock" => Some(Command::Lock),
+            "unlock" => Some(Command::Unlock),
+            _ => None,
+        }
+    }
+
+    /// Split `line` into a command and its first argument, if any. On
+    /// failure the unrecognised command word is returned.
+    fn parse(line: &str) -> Result<(Command, Option<&str>), &str> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        match Command::from_name(name) {
+            Some(command) => Ok((command, words.next())),
+            None => Err(name),
+        }
+    }
+
+    /// Whether the command can be used while the console is locked.
+    fn allowed_when_locked(&self) -> bool {
+        match self {
+            Command::Help | Command::Status | Command::Unlock => true,
+            _ => false,
+        }
+    }
+}
+
 /// Data structure to hold addresses about how the kernel is stored in memory on
 /// the chip.
 ///
@@ -214,6 +301,17 @@ pub struct ProcessConsole<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
     /// Memory addresses of where the kernel is placed in memory on chip.
     kernel_addresses: KernelAddresses,
 
+    /// Optional secret that must be entered with `unlock` before any command
+    /// other than `help` and `status` is accepted.
+    secret: Option<&'static [u8]>,
+
+    /// Whether privileged commands are currently allowed.
+    unlocked: Cell<bool>,
+
+    /// Retained kernel debug output replayed by `dmesg`, if the board keeps
+    /// one.
+    debug_history: Option<&'static DebugHistory>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -265,6 +363,8 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         cmd_buffer: &'static mut [u8],
         kernel: &'static Kernel,
         kernel_addresses: KernelAddresses,
+        secret: Option<&'static [u8]>,
+        debug_history: Option<&'static DebugHistory>,
         capability: C,
     ) -> ProcessConsole<'a, A, C> {
         ProcessConsole {
@@ -287,6 +387,9 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
             execute: Cell::new(false),
             kernel: kernel,
             kernel_addresses: kernel_addresses,
+            secret: secret,
+            unlocked: Cell::new(secret.is_none()),
+            debug_history: debug_history,
             capability: capability,
         }
     }
@@ -328,7 +431,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
 
         let _ = self.write_bytes(b"Welcome to the process console.\r\n");
         let _ = self.write_bytes(
-            b"Valid commands are: help status list stop start fault process kernel\r\n",
+            b"Valid commands are: help status list stop start fault process kernel dmesg lock unlock\r\n",
         );
         self.prompt();
     }
@@ -350,6 +453,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                 process_id,
                 context,
             },
+            WriterState::Dmesg { position, end } => WriterState::Dmesg { position, end },
             WriterState::List { index, total } => {
                 // Next state just increments index, unless we are at end in
                 // which next state is just the empty state.
@@ -529,6 +633,26 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                         }
                     });
             }
+            WriterState::Dmesg { position, end } => {
+                let mut chunk = [0; DMESG_CHUNK_SIZE];
+                let (start, count) = self.debug_history.map_or((end, 0), |history| {
+                    let len = cmp::min(end.saturating_sub(position), chunk.len());
+                    history.read(position, &mut chunk[..len])
+                });
+
+                if count > 0 {
+                    let _ = self.write_bytes(&chunk[..count]);
+                    self.writer_state.replace(WriterState::Dmesg {
+                        position: start + count,
+                        end,
+                    });
+                } else {
+                    // Either everything up to `end` has been sent, or it was
+                    // overwritten while we were sending it.
+                    self.writer_state.replace(WriterState::Empty);
+                    self.prompt();
+                }
+            }
             WriterState::Empty => {
                 self.prompt();
             }
@@ -558,182 +682,249 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                     Ok(s) => {
                         let clean_str = s.trim();
 
-                        if clean_str.starts_with("help") {
-                            let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(
-                                b"help status list stop start fault process kernel\r\n",
-                            );
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} resumed.\r\n", name),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} stopped\r\n", proc_name),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!(
-                                                    "Process {} now faulted\r\n",
-                                                    proc_name
-                                                ),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("list") {
-                            let _ = self.write_bytes(b" PID    Name                Quanta  ");
-                            let _ = self.write_bytes(b"Syscalls  Dropped Upcalls  ");
-                            let _ = self.write_bytes(b"Restarts    State  Grants\r\n");
-
-                            // Count the number of current processes.
-                            let mut count = 0;
-                            self.kernel.process_each_capability(&self.capability, |_| {
-                                count += 1;
-                            });
-
-                            if count > 0 {
-                                // Start the state machine to print each separately.
-                                self.write_state(WriterState::List {
-                                    index: -1,
-                                    total: count,
+                        match Command::parse(clean_str) {
+                            Ok((command, _))
+                                if !self.unlocked.get() && !command.allowed_when_locked() =>
+                            {
+                                let _ = self.write_bytes(b"Console locked, use unlock.\r\n");
+                            }
+                            Ok((Command::Help, _)) => {
+                                let _ = self.write_bytes(b"Welcome to the process console.\r\n");
+                                let _ = self.write_bytes(b"Valid commands are: ");
+                                let _ = self.write_bytes(
+                                    b"help status list stop start fault process kernel dmesg lock unlock\r\n",
+                                );
+                            }
+                            Ok((Command::Unlock, argument)) => {
+                                let argument = argument.unwrap_or("");
+                                if self.check_secret(argument.as_bytes()) {
+                                    self.unlocked.set(true);
+                                    let _ = self.write_bytes(b"Console unlocked.\r\n");
+                                } else {
+                                    let _ = self.write_bytes(b"Incorrect secret.\r\n");
+                                }
+                            }
+                            Ok((Command::Lock, _)) => {
+                                if self.secret.is_some() {
+                                    self.unlocked.set(false);
+                                    let _ = self.write_bytes(b"Console locked.\r\n");
+                                } else {
+                                    let _ = self.write_bytes(b"No secret configured.\r\n");
+                                }
+                            }
+                            Ok((Command::Start, argument)) => {
+                                argument.map(|name| {
+                                    self.kernel
+                                        .process_each_capability(&self.capability, |proc| {
+                                            let proc_name = proc.get_process_name();
+                                            if proc_name == name {
+                                                proc.resume();
+                                                let mut console_writer = ConsoleWriter::new();
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!("Process {} resumed.\r\n", name),
+                                                );
+
+                                                let _ = self.write_bytes(
+                                                    &(console_writer.buf)[..console_writer.size],
+                                                );
+                                            }
+                                        });
                                 });
                             }
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            let mut console_writer = ConsoleWriter::new();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Total processes: {}\r\n",
-                                    info.number_loaded_processes(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Active processes: {}\r\n",
-                                    info.number_active_processes(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Timeslice expirations: {}\r\n",
-                                    info.timeslice_expirations(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                        } else if clean_str.starts_with("process") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                // If two processes have the same name, only
-                                // print the first one we find.
-                                let mut found = false;
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        if found {
-                                            return;
-                                        }
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let mut context: Option<ProcessPrinterContext> = None;
-                                            context = self.process_printer.print_overview(
-                                                proc,
-                                                &mut console_writer,
-                                                context,
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-
-                                            if context.is_some() {
-                                                self.writer_state.replace(
-                                                    WriterState::ProcessPrint {
-                                                        process_id: proc.processid(),
-                                                        context: context,
-                                                    },
+                            Ok((Command::Stop, argument)) => {
+                                argument.map(|name| {
+                                    self.kernel
+                                        .process_each_capability(&self.capability, |proc| {
+                                            let proc_name = proc.get_process_name();
+                                            if proc_name == name {
+                                                proc.stop();
+                                                let mut console_writer = ConsoleWriter::new();
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!(
+                                                        "Process {} stopped\r\n",
+                                                        proc_name
+                                                    ),
+                                                );
+
+                                                let _ = self.write_bytes(
+                                                    &(console_writer.buf)[..console_writer.size],
                                                 );
                                             }
+                                        });
+                                });
+                            }
+                            Ok((Command::Fault, argument)) => {
+                                argument.map(|name| {
+                                    self.kernel
+                                        .process_each_capability(&self.capability, |proc| {
+                                            let proc_name = proc.get_process_name();
+                                            if proc_name == name {
+                                                proc.set_fault_state();
+                                                let mut console_writer = ConsoleWriter::new();
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!(
+                                                        "Process {} now faulted\r\n",
+                                                        proc_name
+                                                    ),
+                                                );
 
-                                            found = true;
-                                        }
+                                                let _ = self.write_bytes(
+                                                    &(console_writer.buf)[..console_writer.size],
+                                                );
+                                            }
+                                        });
+                                });
+                            }
+                            Ok((Command::List, _)) => {
+                                let _ = self.write_bytes(b" PID    Name                Quanta  ");
+                                let _ = self.write_bytes(b"Syscalls  Dropped Upcalls  ");
+                                let _ = self.write_bytes(b"Restarts    State  Grants\r\n");
+
+                                // Count the number of current processes.
+                                let mut count = 0;
+                                self.kernel.process_each_capability(&self.capability, |_| {
+                                    count += 1;
+                                });
+
+                                if count > 0 {
+                                    // Start the state machine to print each separately.
+                                    self.write_state(WriterState::List {
+                                        index: -1,
+                                        total: count,
                                     });
-                            });
-                        } else if clean_str.starts_with("kernel") {
-                            let mut console_writer = ConsoleWriter::new();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Kernel version: {}.{} (build {})\r\n",
-                                    kernel::KERNEL_MAJOR_VERSION,
-                                    kernel::KERNEL_MINOR_VERSION,
-                                    option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown")
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-
-                            // Prints kernel memory by moving the writer to the
-                            // start state.
-                            self.writer_state.replace(WriterState::KernelStart);
-                        } else {
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(
-                                b"help status list stop start fault process kernel\r\n",
-                            );
+                                }
+                            }
+                            Ok((Command::Status, _)) => {
+                                let info: KernelInfo = KernelInfo::new(self.kernel);
+                                let mut console_writer = ConsoleWriter::new();
+                                let _ = write(
+                                    &mut console_writer,
+                                    format_args!(
+                                        "Total processes: {}\r\n",
+                                        info.number_loaded_processes(&self.capability)
+                                    ),
+                                );
+                                let _ =
+                                    self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                                console_writer.clear();
+                                let _ = write(
+                                    &mut console_writer,
+                                    format_args!(
+                                        "Active processes: {}\r\n",
+                                        info.number_active_processes(&self.capability)
+                                    ),
+                                );
+                                let _ =
+                                    self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                                console_writer.clear();
+                                let _ = write(
+                                    &mut console_writer,
+                                    format_args!(
+                                        "Timeslice expirations: {}\r\n",
+                                        info.timeslice_expirations(&self.capability)
+                                    ),
+                                );
+                                let _ =
+                                    self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            }
+                            Ok((Command::Process, argument)) => {
+                                argument.map(|name| {
+                                    // If two processes have the same name, only
+                                    // print the first one we find.
+                                    let mut found = false;
+                                    self.kernel
+                                        .process_each_capability(&self.capability, |proc| {
+                                            if found {
+                                                return;
+                                            }
+                                            let proc_name = proc.get_process_name();
+                                            if proc_name == name {
+                                                let mut console_writer = ConsoleWriter::new();
+                                                let mut context: Option<ProcessPrinterContext> =
+                                                    None;
+                                                context = self.process_printer.print_overview(
+                                                    proc,
+                                                    &mut console_writer,
+                                                    context,
+                                                );
+
+                                                let _ = self.write_bytes(
+                                                    &(console_writer.buf)[..console_writer.size],
+                                                );
+
+                                                if context.is_some() {
+                                                    self.writer_state.replace(
+                                                        WriterState::ProcessPrint {
+                                                            process_id: proc.processid(),
+                                                            context: context,
+                                                        },
+                                                    );
+                                                }
+
+                                                found = true;
+                                            }
+                                        });
+                                });
+                            }
+                            Ok((Command::Kernel, _)) => {
+                                let mut console_writer = ConsoleWriter::new();
+                                let _ = write(
+                                    &mut console_writer,
+                                    format_args!(
+                                        "Kernel version: {}.{} (build {})\r\n",
+                                        kernel::KERNEL_MAJOR_VERSION,
+                                        kernel::KERNEL_MINOR_VERSION,
+                                        option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown")
+                                    ),
+                                );
+                                let _ =
+                                    self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                                console_writer.clear();
+
+                                // Prints kernel memory by moving the writer to the
+                                // start state.
+                                self.writer_state.replace(WriterState::KernelStart);
+                            }
+                            Ok((Command::Dmesg, _)) => {
+                                match self.debug_history.map(|history| history.span()) {
+                                    None => {
+                                        let _ = self.write_bytes(b"No kernel debug history.\r\n");
+                                    }
+                                    Some((start, end)) if start == end => {
+                                        let _ =
+                                            self.write_bytes(b"Kernel debug history is empty.\r\n");
+                                    }
+                                    Some((start, end)) => {
+                                        // Only replay what has been written so far, so
+                                        // that output printed while replaying can't keep
+                                        // the dump going forever.
+                                        let state = WriterState::Dmesg {
+                                            position: start,
+                                            end,
+                                        };
+                                        self.writer_state.replace(state);
+                                        self.create_state_buffer(state);
+                                    }
+                                }
+                            }
+                            Err(name) => {
+                                if !name.is_empty() {
+                                    let mut console_writer = ConsoleWriter::new();
+                                    let _ = write(
+                                        &mut console_writer,
+                                        format_args!("Unknown command: {}\r\n", name),
+                                    );
+                                    let _ = self
+                                        .write_bytes(&(console_writer.buf)[..console_writer.size]);
+                                }
+                                let _ = self.write_bytes(b"Valid commands are: ");
+                                let _ = self.write_bytes(
+                                    b"help status list stop start fault process kernel dmesg lock unlock\r\n",
+                                );
+                            }
                         }
                     }
                     Err(_e) => {
@@ -756,6 +947,18 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         }
     }
 
+    /// Compare `guess` against the configured secret without exiting early,
+    /// so the time taken does not depend on how many leading bytes match.
+    fn check_secret(&self, guess: &[u8]) -> bool {
+        self.secret.map_or(true, |secret| {
+            let mut diff = (secret.len() != guess.len()) as u8;
+            for (i, s) in secret.iter().enumerate() {
+                diff |= s ^ guess.get(i).copied().unwrap_or(0);
+            }
+            diff == 0
+        })
+    }
+
     fn prompt(&self) {
         let _ = self.write_bytes(b"tock$ ");
     }
@@ -956,3 +1159,44 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::ReceiveClient
         let _ = self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_exact_commands() {
+        assert_eq!(Command::parse("status"), Ok((Command::Status, None)));
+        assert_eq!(
+            Command::parse("start blink"),
+            Ok((Command::Start, Some("blink")))
+        );
+        assert_eq!(
+            Command::parse("stop  blink"),
+            Ok((Command::Stop, Some("blink")))
+        );
+        assert_eq!(Command::parse("lock"), Ok((Command::Lock, None)));
+        assert_eq!(
+            Command::parse("unlock s3cret"),
+            Ok((Command::Unlock, Some("s3cret")))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_prefixes_and_typos() {
+        assert_eq!(Command::parse("starttt blink"), Err("starttt"));
+        assert_eq!(Command::parse("sta"), Err("sta"));
+        assert_eq!(Command::parse("statusx"), Err("statusx"));
+        assert_eq!(Command::parse("stopblink"), Err("stopblink"));
+        assert_eq!(Command::parse("lockdown"), Err("lockdown"));
+        assert_eq!(Command::parse(""), Err(""));
+    }
+
+    #[test]
+    fn locked_commands() {
+        assert!(Command::Status.allowed_when_locked());
+        assert!(Command::Unlock.allowed_when_locked());
+        assert!(!Command::Start.allowed_when_locked());
+        assert!(!Command::Stop.allowed_when_locked());
+    }
+}
@@ -8,17 +8,29 @@ use kernel::dynamic_deferred_call::{
 };
 use kernel::hil;
 use kernel::hil::symmetric_encryption;
-use kernel::hil::symmetric_encryption::{AES128_BLOCK_SIZE, AES128_KEY_SIZE};
+use kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 
 const MAX_LENGTH: usize = 128;
 
+// We must write the key registers a shorter key doesn't use as well
+// This should be written with random data, for now this will do
+const UNUSED_KEY_SHARE0: [u32; 4] = [0x12, 0x34, 0x56, 0x78];
+const UNUSED_KEY_SHARE1: [u32; 4] = [0xAB, 0xCD, 0xEF, 0x00];
+
+/// Default initial value from RFC 3394 section 2.2.3.1
+const KEY_WRAP_IV: [u8; KEY_WRAP_OVERHEAD] = [0xA6; KEY_WRAP_OVERHEAD];
+
+/// Number of bytes that `key_wrap()` adds to the key, the wrapped form of
+/// an `n` byte key is `n + KEY_WRAP_OVERHEAD` bytes long.
+pub const KEY_WRAP_OVERHEAD: usize = 8;
+
 register_structs! {
     pub AesRegisters {
         (0x00 => alert_test: WriteOnly<u32, ALERT_TEST::Register>),
@@ -102,7 +114,7 @@ register_bitfields![u32,
     ]
 ];
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 enum Mode {
     IDLE,
     AES128CTR,
@@ -110,8 +122,35 @@ enum Mode {
     AES128ECB,
 }
 
+/// Key sizes supported by the AES block, see `Aes::set_key_with_size()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySize {
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl KeySize {
+    /// Length of the key in bytes
+    pub const fn len(&self) -> usize {
+        match self {
+            KeySize::Aes128 => 16,
+            KeySize::Aes192 => 24,
+            KeySize::Aes256 => 32,
+        }
+    }
+
+    fn key_len(&self) -> FieldValue<u32, CTRL::Register> {
+        match self {
+            KeySize::Aes128 => CTRL::KEY_LEN::Key128,
+            KeySize::Aes192 => CTRL::KEY_LEN::Key192,
+            KeySize::Aes256 => CTRL::KEY_LEN::Key256,
+        }
+    }
+}
+
 // https://docs.opentitan.org/hw/top_earlgrey/doc/
-const AES_BASE: StaticRef<AesRegisters> =
+pub(crate) const AES_BASE: StaticRef<AesRegisters> =
     unsafe { StaticRef::new(0x4110_0000 as *const AesRegisters) };
 
 pub struct Aes<'a> {
@@ -121,6 +160,8 @@ pub struct Aes<'a> {
     source: TakeCell<'static, [u8]>,
     dest: TakeCell<'static, [u8]>,
     mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+    key_size: Cell<KeySize>,
 
     deferred_call: Cell<bool>,
     deferred_caller: &'static DynamicDeferredCall,
@@ -135,6 +176,8 @@ impl<'a> Aes<'a> {
             source: TakeCell::empty(),
             dest: TakeCell::empty(),
             mode: Cell::new(Mode::IDLE),
+            encrypting: Cell::new(true),
+            key_size: Cell::new(KeySize::Aes128),
             deferred_call: Cell::new(false),
             deferred_caller,
             deferred_handle: OptionalCell::empty(),
@@ -251,6 +294,117 @@ impl<'a> Aes<'a> {
         )
     }
 
+    /// Select `mode` and the direction, using the current key size
+    fn set_mode(&self, mode: Mode, encrypting: bool) -> Result<(), ErrorCode> {
+        if !self.idle() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.mode.set(mode);
+        self.encrypting.set(encrypting);
+        self.write_ctrl();
+
+        Ok(())
+    }
+
+    fn write_ctrl(&self) {
+        let mut ctrl = if self.encrypting.get() {
+            CTRL::OPERATION::Encrypting
+        } else {
+            CTRL::OPERATION::Decrypting
+        };
+        ctrl += match self.mode.get() {
+            Mode::AES128CTR => CTRL::MODE::AES_CTR,
+            Mode::AES128CBC => CTRL::MODE::AES_CBC,
+            Mode::AES128ECB => CTRL::MODE::AES_ECB,
+            Mode::IDLE => CTRL::MODE::AES_NONE,
+        };
+        ctrl += self.key_size.get().key_len();
+        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
+
+        // We need to set the control register twice as it's shadowed
+        self.registers.ctrl.write(ctrl);
+        self.registers.ctrl.write(ctrl);
+    }
+
+    /// Set a 128, 192 or 256 bit key, `key` must be exactly `size.len()`
+    /// bytes. This can be called before or after selecting the mode, the key
+    /// size is kept until the next call. The `AES128` `set_key()` is the same
+    /// as calling this with `KeySize::Aes128`.
+    ///
+    /// Returns `INVAL` if the length of `key` doesn't match `size` and `BUSY`
+    /// if an operation is in progress.
+    pub fn set_key_with_size(&self, key: &[u8], size: KeySize) -> Result<(), ErrorCode> {
+        if !self.idle() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if key.len() != size.len() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.key_size.replace(size) != size && self.mode.get() != Mode::IDLE {
+            self.write_ctrl();
+        }
+
+        for i in 0..8 {
+            let (share0, share1) = if i < size.len() / 4 {
+                let mut k = key[i * 4 + 0] as u32;
+                k |= (key[i * 4 + 1] as u32) << 8;
+                k |= (key[i * 4 + 2] as u32) << 16;
+                k |= (key[i * 4 + 3] as u32) << 24;
+                (k, 0)
+            } else {
+                // Even the shortest key fills the first 4 words
+                (UNUSED_KEY_SHARE0[i - 4], UNUSED_KEY_SHARE1[i - 4])
+            };
+            self.write_key_word(i, share0, share1);
+        }
+
+        Ok(())
+    }
+
+    fn write_key_word(&self, i: usize, share0: u32, share1: u32) {
+        let regs = self.registers;
+        match i {
+            0 => {
+                regs.key_share0_0.set(share0);
+                regs.key_share1_0.set(share1);
+            }
+            1 => {
+                regs.key_share0_1.set(share0);
+                regs.key_share1_1.set(share1);
+            }
+            2 => {
+                regs.key_share0_2.set(share0);
+                regs.key_share1_2.set(share1);
+            }
+            3 => {
+                regs.key_share0_3.set(share0);
+                regs.key_share1_3.set(share1);
+            }
+            4 => {
+                regs.key_share0_4.set(share0);
+                regs.key_share1_4.set(share1);
+            }
+            5 => {
+                regs.key_share0_5.set(share0);
+                regs.key_share1_5.set(share1);
+            }
+            6 => {
+                regs.key_share0_6.set(share0);
+                regs.key_share1_6.set(share1);
+            }
+            7 => {
+                regs.key_share0_7.set(share0);
+                regs.key_share1_7.set(share1);
+            }
+            _ => {
+                unreachable!()
+            }
+        }
+    }
+
     fn do_crypt(
         &self,
         start_index: usize,
@@ -271,6 +425,176 @@ impl<'a> Aes<'a> {
 
         Ok(())
     }
+
+    /// Encrypt or decrypt a single block in place, polling for completion
+    fn crypt_block_sync(&self, block: &mut [u8; AES128_BLOCK_SIZE]) -> Result<(), ErrorCode> {
+        self.wait_for_input_ready()?;
+        for i in 0..4 {
+            let mut v = block[i * 4 + 0] as u32;
+            v |= (block[i * 4 + 1] as u32) << 8;
+            v |= (block[i * 4 + 2] as u32) << 16;
+            v |= (block[i * 4 + 3] as u32) << 24;
+            match i {
+                0 => self.registers.data_in0.set(v),
+                1 => self.registers.data_in1.set(v),
+                2 => self.registers.data_in2.set(v),
+                3 => self.registers.data_in3.set(v),
+                _ => unreachable!(),
+            }
+        }
+
+        self.wait_for_output_valid()?;
+        for i in 0..4 {
+            let v = match i {
+                0 => self.registers.data_out0.get(),
+                1 => self.registers.data_out1.get(),
+                2 => self.registers.data_out2.get(),
+                3 => self.registers.data_out3.get(),
+                _ => unreachable!(),
+            };
+            block[i * 4..(i + 1) * 4].copy_from_slice(&v.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Check the lengths and load `kek` for a key wrap operation, returning
+    /// the number of 64-bit blocks in the key.
+    fn key_wrap_setup(
+        &self,
+        kek: &[u8],
+        size: KeySize,
+        key_len: usize,
+        wrapped_len: usize,
+        encrypting: bool,
+    ) -> Result<usize, ErrorCode> {
+        if self.deferred_call.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // RFC 3394 requires at least two 64-bit blocks
+        if key_len < 2 * KEY_WRAP_OVERHEAD
+            || key_len % KEY_WRAP_OVERHEAD != 0
+            || wrapped_len != key_len + KEY_WRAP_OVERHEAD
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.set_mode(Mode::AES128ECB, encrypting)?;
+        self.set_key_with_size(kek, size)?;
+
+        Ok(key_len / KEY_WRAP_OVERHEAD)
+    }
+
+    /// Clear the key encryption key and the last block from the hardware
+    fn key_wrap_clear(&self) {
+        self.registers
+            .trigger
+            .write(TRIGGER::KEY_IV_DATA_IN_CLEAR::SET + TRIGGER::DATA_OUT_CLEAR::SET);
+    }
+
+    /// Wrap `key` under the key encryption key `kek` using the RFC 3394 AES
+    /// key wrap algorithm, writing the result into `wrapped`.
+    ///
+    /// `key` must be a multiple of 8 bytes and at least 16 bytes long and
+    /// `wrapped` must be exactly `KEY_WRAP_OVERHEAD` bytes longer. This runs
+    /// synchronously and switches the block to ECB mode. The key is cleared
+    /// from the hardware afterwards, so `set_key()` has to be called again
+    /// before the next `crypt()`.
+    ///
+    /// Returns `INVAL` if the lengths are wrong and `BUSY` if an operation
+    /// is in progress.
+    pub fn key_wrap(
+        &self,
+        kek: &[u8],
+        size: KeySize,
+        key: &[u8],
+        wrapped: &mut [u8],
+    ) -> Result<(), ErrorCode> {
+        let n = self.key_wrap_setup(kek, size, key.len(), wrapped.len(), true)?;
+
+        let mut a = KEY_WRAP_IV;
+        wrapped[KEY_WRAP_OVERHEAD..].copy_from_slice(key);
+
+        let mut block = [0; AES128_BLOCK_SIZE];
+        let ret = (0..6).try_for_each(|j| {
+            (1..=n).try_for_each(|i| {
+                let r = &mut wrapped[i * KEY_WRAP_OVERHEAD..(i + 1) * KEY_WRAP_OVERHEAD];
+                block[..KEY_WRAP_OVERHEAD].copy_from_slice(&a);
+                block[KEY_WRAP_OVERHEAD..].copy_from_slice(r);
+                self.crypt_block_sync(&mut block)?;
+
+                let t = ((n * j + i) as u64).to_be_bytes();
+                for ((a, b), t) in a.iter_mut().zip(block.iter()).zip(t.iter()) {
+                    *a = b ^ t;
+                }
+                r.copy_from_slice(&block[KEY_WRAP_OVERHEAD..]);
+                Ok(())
+            })
+        });
+        self.key_wrap_clear();
+
+        if ret.is_err() {
+            wrapped.iter_mut().for_each(|b| *b = 0);
+        } else {
+            wrapped[..KEY_WRAP_OVERHEAD].copy_from_slice(&a);
+        }
+        ret
+    }
+
+    /// Unwrap `wrapped`, produced by `key_wrap()` with the same `kek`, into
+    /// `key` and check its integrity.
+    ///
+    /// `key` must be exactly `KEY_WRAP_OVERHEAD` bytes shorter than
+    /// `wrapped`. As with `key_wrap()` this runs synchronously and clears
+    /// the key from the hardware afterwards.
+    ///
+    /// Returns `FAIL` if the integrity check fails, which means `wrapped`
+    /// was modified or `kek` is not the key it was wrapped with. `key` is
+    /// zeroed on any error.
+    pub fn key_unwrap(
+        &self,
+        kek: &[u8],
+        size: KeySize,
+        wrapped: &[u8],
+        key: &mut [u8],
+    ) -> Result<(), ErrorCode> {
+        let n = self.key_wrap_setup(kek, size, key.len(), wrapped.len(), false)?;
+
+        let mut a = [0; KEY_WRAP_OVERHEAD];
+        a.copy_from_slice(&wrapped[..KEY_WRAP_OVERHEAD]);
+        key.copy_from_slice(&wrapped[KEY_WRAP_OVERHEAD..]);
+
+        let mut block = [0; AES128_BLOCK_SIZE];
+        let ret = (0..6).rev().try_for_each(|j| {
+            (1..=n).rev().try_for_each(|i| {
+                let r = &mut key[(i - 1) * KEY_WRAP_OVERHEAD..i * KEY_WRAP_OVERHEAD];
+                let t = ((n * j + i) as u64).to_be_bytes();
+                for ((b, a), t) in block.iter_mut().zip(a.iter()).zip(t.iter()) {
+                    *b = a ^ t;
+                }
+                block[KEY_WRAP_OVERHEAD..].copy_from_slice(r);
+                self.crypt_block_sync(&mut block)?;
+
+                a.copy_from_slice(&block[..KEY_WRAP_OVERHEAD]);
+                r.copy_from_slice(&block[KEY_WRAP_OVERHEAD..]);
+                Ok(())
+            })
+        });
+        self.key_wrap_clear();
+
+        let ret = ret.and_then(|()| {
+            if a == KEY_WRAP_IV {
+                Ok(())
+            } else {
+                Err(ErrorCode::FAIL)
+            }
+        });
+        if ret.is_err() {
+            key.iter_mut().for_each(|b| *b = 0);
+        }
+        ret
+    }
 }
 
 impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
@@ -323,55 +647,7 @@ impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
     }
 
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
-        if !self.idle() {
-            return Err(ErrorCode::BUSY);
-        }
-
-        if key.len() != AES128_KEY_SIZE {
-            return Err(ErrorCode::INVAL);
-        }
-
-        for i in 0..(AES128_KEY_SIZE / 4) {
-            let mut k = key[i * 4 + 0] as u32;
-            k |= (key[i * 4 + 1] as u32) << 8;
-            k |= (key[i * 4 + 2] as u32) << 16;
-            k |= (key[i * 4 + 3] as u32) << 24;
-            match i {
-                0 => {
-                    self.registers.key_share0_0.set(k);
-                    self.registers.key_share1_0.set(0);
-                }
-                1 => {
-                    self.registers.key_share0_1.set(k);
-                    self.registers.key_share1_1.set(0);
-                }
-                2 => {
-                    self.registers.key_share0_2.set(k);
-                    self.registers.key_share1_2.set(0);
-                }
-                3 => {
-                    self.registers.key_share0_3.set(k);
-                    self.registers.key_share1_3.set(0);
-                }
-                _ => {
-                    unreachable!()
-                }
-            }
-        }
-
-        // We must write the rest of the registers as well
-        // This should be written with random data, for now this will do
-        self.registers.key_share0_4.set(0x12);
-        self.registers.key_share0_5.set(0x34);
-        self.registers.key_share0_6.set(0x56);
-        self.registers.key_share0_7.set(0x78);
-
-        self.registers.key_share1_4.set(0xAB);
-        self.registers.key_share1_5.set(0xCD);
-        self.registers.key_share1_6.set(0xEF);
-        self.registers.key_share1_7.set(0x00);
-
-        Ok(())
+        self.set_key_with_size(key, KeySize::Aes128)
     }
 
     fn start_message(&self) {}
@@ -433,79 +709,19 @@ impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
 
 impl kernel::hil::symmetric_encryption::AES128Ctr for Aes<'_> {
     fn set_mode_aes128ctr(&self, encrypting: bool) -> Result<(), ErrorCode> {
-        if !self.idle() {
-            return Err(ErrorCode::BUSY);
-        }
-
-        self.mode.set(Mode::AES128CTR);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_CTR;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
-
-        Ok(())
+        self.set_mode(Mode::AES128CTR, encrypting)
     }
 }
 
 impl kernel::hil::symmetric_encryption::AES128ECB for Aes<'_> {
     fn set_mode_aes128ecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
-        if !self.idle() {
-            return Err(ErrorCode::BUSY);
-        }
-
-        self.mode.set(Mode::AES128ECB);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_ECB;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
-
-        Ok(())
+        self.set_mode(Mode::AES128ECB, encrypting)
     }
 }
 
 impl kernel::hil::symmetric_encryption::AES128CBC for Aes<'_> {
     fn set_mode_aes128cbc(&self, encrypting: bool) -> Result<(), ErrorCode> {
-        if !self.idle() {
-            return Err(ErrorCode::BUSY);
-        }
-
-        self.mode.set(Mode::AES128CBC);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_CBC;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
-
-        Ok(())
+        self.set_mode(Mode::AES128CBC, encrypting)
     }
 }
 
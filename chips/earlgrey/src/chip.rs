@@ -4,6 +4,7 @@ use core::fmt::Write;
 use kernel;
 use kernel::dynamic_deferred_call::DynamicDeferredCall;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use rv32i::csr::{mcause, mie::mie, mtvec::mtvec, CSR};
 use rv32i::epmp::PMP;
@@ -21,6 +22,13 @@ pub struct EarlGrey<'a, I: InterruptService<()> + 'a> {
     timer: &'static crate::timer::RvTimer<'static>,
     pwrmgr: lowrisc::pwrmgr::PwrMgr,
     plic_interrupt_service: &'a I,
+    peripheral_dump: OptionalCell<&'a dyn PeripheralDump>,
+}
+
+/// Peripherals that can write a snapshot of their registers into the panic
+/// output, see `EarlGrey::set_peripheral_dump()`.
+pub trait PeripheralDump {
+    fn dump_state(&self, writer: &mut dyn Write);
 }
 
 pub struct EarlGreyDefaultPeripherals<'a> {
@@ -43,20 +51,19 @@ impl<'a> EarlGreyDefaultPeripherals<'a> {
             aes: crate::aes::Aes::new(deferred_caller),
             hmac: lowrisc::hmac::Hmac::new(crate::hmac::HMAC0_BASE),
             usb: lowrisc::usbdev::Usb::new(crate::usbdev::USB0_BASE),
-            uart0: lowrisc::uart::Uart::new(crate::uart::UART0_BASE, CONFIG.peripheral_freq),
+            uart0: lowrisc::uart::Uart::new(crate::uart::UART0_BASE, CONFIG.uart_freq),
             otbn: lowrisc::otbn::Otbn::new(crate::otbn::OTBN_BASE),
-            gpio_port: crate::gpio::Port::new(),
-            i2c0: lowrisc::i2c::I2c::new(
-                crate::i2c::I2C0_BASE,
-                (1 / CONFIG.cpu_freq) * 1000 * 1000,
-            ),
+            gpio_port: crate::gpio::Port::new(deferred_caller),
+            i2c0: lowrisc::i2c::I2c::new(crate::i2c::I2C0_BASE, 1_000_000_000 / CONFIG.i2c_freq),
             spi_host0: lowrisc::spi_host::SpiHost::new(
                 crate::spi_host::SPIHOST0_BASE,
-                CONFIG.cpu_freq,
+                CONFIG.spi_host_freq,
+                deferred_caller,
             ),
             spi_host1: lowrisc::spi_host::SpiHost::new(
                 crate::spi_host::SPIHOST1_BASE,
-                CONFIG.cpu_freq,
+                CONFIG.spi_host_freq,
+                deferred_caller,
             ),
             flash_ctrl: lowrisc::flash_ctrl::FlashCtrl::new(
                 crate::flash_ctrl::FLASH_CTRL_BASE,
@@ -68,6 +75,26 @@ impl<'a> EarlGreyDefaultPeripherals<'a> {
     }
 }
 
+impl<'a> PeripheralDump for EarlGreyDefaultPeripherals<'a> {
+    fn dump_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!("\r\n---| Peripheral State |---"));
+        let _ = writer.write_fmt(format_args!("\r\nuart0:     "));
+        self.uart0.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\nspi_host0: "));
+        self.spi_host0.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\nspi_host1: "));
+        self.spi_host1.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\ni2c0:      "));
+        self.i2c0.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\nflash:     "));
+        self.flash_ctrl.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\ncsrng:     "));
+        self.rng.dump_state(writer);
+        let _ = writer.write_fmt(format_args!("\r\nhmac:      "));
+        self.hmac.dump_state(writer);
+    }
+}
+
 impl<'a> InterruptService<()> for EarlGreyDefaultPeripherals<'a> {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         match interrupt {
@@ -75,8 +102,8 @@ impl<'a> InterruptService<()> for EarlGreyDefaultPeripherals<'a> {
                 self.uart0.handle_interrupt();
             }
             int_pin @ interrupts::GPIO_PIN0..=interrupts::GPIO_PIN31 => {
-                let pin = &self.gpio_port[(int_pin - interrupts::GPIO_PIN0) as usize];
-                pin.handle_interrupt();
+                self.gpio_port
+                    .handle_interrupt((int_pin - interrupts::GPIO_PIN0) as usize);
             }
             interrupts::HMAC_HMACDONE..=interrupts::HMAC_HMACERR => {
                 self.hmac.handle_interrupt();
@@ -119,12 +146,44 @@ impl<'a, I: InterruptService<()> + 'a> EarlGrey<'a, I> {
             userspace_kernel_boundary: SysCall::new(),
             pmp: PMP::new(),
             plic: &PLIC,
-            pwrmgr: lowrisc::pwrmgr::PwrMgr::new(crate::pwrmgr::PWRMGR_BASE),
+            pwrmgr: lowrisc::pwrmgr::PwrMgr::new(
+                crate::pwrmgr::PWRMGR_BASE,
+                crate::pwrmgr::RSTMGR_BASE,
+                crate::pwrmgr::RESET_RETENTION_BASE,
+            ),
             timer,
             plic_interrupt_service,
+            peripheral_dump: OptionalCell::empty(),
         }
     }
 
+    /// Include the state of `peripherals` in the chip state printed on a
+    /// panic. This is usually the same `EarlGreyDefaultPeripherals` passed
+    /// to `new()`.
+    pub fn set_peripheral_dump(&self, peripherals: &'a dyn PeripheralDump) {
+        self.peripheral_dump.set(peripherals);
+    }
+
+    /// Record `reason` and reset the chip, see `PwrMgr::request_reset()`
+    pub fn request_reset(&self, reason: lowrisc::pwrmgr::SwResetReason) -> ! {
+        self.pwrmgr.request_reset(reason)
+    }
+
+    /// Flush `flush` before `request_reset()`, see `PwrMgr::set_reset_flush()`
+    pub fn set_reset_flush(&self, flush: &'static dyn lowrisc::pwrmgr::ResetFlush) {
+        self.pwrmgr.set_reset_flush(flush);
+    }
+
+    /// The cause of the last reset, see `PwrMgr::reset_reason()`
+    pub fn reset_reason(&self) -> lowrisc::pwrmgr::ResetReason {
+        self.pwrmgr.reset_reason()
+    }
+
+    /// Notify `client` after each low power exit, see `PwrMgr::notify_wakeup()`
+    pub fn set_wakeup_client(&self, client: &'static dyn lowrisc::pwrmgr::WakeupClient) {
+        self.pwrmgr.set_wakeup_client(client);
+    }
+
     pub unsafe fn enable_plic_interrupts(&self) {
         self.plic.disable_all();
         self.plic.enable_all();
@@ -135,10 +194,14 @@ impl<'a, I: InterruptService<()> + 'a> EarlGrey<'a, I> {
             match interrupt {
                 interrupts::PWRMGRAONWAKEUP => {
                     self.pwrmgr.handle_interrupt();
-                    self.check_until_true_or_interrupt(
+                    // If another interrupt arrives first the wakeup interrupt
+                    // is still pending, so we come back here afterwards.
+                    if self.check_until_true_or_interrupt(
                         || self.pwrmgr.check_clock_propagation(),
                         None,
-                    );
+                    ) {
+                        self.pwrmgr.notify_wakeup();
+                    }
                 }
                 interrupts::RVTIMERTIMEREXPIRED0_0 => self.timer.service_interrupt(),
                 _ => {
@@ -251,9 +314,31 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for EarlGrey
     }
 
     fn sleep(&self) {
+        // The kernel checks this before calling us, but sleeping with work
+        // pending would stall it until some unrelated interrupt arrives, so
+        // don't rely on every caller getting that right.
+        if self.has_pending_interrupts()
+            || unsafe { DynamicDeferredCall::global_instance_calls_pending() }.unwrap_or(false)
+        {
+            return;
+        }
+
         unsafe {
             self.pwrmgr.enable_low_power();
-            self.check_until_true_or_interrupt(|| self.pwrmgr.check_clock_propagation(), None);
+
+            // An interrupt arrived while the low power request propagated,
+            // return so it gets serviced.
+            if !self.check_until_true_or_interrupt(|| self.pwrmgr.check_clock_propagation(), None) {
+                return;
+            }
+
+            // We are called from within `atomic()`, so MIE is clear and an
+            // interrupt that fires from here on stays pending in `mip`,
+            // which makes the `wfi` below return straight away instead of
+            // the interrupt being lost. The fence makes sure the pwrmgr
+            // writes above have reached the device before the `wfi` that
+            // triggers low power entry.
+            core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
             rv32i::support::wfi();
         }
     }
@@ -272,6 +357,12 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for EarlGrey
         ));
         rv32i::print_riscv_state(writer);
         let _ = writer.write_fmt(format_args!("{}", self.pmp));
+
+        let _ = writer.write_fmt(format_args!("\r\n---| PLIC State |---"));
+        self.plic.dump_state(writer);
+        self.peripheral_dump
+            .map(|peripherals| peripherals.dump_state(writer));
+        let _ = writer.write_fmt(format_args!("\r\n"));
     }
 }
 
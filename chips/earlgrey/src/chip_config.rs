@@ -17,8 +17,18 @@ pub struct Config<'a> {
     pub name: &'a str,
     /// The clock speed of the CPU in Hz.
     pub cpu_freq: u32,
-    /// The clock speed of the peripherals in Hz.
+    /// The clock speed of the peripherals in Hz. Peripherals without their
+    /// own entry below run from this clock.
     pub peripheral_freq: u32,
+    /// The clock speed of the SPI hosts in Hz, which their `CLKDIV` divides
+    /// down to the SCK rate.
+    pub spi_host_freq: u32,
+    /// The clock speed of the I2C host in Hz, which its timing parameters
+    /// are counted in.
+    pub i2c_freq: u32,
+    /// The clock speed of the UART in Hz, which its `NCO` divides down to the
+    /// baud rate.
+    pub uart_freq: u32,
     /// The baud rate for UART. This allows for a version of the chip that can
     /// support a faster baud rate to use it to help with debugging.
     pub uart_baudrate: u32,
@@ -30,6 +40,9 @@ pub const CONFIG: Config = Config {
     name: "fpga_cw310",
     cpu_freq: 10_000_000,
     peripheral_freq: 2_500_000,
+    spi_host_freq: 10_000_000,
+    i2c_freq: 2_500_000,
+    uart_freq: 2_500_000,
     uart_baudrate: 115200,
 };
 
@@ -39,5 +52,8 @@ pub const CONFIG: Config = Config {
     name: "sim_verilator",
     cpu_freq: 500_000,
     peripheral_freq: 125_000,
+    spi_host_freq: 500_000,
+    i2c_freq: 125_000,
+    uart_freq: 125_000,
     uart_baudrate: 7200,
 };
@@ -2,58 +2,169 @@
 
 use core::ops::{Index, IndexMut};
 
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::gpio::{self, InterruptEdge};
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::StaticRef;
-use lowrisc::gpio::GpioRegisters;
+use kernel::ErrorCode;
 pub use lowrisc::gpio::{pins, GpioPin};
-use lowrisc::padctrl::PadCtrlRegisters;
+use lowrisc::gpio::{GpioPort, GpioRegisters, GpioWakeup};
+use lowrisc::padctrl::{PadCtrlRegisters, PinmuxRegisters};
+use lowrisc::pwrmgr::PwrMgr;
 
 pub const PADCTRL_BASE: StaticRef<PadCtrlRegisters> =
     unsafe { StaticRef::new(0x4047_0000 as *const PadCtrlRegisters) };
 
+pub const PINMUX_BASE: StaticRef<PinmuxRegisters> =
+    unsafe { StaticRef::new(0x4046_0000 as *const PinmuxRegisters) };
+
+/// The pinmux's wakeup source number in the pwrmgr
+const PINMUX_WAKEUP_SOURCE: usize = 2;
+
 pub const GPIO0_BASE: StaticRef<GpioRegisters> =
     unsafe { StaticRef::new(0x4004_0000 as *const GpioRegisters) };
 
+/// Number of pins implemented by the GPIO block
+pub const NUM_PINS: usize = 32;
+
+/// The GPIO pins. Every pin has its own interrupt (and PLIC source) and its
+/// own client, so different capsules can each handle the edges of their own
+/// pins. An interrupt on a pin without a client is cleared and ignored.
+///
+/// A pin's client can instead be called from a deferred call, see
+/// `set_deferred()`, so the ISR only acknowledges the interrupt. Every pin
+/// that fires before the deferred call runs has its client called once.
 pub struct Port<'a> {
-    pins: [GpioPin<'a>; 32],
+    pins: [GpioPin<'a>; NUM_PINS],
+    wakeup: GpioWakeup,
+    deferred_caller: &'static DynamicDeferredCall,
+    deferred_handle: OptionalCell<DeferredCallHandle>,
 }
 
 impl<'a> Port<'a> {
-    pub const fn new() -> Self {
+    pub const fn new(deferred_caller: &'static DynamicDeferredCall) -> Self {
         Self {
             pins: [
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin0),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin1),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin2),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin3),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin4),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin5),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin6),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin7),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin8),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin9),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin10),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin11),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin12),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin13),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin14),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin15),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin16),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin17),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin18),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin19),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin20),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin21),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin22),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin23),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin24),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin25),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin26),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin27),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin28),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin29),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin30),
-                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, pins::pin31),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin0),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin1),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin2),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin3),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin4),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin5),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin6),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin7),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin8),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin9),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin10),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin11),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin12),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin13),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin14),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin15),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin16),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin17),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin18),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin19),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin20),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin21),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin22),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin23),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin24),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin25),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin26),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin27),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin28),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin29),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin30),
+                GpioPin::new(GPIO0_BASE, PADCTRL_BASE, PINMUX_BASE, pins::pin31),
             ],
+            wakeup: GpioWakeup::new(
+                PINMUX_BASE,
+                PwrMgr::new(
+                    crate::pwrmgr::PWRMGR_BASE,
+                    crate::pwrmgr::RSTMGR_BASE,
+                    crate::pwrmgr::RESET_RETENTION_BASE,
+                ),
+                PINMUX_WAKEUP_SOURCE,
+            ),
+            deferred_caller,
+            deferred_handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Give the port the deferred call used by pins set with
+    /// `set_deferred()`
+    pub fn initialise_deferred_call(&self, deferred_call_handle: DeferredCallHandle) {
+        self.deferred_handle.set(deferred_call_handle);
+    }
+
+    /// Call the client of `pin` from a deferred call rather than from the
+    /// interrupt handler if `defer`, otherwise go back to calling it
+    /// directly. An interrupt that is already pending is still delivered.
+    ///
+    /// Returns `INVAL` if `pin` isn't less than `NUM_PINS` and `OFF` if
+    /// `defer` is set before `initialise_deferred_call()`.
+    pub fn set_deferred(&self, pin: usize, defer: bool) -> Result<(), ErrorCode> {
+        let pin = self.pins.get(pin).ok_or(ErrorCode::INVAL)?;
+        if defer && self.deferred_handle.is_none() {
+            return Err(ErrorCode::OFF);
+        }
+        pin.set_deferred(defer);
+        Ok(())
+    }
+
+    /// Handle the interrupt of `pin`, scheduling the deferred call if its
+    /// client is deferred
+    pub fn handle_interrupt(&self, pin: usize) {
+        if let Some(pin) = self.pins.get(pin) {
+            if pin.handle_interrupt() {
+                self.deferred_handle
+                    .map(|handle| self.deferred_caller.set(*handle));
+            }
+        }
+    }
+
+    /// Set the client called for interrupts on `pin`, replacing any previous
+    /// one. This is the same as calling `set_client()` on the pin itself,
+    /// but checks the index.
+    ///
+    /// Returns `INVAL` if `pin` isn't less than `NUM_PINS`.
+    pub fn set_client(&self, pin: usize, client: &'a dyn gpio::Client) -> Result<(), ErrorCode> {
+        let pin = self.pins.get(pin).ok_or(ErrorCode::INVAL)?;
+        gpio::Interrupt::set_client(pin, client);
+        Ok(())
+    }
+
+    /// Arm `pin` to wake the chip from low power on `edge`.
+    ///
+    /// On EarlGrey only GPIOs the pinmux routes from one of the MIO pads can
+    /// wake the chip, and at most 8 can be armed at once. See `GpioWakeup`.
+    pub fn set_wakeup(&self, pin: usize, edge: InterruptEdge) -> Result<(), ErrorCode> {
+        self.wakeup.set_wakeup(pin, edge)
+    }
+
+    /// Stop `pin` from waking the chip
+    pub fn clear_wakeup(&self, pin: usize) -> Result<(), ErrorCode> {
+        self.wakeup.clear_wakeup(pin)
+    }
+
+    /// The pin that caused the last wakeup, if any
+    pub fn wakeup_pin(&self) -> Option<usize> {
+        self.wakeup.wakeup_pin()
+    }
+
+    /// Clear the recorded wakeup pin
+    pub fn clear_wakeup_cause(&self) {
+        self.wakeup.clear_wakeup_cause()
+    }
+}
+
+impl DynamicDeferredCallClient for Port<'_> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        for pin in self.pins.iter() {
+            pin.service_deferred();
         }
     }
 }
@@ -71,3 +182,55 @@ impl<'a> IndexMut<usize> for Port<'a> {
         &mut self.pins[index]
     }
 }
+
+/// A contiguous range of pins used together as a parallel bus, for example
+/// to bit-bang the data lines of an LCD or camera interface.
+///
+/// Bit 0 of a value is the first pin of the bus. Every pin of the bus is
+/// written or read with a single access through the masked registers, two
+/// if the bus spans pins 15 and 16. See `GpioPort` for the pinmux routing
+/// the pins need first.
+pub struct GpioBus {
+    port: GpioPort,
+    first: usize,
+    mask: u32,
+}
+
+impl GpioBus {
+    /// A bus of `width` pins starting at pin `first`.
+    ///
+    /// Returns `INVAL` if `width` is zero or the range runs past the last
+    /// pin.
+    pub fn new(first: usize, width: usize) -> Result<GpioBus, ErrorCode> {
+        if width == 0 || first >= NUM_PINS || width > NUM_PINS - first {
+            return Err(ErrorCode::INVAL);
+        }
+
+        Ok(GpioBus {
+            port: GpioPort::new(GPIO0_BASE),
+            first,
+            mask: (u32::MAX >> (32 - width)) << first,
+        })
+    }
+
+    /// The number of pins in the bus
+    pub fn width(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+
+    /// Drive the bus to `value`, bits above the bus width are ignored
+    pub fn write(&self, value: u32) {
+        self.port.write_masked(self.mask, value << self.first);
+    }
+
+    /// The value on the bus
+    pub fn read(&self) -> u32 {
+        (self.port.read_all() & self.mask) >> self.first
+    }
+
+    /// Make every pin of the bus an output if `out`, otherwise an input
+    pub fn set_direction(&self, out: bool) {
+        self.port
+            .set_output_enable_masked(self.mask, if out { self.mask } else { 0 });
+    }
+}
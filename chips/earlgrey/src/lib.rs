@@ -15,6 +15,7 @@ pub mod flash_ctrl;
 pub mod gpio;
 pub mod hmac;
 pub mod i2c;
+pub mod memory_map;
 pub mod otbn;
 pub mod plic;
 pub mod pwrmgr;
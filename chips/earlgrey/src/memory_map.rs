@@ -0,0 +1,107 @@
+//! The MMIO regions used by the EarlGrey peripheral drivers
+//!
+//! The `*_BASE` addresses are transcribed by hand from the OpenTitan memory
+//! map, and the size of each region is the size of its register block (the
+//! `@END` offset of its `register_structs!`). `peripheral_regions()` lists
+//! them all so they can be checked against each other, two regions that
+//! overlap means one of the addresses is wrong.
+
+use core::mem::size_of;
+use kernel::utilities::StaticRef;
+
+/// A peripheral's register block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeripheralRegion {
+    pub name: &'static str,
+    pub start: usize,
+    pub size: usize,
+}
+
+impl PeripheralRegion {
+    fn new<T: 'static>(name: &'static str, base: StaticRef<T>) -> Self {
+        PeripheralRegion {
+            name,
+            start: &*base as *const T as usize,
+            size: size_of::<T>(),
+        }
+    }
+
+    /// One past the last byte of the region
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    /// True if any byte is part of both `self` and `other`
+    pub fn overlaps(&self, other: &PeripheralRegion) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+}
+
+pub const NUM_PERIPHERAL_REGIONS: usize = 18;
+
+/// Every register block a driver in this crate accesses
+pub fn peripheral_regions() -> [PeripheralRegion; NUM_PERIPHERAL_REGIONS] {
+    [
+        PeripheralRegion::new("uart0", crate::uart::UART0_BASE),
+        PeripheralRegion::new("gpio0", crate::gpio::GPIO0_BASE),
+        PeripheralRegion::new("i2c0", crate::i2c::I2C0_BASE),
+        PeripheralRegion::new("timer", crate::timer::TIMER_BASE),
+        PeripheralRegion::new("usbdev", crate::usbdev::USB0_BASE),
+        PeripheralRegion::new("spi_host0", crate::spi_host::SPIHOST0_BASE),
+        PeripheralRegion::new("spi_host1", crate::spi_host::SPIHOST1_BASE),
+        PeripheralRegion::new("pwrmgr", crate::pwrmgr::PWRMGR_BASE),
+        PeripheralRegion::new("rstmgr", crate::pwrmgr::RSTMGR_BASE),
+        PeripheralRegion::new("pinmux", crate::gpio::PINMUX_BASE),
+        PeripheralRegion::new("padctrl", crate::gpio::PADCTRL_BASE),
+        PeripheralRegion::new("ret_sram", crate::pwrmgr::RESET_RETENTION_BASE),
+        PeripheralRegion::new("flash_ctrl", crate::flash_ctrl::FLASH_CTRL_BASE),
+        PeripheralRegion::new("aes", crate::aes::AES_BASE),
+        PeripheralRegion::new("hmac", crate::hmac::HMAC0_BASE),
+        PeripheralRegion::new("otbn", crate::otbn::OTBN_BASE),
+        PeripheralRegion::new("csrng", crate::csrng::CSRNG_BASE),
+        PeripheralRegion::new("plic", crate::plic::PLIC_BASE),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::peripheral_regions;
+
+    /// Every peripheral on EarlGrey sits between 0x4000_0000 and 0x5000_0000
+    const MMIO_START: usize = 0x4000_0000;
+    const MMIO_END: usize = 0x5000_0000;
+
+    #[test]
+    fn regions_in_mmio_space() {
+        for r in peripheral_regions().iter() {
+            assert!(r.size > 0, "{} is empty", r.name);
+            assert!(
+                r.start >= MMIO_START && r.end() <= MMIO_END,
+                "{} [{:#x}, {:#x}) is outside the peripheral address space",
+                r.name,
+                r.start,
+                r.end()
+            );
+        }
+    }
+
+    #[test]
+    fn regions_disjoint() {
+        let regions = peripheral_regions();
+        for (i, a) in regions.iter().enumerate() {
+            for b in regions[i + 1..].iter() {
+                assert_ne!(a.name, b.name);
+                assert!(
+                    !a.overlaps(b),
+                    "{} [{:#x}, {:#x}) overlaps {} [{:#x}, {:#x})",
+                    a.name,
+                    a.start,
+                    a.end(),
+                    b.name,
+                    b.start,
+                    b.end()
+                );
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Platform Level Interrupt Control peripheral driver.
 
+use core::fmt::Write;
 use kernel::utilities::cells::VolatileCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::LocalRegisterCopy;
@@ -171,6 +172,22 @@ impl Plic {
         None
     }
 
+    /// Write the pending, enabled and saved interrupts to `writer`, one line
+    /// per register, for the panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        for i in 0..PLIC_REGS {
+            let _ = writer.write_fmt(format_args!(
+                "\r\n pending[{}]: {:#010X}  enable[{}]: {:#010X}  saved[{}]: {:#010X}",
+                i,
+                self.registers.pending[i].get(),
+                i,
+                self.registers.enable[i].get(),
+                i,
+                self.saved[i].get().get()
+            ));
+        }
+    }
+
     /// Signal that an interrupt is finished being handled. In Tock, this should be
     /// called from the normal main loop (not the interrupt handler).
     /// Interrupts must be disabled before this is called.
@@ -1,5 +1,13 @@
 use kernel::utilities::StaticRef;
-use lowrisc::pwrmgr::PwrMgrRegisters;
+use lowrisc::pwrmgr::{PwrMgrRegisters, ResetRetention, RstMgrRegisters};
 
 pub(crate) const PWRMGR_BASE: StaticRef<PwrMgrRegisters> =
     unsafe { StaticRef::new(0x4040_0000 as *const PwrMgrRegisters) };
+
+pub(crate) const RSTMGR_BASE: StaticRef<RstMgrRegisters> =
+    unsafe { StaticRef::new(0x4041_0000 as *const RstMgrRegisters) };
+
+/// The start of the owner half of the 4 KiB retention SRAM at 0x4060_0000.
+/// The first 2 KiB are used by the ROM and ROM_EXT.
+pub(crate) const RESET_RETENTION_BASE: StaticRef<ResetRetention> =
+    unsafe { StaticRef::new(0x4060_0800 as *const ResetRetention) };
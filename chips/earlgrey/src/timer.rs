@@ -1,7 +1,7 @@
 //! Timer driver.
 
 use crate::chip_config::CONFIG;
-use kernel::hil::time::{self, Ticks64};
+use kernel::hil::time::{self, Frequency, Ticks, Ticks64};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
@@ -20,6 +20,46 @@ impl time::Frequency for Freq10KHz {
     }
 }
 
+/// Convert `ticks` of the mtime counter to microseconds, for a timer set up
+/// by `RvTimer::setup()` on a CPU clocked at `cpu_freq` Hz.
+///
+/// The tick period comes from the integer prescaler rather than the nominal
+/// 10KHz, so it stays accurate for a `cpu_freq` that isn't a multiple of
+/// 10KHz. The result saturates at `u64::MAX` rather than overflowing.
+pub const fn ticks_to_us(ticks: u64, cpu_freq: u32) -> u64 {
+    let cpu_freq = cpu_freq as u64;
+    // CPU cycles per tick, `PRESCALE + 1`
+    let cycles = cpu_freq / 10_000;
+
+    // Split off whole multiples of `cpu_freq` so the intermediate product
+    // of the remainder can't overflow
+    let whole = (ticks / cpu_freq)
+        .saturating_mul(cycles)
+        .saturating_mul(1_000_000);
+    let part = (ticks % cpu_freq) * cycles * 1_000_000 / cpu_freq;
+
+    whole.saturating_add(part)
+}
+
+/// The compare value for an alarm `dt` ticks after `reference`, when the
+/// counter reads `now`.
+///
+/// Everything is done in 64 bits. If the alarm time has already passed,
+/// including `dt` of 0, the result is `now` so the alarm fires straight
+/// away. An alarm time past the end of the counter saturates at
+/// `u64::MAX - 1` (`u64::MAX` is the disarmed value), rather than wrapping
+/// round to a small value that would also fire straight away.
+pub const fn alarm_compare(now: u64, reference: u64, dt: u64) -> u64 {
+    if now.wrapping_sub(reference) >= dt {
+        return now;
+    }
+
+    match reference.checked_add(dt) {
+        Some(expire) if expire < u64::MAX => expire,
+        _ => u64::MAX - 1,
+    }
+}
+
 register_structs! {
     pub TimerRegisters {
         (0x000 => alert_test: WriteOnly<u32>),
@@ -60,6 +100,7 @@ pub struct RvTimer<'a> {
     alarm_client: OptionalCell<&'a dyn time::AlarmClient>,
     overflow_client: OptionalCell<&'a dyn time::OverflowClient>,
     mtimer: MachineTimer<'a>,
+    period: OptionalCell<Ticks64>,
 }
 
 impl<'a> RvTimer<'a> {
@@ -74,6 +115,7 @@ impl<'a> RvTimer<'a> {
                 &TIMER_BASE.value_low,
                 &TIMER_BASE.value_high,
             ),
+            period: OptionalCell::empty(),
         }
     }
 
@@ -88,10 +130,99 @@ impl<'a> RvTimer<'a> {
         regs.ctrl.write(ctrl::enable::SET);
     }
 
+    /// Busy-wait for at least `us` microseconds.
+    ///
+    /// The delay is measured with the free running mtime counter, whose
+    /// prescaler is derived from `CONFIG.cpu_freq`, so the wall-clock time
+    /// is the same on the FPGA and in Verilator. The counter ticks at 10KHz,
+    /// so the delay is rounded up to the next 100us tick, plus one tick to
+    /// cover starting part way through a tick.
+    ///
+    /// This blocks the whole kernel. It is intended for short, timing
+    /// sensitive register sequences during driver bringup, anything longer
+    /// should use an `Alarm`.
+    pub fn delay_us(&self, us: u32) {
+        if us == 0 {
+            return;
+        }
+
+        let freq = Freq10KHz::frequency() as u64;
+        let ticks = (us as u64 * freq + 999_999) / 1_000_000 + 1;
+        let start = self.mtimer.now();
+
+        while self.mtimer.now().wrapping_sub(start).into_u64() < ticks {}
+    }
+
+    /// Microseconds since the timer was set up, see `ticks_to_us()`.
+    ///
+    /// mtime keeps counting while the core sleeps, `PwrMgr::enable_low_power()`
+    /// leaves the IO clock running, so timestamps taken either side of a
+    /// `sleep()` can be compared. A low power exit that resets the chip
+    /// restarts the counter.
+    ///
+    /// The conversion uses `CONFIG.cpu_freq`, so a board built for the FPGA
+    /// config and run in Verilator (or the other way round) gets timestamps
+    /// scaled by the ratio of the two clocks.
+    pub fn timestamp_us(&self) -> u64 {
+        ticks_to_us(self.mtimer.now().into_u64(), CONFIG.cpu_freq)
+    }
+
+    /// Fire the alarm client every `interval` ticks until
+    /// `cancel_periodic()`, `disarm()` or `set_alarm()` is called.
+    ///
+    /// The timer has no auto-reload, so the next compare value is set from
+    /// the interrupt handler before the client is called. Deadlines are
+    /// fixed multiples of `interval` from now, so interrupt latency doesn't
+    /// cause drift. If the handler runs so late that whole periods have been
+    /// missed, they are skipped rather than caught up: the client gets one
+    /// callback and the next deadline is the first one still in the future.
+    ///
+    /// This reprograms the hardware timer directly, so can't be used while
+    /// the timer is shared through a `MuxAlarm`.
+    pub fn set_periodic(&self, interval: Ticks64) {
+        let interval = if interval < self.mtimer.minimum_dt() {
+            self.mtimer.minimum_dt()
+        } else {
+            interval
+        };
+
+        self.period.set(interval);
+        self.registers.intr_enable.write(intr::timer0::SET);
+        self.mtimer.set_alarm(self.mtimer.now(), interval);
+    }
+
+    /// Stop a periodic alarm started by `set_periodic()`.
+    pub fn cancel_periodic(&self) -> Result<(), ErrorCode> {
+        self.period.clear();
+        self.registers.intr_enable.write(intr::timer0::CLEAR);
+        self.mtimer.disarm()
+    }
+
+    /// Move the compare value on to the next period after the one that
+    /// just expired.
+    fn rearm_periodic(&self, interval: Ticks64) {
+        let interval = interval.into_u64();
+        let deadline = self.mtimer.get_alarm().into_u64();
+        let now = self.mtimer.now().into_u64();
+
+        // Skip any periods that have already passed
+        let missed = now.saturating_sub(deadline) / interval;
+        let next = deadline
+            .saturating_add((missed + 1).saturating_mul(interval))
+            .min(u64::MAX - 1);
+
+        self.registers.intr_enable.write(intr::timer0::SET);
+        self.mtimer
+            .set_alarm(Ticks64::from(now), Ticks64::from(next - now));
+    }
+
     pub fn service_interrupt(&self) {
         let regs = self.registers;
         regs.intr_enable.write(intr::timer0::CLEAR);
         regs.intr_state.write(intr::timer0::SET);
+        if let Some(interval) = self.period.extract() {
+            self.rearm_periodic(interval);
+        }
         self.alarm_client.map(|client| {
             client.alarm();
         });
@@ -137,9 +268,15 @@ impl<'a> time::Alarm<'a> for RvTimer<'a> {
     }
 
     fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.period.clear();
         self.registers.intr_enable.write(intr::timer0::SET);
 
-        self.mtimer.set_alarm(reference, dt)
+        // `MachineTimer` would let `reference + dt` wrap, see
+        // `alarm_compare()`
+        let now = self.mtimer.now();
+        let expire = alarm_compare(now.into_u64(), reference.into_u64(), dt.into_u64());
+        self.mtimer
+            .set_alarm(now, Ticks64::from(expire.saturating_sub(now.into_u64())))
     }
 
     fn get_alarm(&self) -> Self::Ticks {
@@ -147,6 +284,7 @@ impl<'a> time::Alarm<'a> for RvTimer<'a> {
     }
 
     fn disarm(&self) -> Result<(), ErrorCode> {
+        self.period.clear();
         self.registers.intr_enable.write(intr::timer0::CLEAR);
 
         self.mtimer.disarm()
@@ -161,5 +299,5 @@ impl<'a> time::Alarm<'a> for RvTimer<'a> {
     }
 }
 
-const TIMER_BASE: StaticRef<TimerRegisters> =
+pub(crate) const TIMER_BASE: StaticRef<TimerRegisters> =
     unsafe { StaticRef::new(0x4010_0000 as *const TimerRegisters) };
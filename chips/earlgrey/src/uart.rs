@@ -1,4 +1,8 @@
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::uart::{self, Receive, ReceiveClient};
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 pub use lowrisc::uart::Uart;
 use lowrisc::uart::UartRegisters;
 
@@ -8,3 +12,74 @@ pub const UART0_BAUDRATE: u32 = CONFIG.uart_baudrate;
 
 pub const UART0_BASE: StaticRef<UartRegisters> =
     unsafe { StaticRef::new(0x4000_0000 as *const UartRegisters) };
+
+/// A receive on a `Uart` that gives up after a timeout.
+///
+/// `receive_with_timeout()` starts a receive and an alarm, whichever finishes
+/// first completes the operation. If the alarm fires first the receive is
+/// aborted and the buffer is returned to the client, holding whatever had
+/// arrived so far, with `uart::Error::Aborted`.
+///
+/// This must be set as both the receive client of the UART and the client of
+/// the alarm.
+pub struct UartReceiveTimeout<'a, A: Alarm<'a>> {
+    uart: &'a Uart<'a>,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn ReceiveClient>,
+}
+
+impl<'a, A: Alarm<'a>> UartReceiveTimeout<'a, A> {
+    pub fn new(uart: &'a Uart<'a>, alarm: &'a A) -> UartReceiveTimeout<'a, A> {
+        UartReceiveTimeout {
+            uart,
+            alarm,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ReceiveClient) {
+        self.client.set(client);
+    }
+
+    /// Receive `rx_len` bytes into `rx_buffer`, giving up after `timeout_ms`
+    pub fn receive_with_timeout(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        timeout_ms: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.alarm.is_armed() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
+
+        self.uart.receive_buffer(rx_buffer, rx_len)?;
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>> ReceiveClient for UartReceiveTimeout<'a, A> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        let _ = self.alarm.disarm();
+
+        self.client.map(move |client| {
+            client.received_buffer(rx_buffer, rx_len, rval, error);
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for UartReceiveTimeout<'a, A> {
+    fn alarm(&self) {
+        // The buffer comes back through `received_buffer()`. If the receive
+        // has already completed there is nothing to abort.
+        let _ = self.uart.receive_abort();
+    }
+}
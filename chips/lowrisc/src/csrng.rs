@@ -1,12 +1,101 @@
 //! Support for the CSRNG hardware block on OpenTitan
 //!
 //! <https://docs.opentitan.org/hw/ip/csrng/doc>
+//!
+//! Software supplied entropy
+//! -------------------------
+//!
+//! By default the DRBG is seeded by the hardware entropy source. For
+//! configurations without one attached (for example Verilator) the
+//! `csrng_seed_source` feature lets a board register a [`SeedSource`] with
+//! `set_seed_source()`. When set, the DRBG is instantiated with the entropy
+//! source bypassed (`flag0`) using the seed material provided by the board.
+//! An `ENTROPY_REQ` interrupt is answered by reseeding from the same source,
+//! but only once the command in flight has completed: the `RESEED` is issued
+//! by the next `get()` or `fill_bytes()`, before its `GENERATE`.
+//!
+//! The output of the CSRNG is then only as unpredictable as the seed the
+//! board provides. A fixed or guessable seed makes every value generated
+//! predictable, so this must never be used on production silicon with a real
+//! TRNG; leave the seed source unset there and the driver behaves exactly as
+//! before.
+//!
+//! Deterministic test seed
+//! -----------------------
+//!
+//! **INSECURE, TEST ONLY.** With the `csrng_test_seed` feature enabled,
+//! `set_test_seed()` instantiates the DRBG from a fixed seed with the entropy
+//! source bypassed and no prediction resistance, so every boot produces the
+//! same sequence of values. This destroys every security guarantee of the
+//! RNG: anyone who knows the seed knows all of its output. It exists only so
+//! tests of entropy consumers can assert exact values. The feature is off by
+//! default and must never be enabled in a production build.
+//!
+//! Instantiation
+//! -------------
+//!
+//! The DRBG is instantiated by the first `get()` and then left instantiated,
+//! so later requests only issue a `GENERATE`. It is instantiated again after
+//! `cancel()`, which uninstantiates it, or after the CSRNG reports an error.
+//!
+//! A client that goes away should call `clear_client()`. That cancels
+//! anything in flight and leaves the DRBG uninstantiated with interrupts
+//! disabled, so the next client doesn't wait on a callback meant for the old
+//! one. A `GENERATE` in flight only finishes once its output has been read,
+//! so the output is dropped while waiting for it.
+//!
+//! Synchronous requests
+//! --------------------
+//!
+//! `fill_bytes()` is for callers that only occasionally need a few random
+//! bytes, such as a nonce, and don't want to handle a callback. It issues the
+//! same commands as `get()` but polls for the output instead of waiting for
+//! an interrupt, and can't be used while an asynchronous request is in
+//! flight.
+//!
+//! Instances
+//! ---------
+//!
+//! The CSRNG runs a separate DRBG instance for each of its application
+//! interfaces. On EarlGrey instances 0 and 1 belong to the hardware
+//! interfaces, connected to EDN0 and EDN1 (EDN0 feeds the key manager), and
+//! instance 2 (`SW_INSTANCE`) is the software interface used by this driver.
+//!
+//! The instance a command acts on is fixed by the interface it arrives on,
+//! there is no instance field in the command, so software can only ever
+//! drive `SW_INSTANCE`. Software requests therefore never contend with the
+//! key manager for a DRBG. The hardware instances can only be observed,
+//! `instance_exception()` reports whether one of them has signalled an
+//! exception.
+//!
+//! Health monitoring
+//! -----------------
+//!
+//! The continuous health tests (repetition count, adaptive proportion, ...)
+//! run in the entropy source, ahead of the CSRNG. Entropy that fails them is
+//! never handed on, and the resulting stall or error shows up in the CSRNG
+//! status registers. `health_status()` reports those registers so a user can
+//! poll them and refuse to use output from a CSRNG that has reported a
+//! problem. The per-test failure counters themselves are only visible through
+//! the entropy source registers.
+//!
+//! Register lock
+//! -------------
+//!
+//! `REGWEN` is cleared by writing zero to it, by an earlier boot stage or by
+//! `lock()`, and once cleared `CTRL` is read only until the next reset. The
+//! CSRNG can't be enabled while it is locked, so `get()` returns `RESERVE`
+//! to tell this configuration problem apart from the `FAIL` reported when
+//! the hardware signals an error.
 
+use core::cell::Cell;
+use core::cmp;
+use core::fmt::Write;
 use kernel::hil::entropy::{Client32, Continue, Entropy32};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
@@ -79,10 +168,76 @@ register_bitfields![u32,
     ],
 ];
 
+/// Snapshot of the CSRNG error status, see `CsRng::health_status()`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HealthStatus {
+    /// `HW_EXC_STS`: one bit per hardware application interface (for
+    /// example an EDN) that has signalled an exception.
+    pub hw_exceptions: u32,
+    /// `ERR_CODE`: fatal FIFO, state machine and counter errors. Any bit set
+    /// means the CSRNG has to be reset before it can be trusted again.
+    pub fatal_errors: u32,
+    /// `SW_CMD_STS.CMD_STS`: the last software command completed with an
+    /// error.
+    pub cmd_error: bool,
+    /// The DRBG is seeded from a `SeedSource`, so the entropy source health
+    /// tests don't cover its output.
+    pub entropy_bypassed: bool,
+}
+
+impl HealthStatus {
+    /// True if no errors have been reported and the DRBG is seeded by the
+    /// hardware entropy source.
+    pub fn is_healthy(&self) -> bool {
+        self.hw_exceptions == 0
+            && self.fatal_errors == 0
+            && !self.cmd_error
+            && !self.entropy_bypassed
+    }
+}
+
+/// Number of DRBG instances, one per application interface
+pub const NUM_INSTANCES: usize = 3;
+
+/// The instance used by the software interface, see the module documentation
+pub const SW_INSTANCE: usize = NUM_INSTANCES - 1;
+
+/// Number of 32-bit words of seed material supplied to an `INSTANTIATE` or
+/// `RESEED` command when the entropy source is bypassed.
+pub const SEED_WORDS: usize = 12;
+
+/// Number of status reads `fill_bytes()` waits for each output word, and
+/// the other synchronous paths wait for the CSRNG to be ready for a command
+pub const SYNC_POLL_LIMIT: usize = 1_000_000;
+
+/// Bytes produced per `GLEN` unit of a `GENERATE` command
+const GENERATE_BLOCK_BYTES: usize = 16;
+
+/// Provider of seed material used in place of the hardware entropy source.
+///
+/// See the module documentation for the security implications.
+#[cfg(feature = "csrng_seed_source")]
+pub trait SeedSource {
+    /// Fill `seed` with fresh seed material.
+    fn fill_seed(&self, seed: &mut [u32; SEED_WORDS]);
+}
+
 pub struct CsRng<'a> {
     registers: StaticRef<CsRngRegisters>,
 
     client: OptionalCell<&'a dyn Client32>,
+    #[cfg(feature = "csrng_seed_source")]
+    seed_source: OptionalCell<&'a dyn SeedSource>,
+    /// An `ENTROPY_REQ` is waiting for the next request to reseed
+    #[cfg(feature = "csrng_seed_source")]
+    reseed_pending: Cell<bool>,
+
+    instantiated: Cell<bool>,
+    #[cfg(feature = "csrng_instantiate_count")]
+    instantiate_count: Cell<usize>,
+
+    #[cfg(feature = "csrng_test_seed")]
+    test_seed: OptionalCell<[u32; SEED_WORDS]>,
 }
 
 struct CsRngIter<'a, 'b: 'a>(&'a CsRng<'b>);
@@ -104,18 +259,331 @@ impl<'a> CsRng<'a> {
         CsRng {
             registers: base,
             client: OptionalCell::empty(),
+            #[cfg(feature = "csrng_seed_source")]
+            seed_source: OptionalCell::empty(),
+            #[cfg(feature = "csrng_seed_source")]
+            reseed_pending: Cell::new(false),
+            instantiated: Cell::new(false),
+            #[cfg(feature = "csrng_instantiate_count")]
+            instantiate_count: Cell::new(0),
+            #[cfg(feature = "csrng_test_seed")]
+            test_seed: OptionalCell::empty(),
+        }
+    }
+
+    /// **INSECURE, TEST ONLY.** Make the output repeatable by instantiating
+    /// the DRBG from `seed`, zero padded to `SEED_WORDS`, instead of from the
+    /// entropy source. The DRBG is uninstantiated now and instantiated from
+    /// `seed` by the next `get()`, so the same seed always gives the same
+    /// sequence. See the module documentation.
+    ///
+    /// Returns `SIZE` if `seed` is longer than `SEED_WORDS` and `FAIL` if
+    /// the `UNINSTANTIATE` didn't complete within `SYNC_POLL_LIMIT` status
+    /// reads. The seed is still used by the next instantiation.
+    #[cfg(feature = "csrng_test_seed")]
+    pub fn set_test_seed(&self, seed: &[u32]) -> Result<(), ErrorCode> {
+        if seed.len() > SEED_WORDS {
+            return Err(ErrorCode::SIZE);
+        }
+
+        let mut test_seed = [0; SEED_WORDS];
+        test_seed[..seed.len()].copy_from_slice(seed);
+        self.test_seed.set(test_seed);
+
+        if self.instantiated.get() {
+            self.registers.cmd_req.write(COMMAND::ACMD::UNINSTANTIATE);
+            self.instantiated.set(false);
+            // The next request issues its INSTANTIATE straight away
+            self.wait_cmd_rdy()?;
+        }
+
+        Ok(())
+    }
+
+    /// Go back to seeding from the entropy source (or the seed source). Takes
+    /// effect when the DRBG is next instantiated, after a `cancel()`.
+    #[cfg(feature = "csrng_test_seed")]
+    pub fn clear_test_seed(&self) {
+        self.test_seed.clear();
+    }
+
+    /// True if the DRBG is seeded by software rather than the entropy source
+    fn entropy_bypassed(&self) -> bool {
+        #[cfg(feature = "csrng_test_seed")]
+        if self.test_seed.is_some() {
+            return true;
+        }
+
+        #[cfg(feature = "csrng_seed_source")]
+        if self.seed_source.is_some() {
+            return true;
+        }
+
+        false
+    }
+
+    /// The seed material to use in place of the entropy source, if any
+    fn software_seed(&self) -> Option<[u32; SEED_WORDS]> {
+        #[cfg(feature = "csrng_test_seed")]
+        if let Some(seed) = self.test_seed.extract() {
+            return Some(seed);
+        }
+
+        #[cfg(feature = "csrng_seed_source")]
+        if let Some(seed) = self.seed_source.map(|source| {
+            let mut seed = [0; SEED_WORDS];
+            source.fill_seed(&mut seed);
+            seed
+        }) {
+            return Some(seed);
+        }
+
+        None
+    }
+
+    /// Seed the DRBG from `source` instead of the hardware entropy source.
+    ///
+    /// This is only intended for configurations without a hardware entropy
+    /// source, see the module documentation.
+    #[cfg(feature = "csrng_seed_source")]
+    pub fn set_seed_source(&self, source: &'a dyn SeedSource) {
+        self.seed_source.set(source);
+    }
+
+    /// Read the error status of the CSRNG. This doesn't change any state, so
+    /// can be polled while requests are in flight.
+    pub fn health_status(&self) -> HealthStatus {
+        HealthStatus {
+            hw_exceptions: self.registers.hw_exc_sts.get(),
+            fatal_errors: self.registers.err_code.get(),
+            cmd_error: self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_STS),
+            entropy_bypassed: self.entropy_bypassed(),
         }
     }
 
+    /// True if hardware `instance` has signalled an exception, such as being
+    /// sent a command it can't execute. The bits stay set until reset.
+    ///
+    /// Returns `INVAL` if `instance` isn't one of the hardware instances, the
+    /// software instance reports errors through `health_status()` instead.
+    pub fn instance_exception(&self, instance: usize) -> Result<bool, ErrorCode> {
+        if instance >= SW_INSTANCE {
+            return Err(ErrorCode::INVAL);
+        }
+
+        Ok(self.registers.hw_exc_sts.get() & (1 << instance) != 0)
+    }
+
+    /// True if `REGWEN` has been cleared, so `CTRL` can't be written until
+    /// the next reset.
+    pub fn is_locked(&self) -> bool {
+        !self.registers.regwen.is_set(REGWEN::REGWEN)
+    }
+
+    /// Clear `REGWEN`, making `CTRL` read only until the next reset. Requests
+    /// after this return `RESERVE`, see the module documentation.
+    pub fn lock(&self) {
+        self.registers.regwen.write(REGWEN::REGWEN::CLEAR);
+    }
+
+    /// Unregister the client, for when it goes away. Any request in flight
+    /// is cancelled, the DRBG is uninstantiated and interrupts are disabled,
+    /// so the next client starts from a clean CSRNG.
+    ///
+    /// Returns `FAIL` if the CSRNG didn't become ready for the next command
+    /// within `SYNC_POLL_LIMIT` status reads, either for the request in
+    /// flight to finish or for the `UNINSTANTIATE`. The client is cleared
+    /// and the DRBG instantiated again by the next request either way.
+    pub fn clear_client(&self) -> Result<(), ErrorCode> {
+        self.client.clear();
+        self.disable_interrupts();
+
+        // Drop anything generated for the old client, so a GENERATE in
+        // flight can finish
+        let ret = self.drain_until_ready().and_then(|()| {
+            let _ = self.cancel();
+            self.drain_until_ready()
+        });
+        self.instantiated.set(false);
+        ret
+    }
+
+    /// Read and drop any generated output until the CSRNG is ready for a
+    /// command, for at most `SYNC_POLL_LIMIT` status reads. Returns `FAIL`
+    /// if it still isn't ready.
+    fn drain_until_ready(&self) -> Result<(), ErrorCode> {
+        let ready = (0..SYNC_POLL_LIMIT).any(|_| {
+            CsRngIter(self).for_each(drop);
+            self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_RDY)
+        });
+
+        if ready {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    /// Fill `out` with random bytes, instantiating the DRBG first if needed
+    /// and polling for the output. See the module documentation.
+    ///
+    /// The output is generated in 128-bit blocks, any bytes of the last
+    /// block beyond `out.len()` are discarded. Each word is waited for for at
+    /// most `SYNC_POLL_LIMIT` status reads, so this blocks for at most
+    /// `out.len()` rounded up to 16 bytes, divided by 4, times
+    /// `SYNC_POLL_LIMIT` reads, plus the time taken to instantiate the DRBG
+    /// on first use. Normally output is ready within a few hundred cycles.
+    ///
+    /// Returns `RESERVE` if `REGWEN` is locked, `BUSY` while an asynchronous
+    /// request is in flight, `SIZE` if `out` is too long for one `GENERATE`
+    /// and `FAIL` if the output didn't arrive in time or the CSRNG reported
+    /// an error. `out` may have been partly written on error.
+    pub fn fill_bytes(&self, out: &mut [u8]) -> Result<(), ErrorCode> {
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        if self.is_locked() {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        if self.registers.intr_enable.is_set(INTR::CMD_REQ_DONE)
+            || !self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_RDY)
+        {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Drop anything left over from a request that timed out
+        CsRngIter(self).for_each(drop);
+
+        self.prepare()?;
+
+        let blocks = (out.len() + GENERATE_BLOCK_BYTES - 1) / GENERATE_BLOCK_BYTES;
+        if blocks > COMMAND::GLEN.mask as usize {
+            return Err(ErrorCode::SIZE);
+        }
+        self.registers.cmd_req.write(
+            COMMAND::ACMD::GENERATE + COMMAND::FLAGS.val(0) + COMMAND::GLEN.val(blocks as u32),
+        );
+
+        // Every word has to be read for the command to finish
+        for i in 0..blocks * GENERATE_BLOCK_BYTES / 4 {
+            let valid = (0..SYNC_POLL_LIMIT)
+                .any(|_| self.registers.genbits_vld.is_set(GENBIT_VLD::GENBITS_VLD));
+            if !valid {
+                // Give up on the command, the next request instantiates again
+                let _ = self.cancel();
+                return Err(ErrorCode::FAIL);
+            }
+
+            let word = self.registers.genbits.get().to_le_bytes();
+            let start = cmp::min(i * 4, out.len());
+            let end = cmp::min(start + 4, out.len());
+            out[start..end].copy_from_slice(&word[..end - start]);
+        }
+
+        if self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_STS) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        Ok(())
+    }
+
+    /// Instantiate the DRBG if it isn't, otherwise issue a reseed the CSRNG
+    /// asked for since the last request. Returns `FAIL` if the command
+    /// didn't complete, see `seed_command()`.
+    fn prepare(&self) -> Result<(), ErrorCode> {
+        if !self.instantiated.get() {
+            return self.instantiate();
+        }
+
+        #[cfg(feature = "csrng_seed_source")]
+        if self.reseed_pending.replace(false) {
+            return self.seed_command(COMMAND::ACMD::RESEED);
+        }
+
+        Ok(())
+    }
+
+    /// Enable the CSRNG and instantiate the DRBG
+    fn instantiate(&self) -> Result<(), ErrorCode> {
+        self.registers.ctrl.write(
+            CTRL::ENABLE::ENABLE + CTRL::READ_INT_STATE::ENABLE + CTRL::SW_APP_ENABLE::ENABLE,
+        );
+
+        // A fresh instantiation covers any reseed that was asked for
+        #[cfg(feature = "csrng_seed_source")]
+        self.reseed_pending.set(false);
+
+        self.seed_command(COMMAND::ACMD::INSTANTIATE)?;
+        self.instantiated.set(true);
+        #[cfg(feature = "csrng_instantiate_count")]
+        self.instantiate_count.set(self.instantiate_count.get() + 1);
+        Ok(())
+    }
+
+    /// The number of times the DRBG has been instantiated since boot, for
+    /// tests.
+    #[cfg(feature = "csrng_instantiate_count")]
+    pub fn instantiate_count(&self) -> usize {
+        self.instantiate_count.get()
+    }
+
     fn enable_interrupts(&self) {
+        #[cfg(feature = "csrng_seed_source")]
+        let entropy_req = if self.seed_source.is_some() {
+            INTR::ENTROPY_REQ::SET
+        } else {
+            INTR::ENTROPY_REQ::CLEAR
+        };
+        #[cfg(not(feature = "csrng_seed_source"))]
+        let entropy_req = INTR::ENTROPY_REQ::CLEAR;
+
         self.registers.intr_enable.write(
-            INTR::CMD_REQ_DONE::SET
-                + INTR::ENTROPY_REQ::CLEAR
-                + INTR::HW_INST_EXC::SET
-                + INTR::FATAL_ERR::SET,
+            INTR::CMD_REQ_DONE::SET + entropy_req + INTR::HW_INST_EXC::SET + INTR::FATAL_ERR::SET,
         );
     }
 
+    /// Issue an `INSTANTIATE` or `RESEED` command and wait for it to
+    /// complete.
+    ///
+    /// If a seed source or test seed is set the entropy source is bypassed
+    /// and the seed material is written as additional data after the command
+    /// header.
+    ///
+    /// Returns `FAIL` if the CSRNG isn't ready for another command within
+    /// `SYNC_POLL_LIMIT` status reads.
+    fn seed_command(&self, acmd: FieldValue<u32, COMMAND::Register>) -> Result<(), ErrorCode> {
+        match self.software_seed() {
+            Some(seed) => {
+                self.registers.cmd_req.write(
+                    acmd + COMMAND::FLAGS.val(1)
+                        + COMMAND::CLEN.val(SEED_WORDS as u32)
+                        + COMMAND::GLEN.val(0),
+                );
+                for word in seed.iter() {
+                    self.registers.cmd_req.set(*word);
+                }
+            }
+            None => {
+                self.registers.cmd_req.write(
+                    acmd + COMMAND::FLAGS.val(0) + COMMAND::CLEN.val(0) + COMMAND::GLEN.val(0),
+                );
+            }
+        }
+        self.wait_cmd_rdy()
+    }
+
+    /// Wait for at most `SYNC_POLL_LIMIT` status reads for the CSRNG to be
+    /// ready for a command. Returns `FAIL` if it isn't.
+    fn wait_cmd_rdy(&self) -> Result<(), ErrorCode> {
+        if (0..SYNC_POLL_LIMIT).any(|_| self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_RDY)) {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
     fn disable_interrupts(&self) {
         self.registers.intr_state.write(
             INTR::CMD_REQ_DONE::SET
@@ -132,11 +600,24 @@ impl<'a> CsRng<'a> {
         );
     }
 
+    /// Write the command status, error and interrupt registers to `writer`,
+    /// for the panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " sw_cmd_sts: {:#010X}  err_code: {:#010X}  intr_state: {:#010X}",
+            regs.sw_cmd_sts.get(),
+            regs.err_code.get(),
+            regs.intr_state.get()
+        ));
+    }
+
     pub fn handle_interrupt(&self) {
         let irqs = self.registers.intr_state.extract();
         self.disable_interrupts();
 
         if irqs.is_set(INTR::HW_INST_EXC) {
+            self.instantiated.set(false);
             self.client.map(move |client| {
                 client.entropy_available(&mut (0..0), Err(ErrorCode::FAIL));
             });
@@ -144,12 +625,26 @@ impl<'a> CsRng<'a> {
         }
 
         if irqs.is_set(INTR::FATAL_ERR) {
+            self.instantiated.set(false);
             self.client.map(move |client| {
                 client.entropy_available(&mut (0..0), Err(ErrorCode::FAIL));
             });
             return;
         }
 
+        // Another command can't be issued until the one in flight has
+        // completed, leave the reseed to the next request
+        #[cfg(feature = "csrng_seed_source")]
+        if irqs.is_set(INTR::ENTROPY_REQ) && self.seed_source.is_some() {
+            self.reseed_pending.set(true);
+
+            if !irqs.is_set(INTR::CMD_REQ_DONE) {
+                // Still waiting on the outstanding request
+                self.enable_interrupts();
+                return;
+            }
+        }
+
         if irqs.is_set(INTR::CMD_REQ_DONE) {
             if self
                 .client
@@ -175,22 +670,13 @@ impl<'a> Entropy32<'a> for CsRng<'a> {
     fn get(&self) -> Result<(), ErrorCode> {
         self.disable_interrupts();
 
-        if !self.registers.regwen.is_set(REGWEN::REGWEN) {
-            // Registers are read only
-            return Err(ErrorCode::FAIL);
+        if self.is_locked() {
+            // Registers are read only, this is a configuration problem rather
+            // than a hardware failure
+            return Err(ErrorCode::RESERVE);
         }
 
-        self.registers.ctrl.write(
-            CTRL::ENABLE::ENABLE + CTRL::READ_INT_STATE::ENABLE + CTRL::SW_APP_ENABLE::ENABLE,
-        );
-
-        self.registers.cmd_req.write(
-            COMMAND::ACMD::INSTANTIATE
-                + COMMAND::FLAGS.val(0)
-                + COMMAND::CLEN.val(0)
-                + COMMAND::GLEN.val(0),
-        );
-        while !self.registers.sw_cmd_sts.is_set(SW_CMD_STS::CMD_RDY) {}
+        self.prepare()?;
 
         self.disable_interrupts();
         self.enable_interrupts();
@@ -207,6 +693,7 @@ impl<'a> Entropy32<'a> for CsRng<'a> {
         self.disable_interrupts();
 
         self.registers.cmd_req.write(COMMAND::ACMD::UNINSTANTIATE);
+        self.instantiated.set(false);
 
         Ok(())
     }
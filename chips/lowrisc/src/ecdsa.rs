@@ -0,0 +1,279 @@
+//! ECDSA P-256 Implemented on top of the OTBN
+//!
+//! This drives the OpenTitan `p256_ecdsa` OTBN program, which is loaded from
+//! a disabled app in the same way as the RSA program. Hashes, scalars and
+//! coordinates are 32 bytes, signatures are `r || s` and public keys are
+//! `x || y`, all big endian.
+//!
+//! The private key is passed to the program as two shares, this driver uses
+//! the key itself as the first share and zero as the second, so the key is
+//! not masked while it is in DMEM. Call `clear_data()` once an operation has
+//! completed to release the OTBN and wipe DMEM.
+
+use crate::rsa::AppAddresses;
+use crate::virtual_otbn::VirtualMuxAccel;
+use core::cell::Cell;
+use kernel::hil::time::Alarm;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// Length in bytes of a hash, private key or single coordinate
+pub const P256_SCALAR_LEN: usize = 32;
+/// Length in bytes of a signature, `r || s`
+pub const P256_SIGNATURE_LEN: usize = 2 * P256_SCALAR_LEN;
+/// Length in bytes of a public key, `x || y`
+pub const P256_PUBLIC_KEY_LEN: usize = 2 * P256_SCALAR_LEN;
+
+// The addresses are the offsets of the symbols in the p256_ecdsa elf
+const MODE_ADDR: usize = 0x00;
+const MSG_ADDR: usize = 0x20;
+const R_ADDR: usize = 0x40;
+const S_ADDR: usize = 0x60;
+const X_ADDR: usize = 0x80;
+const Y_ADDR: usize = 0xA0;
+const D0_ADDR: usize = 0xC0;
+const D1_ADDR: usize = 0x100;
+const X_R_ADDR: usize = 0x140;
+
+/// Length in bytes of each private key share
+const SHARE_LEN: usize = 64;
+
+const MODE_SIGN: u8 = 1;
+const MODE_VERIFY: u8 = 2;
+
+/// Implement this trait and use `set_client()` in order to receive callbacks.
+pub trait Client<'a> {
+    /// Called when a `ecdsa_p256_sign()` operation completes. On success
+    /// `signature` holds `r || s`.
+    fn sign_done(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        hash: &'static [u8],
+        private_key: &'static [u8],
+        signature: &'static mut [u8],
+    );
+
+    /// Called when a `ecdsa_p256_verify()` operation completes. `result` is
+    /// `Ok(true)` if the signature is valid.
+    fn verify_done(
+        &'a self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static [u8],
+        public_key: &'static [u8],
+        signature: &'static [u8],
+    );
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Sign,
+    Verify,
+}
+
+pub struct OtbnEcdsaP256<'a, A: Alarm<'a>> {
+    otbn: &'a VirtualMuxAccel<'a, A>,
+    client: OptionalCell<&'a dyn Client<'a>>,
+
+    internal: TakeCell<'static, [u8]>,
+
+    hash: OptionalCell<&'static [u8]>,
+    key: OptionalCell<&'static [u8]>,
+    signature: OptionalCell<&'static [u8]>,
+    op: Cell<Operation>,
+
+    ecdsa: AppAddresses,
+}
+
+impl<'a, A: Alarm<'a>> OtbnEcdsaP256<'a, A> {
+    pub fn new(
+        otbn: &'a VirtualMuxAccel<'a, A>,
+        ecdsa: AppAddresses,
+        internal_buffer: &'static mut [u8; SHARE_LEN],
+    ) -> Self {
+        OtbnEcdsaP256 {
+            otbn,
+            client: OptionalCell::empty(),
+            internal: TakeCell::new(internal_buffer),
+            hash: OptionalCell::empty(),
+            key: OptionalCell::empty(),
+            signature: OptionalCell::empty(),
+            op: Cell::new(Operation::Idle),
+            ecdsa,
+        }
+    }
+
+    pub fn set_client(&'a self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    /// Disable the OTBN and clear the keys and any other sensitive data
+    pub fn clear_data(&self) {
+        self.otbn.clear_data();
+    }
+
+    /// Load the program, then the mode and the operands that are common to
+    /// both operations.
+    fn load_program(&self, mode: u8, hash: &[u8]) -> Result<(), ErrorCode> {
+        let slice = unsafe {
+            core::slice::from_raw_parts(self.ecdsa.imem_start as *mut u8, self.ecdsa.imem_size)
+        };
+        self.otbn.load_binary(slice)?;
+
+        let slice = unsafe {
+            core::slice::from_raw_parts(self.ecdsa.dmem_start as *mut u8, self.ecdsa.dmem_size)
+        };
+        self.otbn.load_data(0, slice)?;
+
+        self.otbn.load_data(MODE_ADDR, &[mode, 0, 0, 0])?;
+        self.load_operand(MSG_ADDR, hash)
+    }
+
+    /// Load a big endian operand into DMEM at `address`, padded with zeros
+    /// to `SHARE_LEN` if it is longer than `value`.
+    fn load_operand(&self, address: usize, value: &[u8]) -> Result<(), ErrorCode> {
+        self.internal.map_or(Err(ErrorCode::NOMEM), |data| {
+            let len = value.len();
+            data.iter_mut().for_each(|b| *b = 0);
+            data[0..len].copy_from_slice(value);
+            // We were passed BE data and the OTBN expects LE
+            // so reverse the order.
+            data[0..len].reverse();
+            self.otbn.load_data(address, &data[0..len])
+        })
+    }
+
+    /// Load a private key share, zero padded to `SHARE_LEN`
+    fn load_share(&self, address: usize, value: &[u8]) -> Result<(), ErrorCode> {
+        self.load_operand(address, value)?;
+        self.internal.map_or(Err(ErrorCode::NOMEM), |data| {
+            self.otbn
+                .load_data(address + value.len(), &data[value.len()..SHARE_LEN])
+        })
+    }
+
+    /// Sign the 32 byte `hash` with `private_key`, writing `r || s` into
+    /// `signature`. `sign_done()` is called once complete.
+    pub fn ecdsa_p256_sign(
+        &self,
+        hash: &'static [u8],
+        private_key: &'static [u8],
+        signature: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static [u8], &'static [u8], &'static mut [u8])> {
+        if self.op.get() != Operation::Idle {
+            return Err((ErrorCode::BUSY, hash, private_key, signature));
+        }
+
+        if hash.len() != P256_SCALAR_LEN
+            || private_key.len() != P256_SCALAR_LEN
+            || signature.len() < P256_SIGNATURE_LEN
+        {
+            return Err((ErrorCode::SIZE, hash, private_key, signature));
+        }
+
+        if let Err(e) = self
+            .load_program(MODE_SIGN, hash)
+            .and_then(|_| self.load_share(D0_ADDR, private_key))
+            .and_then(|_| self.load_share(D1_ADDR, &[0; P256_SCALAR_LEN]))
+        {
+            return Err((e, hash, private_key, signature));
+        }
+
+        // Get the data out
+        // `r` is immediately followed by `s`
+        if let Err(e) = self.otbn.run(R_ADDR, signature) {
+            return Err((e.0, hash, private_key, e.1));
+        }
+
+        self.hash.set(hash);
+        self.key.set(private_key);
+        self.op.set(Operation::Sign);
+
+        Ok(())
+    }
+
+    /// Verify that `signature` (`r || s`) is a signature of the 32 byte
+    /// `hash` by `public_key` (`x || y`). `verify_done()` is called once
+    /// complete.
+    pub fn ecdsa_p256_verify(
+        &self,
+        hash: &'static [u8],
+        public_key: &'static [u8],
+        signature: &'static [u8],
+    ) -> Result<(), (ErrorCode, &'static [u8], &'static [u8], &'static [u8])> {
+        if self.op.get() != Operation::Idle {
+            return Err((ErrorCode::BUSY, hash, public_key, signature));
+        }
+
+        if hash.len() != P256_SCALAR_LEN
+            || public_key.len() != P256_PUBLIC_KEY_LEN
+            || signature.len() != P256_SIGNATURE_LEN
+        {
+            return Err((ErrorCode::SIZE, hash, public_key, signature));
+        }
+
+        if let Err(e) = self
+            .load_program(MODE_VERIFY, hash)
+            .and_then(|_| self.load_operand(R_ADDR, &signature[..P256_SCALAR_LEN]))
+            .and_then(|_| self.load_operand(S_ADDR, &signature[P256_SCALAR_LEN..]))
+            .and_then(|_| self.load_operand(X_ADDR, &public_key[..P256_SCALAR_LEN]))
+            .and_then(|_| self.load_operand(Y_ADDR, &public_key[P256_SCALAR_LEN..]))
+        {
+            return Err((e, hash, public_key, signature));
+        }
+
+        // Get the recovered `x_r` out, the signature is valid if it matches `r`
+        let output = match self.internal.take() {
+            Some(output) => output,
+            None => return Err((ErrorCode::NOMEM, hash, public_key, signature)),
+        };
+        if let Err(e) = self.otbn.run(X_R_ADDR, output) {
+            self.internal.replace(e.1);
+            return Err((e.0, hash, public_key, signature));
+        }
+
+        self.hash.set(hash);
+        self.key.set(public_key);
+        self.signature.set(signature);
+        self.op.set(Operation::Verify);
+
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>> crate::otbn::Client<'a> for OtbnEcdsaP256<'a, A> {
+    fn op_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+        match self.op.replace(Operation::Idle) {
+            Operation::Sign => {
+                if result.is_ok() {
+                    // We want to return BE data
+                    output[..P256_SCALAR_LEN].reverse();
+                    output[P256_SCALAR_LEN..P256_SIGNATURE_LEN].reverse();
+                }
+
+                let hash = self.hash.take().unwrap();
+                let key = self.key.take().unwrap();
+                self.client.map(|client| {
+                    client.sign_done(result, hash, key, output);
+                });
+            }
+            Operation::Verify => {
+                let signature = self.signature.take().unwrap();
+                let result = result.map(|()| {
+                    // We want to compare BE data
+                    output[..P256_SCALAR_LEN].reverse();
+                    output[..P256_SCALAR_LEN] == signature[..P256_SCALAR_LEN]
+                });
+                self.internal.replace(output);
+
+                let hash = self.hash.take().unwrap();
+                let key = self.key.take().unwrap();
+                self.client.map(|client| {
+                    client.verify_done(result, hash, key, signature);
+                });
+            }
+            Operation::Idle => {}
+        }
+    }
+}
@@ -1,12 +1,40 @@
 //! Flash Controller
+//!
+//! Erase suspend
+//! -------------
+//!
+//! A page erase can be suspended with `suspend_erase()` so that reads don't
+//! have to wait for it. The controller ends the erase early and reports
+//! `OP_DONE`, which the driver takes as the suspension rather than the end
+//! of the erase (unless the page turns out to be fully erased already, in
+//! which case the erase completes as normal). While suspended reads go
+//! ahead, anything that modifies the flash returns `BUSY`.
+//!
+//! The controller can't carry on from where it stopped, so resuming issues
+//! the erase again. This happens once a read has finished and the driver is
+//! idle, or on `resume_erase()`, and `erase_complete()` is only called when
+//! that erase is done.
+//!
+//! Faults
+//! ------
+//!
+//! `FAULT_STATUS` records errors the hardware detects on its own, such as a
+//! register integrity failure or a corrupted life cycle transaction, as
+//! opposed to `ERR_CODE` which describes the failure of an operation
+//! software asked for. The bits stay set until reset. They also raise one
+//! of the controller's alerts, which is what actually responds to them, but
+//! a board can watch them with `fault_status()` or a `FaultClient`. The
+//! client is checked from the interrupt handler and by `check_faults()`,
+//! and is only called when a bit it hasn't been told about is set.
 
 use core::cell::Cell;
+use core::fmt::Write;
 use core::ops::{Index, IndexMut};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, Field, FieldValue, ReadOnly, ReadWrite, WriteOnly,
 };
 
 use kernel::hil;
@@ -19,7 +47,7 @@ register_structs! {
         (0x004 => intr_enable: ReadWrite<u32, INTR::Register>),
         (0x008 => intr_test: WriteOnly<u32, INTR::Register>),
         (0x00C => alert_test: WriteOnly<u32>),
-        (0x010 => disable: ReadWrite<u32>),
+        (0x010 => disable: ReadWrite<u32, DIS::Register>),
         (0x014 => exec: ReadWrite<u32>),
         (0x018 => init: ReadWrite<u32, INIT::Register>),
         (0x01C => ctrl_regwen: ReadOnly<u32, CTRL_REGWEN::Register>),
@@ -50,7 +78,7 @@ register_structs! {
         (0x14C => op_status: ReadWrite<u32, OP_STATUS::Register>),
         (0x150 => status: ReadOnly<u32, STATUS::Register>),
         (0x154 => err_code: ReadOnly<u32>),
-        (0x158 => fault_status: ReadOnly<u32>),
+        (0x158 => fault_status: ReadOnly<u32, FAULT_STATUS::Register>),
         (0x15C => err_addr: ReadOnly<u32>),
         (0x160 => ecc_single_err_cnt: ReadOnly<u32>),
         (0x164 => ecc_single_addr: [ReadOnly<u32>; 2]),
@@ -74,6 +102,12 @@ register_bitfields![u32,
         OP_DONE OFFSET(4) NUMBITS(1) [],
         OP_ERROR OFFSET(5) NUMBITS(1) []
     ],
+    DIS [
+        VAL OFFSET(0) NUMBITS(4) [
+            TRUE = 0x6,
+            FALSE = 0x9,
+        ]
+    ],
     INIT [
         VAL OFFSET(0) NUMBITS(1) []
     ],
@@ -171,6 +205,18 @@ register_bitfields![u32,
         PROG_EMPTY OFFSET(3) NUMBITS(1) [],
         INIT_WIP OFFSET(4) NUMBITS(1) [],
     ],
+    FAULT_STATUS [
+        OOB_ERR OFFSET(0) NUMBITS(1) [],
+        MP_ERR OFFSET(1) NUMBITS(1) [],
+        RD_ERR OFFSET(2) NUMBITS(1) [],
+        PROG_WIN_ERR OFFSET(3) NUMBITS(1) [],
+        PROG_TYPE_ERR OFFSET(4) NUMBITS(1) [],
+        FLASH_PHY_ERR OFFSET(5) NUMBITS(1) [],
+        REG_INTG_ERR OFFSET(6) NUMBITS(1) [],
+        PHY_INTG_ERR OFFSET(7) NUMBITS(1) [],
+        LCMGR_ERR OFFSET(8) NUMBITS(1) [],
+        STORAGE_ERR OFFSET(9) NUMBITS(1) []
+    ],
     PHY_STATUS [
         INIT_WIP OFFSET(0) NUMBITS(1) [],
         PROG_NORMAL_AVAIL OFFSET(1) NUMBITS(1) [],
@@ -220,13 +266,87 @@ impl AsMut<[u8]> for LowRiscPage {
     }
 }
 
-#[derive(PartialEq)]
-enum FlashBank {
+/// Size of a flash bank in bytes
+pub const BANK_SIZE: usize = 0x8_0000;
+
+/// Number of `PAGE_SIZE` pages in a flash bank
+pub const PAGES_PER_BANK: usize = BANK_SIZE / PAGE_SIZE;
+
+/// Address of the start of the flash in the memory map
+const FLASH_BASE: usize = 0x2000_0000;
+
+/// Number of status reads `wait_for_ready()` waits for initialisation
+pub const INIT_POLL_LIMIT: usize = 1_000_000;
+
+/// Number of status reads `write_words()` waits for room in the program FIFO,
+/// and for each program operation to finish
+const PROG_POLL_LIMIT: usize = 1_000_000;
+
+/// Number of flash banks
+pub const NUM_BANKS: usize = 2;
+
+/// Size in bytes of a word written to the program FIFO
+pub const WORD_SIZE: usize = 4;
+
+/// Size in bytes of the program window. A single program operation can't
+/// cross a window boundary, which is why `PAGE_SIZE` is this size.
+pub const PROG_WINDOW_SIZE: usize = PAGE_SIZE;
+
+/// The number of words, at most `remaining`, that can be programmed in one
+/// operation starting from `addr` without crossing a program window.
+fn calculate_max_prog_len(addr: usize, remaining: usize) -> usize {
+    core::cmp::min(
+        remaining,
+        (PROG_WINDOW_SIZE - addr % PROG_WINDOW_SIZE) / WORD_SIZE,
+    )
+}
+
+/// Size in bytes of a physical flash page. Info partition access is
+/// configured a physical page at a time.
+pub const FLASH_PAGE_SIZE: usize = 2048;
+
+/// Number of physical pages in each info partition type of a bank, indexed
+/// by the info type (`INFO_SEL`).
+pub const INFO_PAGES: [usize; 3] = [10, 1, 2];
+
+/// Layout of the flash, as seen through the `Flash` HIL
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct FlashGeometry {
+    /// Size in bytes of the pages passed to `read_page()`/`write_page()`
+    pub page_size: usize,
+    pub pages_per_bank: usize,
+    pub num_banks: usize,
+    /// Reads and writes are done in units of this many bytes
+    pub word_size: usize,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum FlashBank {
     BANK0 = 0,
     BANK1 = 1,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// Number of data partition memory protection regions
+pub const NUM_MP_REGIONS: usize = 8;
+
+/// Memory protection configuration of one data partition region, as held in
+/// its `MP_REGION_CFG` register. `base` and `size` are in `FLASH_PAGE_SIZE`
+/// pages.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct FlashMPAdvConfig {
+    pub enabled: bool,
+    pub base: usize,
+    pub size: usize,
+    pub read_en: bool,
+    pub prog_en: bool,
+    pub erase_en: bool,
+    pub scramble_en: bool,
+    pub ecc_en: bool,
+    /// High endurance
+    pub he_en: bool,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum FlashRegion {
     REGION0 = 0,
     REGION1 = 1,
@@ -238,16 +358,110 @@ pub enum FlashRegion {
     REGION7 = 7,
 }
 
+impl FlashRegion {
+    /// Every region, in order
+    pub const ALL: [FlashRegion; NUM_MP_REGIONS] = [
+        FlashRegion::REGION0,
+        FlashRegion::REGION1,
+        FlashRegion::REGION2,
+        FlashRegion::REGION3,
+        FlashRegion::REGION4,
+        FlashRegion::REGION5,
+        FlashRegion::REGION6,
+        FlashRegion::REGION7,
+    ];
+
+    /// The region numbered `n`, or `None` if there is no such region. Use
+    /// this to check a region number from outside the kernel, such as a
+    /// syscall argument, before passing it to the memory protection
+    /// methods, which index the region registers with it.
+    pub fn from_usize(n: usize) -> Option<FlashRegion> {
+        FlashRegion::ALL.get(n).copied()
+    }
+}
+
+/// Client for `read_pages()`
+pub trait ReadPagesClient {
+    /// All of the pages requested by `read_pages()` have been read into
+    /// `buf`, or the read failed part way through and `result` is an error.
+    fn read_pages_complete(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
+/// Optional client told how far through a `write_page()` the controller is
+pub trait WriteProgressClient {
+    /// Each time the program FIFO drains, `bytes_written` of the `total`
+    /// bytes of the page have been programmed. The final completion is still
+    /// reported by `write_complete()`.
+    fn write_progress(&self, bytes_written: usize, total: usize);
+}
+
+/// Client for `write_page_verified()`
+pub trait VerifiedWriteClient {
+    /// The page has been written and read back. `result` is `Err(FAIL)` if
+    /// the flash doesn't match `buf` or either operation failed.
+    fn write_verified_complete(&self, buf: &'static mut LowRiscPage, result: Result<(), ErrorCode>);
+}
+
+/// Optional client told about hardware faults, see the module documentation
+pub trait FaultClient {
+    /// A fault bit that wasn't set when the client was last called is now
+    /// set. `fault_status` is the whole of `FAULT_STATUS`, as returned by
+    /// `fault_status()`.
+    fn fault_detected(&self, fault_status: u32);
+}
+
+/// Progress of a `suspend_erase()` request
+#[derive(PartialEq, Clone, Copy)]
+enum EraseSuspend {
+    None,
+    /// Asked for, waiting for the erase to stop
+    Requested,
+    /// The erase stopped early and has to be issued again
+    Suspended,
+}
+
+/// The operation currently in flight on the flash controller.
+#[derive(PartialEq, Clone, Copy)]
+enum Operation {
+    Idle,
+    Read,
+    Write,
+    PageErase,
+    BankErase,
+}
+
 pub struct FlashCtrl<'a> {
     registers: StaticRef<FlashCtrlRegisters>,
     flash_client: OptionalCell<&'a dyn hil::flash::Client<FlashCtrl<'a>>>,
+    read_pages_client: OptionalCell<&'a dyn ReadPagesClient>,
+    write_progress_client: OptionalCell<&'a dyn WriteProgressClient>,
+    verified_write_client: OptionalCell<&'a dyn VerifiedWriteClient>,
+    fault_client: OptionalCell<&'a dyn FaultClient>,
+    /// The fault bits the fault client has already been called for
+    reported_faults: Cell<u32>,
     data_configured: Cell<bool>,
     info_configured: Cell<bool>,
     read_buf: TakeCell<'static, LowRiscPage>,
     read_index: Cell<usize>,
+    read_pages_buf: TakeCell<'static, [u8]>,
+    read_pages_start: Cell<usize>,
     write_buf: TakeCell<'static, LowRiscPage>,
     write_index: Cell<usize>,
+    verifying: Cell<bool>,
+    verify_page: Cell<usize>,
+    verify_buf: TakeCell<'static, LowRiscPage>,
+    verify_written: TakeCell<'static, LowRiscPage>,
     region_num: FlashRegion,
+    op: Cell<Operation>,
+    strict_write: Cell<bool>,
+    data_scramble_en: Cell<bool>,
+    data_ecc_en: Cell<bool>,
+    erase_addr: Cell<usize>,
+    /// End of the region `erase_region()` is erasing, 0 for a single page
+    erase_end: Cell<usize>,
+    suspend: Cell<EraseSuspend>,
+    count_erases: Cell<bool>,
+    erase_counts: [Cell<u32>; NUM_BANKS],
 }
 
 impl<'a> FlashCtrl<'a> {
@@ -255,13 +469,33 @@ impl<'a> FlashCtrl<'a> {
         FlashCtrl {
             registers: base,
             flash_client: OptionalCell::empty(),
+            read_pages_client: OptionalCell::empty(),
+            write_progress_client: OptionalCell::empty(),
+            verified_write_client: OptionalCell::empty(),
+            fault_client: OptionalCell::empty(),
+            reported_faults: Cell::new(0),
             data_configured: Cell::new(false),
             info_configured: Cell::new(false),
             read_buf: TakeCell::empty(),
             read_index: Cell::new(0),
+            read_pages_buf: TakeCell::empty(),
+            read_pages_start: Cell::new(0),
             write_buf: TakeCell::empty(),
             write_index: Cell::new(0),
+            verifying: Cell::new(false),
+            verify_page: Cell::new(0),
+            verify_buf: TakeCell::empty(),
+            verify_written: TakeCell::empty(),
             region_num,
+            op: Cell::new(Operation::Idle),
+            strict_write: Cell::new(false),
+            data_scramble_en: Cell::new(false),
+            data_ecc_en: Cell::new(false),
+            erase_addr: Cell::new(0),
+            erase_end: Cell::new(0),
+            suspend: Cell::new(EraseSuspend::None),
+            count_erases: Cell::new(false),
+            erase_counts: Default::default(),
         }
     }
 
@@ -270,17 +504,19 @@ impl<'a> FlashCtrl<'a> {
         self.registers.intr_enable.write(
             INTR::PROG_EMPTY::SET
                 + INTR::PROG_LVL::CLEAR
-                + INTR::RD_FULL::CLEAR
+                + INTR::RD_FULL::SET
                 + INTR::RD_LVL::SET
                 + INTR::OP_DONE::SET
                 + INTR::OP_ERROR::SET,
         );
     }
 
-    fn disable_interrupts(&self) {
-        // Disable and clear all interrupts
+    /// Disable all interrupts and clear the ones in `irqs`. Anything that
+    /// arrives after `irqs` was read stays pending until interrupts are
+    /// enabled again.
+    fn disable_interrupts(&self, irqs: u32) {
         self.registers.intr_enable.set(0x00);
-        self.registers.intr_state.set(0xFFFF_FFFF);
+        self.registers.intr_state.set(irqs);
     }
 
     fn configure_data_partition(&self, num: FlashRegion) {
@@ -299,8 +535,8 @@ impl<'a> FlashCtrl<'a> {
                     + MP_REGION_CFG::RD_EN::SET
                     + MP_REGION_CFG::PROG_EN::SET
                     + MP_REGION_CFG::ERASE_EN::SET
-                    + MP_REGION_CFG::SCRAMBLE_EN::CLEAR
-                    + MP_REGION_CFG::ECC_EN::CLEAR
+                    + MP_REGION_CFG::SCRAMBLE_EN.val(self.data_scramble_en.get() as u32)
+                    + MP_REGION_CFG::ECC_EN.val(self.data_ecc_en.get() as u32)
                     + MP_REGION_CFG::EN::SET,
             );
         }
@@ -308,240 +544,416 @@ impl<'a> FlashCtrl<'a> {
     }
 
     fn configure_info_partition(&self, bank: FlashBank, num: FlashRegion) {
-        for _ in 0..2 {
-            if bank == FlashBank::BANK0 {
-                self.registers.bank0_info0_page_cfg_shadowed[num as usize].write(
-                    BANK_INFO_PAGE_CFG::RD_EN::SET
-                        + BANK_INFO_PAGE_CFG::PROG_EN::SET
-                        + BANK_INFO_PAGE_CFG::ERASE_EN::SET
-                        + BANK_INFO_PAGE_CFG::SCRAMBLE_EN::CLEAR
-                        + BANK_INFO_PAGE_CFG::ECC_EN::CLEAR
-                        + BANK_INFO_PAGE_CFG::EN::SET,
-                );
-            } else if bank == FlashBank::BANK1 {
-                self.registers.bank1_info0_page_cfg_shadowed[num as usize].write(
-                    BANK_INFO_PAGE_CFG::RD_EN::SET
-                        + BANK_INFO_PAGE_CFG::PROG_EN::SET
-                        + BANK_INFO_PAGE_CFG::ERASE_EN::SET
-                        + BANK_INFO_PAGE_CFG::SCRAMBLE_EN::CLEAR
-                        + BANK_INFO_PAGE_CFG::ECC_EN::CLEAR
-                        + BANK_INFO_PAGE_CFG::EN::SET,
-                );
-            } else {
-                panic!("Unsupported bank");
-            }
-        }
+        // `num` is always a valid info0 page
+        let _ = self.configure_info_page(bank, 0, num as usize);
         self.info_configured.set(true);
     }
 
-    pub fn handle_interrupt(&self) {
-        let irqs = self.registers.intr_state.extract();
-
-        self.disable_interrupts();
-
-        if irqs.is_set(INTR::OP_ERROR) {
-            let read_buf = self.read_buf.take();
-            if let Some(buf) = read_buf {
-                // We were doing a read
-                self.flash_client.map(move |client| {
-                    client.read_complete(buf, hil::flash::Error::FlashError);
-                });
-            }
-
-            let write_buf = self.write_buf.take();
-            if let Some(buf) = write_buf {
-                // We were doing a write
-                self.flash_client.map(move |client| {
-                    client.write_complete(buf, hil::flash::Error::FlashError);
-                });
-            }
+    /// The page config register of physical page `page` of info partition
+    /// type `info_type` in `bank`
+    fn info_page_cfg(
+        &self,
+        bank: FlashBank,
+        info_type: usize,
+        page: usize,
+    ) -> Option<&ReadWrite<u32, BANK_INFO_PAGE_CFG::Register>> {
+        let regs = &*self.registers;
+        match (bank, info_type) {
+            (FlashBank::BANK0, 0) => regs.bank0_info0_page_cfg_shadowed.get(page),
+            (FlashBank::BANK0, 1) if page == 0 => Some(&regs.bank0_info1_page_cfg_shadowed),
+            (FlashBank::BANK0, 2) => regs.bank0_info2_page_cfg_shadowed.get(page),
+            (FlashBank::BANK1, 0) => regs.bank1_info0_page_cfg_shadowed.get(page),
+            (FlashBank::BANK1, 1) if page == 0 => Some(&regs.bank1_info1_page_cfg_shadowed),
+            (FlashBank::BANK1, 2) => regs.bank1_info2_page_cfg_shadowed.get(page),
+            _ => None,
         }
+    }
 
-        if irqs.is_set(INTR::RD_LVL) {
-            self.read_buf.map(|buf| {
-                while !self.registers.status.is_set(STATUS::RD_EMPTY)
-                    && self.read_index.get() < PAGE_SIZE
-                {
-                    let data = self.registers.rd_fifo.get().to_ne_bytes();
-                    let buf_offset = self.read_index.get();
-
-                    buf[buf_offset] = data[0];
-                    buf[buf_offset + 1] = data[1];
-                    buf[buf_offset + 2] = data[2];
-                    buf[buf_offset + 3] = data[3];
+    /// Enable access to physical page `page` of info partition type
+    /// `info_type` in `bank`
+    fn configure_info_page(
+        &self,
+        bank: FlashBank,
+        info_type: usize,
+        page: usize,
+    ) -> Result<(), ErrorCode> {
+        let cfg = self
+            .info_page_cfg(bank, info_type, page)
+            .ok_or(ErrorCode::INVAL)?;
 
-                    self.read_index.set(buf_offset + 4);
-                }
-                self.enable_interrupts();
-            });
+        for _ in 0..2 {
+            cfg.write(
+                BANK_INFO_PAGE_CFG::RD_EN::SET
+                    + BANK_INFO_PAGE_CFG::PROG_EN::SET
+                    + BANK_INFO_PAGE_CFG::ERASE_EN::SET
+                    + BANK_INFO_PAGE_CFG::SCRAMBLE_EN::CLEAR
+                    + BANK_INFO_PAGE_CFG::ECC_EN::CLEAR
+                    + BANK_INFO_PAGE_CFG::EN::SET,
+            );
         }
+        Ok(())
+    }
 
-        if irqs.is_set(INTR::PROG_EMPTY) {
-            self.write_buf.map(|buf| {
-                // Write the data in until we are full
-                while !self.registers.status.is_set(STATUS::PROG_FULL)
-                    && self.write_index.get() < buf.0.len()
-                {
-                    let buf_offset = self.write_index.get();
-                    let data: u32 = buf[buf_offset] as u32
-                        | (buf[buf_offset + 1] as u32) << 8
-                        | (buf[buf_offset + 2] as u32) << 16
-                        | (buf[buf_offset + 3] as u32) << 24;
-
-                    self.registers.prog_fifo.set(data);
-
-                    self.write_index.set(buf_offset + 4);
-                }
-                self.enable_interrupts();
-            });
+    /// Check the info partition `page`, in `PAGE_SIZE` units, and enable
+    /// access to the physical page it is in. Returns the address of the page
+    /// and the partition select for `CONTROL`.
+    fn info_page_setup(
+        &self,
+        bank: FlashBank,
+        info_type: usize,
+        page: usize,
+    ) -> Result<(usize, FieldValue<u32, CONTROL::Register>), ErrorCode> {
+        let pages = INFO_PAGES.get(info_type).ok_or(ErrorCode::INVAL)?;
+        if page >= pages * (FLASH_PAGE_SIZE / PAGE_SIZE) {
+            return Err(ErrorCode::INVAL);
         }
 
-        if irqs.is_set(INTR::OP_DONE) {
-            if self.registers.control.matches_all(CONTROL::OP::READ) {
-                let read_buf = self.read_buf.take();
-                if let Some(buf) = read_buf {
-                    // We were doing a read
-                    if self.read_index.get() >= buf.0.len() {
-                        // We have all of the data, call the client
-                        self.flash_client.map(move |client| {
-                            client.read_complete(buf, hil::flash::Error::CommandComplete);
-                        });
-                    } else {
-                        // Still waiting on data, keep waiting
-                        self.read_buf.replace(buf);
-                        self.enable_interrupts();
-                    }
-                }
-            } else if self.registers.control.matches_all(CONTROL::OP::PROG) {
-                let write_buf = self.write_buf.take();
-                if let Some(buf) = write_buf {
-                    // We were doing a write
-                    if self.write_index.get() >= buf.0.len() {
-                        // We sent all of the data, call the client
-                        self.flash_client.map(move |client| {
-                            client.write_complete(buf, hil::flash::Error::CommandComplete);
-                        });
-                    } else {
-                        // Still writing data, keep trying
-                        self.write_buf.replace(buf);
-                        self.enable_interrupts();
-                    }
-                }
-            } else if self.registers.control.matches_all(CONTROL::OP::ERASE) {
-                self.flash_client.map(move |client| {
-                    client.erase_complete(hil::flash::Error::CommandComplete);
-                });
-            }
-        }
-    }
-}
+        let addr = bank as usize * BANK_SIZE + page * PAGE_SIZE;
+        self.configure_info_page(bank, info_type, page * PAGE_SIZE / FLASH_PAGE_SIZE)?;
 
-impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for FlashCtrl<'_> {
-    fn set_client(&self, client: &'static C) {
-        self.flash_client.set(client);
+        Ok((
+            addr,
+            CONTROL::PARTITION_SEL::INFO + CONTROL::INFO_SEL.val(info_type as u32),
+        ))
     }
-}
 
-impl hil::flash::Flash for FlashCtrl<'_> {
-    type Page = LowRiscPage;
+    fn disable_bank_erase(&self) {
+        for _ in 0..2 {
+            self.registers
+                .mp_bank_cfg_shadowed
+                .modify(MP_BANK_CFG::ERASE_EN_0::CLEAR + MP_BANK_CFG::ERASE_EN_1::CLEAR);
+        }
+    }
 
-    fn read_page(
-        &self,
-        page_number: usize,
-        buf: &'static mut Self::Page,
-    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
-        let addr = page_number * PAGE_SIZE;
+    /// Erase an entire data bank. This is a single long operation that
+    /// finishes with one `erase_complete()` callback. Bank erase is only
+    /// enabled in the memory protection config for the duration of the
+    /// operation.
+    pub fn erase_bank(&self, bank: FlashBank) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.op.set(Operation::BankErase);
 
         if !self.data_configured.get() {
             // If we aren't configured yet, configure now
             self.configure_data_partition(self.region_num);
         }
 
-        if !self.info_configured.get() {
-            // If we aren't configured yet, configure now
-            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        // Enable bank erase for just this bank
+        for _ in 0..2 {
+            match bank {
+                FlashBank::BANK0 => self
+                    .registers
+                    .mp_bank_cfg_shadowed
+                    .modify(MP_BANK_CFG::ERASE_EN_0::SET + MP_BANK_CFG::ERASE_EN_1::CLEAR),
+                FlashBank::BANK1 => self
+                    .registers
+                    .mp_bank_cfg_shadowed
+                    .modify(MP_BANK_CFG::ERASE_EN_0::CLEAR + MP_BANK_CFG::ERASE_EN_1::SET),
+            }
         }
 
-        // Enable interrupts and set the FIFO level
-        self.enable_interrupts();
-        self.registers.fifo_lvl.modify(FIFO_LVL::RD.val(0xF));
-
-        // Set the address
-        self.registers.addr.write(ADDR::START.val(addr as u32));
+        // Any address in the bank selects it
+        self.erase_addr.set(FLASH_BASE + bank as usize * BANK_SIZE);
+        self.registers
+            .addr
+            .write(ADDR::START.val((bank as usize * BANK_SIZE) as u32));
 
-        // Save the buffer
-        self.read_buf.replace(buf);
-        self.read_index.set(0);
+        // Enable interrupts
+        self.enable_interrupts();
 
         // Start the transaction
         self.registers.control.write(
-            CONTROL::OP::READ
+            CONTROL::OP::ERASE
+                + CONTROL::ERASE_SEL::BANK
                 + CONTROL::PARTITION_SEL::DATA
-                + CONTROL::NUM.val(((PAGE_SIZE / 4) - 1) as u32)
                 + CONTROL::START::SET,
         );
 
         Ok(())
     }
 
-    fn write_page(
-        &self,
-        page_number: usize,
-        buf: &'static mut Self::Page,
-    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
-        let addr = page_number * PAGE_SIZE;
+    /// Report the page and bank layout of the flash, so users can size
+    /// themselves at runtime instead of hardcoding the constants.
+    pub fn geometry(&self) -> FlashGeometry {
+        FlashGeometry {
+            page_size: PAGE_SIZE,
+            pages_per_bank: PAGES_PER_BANK,
+            num_banks: NUM_BANKS,
+            word_size: WORD_SIZE,
+        }
+    }
 
-        if !self.data_configured.get() {
-            // If we aren't configured yet, configure now
-            self.configure_data_partition(self.region_num);
+    /// When enabled `write_page()` first checks that the page is in the flash
+    /// and erased, and returns `INVAL` if it isn't. Programming can only clear bits, so a
+    /// write over old data would otherwise fail silently or leave ECC errors
+    /// behind. This costs a read of the page per write, so is off by default.
+    pub fn set_strict_write(&self, enable: bool) {
+        self.strict_write.set(enable);
+    }
+
+    /// Count the erases done by this driver in each bank, for wear
+    /// monitoring. Off by default. See `erase_count()`.
+    pub fn set_erase_counting(&self, enable: bool) {
+        self.count_erases.set(enable);
+    }
+
+    /// The number of physical pages erased in `bank` since counting was
+    /// enabled, or since the count was restored with `set_erase_count()`.
+    /// A bank erase counts as an erase of every page in the bank, so
+    /// dividing by `BANK_SIZE / FLASH_PAGE_SIZE` gives the average number of
+    /// erase cycles per page.
+    ///
+    /// Only erases that complete successfully are counted, and a suspended
+    /// erase only counts once it is finished. The count is kept in RAM, a
+    /// board that wants it to survive a reset has to store it, for example
+    /// in an info page, and put it back with `set_erase_count()` at boot.
+    pub fn erase_count(&self, bank: FlashBank) -> u32 {
+        self.erase_counts[bank as usize].get()
+    }
+
+    /// Restore a count saved from `erase_count()`
+    pub fn set_erase_count(&self, bank: FlashBank, count: u32) {
+        self.erase_counts[bank as usize].set(count);
+    }
+
+    /// Add `pages` physical page erases to the count of the bank containing
+    /// `addr`, if counting is enabled
+    fn count_erase(&self, addr: usize, pages: usize) {
+        if !self.count_erases.get() {
+            return;
         }
 
-        if !self.info_configured.get() {
-            // If we aren't configured yet, configure now
-            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        let bank = addr
+            .checked_sub(FLASH_BASE)
+            .and_then(|offset| self.erase_counts.get(offset / BANK_SIZE));
+        if let Some(count) = bank {
+            count.set(count.get().saturating_add(pages as u32));
         }
+    }
 
-        // Set the address
-        self.registers.addr.write(ADDR::START.val(addr as u32));
+    /// The `(done, err)` bits of `OP_STATUS`, for polling and diagnostics.
+    ///
+    /// This only reads the register, so the bits are left as they are. The
+    /// driver never clears them either, so they describe the most recent
+    /// operation the controller has finished, not necessarily the current
+    /// one.
+    pub fn op_status(&self) -> (bool, bool) {
+        let status = self.registers.op_status.extract();
+        (
+            status.is_set(OP_STATUS::DONE),
+            status.is_set(OP_STATUS::ERR),
+        )
+    }
 
-        // Reset the write index
-        self.write_index.set(0);
+    /// The `FAULT_STATUS` register, zero if no fault has been detected since
+    /// reset. The bits are:
+    ///
+    /// - 0 `OOB_ERR`: an operation the controller issued itself, rather
+    ///   than one from software, was out of bounds.
+    /// - 1 `MP_ERR`: such an operation was refused by memory protection.
+    /// - 2 `RD_ERR`: such an operation read data with an uncorrectable ECC
+    ///   error.
+    /// - 3 `PROG_WIN_ERR`: such a program crossed a flash word window.
+    /// - 4 `PROG_TYPE_ERR`: such a program used a disabled program type.
+    /// - 5 `FLASH_PHY_ERR`: the flash phy reported an error.
+    /// - 6 `REG_INTG_ERR`: a register bus transaction failed its integrity
+    ///   check.
+    /// - 7 `PHY_INTG_ERR`: a host transaction to the flash phy failed its
+    ///   integrity check.
+    /// - 8 `LCMGR_ERR`: the life cycle management interface failed, for
+    ///   example while reading the scrambling seeds.
+    /// - 9 `STORAGE_ERR`: a shadowed register's two copies don't match.
+    ///
+    /// Any of these can mean the chip has been tampered with. None of them
+    /// can be cleared by software.
+    pub fn fault_status(&self) -> u32 {
+        self.registers.fault_status.get()
+    }
 
-        // Start the transaction
-        self.registers.control.write(
-            CONTROL::OP::PROG
-                + CONTROL::PARTITION_SEL::DATA
-                + CONTROL::NUM.val(((PAGE_SIZE / 4) - 1) as u32)
-                + CONTROL::START::SET,
-        );
+    /// Set the client called when a new fault bit is set
+    pub fn set_fault_client(&self, client: &'a dyn FaultClient) {
+        self.fault_client.set(client);
+    }
 
-        // Write the data until we are full or have written all the data
-        while !self.registers.status.is_set(STATUS::PROG_FULL)
-            && self.write_index.get() < (buf.0.len() - 4)
-        {
-            let buf_offset = self.write_index.get();
-            let data: u32 = buf[buf_offset] as u32
-                | (buf[buf_offset + 1] as u32) << 8
-                | (buf[buf_offset + 2] as u32) << 16
-                | (buf[buf_offset + 3] as u32) << 24;
+    /// Read `FAULT_STATUS` and call the fault client if a bit it hasn't
+    /// been told about yet is set. Returns the register.
+    ///
+    /// The interrupt handler already does this, but a fault can be raised
+    /// while the controller is idle, so a board monitoring faults should
+    /// also call this periodically.
+    pub fn check_faults(&self) -> u32 {
+        let status = self.fault_status();
+        if status & !self.reported_faults.get() != 0 {
+            self.reported_faults.set(status);
+            self.fault_client.map(|client| {
+                client.fault_detected(status);
+            });
+        }
+        status
+    }
 
-            self.registers.prog_fifo.set(data);
+    /// True once both the controller and the flash phy have finished
+    /// initialising after reset, and flash can be accessed.
+    pub fn is_ready(&self) -> bool {
+        !self.registers.status.is_set(STATUS::INIT_WIP)
+            && !self.registers.phy_status.is_set(PHY_STATUS::INIT_WIP)
+    }
 
-            self.write_index.set(buf_offset + 4);
+    /// Busy wait, for at most `INIT_POLL_LIMIT` status reads, until the
+    /// controller has finished initialising. Boards should call this before
+    /// the first flash operation.
+    ///
+    /// Initialisation is started by the ROM, and mostly consists of reading
+    /// the scrambling seeds out of the info partition, so it takes in the
+    /// order of a few thousand clock cycles. It has normally completed by the
+    /// time the kernel starts, in which case this returns straight away.
+    ///
+    /// Returns `BUSY` if the controller still isn't ready.
+    pub fn wait_for_ready(&self) -> Result<(), ErrorCode> {
+        if (0..INIT_POLL_LIMIT).any(|_| self.is_ready()) {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
         }
+    }
 
-        // Save the buffer
-        self.write_buf.replace(buf);
+    /// Permanently disable all flash access, by both the controller and the
+    /// host, until the next reset. Meant as a tamper response or as the last
+    /// step of a secure shutdown.
+    ///
+    /// This can't be undone: the register can only be cleared by a reset.
+    /// Code executing from flash stops with it, so the caller must already
+    /// be running from RAM or be about to reset the chip. Afterwards every
+    /// flash operation returns `OFF`.
+    ///
+    /// Returns `BUSY` while an operation is in progress, so a client isn't
+    /// left waiting for a callback that never comes, and `ALREADY` if flash
+    /// is already disabled.
+    pub fn disable_flash(&self) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::ALREADY);
+        }
 
-        // Enable interrupts and set the FIFO level
-        self.enable_interrupts();
-        self.registers.fifo_lvl.modify(FIFO_LVL::PROG.val(0xF));
+        if self.is_busy() || self.suspend.get() != EraseSuspend::None {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.registers.disable.write(DIS::VAL::TRUE);
 
         Ok(())
     }
 
-    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
-        let addr = page_number * PAGE_SIZE;
+    /// True once `disable_flash()` has been called, until the next reset.
+    /// Any value other than false counts as disabled.
+    pub fn is_flash_disabled(&self) -> bool {
+        !self.registers.disable.matches_all(DIS::VAL::FALSE)
+    }
+
+    /// An operation is in flight, either one we started or one the hardware
+    /// hasn't finished yet (`CTRL_REGWEN` is cleared while the controller
+    /// is busy). Only one operation can run at a time: the read and write
+    /// paths share the `ADDR` and `CONTROL` registers, so starting a read
+    /// part way through a multi-window program would corrupt both.
+    fn is_busy(&self) -> bool {
+        self.op.get() != Operation::Idle || !self.registers.ctrl_regwen.is_set(CTRL_REGWEN::EN)
+    }
+
+    /// Busy, or a suspended erase is waiting to be resumed. Reads can go
+    /// ahead of a suspended erase, anything that modifies the flash can't.
+    fn is_busy_for_modify(&self) -> bool {
+        self.is_busy() || self.suspend.get() == EraseSuspend::Suspended
+    }
+
+    /// Ask the controller to suspend the page erase in progress, so reads can
+    /// go ahead of it. See the module documentation.
+    ///
+    /// Returns `INVAL` if no page erase is in progress and `ALREADY` if a
+    /// suspend has already been requested.
+    pub fn suspend_erase(&self) -> Result<(), ErrorCode> {
+        if self.op.get() != Operation::PageErase {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.suspend.get() != EraseSuspend::None {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        self.suspend.set(EraseSuspend::Requested);
+        self.registers.erase_suspend.write(ERASE_SUSPEND::REQ::SET);
+
+        Ok(())
+    }
+
+    /// True while a suspended erase is waiting to be resumed
+    pub fn is_erase_suspended(&self) -> bool {
+        self.suspend.get() == EraseSuspend::Suspended
+    }
+
+    /// Issue a suspended erase again without waiting for a read to finish.
+    ///
+    /// Returns `INVAL` if no erase is suspended and `BUSY` while another
+    /// operation is in progress.
+    pub fn resume_erase(&self) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.suspend.get() != EraseSuspend::Suspended {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.suspend.set(EraseSuspend::None);
+        self.op.set(Operation::PageErase);
+        self.start_page_erase(self.erase_addr.get());
+
+        Ok(())
+    }
+
+    /// Erase `num_pages` successive pages from `page_number`, one physical
+    /// page at a time, calling `erase_complete()` once when they are all
+    /// erased or when the first one fails.
+    ///
+    /// Pages are in `PAGE_SIZE` units, as for `erase_page()`, but the
+    /// controller erases whole physical pages, so the region has to start
+    /// and end on a `FLASH_PAGE_SIZE` boundary. The erase can be suspended
+    /// like a single page erase, the suspension applies to the physical page
+    /// being erased at the time.
+    ///
+    /// Returns `INVAL` if the region is empty, isn't aligned to physical
+    /// pages or isn't all in the data partition, and `BUSY` if another
+    /// operation is in progress.
+    pub fn erase_region(&self, page_number: usize, num_pages: usize) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        let start = page_number.checked_mul(PAGE_SIZE);
+        let end = num_pages
+            .checked_mul(PAGE_SIZE)
+            .and_then(|len| start.and_then(|start| start.checked_add(len)));
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if num_pages > 0 => (start, end),
+            _ => return Err(ErrorCode::INVAL),
+        };
+        if start % FLASH_PAGE_SIZE != 0
+            || end % FLASH_PAGE_SIZE != 0
+            || start < FLASH_BASE
+            || end > FLASH_BASE + NUM_BANKS * BANK_SIZE
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.op.set(Operation::PageErase);
 
         if !self.data_configured.get() {
             // If we aren't configured yet, configure now
@@ -553,12 +965,17 @@ impl hil::flash::Flash for FlashCtrl<'_> {
             self.configure_info_partition(FlashBank::BANK1, self.region_num);
         }
 
-        // Disable bank erase
-        for _ in 0..2 {
-            self.registers
-                .mp_bank_cfg_shadowed
-                .modify(MP_BANK_CFG::ERASE_EN_0::CLEAR + MP_BANK_CFG::ERASE_EN_1::CLEAR);
-        }
+        self.erase_addr.set(start);
+        self.erase_end.set(end);
+        self.start_page_erase(start);
+
+        Ok(())
+    }
+
+    /// Start erasing the physical page containing `addr`
+    fn start_page_erase(&self, addr: usize) {
+        // Disable bank erase
+        self.disable_bank_erase();
 
         // Set the address
         self.registers.addr.write(ADDR::START.val(addr as u32));
@@ -573,6 +990,958 @@ impl hil::flash::Flash for FlashCtrl<'_> {
                 + CONTROL::PARTITION_SEL::DATA
                 + CONTROL::START::SET,
         );
+    }
+
+    /// Check that the whole physical page containing `addr` is erased
+    fn flash_page_is_erased(&self, addr: usize) -> bool {
+        let start = addr - addr % FLASH_PAGE_SIZE;
+        (0..FLASH_PAGE_SIZE / PAGE_SIZE).all(|i| self.page_is_erased(start + i * PAGE_SIZE))
+    }
+
+    pub fn set_read_pages_client(&self, client: &'a dyn ReadPagesClient) {
+        self.read_pages_client.set(client);
+    }
+
+    pub fn set_write_progress_client(&self, client: &'a dyn WriteProgressClient) {
+        self.write_progress_client.set(client);
+    }
+
+    pub fn set_verified_write_client(&self, client: &'a dyn VerifiedWriteClient) {
+        self.verified_write_client.set(client);
+    }
+
+    /// Provide the buffer `write_page_verified()` reads the page back into
+    pub fn set_verify_buffer(&self, buf: &'static mut LowRiscPage) {
+        self.verify_buf.replace(buf);
+    }
+
+    /// Write `buf` to `page_number` like `write_page()`, then read the page
+    /// back and compare it with `buf`. Completes with
+    /// `write_verified_complete()` rather than `write_complete()`.
+    ///
+    /// This takes twice as long as a plain write. Returns NOMEM if no verify
+    /// buffer has been set, otherwise the same errors as `write_page()`.
+    pub fn write_page_verified(
+        &self,
+        page_number: usize,
+        buf: &'static mut LowRiscPage,
+    ) -> Result<(), (ErrorCode, &'static mut LowRiscPage)> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.is_busy_for_modify() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        if self.verify_buf.is_none() {
+            return Err((ErrorCode::NOMEM, buf));
+        }
+
+        self.verifying.set(true);
+        self.verify_page.set(page_number);
+
+        hil::flash::Flash::write_page(self, page_number, buf).map_err(|e| {
+            self.verifying.set(false);
+            e
+        })
+    }
+
+    /// Read `buf.len() / PAGE_SIZE` successive pages, starting at
+    /// `page_number`, into successive `PAGE_SIZE` chunks of `buf`.
+    ///
+    /// The pages are read one at a time, the same as `read_page()`, and a
+    /// single `read_pages_complete()` callback is made once all of them have
+    /// been read. Returns `SIZE` if `buf` isn't a non-zero multiple of
+    /// `PAGE_SIZE`.
+    pub fn read_pages(
+        &self,
+        page_number: usize,
+        buf: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if buf.len() == 0 || buf.len() % PAGE_SIZE != 0 {
+            return Err((ErrorCode::SIZE, buf));
+        }
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        self.op.set(Operation::Read);
+
+        if !self.data_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_data_partition(self.region_num);
+        }
+
+        if !self.info_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        }
+
+        // Save the buffer
+        self.read_pages_buf.replace(buf);
+        self.read_pages_start.set(page_number);
+        self.read_index.set(0);
+
+        self.start_page_read(page_number * PAGE_SIZE, CONTROL::PARTITION_SEL::DATA);
+
+        Ok(())
+    }
+
+    /// Start a read of the page at `addr`. The data is collected by
+    /// `handle_interrupt()` into whichever read buffer is set.
+    fn start_page_read(&self, addr: usize, partition: FieldValue<u32, CONTROL::Register>) {
+        // Enable interrupts and set the FIFO level
+        self.enable_interrupts();
+        self.registers.fifo_lvl.modify(FIFO_LVL::RD.val(0xF));
+
+        // Set the address
+        self.registers.addr.write(ADDR::START.val(addr as u32));
+
+        // Start the transaction
+        self.registers.control.write(
+            CONTROL::OP::READ
+                + partition
+                + CONTROL::NUM.val(((PAGE_SIZE / 4) - 1) as u32)
+                + CONTROL::START::SET,
+        );
+    }
+
+    /// Start programming `buf` at `addr` in `partition`
+    fn start_page_write(
+        &self,
+        addr: usize,
+        partition: FieldValue<u32, CONTROL::Register>,
+        buf: &'static mut LowRiscPage,
+    ) {
+        // Set the address
+        self.registers.addr.write(ADDR::START.val(addr as u32));
+
+        // Reset the write index
+        self.write_index.set(0);
+
+        // Start the transaction
+        self.registers.control.write(
+            CONTROL::OP::PROG
+                + partition
+                + CONTROL::NUM.val(((PAGE_SIZE / 4) - 1) as u32)
+                + CONTROL::START::SET,
+        );
+
+        // Write the data until we are full or have written all the data
+        while !self.registers.status.is_set(STATUS::PROG_FULL)
+            && self.write_index.get() < (buf.0.len() - 4)
+        {
+            let buf_offset = self.write_index.get();
+            let data: u32 = buf[buf_offset] as u32
+                | (buf[buf_offset + 1] as u32) << 8
+                | (buf[buf_offset + 2] as u32) << 16
+                | (buf[buf_offset + 3] as u32) << 24;
+
+            self.registers.prog_fifo.set(data);
+
+            self.write_index.set(buf_offset + 4);
+        }
+
+        // Save the buffer
+        self.write_buf.replace(buf);
+
+        // Enable interrupts and set the FIFO level
+        self.enable_interrupts();
+        self.registers.fifo_lvl.modify(FIFO_LVL::PROG.val(0xF));
+    }
+
+    /// Copy words out of the read FIFO into `buf` until either is exhausted
+    fn drain_read_fifo(&self, buf: &mut [u8]) {
+        while !self.registers.status.is_set(STATUS::RD_EMPTY) && self.read_index.get() < buf.len() {
+            let data = self.registers.rd_fifo.get().to_ne_bytes();
+            let buf_offset = self.read_index.get();
+
+            buf[buf_offset] = data[0];
+            buf[buf_offset + 1] = data[1];
+            buf[buf_offset + 2] = data[2];
+            buf[buf_offset + 3] = data[3];
+
+            self.read_index.set(buf_offset + 4);
+        }
+    }
+
+    /// Program `words` into the data partition starting at the byte address
+    /// `addr`, busy waiting until they have been written. This avoids a
+    /// whole `LowRiscPage` for small records, such as appending to a log.
+    ///
+    /// The words are split into one program operation per program window
+    /// they touch. They must all be erased beforehand, programming can only
+    /// clear bits. No client is called.
+    ///
+    /// Returns `INVAL` if `words` is empty, `addr` isn't `WORD_SIZE` aligned,
+    /// the words aren't all in the data partition or aren't all erased,
+    /// `BUSY` if another operation is in progress and `FAIL` if the
+    /// controller reported an error, didn't take a word or didn't finish
+    /// within `PROG_POLL_LIMIT` status reads. The words before the failing
+    /// window have been programmed.
+    pub fn write_words(&self, addr: usize, words: &[u32]) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        let end = addr.checked_add(words.len() * WORD_SIZE);
+        if words.is_empty()
+            || addr % WORD_SIZE != 0
+            || addr < FLASH_BASE
+            || end.map_or(true, |end| end > FLASH_BASE + NUM_BANKS * BANK_SIZE)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let erased = (0..words.len()).all(|i| {
+            let word = (addr + i * WORD_SIZE) as *const u32;
+            unsafe { core::ptr::read_volatile(word) == 0xFFFF_FFFF }
+        });
+        if !erased {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if !self.data_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_data_partition(self.region_num);
+        }
+
+        if !self.info_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        }
+
+        let mut offset = 0;
+        while offset < words.len() {
+            let word_addr = addr + offset * WORD_SIZE;
+            let len = calculate_max_prog_len(word_addr, words.len() - offset);
+            self.program_words_sync(word_addr, &words[offset..offset + len])?;
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    /// Program `words`, which fit in one program window, at `addr` and wait
+    /// for the operation to finish. Interrupts are left disabled, the
+    /// completion is picked up from `INTR_STATE` and cleared there.
+    fn program_words_sync(&self, addr: usize, words: &[u32]) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        regs.intr_state
+            .write(INTR::OP_DONE::SET + INTR::OP_ERROR::SET);
+        regs.addr.write(ADDR::START.val(addr as u32));
+        regs.control.write(
+            CONTROL::OP::PROG
+                + CONTROL::PARTITION_SEL::DATA
+                + CONTROL::NUM.val((words.len() - 1) as u32)
+                + CONTROL::START::SET,
+        );
+
+        // A window is larger than the FIFO, so wait for room as it drains
+        for &word in words.iter() {
+            if !(0..PROG_POLL_LIMIT).any(|_| !regs.status.is_set(STATUS::PROG_FULL)) {
+                regs.intr_state
+                    .write(INTR::OP_DONE::SET + INTR::OP_ERROR::SET);
+                return Err(ErrorCode::FAIL);
+            }
+            regs.prog_fifo.set(word);
+        }
+
+        let done = (0..PROG_POLL_LIMIT).any(|_| {
+            let irqs = regs.intr_state.extract();
+            irqs.is_set(INTR::OP_DONE) || irqs.is_set(INTR::OP_ERROR)
+        });
+        let failed = regs.intr_state.is_set(INTR::OP_ERROR);
+        regs.intr_state
+            .write(INTR::OP_DONE::SET + INTR::OP_ERROR::SET);
+
+        if done && !failed {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    /// Read `page`, in `PAGE_SIZE` units, of info partition type `info_type`
+    /// (0, 1 or 2) in `bank` into `buf`. Completes with `read_complete()`.
+    pub fn read_info_page(
+        &self,
+        bank: FlashBank,
+        info_type: usize,
+        page: usize,
+        buf: &'static mut LowRiscPage,
+    ) -> Result<(), (ErrorCode, &'static mut LowRiscPage)> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+
+        let (addr, partition) = match self.info_page_setup(bank, info_type, page) {
+            Ok(setup) => setup,
+            Err(e) => return Err((e, buf)),
+        };
+        self.op.set(Operation::Read);
+
+        // Save the buffer
+        self.read_buf.replace(buf);
+        self.read_index.set(0);
+
+        self.start_page_read(addr, partition);
+
+        Ok(())
+    }
+
+    /// Program `buf` into `page`, in `PAGE_SIZE` units, of info partition
+    /// type `info_type` (0, 1 or 2) in `bank`. Completes with
+    /// `write_complete()`.
+    pub fn write_info_page(
+        &self,
+        bank: FlashBank,
+        info_type: usize,
+        page: usize,
+        buf: &'static mut LowRiscPage,
+    ) -> Result<(), (ErrorCode, &'static mut LowRiscPage)> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.is_busy_for_modify() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+
+        let (addr, partition) = match self.info_page_setup(bank, info_type, page) {
+            Ok(setup) => setup,
+            Err(e) => return Err((e, buf)),
+        };
+        self.op.set(Operation::Write);
+
+        self.start_page_write(addr, partition, buf);
+
+        Ok(())
+    }
+
+    /// The memory protection configuration of `region`
+    pub fn read_adv_region_perms(&self, region: FlashRegion) -> FlashMPAdvConfig {
+        let cfg = self.registers.mp_region_cfg_shadowed[region as usize].extract();
+        FlashMPAdvConfig {
+            enabled: cfg.is_set(MP_REGION_CFG::EN),
+            base: cfg.read(MP_REGION_CFG::BASE) as usize,
+            size: cfg.read(MP_REGION_CFG::SIZE) as usize,
+            read_en: cfg.is_set(MP_REGION_CFG::RD_EN),
+            prog_en: cfg.is_set(MP_REGION_CFG::PROG_EN),
+            erase_en: cfg.is_set(MP_REGION_CFG::ERASE_EN),
+            scramble_en: cfg.is_set(MP_REGION_CFG::SCRAMBLE_EN),
+            ecc_en: cfg.is_set(MP_REGION_CFG::ECC_EN),
+            he_en: cfg.is_set(MP_REGION_CFG::HE_EN),
+        }
+    }
+
+    /// True if the configuration of `region` has been locked until reset
+    pub fn is_region_locked(&self, region: FlashRegion) -> bool {
+        !self.registers.region_cfg_regwen[region as usize].is_set(REGION_CFG_REGWEN::REGION)
+    }
+
+    /// True if the configuration of any region has been locked, for example
+    /// by an earlier boot stage or before an unexpected reset
+    pub fn is_any_region_locked(&self) -> bool {
+        FlashRegion::ALL
+            .iter()
+            .any(|region| self.is_region_locked(*region))
+    }
+
+    /// Lock the configuration of `region` until the next reset. This can't
+    /// be undone, so the caller has to pass the configuration it expects to
+    /// be locking as `expected`, and nothing is locked unless it matches
+    /// what is currently set. On success the locked configuration is
+    /// returned, for the caller to log.
+    ///
+    /// Returns `ALREADY` if the region is already locked, `BUSY` while an
+    /// operation is in progress and `INVAL` if the configuration doesn't
+    /// match `expected`.
+    pub fn lock_region_cfg_confirmed(
+        &self,
+        region: FlashRegion,
+        expected: &FlashMPAdvConfig,
+    ) -> Result<FlashMPAdvConfig, ErrorCode> {
+        if self.is_region_locked(region) {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let cfg = self.read_adv_region_perms(region);
+        if cfg != *expected {
+            return Err(ErrorCode::INVAL);
+        }
+
+        // Write 0 to clear, the bit can't be set again until reset
+        self.registers.region_cfg_regwen[region as usize].write(REGION_CFG_REGWEN::REGION::CLEAR);
+
+        Ok(cfg)
+    }
+
+    /// Set the memory protection configuration of `region`.
+    ///
+    /// Returns `RESERVE` if the region is locked, `BUSY` while an operation
+    /// is in progress and `INVAL` if `base` or `size` don't fit the register.
+    pub fn set_adv_region_perms(
+        &self,
+        region: FlashRegion,
+        cfg: &FlashMPAdvConfig,
+    ) -> Result<(), ErrorCode> {
+        if self.is_region_locked(region) {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if cfg.base > MP_REGION_CFG::BASE.mask as usize
+            || cfg.size > MP_REGION_CFG::SIZE.mask as usize
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let flag = |set: bool, field: Field<u32, MP_REGION_CFG::Register>| {
+            if set {
+                field.val(1)
+            } else {
+                field.val(0)
+            }
+        };
+
+        for _ in 0..2 {
+            self.registers.mp_region_cfg_shadowed[region as usize].write(
+                MP_REGION_CFG::BASE.val(cfg.base as u32)
+                    + MP_REGION_CFG::SIZE.val(cfg.size as u32)
+                    + flag(cfg.read_en, MP_REGION_CFG::RD_EN)
+                    + flag(cfg.prog_en, MP_REGION_CFG::PROG_EN)
+                    + flag(cfg.erase_en, MP_REGION_CFG::ERASE_EN)
+                    + flag(cfg.scramble_en, MP_REGION_CFG::SCRAMBLE_EN)
+                    + flag(cfg.ecc_en, MP_REGION_CFG::ECC_EN)
+                    + flag(cfg.he_en, MP_REGION_CFG::HE_EN)
+                    + flag(cfg.enabled, MP_REGION_CFG::EN),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Enable or disable scrambling and ECC for the data partition region.
+    ///
+    /// Only `scramble_en` and `ecc_en` of `cfg` are used, the rest of the
+    /// region configuration is set by the driver. Data written with one
+    /// setting can't be read back with the other, so the region has to be
+    /// erased before either is changed: `INVAL` is returned if they would
+    /// change and the region isn't blank. Returns `RESERVE` if the region is
+    /// locked and `BUSY` while an operation is in progress.
+    pub fn set_data_partition_security(&self, cfg: &FlashMPAdvConfig) -> Result<(), ErrorCode> {
+        if self.is_region_locked(self.region_num) {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if cfg.scramble_en == self.data_scramble_en.get() && cfg.ecc_en == self.data_ecc_en.get() {
+            return Ok(());
+        }
+
+        if !self.data_configured.get() {
+            self.configure_data_partition(self.region_num);
+        }
+
+        let region = self.read_adv_region_perms(self.region_num);
+        let first_page = (FLASH_BASE + region.base * FLASH_PAGE_SIZE) / PAGE_SIZE;
+        let num_pages = region.size * FLASH_PAGE_SIZE / PAGE_SIZE;
+        if !self.is_region_erased(first_page, num_pages)? {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.data_scramble_en.set(cfg.scramble_en);
+        self.data_ecc_en.set(cfg.ecc_en);
+        self.configure_data_partition(self.region_num);
+
+        Ok(())
+    }
+
+    /// The memory protection configuration of every region, to be put back
+    /// with `restore_regions()` after a temporary change.
+    pub fn snapshot_regions(&self) -> [FlashMPAdvConfig; NUM_MP_REGIONS] {
+        let mut snapshot = [FlashMPAdvConfig::default(); NUM_MP_REGIONS];
+        for (cfg, region) in snapshot.iter_mut().zip(FlashRegion::ALL) {
+            *cfg = self.read_adv_region_perms(region);
+        }
+        snapshot
+    }
+
+    /// Put back a configuration saved by `snapshot_regions()`.
+    ///
+    /// Regions that already match the snapshot are left alone. The others
+    /// are restored unless they can't be written, for example because they
+    /// have been locked, and are then reported in the returned bitmask, bit
+    /// `n` for `REGIONn`.
+    pub fn restore_regions(&self, snapshot: &[FlashMPAdvConfig; NUM_MP_REGIONS]) -> Result<(), u8> {
+        let mut failed = 0;
+        for (cfg, region) in snapshot.iter().zip(FlashRegion::ALL) {
+            if self.read_adv_region_perms(region) != *cfg
+                && self.set_adv_region_perms(region, cfg).is_err()
+            {
+                failed |= 1 << region as u8;
+            }
+        }
+
+        if failed == 0 {
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+
+    /// The address of the first of `num_pages` successive pages from
+    /// `page_number`, if they all lie in the data partition
+    fn data_pages_addr(page_number: usize, num_pages: usize) -> Option<usize> {
+        let addr = page_number.checked_mul(PAGE_SIZE)?;
+        let end = addr.checked_add(num_pages.checked_mul(PAGE_SIZE)?)?;
+        if addr >= FLASH_BASE && end <= FLASH_BASE + NUM_BANKS * BANK_SIZE {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// Check that every word of the page at `addr` reads back as erased
+    fn page_is_erased(&self, addr: usize) -> bool {
+        (0..PAGE_SIZE / WORD_SIZE).all(|i| {
+            let word = (addr + i * WORD_SIZE) as *const u32;
+            unsafe { core::ptr::read_volatile(word) == 0xFFFF_FFFF }
+        })
+    }
+
+    /// Check whether the page `page_number` is blank, every byte reading as
+    /// 0xFF, without modifying it. See `is_region_erased()`.
+    pub fn is_page_erased(&self, page_number: usize) -> Result<bool, ErrorCode> {
+        self.is_region_erased(page_number, 1)
+    }
+
+    /// Check whether `num_pages` successive pages from `page_number` are
+    /// blank, so that a redundant erase can be skipped before writing.
+    ///
+    /// The pages are scanned through the memory mapped flash, the same as
+    /// the strict write check, so this completes synchronously and doesn't
+    /// use the controller. Returns `INVAL` for zero pages or pages outside
+    /// the data partition and `BUSY` while an operation that could change
+    /// the pages is in progress.
+    pub fn is_region_erased(
+        &self,
+        page_number: usize,
+        num_pages: usize,
+    ) -> Result<bool, ErrorCode> {
+        let addr = match Self::data_pages_addr(page_number, num_pages) {
+            Some(addr) if num_pages > 0 => addr,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        Ok((0..num_pages).all(|i| self.page_is_erased(addr + i * PAGE_SIZE)))
+    }
+
+    /// Copy the page `page_number` into `buf` through the memory mapped
+    /// flash, the same way `is_region_erased()` scans it, so this completes
+    /// synchronously and no client is called.
+    ///
+    /// The bytes are in flash order, as the controller would return them.
+    /// Returns `INVAL` for a page outside the data partition, `OFF` once the
+    /// flash has been disabled and `BUSY` while an operation that could
+    /// change the page is in progress.
+    pub fn read_page_sync(
+        &self,
+        page_number: usize,
+        buf: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), ErrorCode> {
+        let addr = match page_number.checked_mul(PAGE_SIZE) {
+            Some(addr) if addr >= FLASH_BASE && addr < FLASH_BASE + NUM_BANKS * BANK_SIZE => addr,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        for (i, chunk) in buf.chunks_exact_mut(WORD_SIZE).enumerate() {
+            let word = (addr + i * WORD_SIZE) as *const u32;
+            chunk.copy_from_slice(&unsafe { core::ptr::read_volatile(word) }.to_ne_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Write the operation status, error and interrupt registers to
+    /// `writer`, for the panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " op_status: {:#010X}  status: {:#010X}  err_code: {:#010X}  fault_status: {:#010X}  err_addr: {:#010X}  intr_state: {:#010X}",
+            regs.op_status.get(),
+            regs.status.get(),
+            regs.err_code.get(),
+            regs.fault_status.get(),
+            regs.err_addr.get(),
+            regs.intr_state.get()
+        ));
+    }
+
+    /// Interrupts are masked for the whole of the handler and enabled again
+    /// in one place at the end, once `read_index`, `write_index` and the
+    /// buffers are up to date, and only if an operation is still in
+    /// progress. Branches must not enable interrupts themselves. Starting a
+    /// new operation, including from a client callback, enables them as part
+    /// of starting it.
+    pub fn handle_interrupt(&self) {
+        let irqs = self.registers.intr_state.extract();
+        let read_done =
+            irqs.is_set(INTR::OP_DONE) && self.registers.control.matches_all(CONTROL::OP::READ);
+
+        self.disable_interrupts(irqs.get());
+
+        self.check_faults();
+
+        if irqs.is_set(INTR::OP_ERROR) {
+            let op = self.op.replace(Operation::Idle);
+
+            if self.verifying.replace(false) {
+                // Failed in either the write or the read back
+                if let Some(buf) = self.read_buf.take() {
+                    self.verify_buf.replace(buf);
+                }
+                let written = self.write_buf.take().or_else(|| self.verify_written.take());
+                if let Some(buf) = written {
+                    self.verified_write_client.map(move |client| {
+                        client.write_verified_complete(buf, Err(ErrorCode::FAIL));
+                    });
+                }
+            }
+
+            if op == Operation::PageErase || op == Operation::BankErase {
+                self.disable_bank_erase();
+                self.suspend.set(EraseSuspend::None);
+                self.registers.erase_suspend.set(0);
+                self.erase_end.set(0);
+                self.flash_client.map(|client| {
+                    client.erase_complete(hil::flash::Error::FlashError);
+                });
+            }
+
+            let read_buf = self.read_buf.take();
+            if let Some(buf) = read_buf {
+                // We were doing a read
+                self.flash_client.map(move |client| {
+                    client.read_complete(buf, hil::flash::Error::FlashError);
+                });
+            }
+
+            let read_pages_buf = self.read_pages_buf.take();
+            if let Some(buf) = read_pages_buf {
+                // We were doing a multi-page read
+                self.read_pages_client.map(move |client| {
+                    client.read_pages_complete(buf, Err(ErrorCode::FAIL));
+                });
+            }
+
+            let write_buf = self.write_buf.take();
+            if let Some(buf) = write_buf {
+                // We were doing a write
+                self.flash_client.map(move |client| {
+                    client.write_complete(buf, hil::flash::Error::FlashError);
+                });
+            }
+
+            // The operation is over and any new one was started by a
+            // callback, so the rest of `irqs` is stale.
+            return;
+        }
+
+        // A fast read can fill the FIFO before the watermark interrupt is
+        // handled, drain on either. Both are handled by a single drain that
+        // advances `read_index`, so no word is read twice.
+        if irqs.is_set(INTR::RD_LVL) || irqs.is_set(INTR::RD_FULL) {
+            self.read_buf.map(|buf| self.drain_read_fifo(&mut buf.0));
+            self.read_pages_buf.map(|buf| self.drain_read_fifo(buf));
+        }
+
+        if irqs.is_set(INTR::PROG_EMPTY) {
+            self.write_buf.map(|buf| {
+                // Everything queued so far has been programmed
+                self.write_progress_client.map(|client| {
+                    client.write_progress(self.write_index.get(), buf.0.len());
+                });
+
+                // Write the data in until we are full
+                while !self.registers.status.is_set(STATUS::PROG_FULL)
+                    && self.write_index.get() < buf.0.len()
+                {
+                    let buf_offset = self.write_index.get();
+                    let data: u32 = buf[buf_offset] as u32
+                        | (buf[buf_offset + 1] as u32) << 8
+                        | (buf[buf_offset + 2] as u32) << 16
+                        | (buf[buf_offset + 3] as u32) << 24;
+
+                    self.registers.prog_fifo.set(data);
+
+                    self.write_index.set(buf_offset + 4);
+                }
+            });
+        }
+
+        if irqs.is_set(INTR::OP_DONE) {
+            if self.registers.control.matches_all(CONTROL::OP::READ) {
+                // The operation is done, so the rest of the data is already
+                // in the FIFO, possibly below the watermark.
+                self.read_buf.map(|buf| self.drain_read_fifo(&mut buf.0));
+                self.read_pages_buf.map(|buf| self.drain_read_fifo(buf));
+
+                let read_buf = self.read_buf.take();
+                if let Some(buf) = read_buf {
+                    // We were doing a read
+                    if self.read_index.get() >= buf.0.len() && self.verifying.replace(false) {
+                        // We have read back a verified write, compare it
+                        self.op.set(Operation::Idle);
+                        let written = self.verify_written.take().unwrap();
+                        let result = if buf.0 == written.0 {
+                            Ok(())
+                        } else {
+                            Err(ErrorCode::FAIL)
+                        };
+                        self.verify_buf.replace(buf);
+                        self.verified_write_client.map(move |client| {
+                            client.write_verified_complete(written, result);
+                        });
+                    } else if self.read_index.get() >= buf.0.len() {
+                        // We have all of the data, call the client
+                        self.op.set(Operation::Idle);
+                        self.flash_client.map(move |client| {
+                            client.read_complete(buf, hil::flash::Error::CommandComplete);
+                        });
+                    } else {
+                        // Still waiting on data, keep waiting
+                        self.read_buf.replace(buf);
+                    }
+                } else if let Some(buf) = self.read_pages_buf.take() {
+                    // We were doing a multi-page read, one page per operation
+                    let read_index = self.read_index.get();
+                    if read_index >= buf.len() {
+                        // We have all of the pages, call the client
+                        self.op.set(Operation::Idle);
+                        self.read_pages_client.map(move |client| {
+                            client.read_pages_complete(buf, Ok(()));
+                        });
+                    } else if read_index > 0 && read_index % PAGE_SIZE == 0 {
+                        // This page is done, start on the next one
+                        let page = self.read_pages_start.get() + read_index / PAGE_SIZE;
+                        self.read_pages_buf.replace(buf);
+                        self.start_page_read(page * PAGE_SIZE, CONTROL::PARTITION_SEL::DATA);
+                    } else {
+                        // Still waiting on data, keep waiting
+                        self.read_pages_buf.replace(buf);
+                    }
+                }
+            } else if self.registers.control.matches_all(CONTROL::OP::PROG) {
+                let write_buf = self.write_buf.take();
+                if let Some(buf) = write_buf {
+                    // We were doing a write
+                    if self.write_index.get() >= buf.0.len() && self.verifying.get() {
+                        // Read the page back to check it
+                        self.verify_written.replace(buf);
+                        self.op.set(Operation::Read);
+                        if let Some(cmp) = self.verify_buf.take() {
+                            self.read_buf.replace(cmp);
+                        }
+                        self.read_index.set(0);
+                        self.start_page_read(
+                            self.verify_page.get() * PAGE_SIZE,
+                            CONTROL::PARTITION_SEL::DATA,
+                        );
+                    } else if self.write_index.get() >= buf.0.len() {
+                        // We sent all of the data, call the client
+                        self.op.set(Operation::Idle);
+                        self.flash_client.map(move |client| {
+                            client.write_complete(buf, hil::flash::Error::CommandComplete);
+                        });
+                    } else {
+                        // Still writing data, keep trying
+                        self.write_buf.replace(buf);
+                    }
+                }
+            } else if self.registers.control.matches_all(CONTROL::OP::ERASE) {
+                // A bank erase is one long operation with a single OP_DONE,
+                // only complete an erase we actually started.
+                let op = self.op.get();
+                let suspend = self.suspend.replace(EraseSuspend::None);
+                self.registers.erase_suspend.set(0);
+                if op == Operation::PageErase
+                    && suspend == EraseSuspend::Requested
+                    && !self.flash_page_is_erased(self.erase_addr.get())
+                {
+                    // This is the suspension, not the end of the erase
+                    self.op.set(Operation::Idle);
+                    self.suspend.set(EraseSuspend::Suspended);
+                } else if op == Operation::PageErase
+                    && self.erase_addr.get() + FLASH_PAGE_SIZE < self.erase_end.get()
+                {
+                    // Move on to the next physical page of the region
+                    self.count_erase(self.erase_addr.get(), 1);
+                    let next = self.erase_addr.get() + FLASH_PAGE_SIZE;
+                    self.erase_addr.set(next);
+                    self.start_page_erase(next);
+                } else if op == Operation::PageErase || op == Operation::BankErase {
+                    if op == Operation::BankErase {
+                        // Don't leave bank erase enabled for a later erase
+                        self.disable_bank_erase();
+                        self.count_erase(self.erase_addr.get(), BANK_SIZE / FLASH_PAGE_SIZE);
+                    } else {
+                        self.count_erase(self.erase_addr.get(), 1);
+                    }
+                    self.op.set(Operation::Idle);
+                    self.flash_client.map(move |client| {
+                        client.erase_complete(hil::flash::Error::CommandComplete);
+                    });
+                }
+            }
+        }
+
+        if read_done && self.suspend.get() == EraseSuspend::Suspended {
+            // The read a suspended erase was waiting on is done. If a
+            // callback started another operation this fails with `BUSY` and
+            // the erase waits for that one too.
+            let _ = self.resume_erase();
+        }
+
+        if self.op.get() != Operation::Idle {
+            self.enable_interrupts();
+        }
+    }
+}
+
+impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for FlashCtrl<'_> {
+    fn set_client(&self, client: &'static C) {
+        self.flash_client.set(client);
+    }
+}
+
+impl hil::flash::Flash for FlashCtrl<'_> {
+    type Page = LowRiscPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        self.op.set(Operation::Read);
+
+        let addr = page_number * PAGE_SIZE;
+
+        if !self.data_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_data_partition(self.region_num);
+        }
+
+        if !self.info_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        }
+
+        // Save the buffer
+        self.read_buf.replace(buf);
+        self.read_index.set(0);
+
+        self.start_page_read(addr, CONTROL::PARTITION_SEL::DATA);
+
+        Ok(())
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if self.is_flash_disabled() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.is_busy_for_modify() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        self.op.set(Operation::Write);
+
+        if !self.data_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_data_partition(self.region_num);
+        }
+
+        if !self.info_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        }
+
+        // Only scan pages that are in the flash
+        if self.strict_write.get()
+            && Self::data_pages_addr(page_number, 1).map(|addr| self.page_is_erased(addr))
+                != Some(true)
+        {
+            self.op.set(Operation::Idle);
+            return Err((ErrorCode::INVAL, buf));
+        }
+
+        let addr = page_number * PAGE_SIZE;
+        self.start_page_write(addr, CONTROL::PARTITION_SEL::DATA, buf);
+
+        Ok(())
+    }
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        if self.is_flash_disabled() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.is_busy_for_modify() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.op.set(Operation::PageErase);
+
+        let addr = page_number * PAGE_SIZE;
+
+        if !self.data_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_data_partition(self.region_num);
+        }
+
+        if !self.info_configured.get() {
+            // If we aren't configured yet, configure now
+            self.configure_info_partition(FlashBank::BANK1, self.region_num);
+        }
+
+        self.erase_addr.set(addr);
+        self.erase_end.set(0);
+        self.start_page_erase(addr);
 
         Ok(())
     }
@@ -0,0 +1,74 @@
+//! Verify a region of flash against a SHA-256 digest
+//!
+//! This combines the flash controller and HMAC drivers to check firmware
+//! before it is trusted, for example at boot. Each page is read through the
+//! memory mapped flash and added to the hash in flash order, so the digest
+//! is the SHA-256 of the region's bytes exactly as they are stored. The
+//! whole check completes synchronously and neither driver's client is
+//! called.
+//!
+//! The HMAC block is switched to plain SHA-256, so this shouldn't be used
+//! while another user of the block has it configured for a hash it hasn't
+//! started yet.
+
+use crate::flash_ctrl::{FlashCtrl, PAGE_SIZE};
+use crate::hmac::{DigestMode, Hmac};
+use kernel::ErrorCode;
+
+/// Length in bytes of the SHA-256 digest a region is checked against
+pub const DIGEST_LEN: usize = 32;
+
+pub struct FlashVerifier<'a> {
+    flash: &'a FlashCtrl<'a>,
+    hmac: &'a Hmac<'a>,
+}
+
+impl<'a> FlashVerifier<'a> {
+    pub fn new(flash: &'a FlashCtrl<'a>, hmac: &'a Hmac<'a>) -> Self {
+        FlashVerifier { flash, hmac }
+    }
+
+    /// Hash the `num_pages` pages starting at `start_page` and compare the
+    /// SHA-256 digest against `expected`, in constant time. Returns
+    /// `Ok(true)` if they match.
+    ///
+    /// Page numbers are in `PAGE_SIZE` units, as for the flash HIL. Returns
+    /// `INVAL` if the region is empty or isn't all in the data partition,
+    /// `BUSY` if either driver has an operation in progress and `FAIL` if
+    /// the HMAC block didn't produce a digest.
+    pub fn verify_flash_region(
+        &self,
+        start_page: usize,
+        num_pages: usize,
+        expected: &[u8; DIGEST_LEN],
+    ) -> Result<bool, ErrorCode> {
+        let end_page = match start_page.checked_add(num_pages) {
+            Some(end_page) if num_pages > 0 => end_page,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        let mut page = [0; PAGE_SIZE];
+
+        // Check both ends of the region can be read before the HMAC is
+        // reconfigured, the pages between them are then in range as well
+        self.flash.read_page_sync(start_page, &mut page)?;
+        self.flash.read_page_sync(end_page - 1, &mut page)?;
+
+        self.hmac.set_mode(DigestMode::Sha256, &[])?;
+        for page_number in start_page..end_page {
+            self.flash.read_page_sync(page_number, &mut page)?;
+            self.hmac.add_data_sync(&page)?;
+        }
+
+        let mut digest = [0; DIGEST_LEN];
+        self.hmac.run_sync(&mut digest)?;
+
+        // Look at every byte, so the time taken doesn't depend on how much
+        // of the digest matches
+        let diff = digest
+            .iter()
+            .zip(expected.iter())
+            .fold(0, |acc, (d, e)| acc | (d ^ e));
+
+        Ok(diff == 0)
+    }
+}
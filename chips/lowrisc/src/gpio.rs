@@ -1,6 +1,32 @@
 //! General Purpose Input/Output driver.
+//!
+//! Pin routing
+//! -----------
+//!
+//! The GPIO block only reaches the outside world through the pinmux. GPIO
+//! `n` reads the pad selected by `MIO_PERIPH_INSEL[n]` and drives a pad
+//! only if that pad's `MIO_OUTSEL` selects GPIO `n`. A pin that isn't routed
+//! can be written without anything happening, so:
+//!
+//! - `configuration()` reports `Function` if the pin's pad is driven by
+//!   another peripheral and `Other` if the pin isn't routed to a pad.
+//! - `make_output()` and `make_input()` claim the pad the pin reads from for
+//!   the GPIO, unless its `MIO_OUTSEL` is locked.
+//! - `route_to_pad()` routes an unrouted pin to a given pad.
+//!
+//! Deferred interrupts
+//! -------------------
+//!
+//! By default the client's `fired()` is called from `handle_interrupt()`.
+//! After `set_deferred(true)` the interrupt is only acknowledged there and
+//! `handle_interrupt()` returns true, the owner of the pin is then expected
+//! to call `service_deferred()` from a deferred call, where the client is
+//! called. Each pin remembers its own pending interrupt, so any number of
+//! pins can fire before the deferred call runs.
 
-use crate::padctrl;
+use crate::padctrl::{self, PinmuxRegisters, MIO_PADS, OUTSEL_GPIO0, WAKEUP_DETECTORS};
+use crate::pwrmgr::PwrMgr;
+use core::cell::Cell;
 use kernel::hil::gpio;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
@@ -8,6 +34,7 @@ use kernel::utilities::registers::{
     register_bitfields, register_structs, Field, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 register_structs! {
     pub GpioRegisters {
@@ -75,24 +102,178 @@ register_bitfields![u32,
 pub struct GpioPin<'a> {
     gpio_registers: StaticRef<GpioRegisters>,
     padctrl_registers: StaticRef<padctrl::PadCtrlRegisters>,
+    pinmux: StaticRef<PinmuxRegisters>,
     pin: Field<u32, pins::Register>,
     client: OptionalCell<&'a dyn gpio::Client>,
+    open_drain: Cell<bool>,
+    counting: Cell<bool>,
+    edge_count: Cell<u32>,
+    defer: Cell<bool>,
+    deferred_pending: Cell<bool>,
 }
 
 impl<'a> GpioPin<'a> {
     pub const fn new(
         gpio_base: StaticRef<GpioRegisters>,
         padctrl_base: StaticRef<padctrl::PadCtrlRegisters>,
+        pinmux: StaticRef<PinmuxRegisters>,
         pin: Field<u32, pins::Register>,
     ) -> GpioPin<'a> {
         GpioPin {
             gpio_registers: gpio_base,
             padctrl_registers: padctrl_base,
+            pinmux,
             pin: pin,
             client: OptionalCell::empty(),
+            open_drain: Cell::new(false),
+            counting: Cell::new(false),
+            edge_count: Cell::new(0),
+            defer: Cell::new(false),
+            deferred_pending: Cell::new(false),
         }
     }
 
+    /// Call the client from `service_deferred()` rather than from
+    /// `handle_interrupt()`, see the module documentation
+    pub fn set_deferred(&self, defer: bool) {
+        self.defer.set(defer);
+    }
+
+    pub fn is_deferred(&self) -> bool {
+        self.defer.get()
+    }
+
+    /// Call the client for an interrupt `handle_interrupt()` left pending,
+    /// if there is one
+    pub fn service_deferred(&self) {
+        if self.deferred_pending.replace(false) {
+            self.client.map(|client| {
+                client.fired();
+            });
+        }
+    }
+
+    /// Count `edge` transitions on this pin, read the count with
+    /// `read_and_reset_count()`. While counting, edges aren't reported to
+    /// the interrupt client.
+    ///
+    /// The GPIO block has no hardware counter, so this counts in the
+    /// interrupt handler. The interrupt status is a single sticky bit, so
+    /// edges that arrive before the kernel has serviced the previous one are
+    /// merged and counted once. The highest edge rate that can be counted
+    /// is therefore set by the interrupt latency of the kernel loop, and
+    /// under load can be well below 1 kHz. This suits low rate inputs such
+    /// as tachometers and flow sensors.
+    pub fn enable_edge_counter(&self, edge: gpio::InterruptEdge) {
+        self.edge_count.set(0);
+        self.counting.set(true);
+        gpio::Interrupt::enable_interrupts(self, edge);
+    }
+
+    pub fn disable_edge_counter(&self) {
+        gpio::Interrupt::disable_interrupts(self);
+        self.counting.set(false);
+    }
+
+    /// Return the number of edges counted since the last call and restart
+    /// the count. The count saturates at `u32::MAX`.
+    ///
+    /// The count is only updated from `handle_interrupt()`, which runs in
+    /// the kernel loop rather than preempting it, so no edge counted between
+    /// the read and the reset can be lost.
+    pub fn read_and_reset_count(&self) -> u32 {
+        self.edge_count.replace(0)
+    }
+
+    /// Emulate an open-drain output on this pin.
+    ///
+    /// The output value is held low and `set()`/`clear()` switch the output
+    /// enable instead: `clear()` drives the pin low and `set()` releases it,
+    /// leaving a pull-up to bring the line high. This enables the pad pull-up,
+    /// but that is weak, so a bus with any real capacitance or speed (1-Wire,
+    /// I2C) needs an external pull-up as well.
+    ///
+    /// The pin starts released. `make_output()` or `disable_output()` return
+    /// the pin to normal push-pull operation.
+    pub fn make_open_drain(&self) {
+        self.open_drain.set(true);
+        self.set_oe(false);
+        GpioPin::half_set(
+            false,
+            self.pin,
+            &self.gpio_registers.masked_out_lower,
+            &self.gpio_registers.masked_out_upper,
+        );
+        gpio::Configure::set_floating_state(self, gpio::FloatingState::PullUp);
+    }
+
+    pub fn is_open_drain(&self) -> bool {
+        self.open_drain.get()
+    }
+
+    /// The MIO pad this pin reads from, if it is routed to one
+    fn pad(&self) -> Option<usize> {
+        match self.pinmux.mio_periph_insel[self.pin.shift].read(padctrl::PADSEL::SEL) {
+            // Tied to a constant
+            0 | 1 => None,
+            sel => Some(sel as usize - 2),
+        }
+    }
+
+    /// The `MIO_OUTSEL` value for this pin
+    fn outsel(&self) -> u32 {
+        OUTSEL_GPIO0 + self.pin.shift as u32
+    }
+
+    /// True if the pin's pad is driven by this pin, or undriven
+    fn pad_is_gpio(&self, pad: usize) -> bool {
+        match self.pinmux.mio_outsel[pad].read(padctrl::OUTSEL::SEL) {
+            padctrl::OUTSEL_HIGH_Z => true,
+            sel => sel == self.outsel(),
+        }
+    }
+
+    /// Route the pad this pin reads from to this pin, if it isn't already
+    /// and `MIO_OUTSEL` isn't locked.
+    fn claim_pad(&self) {
+        if let Some(pad) = self.pad() {
+            if self.pinmux.mio_outsel[pad].read(padctrl::OUTSEL::SEL) != self.outsel()
+                && self.pinmux.mio_outsel_regwen[pad].is_set(padctrl::REGEN::WEN)
+            {
+                self.pinmux.mio_outsel[pad].write(padctrl::OUTSEL::SEL.val(self.outsel()));
+            }
+        }
+    }
+
+    /// Route this pin to and from MIO pad `pad`, for a pin the pinmux
+    /// hasn't been set up for.
+    ///
+    /// Returns INVAL if there is no such pad and FAIL if the pad's
+    /// `MIO_OUTSEL` is locked.
+    pub fn route_to_pad(&self, pad: usize) -> Result<(), ErrorCode> {
+        if pad >= MIO_PADS {
+            return Err(ErrorCode::INVAL);
+        }
+        if !self.pinmux.mio_outsel_regwen[pad].is_set(padctrl::REGEN::WEN) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        self.pinmux.mio_periph_insel[self.pin.shift]
+            .write(padctrl::PADSEL::SEL.val(pad as u32 + 2));
+        self.pinmux.mio_outsel[pad].write(padctrl::OUTSEL::SEL.val(self.outsel()));
+
+        Ok(())
+    }
+
+    fn set_oe(&self, enable: bool) {
+        GpioPin::half_set(
+            enable,
+            self.pin,
+            &self.gpio_registers.masked_oe_lower,
+            &self.gpio_registers.masked_oe_upper,
+        );
+    }
+
     #[inline(always)]
     fn half_set(
         val: bool,
@@ -100,32 +281,45 @@ impl<'a> GpioPin<'a> {
         lower: &ReadWrite<u32, mask_half::Register>,
         upper: &ReadWrite<u32, mask_half::Register>,
     ) {
-        let shift = field.shift;
-        let bit = if val { 1u32 } else { 0u32 };
-        if shift < 16 {
-            lower.write(mask_half::data.val(bit << shift) + mask_half::mask.val(1u32 << shift));
-        } else {
-            let upper_shift = shift - 16;
-            upper.write(
-                mask_half::data.val(bit << upper_shift) + mask_half::mask.val(1u32 << upper_shift),
-            );
-        }
+        let bit = 1u32 << field.shift;
+        masked_write(bit, if val { bit } else { 0 }, lower, upper);
     }
 
-    pub fn handle_interrupt(&self) {
+    /// Acknowledge an interrupt on this pin and report it. Returns true if
+    /// the client is still to be called by `service_deferred()`.
+    pub fn handle_interrupt(&self) -> bool {
         let pin = self.pin;
 
         if self.gpio_registers.intr_state.is_set(pin) {
-            self.gpio_registers.intr_state.modify(pin.val(1));
+            // Write one to clear, only this pin
+            self.gpio_registers.intr_state.write(pin.val(1));
+            if self.counting.get() {
+                self.edge_count.set(self.edge_count.get().saturating_add(1));
+                return false;
+            }
+            if self.defer.get() {
+                self.deferred_pending.set(true);
+                return true;
+            }
             self.client.map(|client| {
                 client.fired();
             });
         }
+        false
     }
 }
 
 impl gpio::Configure for GpioPin<'_> {
     fn configuration(&self) -> gpio::Configuration {
+        match self.pad() {
+            None => return gpio::Configuration::Other,
+            Some(pad) if !self.pad_is_gpio(pad) => return gpio::Configuration::Function,
+            Some(_) => {}
+        }
+
+        if self.open_drain.get() {
+            return gpio::Configuration::InputOutput;
+        }
         match self.gpio_registers.direct_oe.is_set(self.pin) {
             true => gpio::Configuration::InputOutput,
             false => gpio::Configuration::Input,
@@ -187,26 +381,20 @@ impl gpio::Configure for GpioPin<'_> {
     }
 
     fn make_output(&self) -> gpio::Configuration {
-        GpioPin::half_set(
-            true,
-            self.pin,
-            &self.gpio_registers.masked_oe_lower,
-            &self.gpio_registers.masked_oe_upper,
-        );
-        gpio::Configuration::InputOutput
+        self.claim_pad();
+        self.open_drain.set(false);
+        self.set_oe(true);
+        self.configuration()
     }
 
     fn disable_output(&self) -> gpio::Configuration {
-        GpioPin::half_set(
-            false,
-            self.pin,
-            &self.gpio_registers.masked_oe_lower,
-            &self.gpio_registers.masked_oe_upper,
-        );
-        gpio::Configuration::Input
+        self.open_drain.set(false);
+        self.set_oe(false);
+        self.configuration()
     }
 
     fn make_input(&self) -> gpio::Configuration {
+        self.claim_pad();
         self.configuration()
     }
 
@@ -224,6 +412,14 @@ impl gpio::Input for GpioPin<'_> {
 impl gpio::Output for GpioPin<'_> {
     fn toggle(&self) -> bool {
         let pin = self.pin;
+
+        if self.open_drain.get() {
+            // Released (high) when the output is disabled
+            let new_state = self.gpio_registers.direct_oe.is_set(pin);
+            self.set_oe(!new_state);
+            return new_state;
+        }
+
         let new_state = !self.gpio_registers.direct_out.is_set(pin);
 
         GpioPin::half_set(
@@ -236,6 +432,10 @@ impl gpio::Output for GpioPin<'_> {
     }
 
     fn set(&self) {
+        if self.open_drain.get() {
+            self.set_oe(false);
+            return;
+        }
         GpioPin::half_set(
             true,
             self.pin,
@@ -245,6 +445,10 @@ impl gpio::Output for GpioPin<'_> {
     }
 
     fn clear(&self) {
+        if self.open_drain.get() {
+            self.set_oe(true);
+            return;
+        }
         GpioPin::half_set(
             false,
             self.pin,
@@ -276,7 +480,9 @@ impl<'a> gpio::Interrupt<'a> for GpioPin<'a> {
                 self.gpio_registers.intr_ctrl_en_falling.modify(pin.val(1));
             }
         }
-        self.gpio_registers.intr_state.modify(pin.val(1));
+        // `INTR_STATE` is write one to clear, so write rather than modify or
+        // the interrupts pending on every other pin are cleared as well.
+        self.gpio_registers.intr_state.write(pin.val(1));
         self.gpio_registers.intr_enable.modify(pin.val(1));
     }
 
@@ -285,10 +491,206 @@ impl<'a> gpio::Interrupt<'a> for GpioPin<'a> {
 
         self.gpio_registers.intr_enable.modify(pin.val(0));
         // Clear any pending interrupt
-        self.gpio_registers.intr_state.modify(pin.val(1));
+        self.gpio_registers.intr_state.write(pin.val(1));
     }
 
     fn is_pending(&self) -> bool {
         self.gpio_registers.intr_state.is_set(self.pin)
     }
 }
+
+/// Write the bits of `value` selected by `mask` through a pair of masked
+/// registers, leaving the other bits alone. Only the halves with a bit set
+/// in `mask` are written.
+fn masked_write(
+    mask: u32,
+    value: u32,
+    lower: &ReadWrite<u32, mask_half::Register>,
+    upper: &ReadWrite<u32, mask_half::Register>,
+) {
+    let (lower_mask, upper_mask) = (mask & 0xFFFF, mask >> 16);
+    if lower_mask != 0 {
+        lower.write(mask_half::data.val(value & 0xFFFF) + mask_half::mask.val(lower_mask));
+    }
+    if upper_mask != 0 {
+        upper.write(mask_half::data.val(value >> 16) + mask_half::mask.val(upper_mask));
+    }
+}
+
+/// Access to several pins of a GPIO block at once.
+///
+/// Writes go through the masked registers, so pins outside the mask are
+/// left alone and no read-modify-write is needed. A mask that only covers
+/// pins 0-15 or 16-31 is a single register write, one that covers both
+/// halves takes two.
+///
+/// This doesn't touch the pinmux, pins must already be routed to their pads,
+/// for example by `make_output()` or `make_input()` on each `GpioPin`.
+pub struct GpioPort {
+    registers: StaticRef<GpioRegisters>,
+}
+
+impl GpioPort {
+    pub const fn new(registers: StaticRef<GpioRegisters>) -> GpioPort {
+        GpioPort { registers }
+    }
+
+    /// The input value of every pin
+    pub fn read_all(&self) -> u32 {
+        self.registers.data_in.get()
+    }
+
+    /// Drive the pins in `mask` to the matching bits of `value`
+    pub fn write_masked(&self, mask: u32, value: u32) {
+        masked_write(
+            mask,
+            value,
+            &self.registers.masked_out_lower,
+            &self.registers.masked_out_upper,
+        );
+    }
+
+    /// Enable the output drivers of the pins in `mask` that are set in
+    /// `enable` and disable the others in `mask`
+    pub fn set_output_enable_masked(&self, mask: u32, enable: u32) {
+        masked_write(
+            mask,
+            enable,
+            &self.registers.masked_oe_lower,
+            &self.registers.masked_oe_upper,
+        );
+    }
+}
+
+/// Wakeup from low power on a GPIO edge.
+///
+/// GPIO inputs can't wake the chip by themselves, as the GPIO block is
+/// powered down in deep sleep. Instead one of the pinmux wakeup detectors is
+/// pointed at the pad the GPIO is routed from and the pinmux is enabled as a
+/// pwrmgr wakeup source.
+///
+/// Only pins routed from a MIO pad can be wakeup sources, the dedicated
+/// (DIO) pads aren't reachable from the GPIO block and unrouted pins have no
+/// pad to watch. There are `WAKEUP_DETECTORS` detectors, so at most that
+/// many pins can be armed at once.
+pub struct GpioWakeup {
+    pinmux: StaticRef<PinmuxRegisters>,
+    pwrmgr: PwrMgr,
+    pwrmgr_source: usize,
+}
+
+impl GpioWakeup {
+    /// `pwrmgr_source` is the pinmux's wakeup source number in the pwrmgr.
+    pub const fn new(
+        pinmux: StaticRef<PinmuxRegisters>,
+        pwrmgr: PwrMgr,
+        pwrmgr_source: usize,
+    ) -> GpioWakeup {
+        GpioWakeup {
+            pinmux,
+            pwrmgr,
+            pwrmgr_source,
+        }
+    }
+
+    /// The pad `pin` is routed from, in `PADSEL` encoding
+    fn pad(&self, pin: usize) -> Option<u32> {
+        if pin >= 32 {
+            return None;
+        }
+        match self.pinmux.mio_periph_insel[pin].read(padctrl::PADSEL::SEL) {
+            // Tied to a constant
+            0 | 1 => None,
+            pad => Some(pad),
+        }
+    }
+
+    /// The enabled detector watching `pad`
+    fn detector(&self, pad: u32) -> Option<usize> {
+        let regs = self.pinmux;
+
+        (0..WAKEUP_DETECTORS).find(|&i| {
+            regs.wkup_detector_en[i].is_set(padctrl::WKUP_DETECTOR_EN::EN)
+                && regs.wkup_detector_padsel[i].read(padctrl::PADSEL::SEL) == pad
+        })
+    }
+
+    /// Arm `pin` to wake the chip on `edge`.
+    ///
+    /// Returns INVAL if the pin isn't routed from a MIO pad and NOMEM if all
+    /// the unlocked detectors are in use. Arming an armed pin changes its
+    /// edge.
+    pub fn set_wakeup(&self, pin: usize, edge: gpio::InterruptEdge) -> Result<(), ErrorCode> {
+        let regs = self.pinmux;
+        let pad = self.pad(pin).ok_or(ErrorCode::INVAL)?;
+
+        let detector = self
+            .detector(pad)
+            .or_else(|| {
+                (0..WAKEUP_DETECTORS).find(|&i| {
+                    regs.wkup_detector_regwen[i].is_set(padctrl::REGEN::WEN)
+                        && !regs.wkup_detector_en[i].is_set(padctrl::WKUP_DETECTOR_EN::EN)
+                })
+            })
+            .ok_or(ErrorCode::NOMEM)?;
+
+        let mode = match edge {
+            gpio::InterruptEdge::RisingEdge => padctrl::WKUP_DETECTOR::MODE::POSEDGE,
+            gpio::InterruptEdge::FallingEdge => padctrl::WKUP_DETECTOR::MODE::NEGEDGE,
+            gpio::InterruptEdge::EitherEdge => padctrl::WKUP_DETECTOR::MODE::EDGE,
+        };
+
+        // The detector must be disabled while it is reconfigured
+        regs.wkup_detector_en[detector].write(padctrl::WKUP_DETECTOR_EN::EN::CLEAR);
+        regs.wkup_detector[detector].write(mode + padctrl::WKUP_DETECTOR::MIODIO::CLEAR);
+        regs.wkup_detector_cnt_th[detector].set(0);
+        regs.wkup_detector_padsel[detector].write(padctrl::PADSEL::SEL.val(pad));
+        regs.wkup_detector_en[detector].write(padctrl::WKUP_DETECTOR_EN::EN::SET);
+
+        self.pwrmgr.enable_wakeup(self.pwrmgr_source)
+    }
+
+    /// Stop `pin` from waking the chip.
+    ///
+    /// The pinmux is removed as a pwrmgr wakeup source once no pins are
+    /// armed.
+    pub fn clear_wakeup(&self, pin: usize) -> Result<(), ErrorCode> {
+        let regs = self.pinmux;
+        let pad = self.pad(pin).ok_or(ErrorCode::INVAL)?;
+        let detector = self.detector(pad).ok_or(ErrorCode::ALREADY)?;
+
+        regs.wkup_detector_en[detector].write(padctrl::WKUP_DETECTOR_EN::EN::CLEAR);
+
+        if (0..WAKEUP_DETECTORS)
+            .all(|i| !regs.wkup_detector_en[i].is_set(padctrl::WKUP_DETECTOR_EN::EN))
+        {
+            self.pwrmgr.disable_wakeup(self.pwrmgr_source)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the pin that woke the chip, if the last wakeup was caused by
+    /// a pin.
+    ///
+    /// If several pins fired the lowest numbered detector wins.
+    pub fn wakeup_pin(&self) -> Option<usize> {
+        let regs = self.pinmux;
+
+        if self.pwrmgr.wakeup_reasons() & (1 << self.pwrmgr_source) == 0 {
+            return None;
+        }
+
+        let cause = regs.wkup_cause.get();
+        let detector = (0..WAKEUP_DETECTORS).find(|&i| cause & (1 << i) != 0)?;
+        let pad = regs.wkup_detector_padsel[detector].read(padctrl::PADSEL::SEL);
+
+        (0..32).find(|&pin| self.pad(pin) == Some(pad))
+    }
+
+    /// Clear the recorded wakeup cause, ready for the next sleep
+    pub fn clear_wakeup_cause(&self) {
+        // WKUP_CAUSE is write 0 to clear
+        self.pinmux.wkup_cause.set(0);
+        self.pwrmgr.clear_wakeup_reasons(1 << self.pwrmgr_source);
+    }
+}
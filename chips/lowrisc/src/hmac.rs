@@ -1,6 +1,12 @@
 //! SHA256 HMAC (Hash-based Message Authentication Code).
+//!
+//! The block only implements SHA-256 and HMAC-SHA256. The SHA-384/512 mode
+//! traits are implemented so the driver fits the digest HIL, but setting
+//! those modes returns `NOSUPPORT` without touching the hardware. Use
+//! `supported_modes()` to find out what can be used.
 
 use core::cell::Cell;
+use core::fmt::Write;
 use core::ops::Index;
 use kernel::hil;
 use kernel::hil::digest::{self, DigestData, DigestHash};
@@ -71,6 +77,33 @@ register_bitfields![u32,
     ]
 ];
 
+/// Intermediate state of a hash in progress, see `Hmac::save_context()`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HmacContext {
+    /// The working digest, as read from the `DIGEST` registers
+    pub digest: [u32; 8],
+    /// Number of message bits processed so far
+    pub msg_length: u64,
+}
+
+/// A digest algorithm that can be requested with `Hmac::set_mode()`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DigestMode {
+    Sha256,
+    Sha384,
+    Sha512,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+/// Number of interrupt state reads `run_sync()` waits for the digest, and
+/// of attempts `add_data_sync()` makes to write into a full message FIFO
+pub const SYNC_POLL_LIMIT: usize = 1_000_000;
+
+/// The modes the HMAC block implements
+pub const SUPPORTED_MODES: [DigestMode; 2] = [DigestMode::Sha256, DigestMode::HmacSha256];
+
 pub struct Hmac<'a> {
     registers: StaticRef<HmacRegisters>,
     client: OptionalCell<&'a dyn hil::digest::Client<32>>,
@@ -79,6 +112,7 @@ pub struct Hmac<'a> {
     digest: Cell<Option<&'static mut [u8; 32]>>,
     cancelled: Cell<bool>,
     busy: Cell<bool>,
+    auto_wipe: Cell<bool>,
 }
 
 impl Hmac<'_> {
@@ -91,12 +125,137 @@ impl Hmac<'_> {
             digest: Cell::new(None),
             cancelled: Cell::new(false),
             busy: Cell::new(false),
+            auto_wipe: Cell::new(false),
+        }
+    }
+
+    /// Automatically `wipe()` the key and digest after every hash completes,
+    /// once the result has been copied out and before the client is called.
+    /// Off by default. While enabled the key has to be set again with
+    /// `set_mode()` before each keyed hash, otherwise the next one uses the
+    /// wiped, all zero key.
+    pub fn set_auto_wipe(&self, enable: bool) {
+        self.auto_wipe.set(enable);
+    }
+
+    /// Clear the key and digest, so a later, unkeyed user of the block can't
+    /// recover them. Every key register is written and the hardware wipe is
+    /// always triggered, so the time taken doesn't depend on the key.
+    pub fn wipe(&self) {
+        let regs = self.registers;
+
+        for key in regs.key.iter() {
+            key.set(0);
+        }
+        // Overwrites the internal key, hash state and digest with the value
+        // written
+        regs.wipe_secret.set(0);
+    }
+
+    /// Returns true if the digest registers are all zero, as they are after a
+    /// `wipe()`. The key registers are write only, so can't be checked.
+    pub fn is_wiped(&self) -> bool {
+        self.registers.digest.iter().fold(0, |acc, d| acc | d.get()) == 0
+    }
+
+    /// The digest modes `set_mode()` accepts
+    pub fn supported_modes(&self) -> &'static [DigestMode] {
+        &SUPPORTED_MODES
+    }
+
+    /// Configure the block for `mode`. `key` is only used by the HMAC
+    /// modes, for the plain hashes it is ignored.
+    ///
+    /// Returns `NOSUPPORT`, leaving the configuration alone, for a mode not
+    /// in `supported_modes()` or a key longer than 32 bytes, and `BUSY`
+    /// while a hash is in progress.
+    pub fn set_mode(&self, mode: DigestMode, key: &[u8]) -> Result<(), ErrorCode> {
+        if !SUPPORTED_MODES.contains(&mode) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        match mode {
+            DigestMode::HmacSha256 => self.configure_hmac_sha256(key),
+            _ => {
+                self.configure_sha256();
+                Ok(())
+            }
+        }
+    }
+
+    fn configure_hmac_sha256(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+        let mut key_idx = 0;
+
+        if key.len() > 32 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        // Ensure the HMAC is setup
+        regs.cfg.write(
+            CFG::HMAC_EN::SET + CFG::SHA_EN::SET + CFG::ENDIAN_SWAP::CLEAR + CFG::DIGEST_SWAP::SET,
+        );
+
+        for i in 0..(key.len() / 4) {
+            let idx = i * 4;
+
+            let mut k = key[idx + 3] as u32;
+            k |= (key[i * 4 + 2] as u32) << 8;
+            k |= (key[i * 4 + 1] as u32) << 16;
+            k |= (key[i * 4 + 0] as u32) << 24;
+
+            regs.key[i as usize].set(k);
+            key_idx = i + 1;
+        }
+
+        if (key.len() % 4) != 0 {
+            let mut k = 0;
+
+            for i in 0..(key.len() % 4) {
+                k = k | (key[key_idx * 4 + i] as u32) << (8 * (3 - i));
+            }
+
+            regs.key[key_idx].set(k);
+            key_idx = key_idx + 1;
+        }
+
+        for i in key_idx..8 {
+            regs.key[i as usize].set(0);
+        }
+
+        Ok(())
+    }
+
+    fn configure_sha256(&self) {
+        let regs = self.registers;
+
+        // Ensure the SHA is setup
+        regs.cfg.write(
+            CFG::HMAC_EN::CLEAR
+                + CFG::SHA_EN::SET
+                + CFG::ENDIAN_SWAP::CLEAR
+                + CFG::DIGEST_SWAP::SET,
+        );
+    }
+
+    /// Stop the hash once the digest has been read out
+    fn finish(&self) {
+        self.registers.cmd.modify(CMD::START::CLEAR);
+        if self.auto_wipe.get() {
+            self.wipe();
         }
+        self.cancelled.set(false);
     }
 
-    fn process(&self, data: &dyn Index<usize, Output = u8>, count: usize) -> usize {
+    fn process<D: Index<usize, Output = u8> + ?Sized>(&self, data: &D, count: usize) -> usize {
         let regs = self.registers;
-        for i in 0..(count / 4) {
+        let words = count / 4;
+
+        for i in 0..words {
             if regs.status.is_set(STATUS::FIFO_FULL) {
                 return i * 4;
             }
@@ -111,12 +270,18 @@ impl Hmac<'_> {
             regs.msg_fifo.set(d);
         }
 
-        if (count % 4) != 0 {
-            for i in 0..(count % 4) {
-                let data_idx = (count - (count % 4)) + i;
-                regs.msg_fifo_8.set(data[data_idx]);
+        // The hardware counts the message length from the bytes written to
+        // the FIFO and pads based on that, so the trailing bytes must be
+        // written one at a time. Writing them as a zero filled word would
+        // hash extra bytes.
+        for data_idx in (words * 4)..count {
+            if regs.status.is_set(STATUS::FIFO_FULL) {
+                return data_idx;
             }
+
+            regs.msg_fifo_8.set(data[data_idx]);
         }
+
         count
     }
 
@@ -149,6 +314,127 @@ impl Hmac<'_> {
         })
     }
 
+    /// Add `data` to the hash without handing over a static buffer, for
+    /// small amounts of transient data such as headers.
+    ///
+    /// This blocks until all of `data` has been written into the message
+    /// FIFO, waiting up to `SYNC_POLL_LIMIT` attempts for the hardware to
+    /// make room whenever the FIFO is full, and makes no `add_data_done()`
+    /// callback. It can be mixed with `add_data()` and `add_mut_data()`,
+    /// which should still be used for large amounts of data. Returns BUSY if
+    /// an asynchronous add or a hash is in progress and FAIL if the FIFO
+    /// stayed full, in which case only part of `data` has been added.
+    pub fn add_data_sync(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.registers.cmd.modify(CMD::START::SET);
+
+        let mut offset = 0;
+        while offset < data.len() {
+            let progress = (0..SYNC_POLL_LIMIT).any(|_| {
+                let count = self.process(&data[offset..], data.len() - offset);
+                offset += count;
+                count > 0
+            });
+            if !progress {
+                return Err(ErrorCode::FAIL);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish the hash started with `add_data_sync()` and copy the digest
+    /// into `digest`, without a `hash_done()` callback.
+    ///
+    /// This waits up to `SYNC_POLL_LIMIT` reads of the interrupt state for
+    /// the hardware to finish. The digest is in the same byte order as
+    /// `run()` returns it. Returns BUSY if an asynchronous add or a hash is
+    /// in progress and FAIL if the hardware reported an error or didn't
+    /// finish in time, in which case `digest` is left alone.
+    pub fn run_sync(&self, digest: &mut [u8; 32]) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        regs.intr_state
+            .modify(INTR_STATE::HMAC_DONE::SET + INTR_STATE::HMAC_ERR::SET);
+        regs.cmd.modify(CMD::PROCESS::SET);
+
+        let done = (0..SYNC_POLL_LIMIT).any(|_| {
+            regs.intr_state.is_set(INTR_STATE::HMAC_DONE)
+                || regs.intr_state.is_set(INTR_STATE::HMAC_ERR)
+        });
+        let intrs = regs.intr_state.extract();
+        regs.intr_state
+            .modify(INTR_STATE::HMAC_DONE::SET + INTR_STATE::HMAC_ERR::SET);
+
+        let result = if done && !intrs.is_set(INTR_STATE::HMAC_ERR) {
+            for (i, chunk) in digest.chunks_exact_mut(4).enumerate() {
+                chunk.copy_from_slice(&regs.digest[i].get().to_ne_bytes());
+            }
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        };
+        self.finish();
+
+        result
+    }
+
+    /// Read the intermediate state of the hash in progress.
+    ///
+    /// The state is only complete on a block boundary, as the hardware
+    /// buffers a partial block internally where it can't be read. Returns
+    /// BUSY if data is still being added and INVAL if the message so far
+    /// isn't a whole number of 512 bit blocks.
+    pub fn save_context(&self) -> Result<HmacContext, ErrorCode> {
+        let regs = self.registers;
+
+        if self.busy.get() || !regs.status.is_set(STATUS::FIFO_EMPTY) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let msg_length =
+            (regs.msg_length_upper.get() as u64) << 32 | regs.msg_length_lower.get() as u64;
+        if msg_length % 512 != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let mut digest = [0; 8];
+        for (i, d) in digest.iter_mut().enumerate() {
+            *d = regs.digest[i].get();
+        }
+
+        Ok(HmacContext { digest, msg_length })
+    }
+
+    /// Load a state saved by `save_context()` back into the hardware.
+    ///
+    /// This revision of the HMAC block has read only `DIGEST` and
+    /// `MSG_LENGTH` registers and no way to resume a hash from a given
+    /// state, so this always returns NOSUPPORT. Interleaving streams on this
+    /// hardware requires finishing one before starting the next.
+    pub fn restore_context(&self, _context: &HmacContext) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Write the status, error and interrupt registers to `writer`, for the
+    /// panic output. The digest and key are not included.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " status: {:#010X}  err_code: {:#010X}  intr_state: {:#010X}",
+            regs.status.get(),
+            regs.err_code.get(),
+            regs.intr_state.get()
+        ));
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let intrs = regs.intr_state.extract();
@@ -181,13 +467,11 @@ impl Hmac<'_> {
                         }
                     }
 
-                    if self.cancelled.get() {
-                        self.clear_data();
-                        self.cancelled.set(false);
+                    let cancelled = self.cancelled.get();
+                    self.finish();
+                    if cancelled {
                         client.verification_done(Err(ErrorCode::CANCEL), digest);
                     } else {
-                        self.clear_data();
-                        self.cancelled.set(false);
                         client.verification_done(Ok(equal), digest);
                     }
                 } else {
@@ -201,13 +485,11 @@ impl Hmac<'_> {
                         digest[idx + 2] = d[2];
                         digest[idx + 3] = d[3];
                     }
-                    if self.cancelled.get() {
-                        self.clear_data();
-                        self.cancelled.set(false);
+                    let cancelled = self.cancelled.get();
+                    self.finish();
+                    if cancelled {
                         client.hash_done(Err(ErrorCode::CANCEL), digest);
                     } else {
-                        self.clear_data();
-                        self.cancelled.set(false);
                         client.hash_done(Ok(()), digest);
                     }
                 }
@@ -312,7 +594,7 @@ impl<'a> hil::digest::DigestData<'a, 32> for Hmac<'a> {
     fn clear_data(&self) {
         let regs = self.registers;
         regs.cmd.modify(CMD::START::CLEAR);
-        regs.wipe_secret.set(1 as u32);
+        self.wipe();
         self.cancelled.set(true);
     }
 }
@@ -358,91 +640,36 @@ impl<'a> hil::digest::Digest<'a, 32> for Hmac<'a> {
 
 impl hil::digest::HmacSha256 for Hmac<'_> {
     fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ErrorCode> {
-        if self.busy.get() {
-            return Err(ErrorCode::BUSY);
-        }
-        let regs = self.registers;
-        let mut key_idx = 0;
-
-        if key.len() > 32 {
-            return Err(ErrorCode::NOSUPPORT);
-        }
-
-        // Ensure the HMAC is setup
-        regs.cfg.write(
-            CFG::HMAC_EN::SET + CFG::SHA_EN::SET + CFG::ENDIAN_SWAP::CLEAR + CFG::DIGEST_SWAP::SET,
-        );
-
-        for i in 0..(key.len() / 4) {
-            let idx = i * 4;
-
-            let mut k = key[idx + 3] as u32;
-            k |= (key[i * 4 + 2] as u32) << 8;
-            k |= (key[i * 4 + 1] as u32) << 16;
-            k |= (key[i * 4 + 0] as u32) << 24;
-
-            regs.key[i as usize].set(k);
-            key_idx = i + 1;
-        }
-
-        if (key.len() % 4) != 0 {
-            let mut k = 0;
-
-            for i in 0..(key.len() % 4) {
-                k = k | (key[key_idx * 4 + i] as u32) << (8 * (3 - i));
-            }
-
-            regs.key[key_idx].set(k);
-            key_idx = key_idx + 1;
-        }
-
-        for i in key_idx..8 {
-            regs.key[i as usize].set(0);
-        }
-
-        Ok(())
+        self.set_mode(DigestMode::HmacSha256, key)
     }
 }
 
 impl hil::digest::HmacSha384 for Hmac<'_> {
-    fn set_mode_hmacsha384(&self, _key: &[u8]) -> Result<(), ErrorCode> {
-        Err(ErrorCode::NOSUPPORT)
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        self.set_mode(DigestMode::HmacSha384, key)
     }
 }
 
 impl hil::digest::HmacSha512 for Hmac<'_> {
-    fn set_mode_hmacsha512(&self, _key: &[u8]) -> Result<(), ErrorCode> {
-        Err(ErrorCode::NOSUPPORT)
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        self.set_mode(DigestMode::HmacSha512, key)
     }
 }
 
 impl hil::digest::Sha256 for Hmac<'_> {
     fn set_mode_sha256(&self) -> Result<(), ErrorCode> {
-        if self.busy.get() {
-            return Err(ErrorCode::BUSY);
-        }
-        let regs = self.registers;
-
-        // Ensure the SHA is setup
-        regs.cfg.write(
-            CFG::HMAC_EN::CLEAR
-                + CFG::SHA_EN::SET
-                + CFG::ENDIAN_SWAP::CLEAR
-                + CFG::DIGEST_SWAP::SET,
-        );
-
-        Ok(())
+        self.set_mode(DigestMode::Sha256, &[])
     }
 }
 
 impl hil::digest::Sha384 for Hmac<'_> {
     fn set_mode_sha384(&self) -> Result<(), ErrorCode> {
-        Err(ErrorCode::NOSUPPORT)
+        self.set_mode(DigestMode::Sha384, &[])
     }
 }
 
 impl hil::digest::Sha512 for Hmac<'_> {
     fn set_mode_sha512(&self) -> Result<(), ErrorCode> {
-        Err(ErrorCode::NOSUPPORT)
+        self.set_mode(DigestMode::Sha512, &[])
     }
 }
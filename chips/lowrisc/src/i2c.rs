@@ -1,6 +1,7 @@
 //! I2C Master Driver
 
 use core::cell::Cell;
+use core::fmt::Write;
 use kernel::hil;
 use kernel::hil::i2c;
 use kernel::utilities::cells::OptionalCell;
@@ -10,6 +11,7 @@ use kernel::utilities::registers::{
     register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 register_structs! {
     pub I2cRegisters {
@@ -125,9 +127,103 @@ register_bitfields![u32,
     ]
 ];
 
+/// I2C bus speed modes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum I2cSpeed {
+    /// Standard-mode, up to 100 kHz
+    Standard,
+    /// Fast-mode, up to 400 kHz
+    Fast,
+    /// Fast-mode Plus, up to 1 MHz
+    FastPlus,
+}
+
+/// Timing requirements of a bus speed mode in nanoseconds, taken from the
+/// I2C-bus specification (NXP UM10204, table 10). All values are minimums,
+/// except the rise and fall times which are the maximum allowed.
+struct TimingSpec {
+    scl_hz: u32,
+    /// The SCL frequency must stay above this for the mode to be worthwhile,
+    /// it is the upper limit of the next slower mode.
+    floor_hz: u32,
+    t_high: u32,
+    t_low: u32,
+    t_r: u32,
+    t_f: u32,
+    thd_sta: u32,
+    tsu_sta: u32,
+    tsu_dat: u32,
+    tsu_sto: u32,
+    t_buf: u32,
+}
+
+const STANDARD_TIMING: TimingSpec = TimingSpec {
+    scl_hz: 100_000,
+    floor_hz: 0,
+    t_high: 4000,
+    t_low: 4700,
+    t_r: 1000,
+    t_f: 300,
+    thd_sta: 4000,
+    tsu_sta: 4700,
+    tsu_dat: 250,
+    tsu_sto: 4000,
+    t_buf: 4700,
+};
+
+const FAST_TIMING: TimingSpec = TimingSpec {
+    scl_hz: 400_000,
+    floor_hz: 100_000,
+    t_high: 600,
+    t_low: 1300,
+    t_r: 300,
+    t_f: 300,
+    thd_sta: 600,
+    tsu_sta: 600,
+    tsu_dat: 100,
+    tsu_sto: 600,
+    t_buf: 1300,
+};
+
+const FAST_PLUS_TIMING: TimingSpec = TimingSpec {
+    scl_hz: 1_000_000,
+    floor_hz: 400_000,
+    t_high: 260,
+    t_low: 500,
+    t_r: 120,
+    t_f: 120,
+    thd_sta: 260,
+    tsu_sta: 260,
+    tsu_dat: 50,
+    tsu_sto: 260,
+    t_buf: 500,
+};
+
+/// Timing register values in peripheral clock cycles
+struct Timing {
+    thigh: u32,
+    tlow: u32,
+    t_r: u32,
+    t_f: u32,
+    thd_sta: u32,
+    tsu_sta: u32,
+    thd_dat: u32,
+    tsu_dat: u32,
+    tsu_sto: u32,
+    t_buf: u32,
+}
+
+impl Timing {
+    /// Length of one SCL period in clock cycles
+    fn scl_cycles(&self) -> u32 {
+        self.thigh + self.tlow + self.t_r + self.t_f
+    }
+}
+
 pub struct I2c<'a> {
     registers: StaticRef<I2cRegisters>,
     clock_period_nanos: u32,
+    speed: Cell<I2cSpeed>,
 
     master_client: OptionalCell<&'a dyn hil::i2c::I2CHwMasterClient>,
 
@@ -149,6 +245,7 @@ impl<'a> I2c<'_> {
         I2c {
             registers: base,
             clock_period_nanos,
+            speed: Cell::new(I2cSpeed::Fast),
             master_client: OptionalCell::empty(),
             slave_read_address: Cell::new(0),
             buffer: TakeCell::empty(),
@@ -159,6 +256,18 @@ impl<'a> I2c<'_> {
         }
     }
 
+    /// Write the status, FIFO and interrupt registers to `writer`, for the
+    /// panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " status: {:#010X}  fifo_status: {:#010X}  intr_state: {:#010X}",
+            regs.status.get(),
+            regs.fifo_status.get(),
+            regs.intr_state.get()
+        ));
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let irqs = regs.intr_state.extract();
@@ -191,26 +300,85 @@ impl<'a> I2c<'_> {
         }
     }
 
-    fn timing_parameter_init(&self, clock_period_nanos: u32) {
+    /// Select the bus speed mode and program the SCL timing registers.
+    ///
+    /// Every timing parameter is the specification minimum rounded up to whole
+    /// clock cycles (at least one cycle, so the data hold time is never zero).
+    /// `t_low` is then stretched so the SCL frequency does not exceed the
+    /// nominal rate of the mode. With a coarse peripheral clock the bus can
+    /// run slower than nominal; if it would drop to the rate of the next
+    /// slower mode, `ErrorCode::INVAL` is returned and nothing is changed.
+    pub fn set_speed(&self, speed: I2cSpeed) -> Result<(), ErrorCode> {
         let regs = self.registers;
 
-        // Setup the timing variables for Fast I2C
-        regs.timing0.modify(
-            TIMING0::THIGH.val(600 / clock_period_nanos)
-                + TIMING0::TLOW.val(1300 / clock_period_nanos),
-        );
+        if regs.ctrl.is_set(CTRL::ENABLEHOST) && !regs.status.is_set(STATUS::HOSTIDLE) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let spec = Self::timing_spec(speed);
+        let timing = self.compute_timing(spec);
+        let scl_hz = 1_000_000_000 / (timing.scl_cycles() * self.clock_period_nanos);
+        if scl_hz <= spec.floor_hz {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.speed.set(speed);
+        self.timing_parameter_init(&timing);
+        Ok(())
+    }
+
+    pub fn get_speed(&self) -> I2cSpeed {
+        self.speed.get()
+    }
+
+    fn timing_spec(speed: I2cSpeed) -> &'static TimingSpec {
+        match speed {
+            I2cSpeed::Standard => &STANDARD_TIMING,
+            I2cSpeed::Fast => &FAST_TIMING,
+            I2cSpeed::FastPlus => &FAST_PLUS_TIMING,
+        }
+    }
+
+    /// Convert the nanosecond requirements of `spec` into clock cycles
+    fn compute_timing(&self, spec: &TimingSpec) -> Timing {
+        let period = self.clock_period_nanos;
+        let cycles = |nanos: u32| ((nanos + period - 1) / period).max(1);
+
+        let mut timing = Timing {
+            thigh: cycles(spec.t_high),
+            tlow: cycles(spec.t_low),
+            t_r: cycles(spec.t_r),
+            t_f: cycles(spec.t_f),
+            thd_sta: cycles(spec.thd_sta),
+            tsu_sta: cycles(spec.tsu_sta),
+            thd_dat: 1,
+            tsu_dat: cycles(spec.tsu_dat),
+            tsu_sto: cycles(spec.tsu_sto),
+            t_buf: cycles(spec.t_buf),
+        };
+
+        // Stretch the low period to bring SCL down to the nominal rate
+        let target = cycles(1_000_000_000 / spec.scl_hz);
+        if timing.scl_cycles() < target {
+            timing.tlow += target - timing.scl_cycles();
+        }
+
+        timing
+    }
+
+    fn timing_parameter_init(&self, timing: &Timing) {
+        let regs = self.registers;
+
+        regs.timing0
+            .write(TIMING0::THIGH.val(timing.thigh) + TIMING0::TLOW.val(timing.tlow));
         regs.timing1
-            .modify(TIMING1::T_F.val(167) + TIMING1::T_R.val(40));
-        regs.timing2.modify(
-            TIMING2::THD_STA.val(600 / clock_period_nanos)
-                + TIMING2::TSU_STA.val(600 / clock_period_nanos),
-        );
+            .write(TIMING1::T_R.val(timing.t_r) + TIMING1::T_F.val(timing.t_f));
+        regs.timing2
+            .write(TIMING2::THD_STA.val(timing.thd_sta) + TIMING2::TSU_STA.val(timing.tsu_sta));
         regs.timing3
-            .modify(TIMING3::THD_DAT.val(100 / clock_period_nanos) + TIMING3::TSU_DAT.val(0));
-        regs.timing4.modify(
-            TIMING4::T_BUF.val(600 / clock_period_nanos)
-                + TIMING4::TSU_STO.val(1300 / clock_period_nanos),
-        );
+            .write(TIMING3::THD_DAT.val(timing.thd_dat) + TIMING3::TSU_DAT.val(timing.tsu_dat));
+        regs.timing4
+            .write(TIMING4::T_BUF.val(timing.t_buf) + TIMING4::TSU_STO.val(timing.tsu_sto));
     }
 
     fn fifo_reset(&self) {
@@ -363,7 +531,8 @@ impl<'a> hil::i2c::I2CMaster for I2c<'a> {
     fn enable(&self) {
         let regs = self.registers;
 
-        self.timing_parameter_init(self.clock_period_nanos);
+        let timing = self.compute_timing(Self::timing_spec(self.speed.get()));
+        self.timing_parameter_init(&timing);
         self.fifo_reset();
 
         // Enable all interrupts
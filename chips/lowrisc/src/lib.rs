@@ -5,7 +5,9 @@
 #![crate_type = "rlib"]
 
 pub mod csrng;
+pub mod ecdsa;
 pub mod flash_ctrl;
+pub mod flash_verify;
 pub mod gpio;
 pub mod hmac;
 pub mod i2c;
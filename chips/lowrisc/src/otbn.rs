@@ -4,7 +4,7 @@ use core::cell::Cell;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
@@ -90,6 +90,10 @@ register_bitfields![u32,
     ],
 ];
 
+/// Number of status reads `wipe()` waits for each wipe command to start, and
+/// again for it to finish
+const WIPE_POLL_LIMIT: usize = 1_000_000;
+
 pub struct Otbn<'a> {
     registers: StaticRef<OtbnRegisters>,
     client: OptionalCell<&'a dyn Client<'a>>,
@@ -97,6 +101,8 @@ pub struct Otbn<'a> {
     out_buffer: TakeCell<'static, [u8]>,
 
     copy_address: Cell<usize>,
+    auto_wipe: Cell<bool>,
+    aborted: Cell<bool>,
 }
 
 impl<'a> Otbn<'a> {
@@ -106,6 +112,8 @@ impl<'a> Otbn<'a> {
             client: OptionalCell::empty(),
             out_buffer: TakeCell::empty(),
             copy_address: Cell::new(0),
+            auto_wipe: Cell::new(false),
+            aborted: Cell::new(false),
         }
     }
 
@@ -113,6 +121,13 @@ impl<'a> Otbn<'a> {
         self.registers.intr_enable.set(0x00);
         self.registers.intr_state.set(0xFFFF_FFFF);
 
+        // The operation was abandoned by `abort()`, drop the results
+        if self.aborted.get() {
+            self.aborted.set(false);
+            let _ = self.wipe();
+            return;
+        }
+
         // Check if there is an error
         if self.registers.err_bits.get() > 0 {
             self.client.map(|client| {
@@ -138,6 +153,15 @@ impl<'a> Otbn<'a> {
                 out_buf[idx + 3] = d[3];
             }
 
+            if self.auto_wipe.get() {
+                if let Err(e) = self.wipe() {
+                    self.client.map(|client| {
+                        client.op_done(Err(e), out_buf);
+                    });
+                    return;
+                }
+            }
+
             self.client.map(|client| {
                 client.op_done(Ok(()), out_buf);
             });
@@ -149,6 +173,12 @@ impl<'a> Otbn<'a> {
         self.client.set(client);
     }
 
+    /// Whether OTBN is idle, so that it isn't running a program or wiping
+    /// its memories and a new operation can be loaded and run.
+    pub fn is_idle(&self) -> bool {
+        self.registers.status.matches_all(STATUS::STATUS::IDLE)
+    }
+
     /// Load the acceleration binary data into the accelerator.
     /// This data will be accelerator specific and could be an
     /// elf file which will be run or could be binary settings used to
@@ -201,6 +231,24 @@ impl<'a> Otbn<'a> {
         Ok(())
     }
 
+    /// Read data back out of the accelerator DMEM, starting at `address`.
+    /// `data` will be filled in little endian.
+    pub fn read_data(&self, address: usize, data: &mut [u8]) -> Result<(), ErrorCode> {
+        if !self.registers.status.matches_all(STATUS::STATUS::IDLE) {
+            // OTBN is performing an operation, DMEM is not accessible
+            return Err(ErrorCode::BUSY);
+        }
+
+        for i in 0..(data.len() / 4) {
+            let idx = i * 4;
+            let d = self.registers.dmem[(address / 4) + i].get().to_le_bytes();
+
+            data[idx..idx + 4].copy_from_slice(&d);
+        }
+
+        Ok(())
+    }
+
     /// Run the acceleration operation.
     /// This doesn't return any data, instead the client needs to have
     /// set a `op_done` handler to determine when this is complete.
@@ -223,7 +271,9 @@ impl<'a> Otbn<'a> {
 
         self.registers.ctrl.modify(CTRL::SOFTWARE_ERRS_FATAL::CLEAR);
 
-        // Clear and enable interrupts
+        // Clear and enable interrupts, this drops the done interrupt of an
+        // aborted operation if it hasn't been handled yet
+        self.aborted.set(false);
         self.registers.intr_state.modify(INTR::DONE::SET);
         self.registers.intr_enable.modify(INTR::DONE::SET);
 
@@ -235,11 +285,90 @@ impl<'a> Otbn<'a> {
         Ok(())
     }
 
+    /// Abandon the operation started by `run()`, returning its output
+    /// buffer. No `op_done()` callback will be made for it.
+    ///
+    /// OTBN has no way to stop a running program, so it runs to completion
+    /// and OTBN stays busy until then. Its results are then wiped rather than
+    /// copied out.
+    pub fn abort(&self) -> Option<&'static mut [u8]> {
+        let output = self.out_buffer.take();
+
+        // The done interrupt is still to come, even if OTBN just finished
+        if output.is_some() {
+            self.aborted.set(true);
+        }
+        output
+    }
+
+    /// Automatically `wipe()` DMEM and IMEM after every operation completes,
+    /// once the result has been copied out and before `op_done()` is called.
+    pub fn set_auto_wipe(&self, enable: bool) {
+        self.auto_wipe.set(enable);
+    }
+
+    /// Securely wipe DMEM and then IMEM, overwriting them with random data.
+    /// This blocks until both wipes have finished and OTBN is idle again, so
+    /// a later program loaded into OTBN can't read secrets left behind by a
+    /// previous operation.
+    ///
+    /// Returns `BUSY` if an operation is running and `FAIL` if OTBN locked
+    /// up during the wipe, or a wipe didn't start and finish within
+    /// `WIPE_POLL_LIMIT` status reads.
+    pub fn wipe(&self) -> Result<(), ErrorCode> {
+        if !self.registers.status.matches_all(STATUS::STATUS::IDLE) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // The done interrupt also fires for wipes, we poll instead
+        let intr_enable = self.registers.intr_enable.get();
+        self.registers.intr_enable.set(0x00);
+
+        let mut ret = self.wipe_cmd(CMD::CMD::SEC_WIPE_DMEM);
+        if ret.is_ok() {
+            ret = self.wipe_cmd(CMD::CMD::SEC_WIPE_IMEM);
+        }
+
+        self.registers.intr_state.modify(INTR::DONE::SET);
+        self.registers.intr_enable.set(intr_enable);
+
+        ret
+    }
+
+    /// Issue a single secure wipe command and wait for OTBN to be idle
+    fn wipe_cmd(&self, cmd: FieldValue<u32, CMD::Register>) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+        regs.cmd.write(cmd);
+
+        // STATUS only leaves IDLE once the wipe has started, don't mistake
+        // the idle state from before it for the wipe being done
+        if !(0..WIPE_POLL_LIMIT).any(|_| !regs.status.matches_all(STATUS::STATUS::IDLE)) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let status = (0..WIPE_POLL_LIMIT)
+            .map(|_| regs.status.read_as_enum(STATUS::STATUS))
+            .find(|status| {
+                matches!(
+                    status,
+                    Some(STATUS::STATUS::Value::IDLE) | Some(STATUS::STATUS::Value::LOCKED)
+                )
+            });
+        if status != Some(Some(STATUS::STATUS::Value::IDLE)) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        if self.registers.err_bits.get() > 0 {
+            return Err(ErrorCode::FAIL);
+        }
+
+        Ok(())
+    }
+
     /// Clear the keys and any other sensitive data.
     /// This won't clear the buffers provided to this API, that is up to the
     /// user to clear those.
     pub fn clear_data(&self) {
-        self.registers.cmd.write(CMD::CMD::SEC_WIPE_DMEM);
-        self.registers.cmd.write(CMD::CMD::SEC_WIPE_IMEM);
+        let _ = self.wipe();
     }
 }
@@ -11,9 +11,37 @@ register_structs! {
         (0x10 => pub mio_pads2: ReadWrite<u32, DIO_PADS::Register>),
         (0x14 => pub mio_pads3: ReadWrite<u32, DIO_PADS::Register>),
         (0x18 => @END),
+    },
+    /// The parts of the pinmux used for GPIO routing and wakeup from low
+    /// power
+    pub PinmuxRegisters {
+        (0x000 => _reserved0),
+        (0x0E8 => pub mio_periph_insel: [ReadWrite<u32, PADSEL::Register>; 57]),
+        (0x1CC => pub mio_outsel_regwen: [ReadWrite<u32, REGEN::Register>; MIO_PADS]),
+        (0x288 => pub mio_outsel: [ReadWrite<u32, OUTSEL::Register>; MIO_PADS]),
+        (0x344 => _reserved1),
+        (0x83C => pub wkup_detector_regwen: [ReadWrite<u32, REGEN::Register>; WAKEUP_DETECTORS]),
+        (0x85C => pub wkup_detector_en: [ReadWrite<u32, WKUP_DETECTOR_EN::Register>; WAKEUP_DETECTORS]),
+        (0x87C => pub wkup_detector: [ReadWrite<u32, WKUP_DETECTOR::Register>; WAKEUP_DETECTORS]),
+        (0x89C => pub wkup_detector_cnt_th: [ReadWrite<u32>; WAKEUP_DETECTORS]),
+        (0x8BC => pub wkup_detector_padsel: [ReadWrite<u32, PADSEL::Register>; WAKEUP_DETECTORS]),
+        (0x8DC => pub wkup_cause: ReadWrite<u32>),
+        (0x8E0 => @END),
     }
 }
 
+/// The number of pinmux wakeup detectors
+pub const WAKEUP_DETECTORS: usize = 8;
+
+/// The number of MIO pads
+pub const MIO_PADS: usize = 47;
+
+/// `OUTSEL` value leaving a pad undriven
+pub const OUTSEL_HIGH_Z: u32 = 2;
+
+/// `OUTSEL` value routing GPIO 0 to a pad, GPIO `n` is `n` higher
+pub const OUTSEL_GPIO0: u32 = 3;
+
 register_bitfields![u32,
     pub REGEN [
         WEN OFFSET(0) NUMBITS(1) []
@@ -43,5 +71,27 @@ register_bitfields![u32,
         ATTR3_PULL_UP OFFSET(27) NUMBITS(1) [],
         ATTR3_KEEPER OFFSET(28) NUMBITS(1) [],
         ATTR3_STRENGTH OFFSET(29) NUMBITS(1) []
+    ],
+    /// Selects a MIO pad. 0 and 1 are constant 0 and 1, pad `n` is `n + 2`.
+    pub PADSEL [
+        SEL OFFSET(0) NUMBITS(6) []
+    ],
+    /// Selects the peripheral output driving a MIO pad, see `OUTSEL_GPIO0`
+    pub OUTSEL [
+        SEL OFFSET(0) NUMBITS(7) []
+    ],
+    pub WKUP_DETECTOR_EN [
+        EN OFFSET(0) NUMBITS(1) []
+    ],
+    pub WKUP_DETECTOR [
+        MODE OFFSET(0) NUMBITS(3) [
+            POSEDGE = 0,
+            NEGEDGE = 1,
+            EDGE = 2,
+            TIMEDHIGH = 3,
+            TIMEDLOW = 4
+        ],
+        FILTER OFFSET(3) NUMBITS(1) [],
+        MIODIO OFFSET(4) NUMBITS(1) []
     ]
 ];
@@ -1,14 +1,54 @@
 //! Power Mangement for LowRISC
+//!
+//! Wakeup notification
+//! -------------------
+//!
+//! How the chip comes out of low power depends on whether the main power
+//! domain was turned off:
+//!
+//! - If it was, the exit is a reset. Nothing is notified, the next boot sees
+//!   `ResetReason::LowPowerExit` from `reset_reason()` and can read the
+//!   sources that woke it with `wakeup_reasons()`.
+//! - Otherwise the core resumes and the pwrmgr raises its wakeup interrupt.
+//!   `handle_interrupt()` turns low power back off and starts the clock
+//!   domain sync, and once `check_clock_propagation()` reports that the
+//!   clocks have stabilized the chip calls `notify_wakeup()`, which passes
+//!   the wakeup reasons to the `WakeupClient`.
+//!
+//! Brown-out
+//! ---------
+//!
+//! There is no brown-out configuration or early warning here. The supply
+//! monitors are in the analog sensor top, their thresholds are fixed by the
+//! silicon rather than set by software, and a supply dropping out of range
+//! resets the chip directly without raising an interrupt first. Firmware
+//! can't flush state ahead of a brown-out, the next boot only sees
+//! `ResetReason::PowerOn` from `reset_reason()`.
+//!
+//! Software reset
+//! --------------
+//!
+//! `request_reset()` records why it reset the chip in the retention SRAM,
+//! for `reset_reason()` on the next boot. The first half of the retention
+//! SRAM belongs to the ROM and ROM_EXT, which keep their own reset state
+//! there, so the chip places `ResetRetention` at the start of the second,
+//! owner half. Before the reset the `ResetFlush` set with
+//! `set_reset_flush()`, normally the console UART, is given the chance to
+//! send what it has queued.
 
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
-use kernel::utilities::registers::{register_bitfields, register_structs, ReadOnly, ReadWrite};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 register_structs! {
     pub PwrMgrRegisters {
-        (0x00 => intr_state: ReadOnly<u32, INTR::Register>),
-        (0x04 => intr_enable: ReadOnly<u32, INTR::Register>),
-        (0x08 => intr_test: ReadOnly<u32, INTR::Register>),
+        (0x00 => intr_state: ReadWrite<u32, INTR::Register>),
+        (0x04 => intr_enable: ReadWrite<u32, INTR::Register>),
+        (0x08 => intr_test: WriteOnly<u32, INTR::Register>),
         (0x0C => ctrl_cfg_regwen: ReadOnly<u32, CTRL_CFG_REGWEN::Register>),
         (0x10 => control: ReadWrite<u32, CONTROL::Register>),
         (0x14 => cfg_cdc_sync: ReadWrite<u32, CFG_CDC_SYNC::Register>),
@@ -22,6 +62,21 @@ register_structs! {
         (0x34 => wake_info_capture_dis: ReadWrite<u32, WAKE_INFO_CAPTURE_DIS::Register>),
         (0x38 => wake_info: ReadWrite<u32, WAKE_INFO::Register>),
         (0x3C => @END),
+    },
+    pub RstMgrRegisters {
+        (0x00 => alert_test: WriteOnly<u32>),
+        (0x04 => reset_req: ReadWrite<u32, RESET_REQ::Register>),
+        (0x08 => reset_info: ReadWrite<u32, RESET_INFO::Register>),
+        (0x0C => @END),
+    },
+    /// Words in the owner half of the retention SRAM used to record why
+    /// software reset the chip, see the module documentation. The retention
+    /// SRAM keeps its contents across a software reset but not across a
+    /// power-on reset.
+    pub ResetRetention {
+        (0x00 => magic: ReadWrite<u32>),
+        (0x04 => reason: ReadWrite<u32>),
+        (0x08 => @END),
     }
 }
 
@@ -78,16 +133,116 @@ register_bitfields![u32,
         REASONS OFFSET(0) NUMBITS(16) [],
         FALL_THROUGH OFFSET(16) NUMBITS(1) [],
         ABORT OFFSET(17) NUMBITS(1) []
+    ],
+    RESET_REQ [
+        VAL OFFSET(0) NUMBITS(4) [
+            TRUE = 0x6,
+            FALSE = 0x9,
+        ]
+    ],
+    RESET_INFO [
+        POR OFFSET(0) NUMBITS(1) [],
+        LOW_POWER_EXIT OFFSET(1) NUMBITS(1) [],
+        SW_RESET OFFSET(2) NUMBITS(1) [],
+        HW_REQ OFFSET(3) NUMBITS(4) []
     ]
 ];
 
+/// Marks the retention words as holding a valid `SwResetReason`
+const RESET_REASON_MAGIC: u32 = 0x5253_5452;
+
+/// Why software requested a reset, recorded across the reset so the next
+/// boot can tell it apart from a crash.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SwResetReason {
+    /// A generic reset requested by the kernel or an application
+    Requested,
+    /// The reset applies a firmware update
+    Update,
+    /// The reset was requested from a console
+    Console,
+}
+
+impl SwResetReason {
+    fn to_u32(self) -> u32 {
+        match self {
+            SwResetReason::Requested => 1,
+            SwResetReason::Update => 2,
+            SwResetReason::Console => 3,
+        }
+    }
+
+    fn from_u32(val: u32) -> Option<Self> {
+        match val {
+            1 => Some(SwResetReason::Requested),
+            2 => Some(SwResetReason::Update),
+            3 => Some(SwResetReason::Console),
+            _ => None,
+        }
+    }
+}
+
+/// The cause of the last reset, from the reset manager
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResetReason {
+    PowerOn,
+    LowPowerExit,
+    /// Reset requested by software, with the reason if one was recorded
+    Software(Option<SwResetReason>),
+    /// Reset requested by a peripheral (watchdog, escalation, ...)
+    Hardware,
+    Unknown,
+}
+
+/// Implement this trait and use `set_reset_flush()` to finish pending output
+/// before `request_reset()` resets the chip.
+pub trait ResetFlush {
+    /// Send anything still queued. This must return in bounded time, the
+    /// reset goes ahead whatever is left.
+    fn flush_before_reset(&self);
+}
+
+/// Implement this trait and use `set_wakeup_client()` to be told when the
+/// chip has resumed from low power.
+pub trait WakeupClient {
+    /// Called once the clocks are running again after a low power exit.
+    /// `reasons` is a bitmask of the wakeup sources, as `wakeup_reasons()`.
+    fn wakeup(&self, reasons: u32);
+}
+
 pub struct PwrMgr {
     registers: StaticRef<PwrMgrRegisters>,
+    rstmgr: StaticRef<RstMgrRegisters>,
+    retention: StaticRef<ResetRetention>,
+    wakeup_client: OptionalCell<&'static dyn WakeupClient>,
+    reset_flush: OptionalCell<&'static dyn ResetFlush>,
 }
 
 impl PwrMgr {
-    pub const fn new(base: StaticRef<PwrMgrRegisters>) -> PwrMgr {
-        PwrMgr { registers: base }
+    pub const fn new(
+        base: StaticRef<PwrMgrRegisters>,
+        rstmgr: StaticRef<RstMgrRegisters>,
+        retention: StaticRef<ResetRetention>,
+    ) -> PwrMgr {
+        PwrMgr {
+            registers: base,
+            rstmgr,
+            retention,
+            wakeup_client: OptionalCell::empty(),
+            reset_flush: OptionalCell::empty(),
+        }
+    }
+
+    /// Set what `request_reset()` flushes before resetting the chip
+    pub fn set_reset_flush(&self, flush: &'static dyn ResetFlush) {
+        self.reset_flush.set(flush);
+    }
+
+    /// Set the client notified after a low power exit and enable the wakeup
+    /// interrupt.
+    pub fn set_wakeup_client(&self, client: &'static dyn WakeupClient) {
+        self.wakeup_client.set(client);
+        self.registers.intr_enable.write(INTR::WAKEUP::SET);
     }
 
     pub fn check_clock_propagation(&self) -> bool {
@@ -110,6 +265,21 @@ impl PwrMgr {
         regs.cfg_cdc_sync.write(CFG_CDC_SYNC::SYNC::SET);
     }
 
+    /// Complete a wakeup started by `handle_interrupt()`. Only call this once
+    /// `check_clock_propagation()` returns true, the client expects the
+    /// clocks to be stable.
+    pub fn notify_wakeup(&self) {
+        let regs = self.registers;
+
+        // INTR_STATE is write 1 to clear
+        regs.intr_state.write(INTR::WAKEUP::SET);
+
+        let reasons = self.wakeup_reasons();
+        self.clear_wakeup_reasons(reasons);
+
+        self.wakeup_client.map(|client| client.wakeup(reasons));
+    }
+
     pub fn enable_low_power(&self) {
         let regs = self.registers;
 
@@ -127,4 +297,100 @@ impl PwrMgr {
             regs.cfg_cdc_sync.write(CFG_CDC_SYNC::SYNC::SET);
         }
     }
+
+    /// Allow wakeup source `source` to bring the chip out of low power.
+    ///
+    /// The source numbering is defined by the top level.
+    pub fn enable_wakeup(&self, source: usize) -> Result<(), ErrorCode> {
+        self.set_wakeup(source, true)
+    }
+
+    /// Stop wakeup source `source` from bringing the chip out of low power.
+    pub fn disable_wakeup(&self, source: usize) -> Result<(), ErrorCode> {
+        self.set_wakeup(source, false)
+    }
+
+    fn set_wakeup(&self, source: usize, enable: bool) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if source > 4 {
+            return Err(ErrorCode::INVAL);
+        }
+        if !regs.wakeup_en_regwen.is_set(WAKEUP_EN_REGWEN::EN) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let bit = 1 << source;
+        let en = regs.wakeup_en.get();
+        regs.wakeup_en
+            .set(if enable { en | bit } else { en & !bit });
+
+        // Propagate changes to slow clock domain
+        regs.cfg_cdc_sync.write(CFG_CDC_SYNC::SYNC::SET);
+
+        Ok(())
+    }
+
+    /// Returns a bitmask of the wakeup sources that woke the chip, bit `n`
+    /// being set for source `n`.
+    pub fn wakeup_reasons(&self) -> u32 {
+        self.registers.wake_info.read(WAKE_INFO::REASONS)
+    }
+
+    /// Clear the recorded wakeup reasons set in `reasons`
+    pub fn clear_wakeup_reasons(&self, reasons: u32) {
+        // WAKE_INFO is write 1 to clear
+        self.registers
+            .wake_info
+            .write(WAKE_INFO::REASONS.val(reasons));
+    }
+
+    /// Flush the `ResetFlush` set with `set_reset_flush()`, if any, record
+    /// `reason` in the retention SRAM and reset the chip.
+    pub fn request_reset(&self, reason: SwResetReason) -> ! {
+        self.reset_flush.map(|flush| flush.flush_before_reset());
+
+        self.retention.reason.set(reason.to_u32());
+        self.retention.magic.set(RESET_REASON_MAGIC);
+
+        self.rstmgr.reset_req.write(RESET_REQ::VAL::TRUE);
+
+        // The reset takes a few cycles to propagate
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the cause of the last reset.
+    ///
+    /// The reset manager accumulates causes until they are cleared, so if
+    /// several are set the earliest in the reset sequence is reported.
+    pub fn reset_reason(&self) -> ResetReason {
+        let info = self.rstmgr.reset_info.extract();
+
+        if info.is_set(RESET_INFO::POR) {
+            ResetReason::PowerOn
+        } else if info.is_set(RESET_INFO::SW_RESET) {
+            let reason = if self.retention.magic.get() == RESET_REASON_MAGIC {
+                SwResetReason::from_u32(self.retention.reason.get())
+            } else {
+                None
+            };
+            ResetReason::Software(reason)
+        } else if info.read(RESET_INFO::HW_REQ) != 0 {
+            ResetReason::Hardware
+        } else if info.is_set(RESET_INFO::LOW_POWER_EXIT) {
+            ResetReason::LowPowerExit
+        } else {
+            ResetReason::Unknown
+        }
+    }
+
+    /// Clear the recorded reset causes, so the next boot only sees the
+    /// cause of the next reset.
+    pub fn clear_reset_reason(&self) {
+        self.retention.magic.set(0);
+        // RESET_INFO is write 1 to clear
+        self.rstmgr.reset_info.set(self.rstmgr.reset_info.get());
+    }
 }
@@ -2,6 +2,7 @@
 
 use crate::virtual_otbn::VirtualMuxAccel;
 use kernel::hil::public_key_crypto::rsa_math::{Client, ClientMut, RsaCryptoBase};
+use kernel::hil::time::Alarm;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::mut_imut_buffer::MutImutBuffer;
@@ -14,8 +15,8 @@ pub struct AppAddresses {
     pub dmem_size: usize,
 }
 
-pub struct OtbnRsa<'a> {
-    otbn: &'a VirtualMuxAccel<'a>,
+pub struct OtbnRsa<'a, A: Alarm<'a>> {
+    otbn: &'a VirtualMuxAccel<'a, A>,
     client: OptionalCell<&'a dyn Client<'a>>,
     client_mut: OptionalCell<&'a dyn ClientMut<'a>>,
 
@@ -28,9 +29,9 @@ pub struct OtbnRsa<'a> {
     rsa: AppAddresses,
 }
 
-impl<'a> OtbnRsa<'a> {
+impl<'a, A: Alarm<'a>> OtbnRsa<'a, A> {
     pub fn new(
-        otbn: &'a VirtualMuxAccel<'a>,
+        otbn: &'a VirtualMuxAccel<'a, A>,
         rsa: AppAddresses,
         internal_buffer: &'static mut [u8],
     ) -> Self {
@@ -84,7 +85,7 @@ impl<'a> OtbnRsa<'a> {
     }
 }
 
-impl<'a> crate::otbn::Client<'a> for OtbnRsa<'a> {
+impl<'a, A: Alarm<'a>> crate::otbn::Client<'a> for OtbnRsa<'a, A> {
     fn op_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
         if let Err(e) = result {
             self.report_error(e, output);
@@ -131,7 +132,7 @@ impl<'a> crate::otbn::Client<'a> for OtbnRsa<'a> {
     }
 }
 
-impl<'a> RsaCryptoBase<'a> for OtbnRsa<'a> {
+impl<'a, A: Alarm<'a>> RsaCryptoBase<'a> for OtbnRsa<'a, A> {
     fn set_client(&'a self, client: &'a dyn Client<'a>) {
         self.client.set(client);
     }
@@ -1,6 +1,32 @@
 //! Serial Peripheral Interface (SPI) Host Driver
+//!
+//! `init()` must be called before anything else. Until it has been, every
+//! method that can fail returns `OFF` and `get_rate()` returns 0.
+//!
+//! Interrupt handling
+//! ------------------
+//!
+//! Once `initialise_deferred_call()` has been given a handle,
+//! `handle_interrupt()` only acknowledges the interrupt and schedules a
+//! deferred call. Draining the RX FIFO, queueing the next chunk and calling
+//! the client happen in the deferred call, so other interrupts aren't held
+//! up behind a large transfer. Interrupts stay disabled in between, and the
+//! buffers stay in the driver until the deferred call hands them to the
+//! client. Without a handle the work is done in `handle_interrupt()`.
+//!
+//! A transfer is complete once the IDLE event shows the last command has
+//! finished and CS has been released, not when the last word leaves the TX
+//! FIFO. After that, and after the client's done callback, a client set with
+//! `set_ready_client()` is told from the READY event that the controller can
+//! take the next transfer, so a producer can stream transfers without
+//! polling. The notification is skipped if the done callback has already
+//! started another transfer.
 use core::cell::Cell;
 use core::cmp;
+use core::fmt::Write;
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
 use kernel::hil;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::spi::{ClockPhase, ClockPolarity};
@@ -8,11 +34,17 @@ use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, FieldValue, LocalRegisterCopy, ReadOnly, ReadWrite,
+    WriteOnly,
 };
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 
+/// Number of chip select lines, and so `CONFIGOPTS` registers, on the SPI
+/// host. The registers after `CONFIGOPTS` move with this, so changing it
+/// also needs the offsets below updating.
+pub const NUM_CHIP_SELECTS: usize = 1;
+
 register_structs! {
     pub SpiHostRegisters {
         //SPI: Interrupt State Register, type rw1c
@@ -27,8 +59,8 @@ register_structs! {
         (0x010 => ctrl: ReadWrite<u32, ctrl::Register>),
         //SPI: Status register
         (0x014 => status: ReadOnly<u32, status::Register>),
-        //SPI: Configuration options register.
-        (0x018 => config_opts: ReadWrite<u32, conf_opts::Register>),
+        //SPI: Configuration options register, one per chip select
+        (0x018 => config_opts: [ReadWrite<u32, conf_opts::Register>; NUM_CHIP_SELECTS]),
         //SPI: Chip-Select ID
         (0x01c => csid: ReadWrite<u32, csid_ctrl::Register>),
         //SPI: Command Register
@@ -129,106 +161,409 @@ register_bitfields![u32,
     ],
 ];
 
+/// How the bytes of each 32-bit FIFO word are shifted out on the bus.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ByteOrder {
+    /// Buffer bytes go out on the wire in the order they appear in the
+    /// buffer. This is the default.
+    LittleEndian,
+    /// Each group of 4 buffer bytes is treated as a little-endian `u32` and
+    /// shifted out most significant byte first, for devices that expect
+    /// MSB-first framing of 32-bit words.
+    BigEndian,
+}
+
+/// Direction of a command segment, the values match `COMMAND.DIRECTION`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SegmentDirection {
+    /// Clock out `len` dummy cycles without transferring data
+    Dummy = 0,
+    RxOnly = 1,
+    TxOnly = 2,
+    Bidirectional = 3,
+}
+
+/// Number of data lanes used by a segment, the values match `COMMAND.SPEED`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SegmentSpeed {
+    Standard = 0,
+    Dual = 1,
+    Quad = 2,
+}
+
+/// One part of a `transfer_segments()` transaction, for example the command,
+/// address or data phase of a flash operation.
+pub struct SpiSegment {
+    pub direction: SegmentDirection,
+    /// Dual and Quad speeds can't be used for `Bidirectional` segments
+    pub speed: SegmentSpeed,
+    /// Data to send, required for `TxOnly` and `Bidirectional` segments
+    pub tx_buf: Option<&'static mut [u8]>,
+    /// Buffer for received data, required for `RxOnly` and `Bidirectional`
+    /// segments
+    pub rx_buf: Option<&'static mut [u8]>,
+    /// Number of bytes to transfer, or cycles for a `Dummy` segment
+    pub len: usize,
+}
+
+/// Most cycles a `Dummy` segment can insert, it is issued as one command and
+/// `COMMAND.LEN` is 8 bits wide
+pub const MAX_DUMMY_CYCLES: usize = 256;
+
+impl SpiSegment {
+    /// A segment that clocks `cycles` dummy cycles without transferring
+    /// data, for example between the address and data phases of a flash
+    /// fast read.
+    pub fn dummy(cycles: usize) -> SpiSegment {
+        SpiSegment {
+            direction: SegmentDirection::Dummy,
+            speed: SegmentSpeed::Standard,
+            tx_buf: None,
+            rx_buf: None,
+            len: cycles,
+        }
+    }
+}
+
+/// Largest value of the `CSNLEAD`, `CSNTRAIL` and `CSNIDLE` timings, which
+/// are 3 bit fields counted in SCK half-periods
+pub const CSN_TIMING_MAX: u8 = 7;
+
+/// The full configuration of one chip select, applied with `configure()`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpiHostConfig {
+    pub chip_select: u32,
+    /// SCK rate in Hz, at most half the CPU clock
+    pub rate: u32,
+    pub polarity: ClockPolarity,
+    pub phase: ClockPhase,
+    /// Extra half SCK cycles between CS going low and the first SCK edge
+    pub csn_lead: u8,
+    /// Extra half SCK cycles between the last SCK edge and CS going high
+    pub csn_trail: u8,
+    /// Extra half SCK cycles CS is held high between commands
+    pub csn_idle: u8,
+}
+
+/// Pack 4 buffer bytes into a FIFO word, with the first byte in the least
+/// significant bits for `ByteOrder::LittleEndian` and in the most for
+/// `ByteOrder::BigEndian`. Every TX path packs with this and every RX path
+/// unpacks with `unpack_fifo_word()` in the same order, so a received byte
+/// lands in the buffer position it would have been sent from.
+pub fn pack_fifo_word(bytes: [u8; 4], order: ByteOrder) -> u32 {
+    match order {
+        ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+        ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+    }
+}
+
+/// Unpack a FIFO word into 4 buffer bytes, the inverse of `pack_fifo_word()`
+pub fn unpack_fifo_word(word: u32, order: ByteOrder) -> [u8; 4] {
+    match order {
+        ByteOrder::LittleEndian => word.to_le_bytes(),
+        ByteOrder::BigEndian => word.to_be_bytes(),
+    }
+}
+
+/// Implement this trait and use `set_segment_client()` to be told when a
+/// `transfer_segments()` transaction completes.
+pub trait SpiSegmentClient {
+    /// Called when all segments have been transferred, or on error. All
+    /// buffers are returned in `segments`.
+    fn transfer_segments_done(
+        &self,
+        segments: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    );
+}
+
+/// Implement this trait and use `set_verify_client()` to be told when a
+/// `transfer_segments_verified()` write and read back completes.
+pub trait SpiVerifyClient {
+    /// Called once the read back has been compared, or on error. `status` is
+    /// `Err(FAIL)` if the data read back didn't match what was written. All
+    /// buffers are returned in `write` and `read`.
+    fn transfer_verified_done(
+        &self,
+        write: &'static mut [SpiSegment],
+        read: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    );
+}
+
+/// Implement this trait and use `set_ready_client()` to be told when the
+/// controller can take a new transfer.
+pub trait SpiReadyClient {
+    /// Called once a transfer has completed, after its done callback, when
+    /// the driver is idle and the controller is ready for a command. A new
+    /// transfer can be started from here.
+    fn ready(&self);
+}
+
+/// Which transaction of a `transfer_segments_verified()` is running
+#[derive(Copy, Clone, PartialEq)]
+enum VerifyPhase {
+    Idle,
+    Write,
+    Read,
+}
+
 pub struct SpiHost {
     registers: StaticRef<SpiHostRegisters>,
     client: OptionalCell<&'static dyn hil::spi::SpiMasterClient>,
+    initialized: Cell<bool>,
     busy: Cell<bool>,
     chip_select: Cell<u32>,
     cpu_clk: u32,
-    tsclk: Cell<u32>,
+    tsclk: [Cell<u32>; NUM_CHIP_SELECTS],
+    /// The `CONFIGOPTS` value of each chip select, see `apply_config()`
+    config: [Cell<u32>; NUM_CHIP_SELECTS],
     tx_buf: TakeCell<'static, [u8]>,
     rx_buf: TakeCell<'static, [u8]>,
     tx_len: Cell<usize>,
     rx_len: Cell<usize>,
     tx_offset: Cell<usize>,
     rx_offset: Cell<usize>,
+    tx_chunk: Cell<usize>,
+    byte_order: Cell<ByteOrder>,
+    segment_client: OptionalCell<&'static dyn SpiSegmentClient>,
+    segments: TakeCell<'static, [SpiSegment]>,
+    segment_index: Cell<usize>,
+    segment_offset: Cell<usize>,
+    segment_chunk: Cell<usize>,
+    verify_client: OptionalCell<&'static dyn SpiVerifyClient>,
+    /// The transaction of a verified transfer that isn't currently running
+    verify_segments: TakeCell<'static, [SpiSegment]>,
+    verify_phase: Cell<VerifyPhase>,
+    deferred_call: Cell<bool>,
+    deferred_caller: &'static DynamicDeferredCall,
+    deferred_handle: OptionalCell<DeferredCallHandle>,
+    /// Take TXWM instead of TXEMPTY, see `set_coalescing()`
+    coalesce: Cell<bool>,
+    tx_watermark: Cell<u8>,
+    interrupt_count: Cell<usize>,
+    ready_client: OptionalCell<&'static dyn SpiReadyClient>,
 }
 // SPI Host Command Direction: Bidirectional
 const SPI_HOST_CMD_BIDIRECTIONAL: u32 = 3;
 // SPI Host Command Speed: Standard SPI
 const SPI_HOST_CMD_STANDARD_SPI: u32 = 0;
+/// Depth of each of the TX and RX FIFOs, in 32-bit words
+pub const SPI_HOST_FIFO_DEPTH: usize = 64;
+// Bytes that fit in the TX/RX FIFOs, and the largest COMMAND.LEN
+const SPI_HOST_FIFO_BYTES: usize = SPI_HOST_FIFO_DEPTH * 4;
+// Offset of TXDATA, for byte wide writes
+const TXDATA_OFFSET: usize = 0x028;
+/// Number of status reads `transfer_sync()` waits for each command
+pub const SYNC_POLL_LIMIT: usize = 1_000_000;
 
 impl SpiHost {
-    pub fn new(base: StaticRef<SpiHostRegisters>, cpu_clk: u32) -> Self {
+    pub fn new(
+        base: StaticRef<SpiHostRegisters>,
+        cpu_clk: u32,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> Self {
         SpiHost {
             registers: base,
             client: OptionalCell::empty(),
+            initialized: Cell::new(false),
             busy: Cell::new(false),
             chip_select: Cell::new(0),
             cpu_clk: cpu_clk,
-            tsclk: Cell::new(0),
+            tsclk: Default::default(),
+            config: Default::default(),
             tx_buf: TakeCell::empty(),
             rx_buf: TakeCell::empty(),
             tx_len: Cell::new(0),
             rx_len: Cell::new(0),
             tx_offset: Cell::new(0),
             rx_offset: Cell::new(0),
+            tx_chunk: Cell::new(0),
+            byte_order: Cell::new(ByteOrder::LittleEndian),
+            segment_client: OptionalCell::empty(),
+            segments: TakeCell::empty(),
+            segment_index: Cell::new(0),
+            segment_offset: Cell::new(0),
+            segment_chunk: Cell::new(0),
+            verify_client: OptionalCell::empty(),
+            verify_segments: TakeCell::empty(),
+            verify_phase: Cell::new(VerifyPhase::Idle),
+            deferred_call: Cell::new(false),
+            deferred_caller,
+            deferred_handle: OptionalCell::empty(),
+            coalesce: Cell::new(false),
+            tx_watermark: Cell::new(0),
+            interrupt_count: Cell::new(0),
+            ready_client: OptionalCell::empty(),
         }
     }
 
+    /// Move the interrupt work into a deferred call, see the module
+    /// documentation.
+    pub fn initialise_deferred_call(&self, deferred_call_handle: DeferredCallHandle) {
+        self.deferred_handle.set(deferred_call_handle);
+    }
+
+    /// True once `init()` has been called
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.get()
+    }
+
+    /// Write the status, error and interrupt registers to `writer`, for the
+    /// panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " status: {:#010X}  err_status: {:#010X}  intr_state: {:#010X}",
+            regs.status.get(),
+            regs.err_status.get(),
+            regs.intr_state.get()
+        ));
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let irq = regs.intr_state.extract();
+        self.interrupt_count
+            .set(self.interrupt_count.get().wrapping_add(1));
         self.disable_interrupts();
 
         if irq.is_set(intr::ERROR) {
-            //Clear all pending errors.
-            self.clear_err_interrupt();
-            //Something went wrong, reset IP and clear buffers
-            self.reset_spi_ip();
-            self.reset_internal_state();
-            //r/w_done() may call r/w_bytes() to re-attempt transfer
-            self.client.map(|client| match self.tx_buf.take() {
-                None => (),
-                Some(tx_buf) => client.read_write_done(
-                    tx_buf,
-                    self.rx_buf.take(),
-                    self.tx_offset.get(),
-                    Err(ErrorCode::FAIL),
-                ),
-            });
+            self.abort_transfer(Err(ErrorCode::FAIL));
             return;
         }
 
         if irq.is_set(intr::SPI_EVENT) {
-            let status = regs.status.extract();
+            //Acknowledge before reading STATUS, an event raised after this
+            //sets the interrupt again rather than being lost
             self.clear_event_interrupt();
+            let status = regs.status.extract();
+
+            //Waiting to tell the ready client the controller is free, see
+            //notify_ready()
+            if !self.is_busy() && regs.event_en.is_set(event_en::READY) {
+                self.enable_interrupts();
+                if status.is_set(status::READY) {
+                    regs.event_en.modify(event_en::READY::CLEAR);
+                    self.ready_client.map(|client| client.ready());
+                }
+                return;
+            }
 
-            //This could be set at init, so only follow through
-            //once a transfer has started (is_busy())
-            if status.is_set(status::TXEMPTY) && self.is_busy() {
-                self.continue_transfer();
+            //Segment transfers wait for the whole command to finish.
+            //Otherwise this could be set at init, so only follow through
+            //once a transfer has started (is_busy()). TXEMPTY is set once
+            //the last word leaves the FIFO, wait for the IDLE event so the
+            //last bytes have been received too.
+            let ready = if self.segments.is_some() {
+                !status.is_set(status::ACTIVE)
             } else {
+                status.is_set(status::TXEMPTY) && !status.is_set(status::ACTIVE) && self.is_busy()
+            };
+
+            if !ready {
                 self.enable_interrupts();
+            } else if self.deferred_handle.is_some() {
+                //Interrupts stay disabled until the deferred call has run
+                self.deferred_call.set(true);
+                self.deferred_handle
+                    .map(|handle| self.deferred_caller.set(*handle));
+            } else {
+                self.continue_command();
             }
         }
     }
 
+    /// Drain the data received by the command that just finished and move
+    /// on to the next one, or complete the transfer.
+    fn continue_command(&self) {
+        if self.segments.is_some() {
+            self.continue_segments();
+        } else {
+            self.continue_transfer();
+        }
+        self.notify_ready();
+    }
+
+    /// Once a transfer has completed, and its client hasn't started another,
+    /// arrange for the ready client to be called from the READY event. The
+    /// controller is normally already ready by now, so the event has been
+    /// and gone and is raised with the test register instead.
+    fn notify_ready(&self) {
+        let regs = self.registers;
+
+        if self.is_busy() || self.ready_client.is_none() {
+            return;
+        }
+
+        regs.event_en.modify(event_en::READY::SET);
+        self.enable_interrupts();
+        if regs.status.is_set(status::READY) {
+            regs.intr_test.write(intr::SPI_EVENT::SET);
+        }
+    }
+
+    /// Abort the current transfer after an error and hand the buffers back
+    /// to the client with `status`.
+    fn abort_transfer(&self, status: Result<(), ErrorCode>) {
+        //The transfer is over, any scheduled deferred call has nothing to do
+        self.deferred_call.set(false);
+        //Clear all pending errors.
+        self.clear_err_interrupt();
+        //Something went wrong, reset IP and clear buffers
+        let _ = self.reset_spi_ip();
+        if self.segments.is_some() {
+            self.finish_segments(status);
+            self.notify_ready();
+            return;
+        }
+        let tx_buf = self.tx_buf.take();
+        let rx_buf = self.rx_buf.take();
+        let tx_offset = self.tx_offset.get();
+        self.disable_tx_interrupt();
+        self.reset_internal_state();
+        //r/w_done() may call r/w_bytes() to re-attempt transfer
+        if let Some(tx_buf) = tx_buf {
+            self.client
+                .map(move |client| client.read_write_done(tx_buf, rx_buf, tx_offset, status));
+        }
+        self.notify_ready();
+    }
+
+    /// An `OVERFLOW` means a write to a full TX FIFO was dropped, an
+    /// `UNDERFLOW` means a read of an empty RX FIFO returned no data. Either
+    /// way the data of the current transfer is corrupt.
+    fn fifo_error(&self) -> bool {
+        let err = self.registers.err_status.extract();
+        err.is_set(err_status::OVERFLOW) || err.is_set(err_status::UNDERFLOW)
+    }
+
     //Determine if transfer complete or if we need to keep
     //writing from an offset.
     fn continue_transfer(&self) {
         self.rx_buf.take().map(|rx_buf| {
             let regs = self.registers;
             let mut val32: u32;
-            let mut val8: u8;
-            let mut shift_mask;
-            let rx_len = self.tx_offset.get() - self.rx_offset.get();
-            let read_cycles = self.div_up(rx_len, 4);
+            let read_cycles = self.div_up(self.tx_chunk.get(), 4);
 
             //Receive rx_data (Only 4byte reads are supported)
             for _n in 0..read_cycles {
                 val32 = regs.rx_data.read(rx_data::DATA);
-                shift_mask = 0xFF;
-                for i in 0..4 {
+                for val8 in self.unpack_word(val32) {
                     if self.rx_offset.get() >= self.rx_len.get() {
                         break;
                     }
-                    val8 = ((val32 & shift_mask) >> i * 8) as u8;
                     rx_buf[self.rx_offset.get()] = val8;
                     self.rx_offset.set(self.rx_offset.get() + 1);
-                    shift_mask = shift_mask << 8;
                 }
             }
+            //The error interrupt may not have been taken yet, don't report
+            //or continue a transfer that lost data.
+            if self.fifo_error() {
+                self.rx_buf.replace(rx_buf);
+                self.abort_transfer(Err(ErrorCode::FAIL));
+                return;
+            }
             //Transfer was complete */
             if self.tx_offset.get() == self.tx_len.get() {
                 self.client.map(|client| match self.tx_buf.take() {
@@ -251,56 +586,664 @@ impl SpiHost {
     /// Continue SPI transfer from offset point
     fn spi_transfer_progress(&self) {
         self.tx_buf.take().map(|tx_buf| {
-            let regs = self.registers;
-            let mut t_byte: u32;
-            let mut tx_slice: [u8; 4];
+            if self.fill_tx_fifo(tx_buf).is_ok() {
+                //Hold tx_buf for offset transfer continue
+                self.tx_buf.replace(tx_buf);
+                return;
+            }
+
+            //The previous chunk should have drained, give up on the transfer
+            let rx_buf = self.rx_buf.take();
+            let tx_offset = self.tx_offset.get();
+            self.disable_tx_interrupt();
+            let _ = self.reset_spi_ip();
+            self.reset_internal_state();
+            self.client.map(move |client| {
+                client.read_write_done(tx_buf, rx_buf, tx_offset, Err(ErrorCode::FAIL))
+            });
+        });
+    }
 
-            assert_eq!(regs.status.read(status::TXQD), 0);
-            assert_eq!(regs.status.read(status::ACTIVE), 0);
+    /// Queue the next chunk of `tx_buf` and issue the command for it.
+    ///
+    /// A transfer is split into chunks of at most `SPI_HOST_FIFO_BYTES`, the
+    /// size of the TX FIFO and the largest `COMMAND.LEN`. Transfers of up to
+    /// 256 bytes are queued in one fill and done with a single command.
+    /// Longer transfers queue the next chunk from `continue_transfer()` once
+    /// the TXEMPTY event shows the previous command has finished, with CS
+    /// held asserted between the commands.
+    ///
+    /// Returns `FAIL` if the TX FIFO isn't empty or a command is still
+    /// active, as the whole chunk couldn't be queued.
+    fn fill_tx_fifo(&self, tx_buf: &[u8]) -> Result<(), ErrorCode> {
+        let regs = self.registers;
 
-            while !regs.status.is_set(status::TXFULL) && regs.status.read(status::TXQD) < 64 {
-                tx_slice = [0, 0, 0, 0];
-                for n in 0..4 {
-                    if self.tx_offset.get() >= self.tx_len.get() {
-                        break;
-                    }
-                    tx_slice[n] = tx_buf[self.tx_offset.get()];
-                    self.tx_offset.set(self.tx_offset.get() + 1);
-                }
-                t_byte = u32::from_le_bytes(tx_slice);
-                regs.tx_data.write(tx_data::DATA.val(t_byte));
+        if regs.status.read(status::TXQD) != 0 || regs.status.is_set(status::ACTIVE) {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let offset = self.tx_offset.get();
+        let chunk = cmp::min(self.tx_len.get() - offset, SPI_HOST_FIFO_BYTES);
+        if chunk == 0 {
+            return Err(ErrorCode::FAIL);
+        }
+
+        self.write_tx_fifo(&tx_buf[offset..offset + chunk]);
+
+        self.tx_offset.set(offset + chunk);
+        self.tx_chunk.set(chunk);
+
+        //Set command register to init transfer
+        self.start_transceive();
+        Ok(())
+    }
+
+    /// Queue `data` in the TX FIFO, packing whole words in the configured
+    /// byte order. The caller must make sure it fits.
+    fn write_tx_fifo(&self, data: &[u8]) {
+        let regs = self.registers;
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            let t_word = self.pack_word([word[0], word[1], word[2], word[3]]);
+            regs.tx_data.write(tx_data::DATA.val(t_word));
+        }
+        //Use byte writes for the tail so no padding is queued
+        let tx_data = (&*regs as *const SpiHostRegisters as usize + TXDATA_OFFSET) as *mut u8;
+        for byte in words.remainder() {
+            unsafe { core::ptr::write_volatile(tx_data, *byte) };
+        }
+    }
 
-                //Transfer Complete in one-shot
-                if self.tx_offset.get() >= self.tx_len.get() {
+    /// Copy the words received for a `len` byte command out of the RX FIFO
+    /// into `rx_buf`, dropping the padding of a partial last word.
+    fn read_rx_fifo(&self, rx_buf: &mut [u8], len: usize) {
+        let regs = self.registers;
+        let mut pos = 0;
+        for _ in 0..self.div_up(len, 4) {
+            let val32 = regs.rx_data.read(rx_data::DATA);
+            for val8 in self.unpack_word(val32) {
+                if pos >= len {
                     break;
                 }
+                rx_buf[pos] = val8;
+                pos += 1;
+            }
+        }
+    }
+
+    /// Transfer `tx` and receive into `rx` by polling, without interrupts or
+    /// a client callback. This is meant for board setup code that needs the
+    /// SPI bus before the kernel loop is running, for example to read a flash
+    /// JEDEC ID. `rx` receives `tx.len()` bytes.
+    ///
+    /// Each command is polled for at most `SYNC_POLL_LIMIT` status reads,
+    /// if it hasn't finished by then the controller is reset and `BUSY` is
+    /// returned. Returns `SIZE` if `tx` is empty or `rx` is shorter, `INVAL`
+    /// for a partial word in `ByteOrder::BigEndian` and `FAIL` if data was
+    /// lost in a FIFO.
+    pub fn transfer_sync(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if self.is_busy() || regs.status.read(status::TXQD) != 0 {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let len = tx.len();
+        if len == 0 || rx.len() < len {
+            return Err(ErrorCode::SIZE);
+        }
+
+        //Partial words can't be reordered, the padding would go out first
+        if self.byte_order.get() == ByteOrder::BigEndian && len % 4 != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+
+        //Keep the async path out of the way until we are done
+        self.set_spi_busy();
+        self.disable_interrupts();
+        self.apply_config(self.chip_select.get() as usize);
+
+        let mut ret = Ok(());
+        let mut offset = 0;
+        while offset < len {
+            let chunk = cmp::min(len - offset, SPI_HOST_FIFO_BYTES);
+            let last = offset + chunk == len;
+
+            self.write_tx_fifo(&tx[offset..offset + chunk]);
+            regs.command.write(
+                command::LEN.val((chunk - 1) as u32)
+                    + command::DIRECTION.val(SPI_HOST_CMD_BIDIRECTIONAL)
+                    + command::SPEED.val(SPI_HOST_CMD_STANDARD_SPI)
+                    + if last {
+                        command::CSAAT::CLEAR
+                    } else {
+                        command::CSAAT::SET
+                    },
+            );
+
+            let done = (0..SYNC_POLL_LIMIT).any(|_| {
+                let status = regs.status.extract();
+                status.is_set(status::TXEMPTY) && !status.is_set(status::ACTIVE)
+            });
+            if !done {
+                ret = Err(ErrorCode::BUSY);
+                break;
+            }
+
+            self.read_rx_fifo(&mut rx[offset..offset + chunk], chunk);
+            if self.fifo_error() {
+                ret = Err(ErrorCode::FAIL);
+                break;
+            }
+
+            offset += chunk;
+        }
+
+        if ret.is_err() {
+            self.clear_err_interrupt();
+            let _ = self.reset_spi_ip();
+        }
+        self.clear_spi_busy();
+        //The events of the polled commands have been dealt with
+        self.clear_event_interrupt();
+        self.enable_interrupts();
+
+        ret
+    }
+
+    /// Pulse chip select `cs` without transferring any data, for devices
+    /// that use the CS edges as a signal, such as shift register latches.
+    ///
+    /// The controller only asserts CS for a command, so this issues a single
+    /// dummy cycle with `CSAAT` clear: CS is asserted, one SCK cycle is
+    /// clocked with the data lines idle and CS is released again after the
+    /// configured `csn_trail`. Like `transfer_sync()` this polls until the
+    /// command is done. The selected chip select is restored afterwards.
+    ///
+    /// Returns `INVAL` if `cs` doesn't exist and `BUSY` while a transfer,
+    /// which may be holding CS asserted, is in progress.
+    pub fn toggle_cs(&self, cs: u32) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if cs as usize >= NUM_CHIP_SELECTS {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy() || self.is_active() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.set_spi_busy();
+        self.disable_interrupts();
+
+        regs.csid.write(csid_ctrl::CSID.val(cs));
+        self.apply_config(cs as usize);
+        regs.command.write(
+            command::LEN.val(0)
+                + command::DIRECTION.val(SegmentDirection::Dummy as u32)
+                + command::SPEED.val(SPI_HOST_CMD_STANDARD_SPI)
+                + command::CSAAT::CLEAR,
+        );
+
+        let done = (0..SYNC_POLL_LIMIT).any(|_| !self.is_active());
+        if !done {
+            self.clear_err_interrupt();
+            let _ = self.reset_spi_ip();
+        }
+
+        regs.csid.write(csid_ctrl::CSID.val(self.chip_select.get()));
+        self.clear_spi_busy();
+        //The events of the polled command have been dealt with
+        self.clear_event_interrupt();
+        self.enable_interrupts();
+
+        if done {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    /// Reset the controller after a transfer has got stuck, for example on
+    /// an unresponsive device, and return the driver to an idle state
+    /// without a chip reset.
+    ///
+    /// Any transfer in progress is abandoned and its buffers are handed back
+    /// to the client, from within this call, with `CANCEL`. The
+    /// configuration (rate, polarity, phase, CS timing) is kept.
+    ///
+    /// Returns `FAIL` if the controller didn't go idle during the reset, the
+    /// buffers are returned regardless.
+    pub fn recover(&self) -> Result<(), ErrorCode> {
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        self.disable_interrupts();
+        //Anything scheduled belongs to the transfer being abandoned
+        self.deferred_call.set(false);
+        self.clear_err_interrupt();
+        self.clear_event_interrupt();
+        let ret = self.reset_spi_ip();
+        self.disable_tx_interrupt();
+        self.enable_interrupts();
+
+        if self.segments.is_some() {
+            self.finish_segments(Err(ErrorCode::CANCEL));
+            return ret;
+        }
+
+        let tx_buf = self.tx_buf.take();
+        let rx_buf = self.rx_buf.take();
+        let tx_offset = self.tx_offset.get();
+        self.reset_internal_state();
+        if let Some(tx_buf) = tx_buf {
+            self.client.map(move |client| {
+                client.read_write_done(tx_buf, rx_buf, tx_offset, Err(ErrorCode::CANCEL))
+            });
+        }
+
+        ret
+    }
+
+    /// True while the controller is running a command, or has commands
+    /// queued. Chip select is released once this is false.
+    pub fn is_active(&self) -> bool {
+        let status = self.registers.status.extract();
+        status.is_set(status::ACTIVE) || status.is_set(status::CMDQD)
+    }
+
+    /// Free space in the TX FIFO, in bytes. The FIFO holds
+    /// `SPI_HOST_FIFO_DEPTH` words, this is how much can be queued without
+    /// overflowing it.
+    pub fn tx_fifo_space(&self) -> usize {
+        let queued = self.registers.status.read(status::TXQD) as usize;
+        SPI_HOST_FIFO_DEPTH.saturating_sub(queued) * 4
+    }
+
+    /// Data waiting in the RX FIFO, in bytes. Only whole words are counted.
+    pub fn rx_fifo_level(&self) -> usize {
+        self.registers.status.read(status::RXQD) as usize * 4
+    }
+
+    /// Reduce the number of interrupts taken by `read_write_bytes()`
+    /// transfers, for throughput bound workloads.
+    ///
+    /// By default each command raises a TXEMPTY event once its last word
+    /// leaves the FIFO and an IDLE event once it has finished on the wire,
+    /// and only the second does any work. With coalescing enabled TXEMPTY
+    /// isn't used, TXWM is taken in its place with `CONTROL.TX_WATERMARK`
+    /// set to `tx_wm` words. A command never holds more than one FIFO's
+    /// worth of data, so there is nothing to refill part way through and a
+    /// TXWM event is only acknowledged: a `tx_wm` of 0, which never fires,
+    /// leaves IDLE as the one interrupt per command. Completion is still
+    /// only reported once the last command is idle.
+    ///
+    /// Returns `INVAL` if `tx_wm` is larger than `SPI_HOST_FIFO_DEPTH` and
+    /// `BUSY` during a transfer.
+    pub fn set_coalescing(&self, enabled: bool, tx_wm: u8) -> Result<(), ErrorCode> {
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if tx_wm as usize > SPI_HOST_FIFO_DEPTH {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.coalesce.set(enabled);
+        self.tx_watermark.set(tx_wm);
+
+        Ok(())
+    }
+
+    /// Number of times `handle_interrupt()` has run since boot, wrapping on
+    /// overflow. For comparing the cost of a workload with and without
+    /// `set_coalescing()`.
+    pub fn interrupt_count(&self) -> usize {
+        self.interrupt_count.get()
+    }
+
+    /// Set how buffer bytes are mapped onto the wire for subsequent
+    /// transfers.
+    ///
+    /// The `STATUS.BYTEORDER` bit is read-only on this IP, so the requested
+    /// order is implemented by how the driver packs the FIFO words. When using
+    /// `ByteOrder::BigEndian` transfer lengths must be a multiple of 4 bytes.
+    ///
+    /// Returns `OFF` before `init()` and `BUSY` during a transfer.
+    pub fn set_byte_order(&self, order: ByteOrder) -> Result<(), ErrorCode> {
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.byte_order.set(order);
+        Ok(())
+    }
+
+    /// The order set with `set_byte_order()`, `ByteOrder::LittleEndian`
+    /// until one is set. Like `get_polarity()` this doesn't need `init()`.
+    pub fn get_byte_order(&self) -> ByteOrder {
+        self.byte_order.get()
+    }
+
+    /// The order to pack FIFO words in, taking into account the order the
+    /// hardware shifts words out in (`STATUS.BYTEORDER`, 0 is LSB first).
+    fn word_order(&self) -> ByteOrder {
+        let hw_little_endian = !self.registers.status.is_set(status::BYTEORDER);
+        match (self.byte_order.get(), hw_little_endian) {
+            (ByteOrder::LittleEndian, true) | (ByteOrder::BigEndian, false) => {
+                ByteOrder::LittleEndian
+            }
+            (ByteOrder::LittleEndian, false) | (ByteOrder::BigEndian, true) => ByteOrder::BigEndian,
+        }
+    }
+
+    /// Pack 4 buffer bytes into a TX FIFO word
+    fn pack_word(&self, bytes: [u8; 4]) -> u32 {
+        pack_fifo_word(bytes, self.word_order())
+    }
+
+    /// Unpack an RX FIFO word into 4 buffer bytes
+    fn unpack_word(&self, word: u32) -> [u8; 4] {
+        unpack_fifo_word(word, self.word_order())
+    }
+
+    pub fn set_segment_client(&self, client: &'static dyn SpiSegmentClient) {
+        self.segment_client.set(client);
+    }
+
+    /// Set the client told when the controller can take a new transfer, see
+    /// the module documentation
+    pub fn set_ready_client(&self, client: &'static dyn SpiReadyClient) {
+        self.ready_client.set(client);
+    }
+
+    /// Transfer `segments` as one transaction, holding chip select asserted
+    /// from the first segment until the end of the last one.
+    ///
+    /// Each segment is issued as one or more commands of at most 256 bytes,
+    /// with `CSAAT` set on every command except the final one. The next
+    /// command is issued once the controller is idle, after any received
+    /// data has been drained. All buffers are returned through
+    /// `SpiSegmentClient::transfer_segments_done()`.
+    ///
+    /// Returns `INVAL` if a segment is empty, is missing a buffer its
+    /// direction needs, or is a `Dummy` segment longer than
+    /// `MAX_DUMMY_CYCLES`.
+    pub fn transfer_segments(
+        &self,
+        segments: &'static mut [SpiSegment],
+    ) -> Result<(), (ErrorCode, &'static mut [SpiSegment])> {
+        if !self.is_initialized() {
+            return Err((ErrorCode::OFF, segments));
+        }
+
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, segments));
+        }
+
+        if !self.segments_valid(segments) {
+            return Err((ErrorCode::INVAL, segments));
+        }
+
+        self.set_spi_busy();
+        self.apply_config(self.chip_select.get() as usize);
+        self.segment_index.set(0);
+        self.segment_offset.set(0);
+        self.segment_chunk.set(0);
+        self.segments.replace(segments);
+
+        self.registers.event_en.modify(event_en::IDLE::SET);
+        self.start_segment_chunk();
+
+        Ok(())
+    }
+
+    /// Check that every segment has the buffers its direction needs, and
+    /// that dummy segments fit in a single command
+    fn segments_valid(&self, segments: &[SpiSegment]) -> bool {
+        !segments.is_empty()
+            && segments.iter().all(|seg| {
+                let tx_ok = seg.tx_buf.as_ref().map_or(false, |b| b.len() >= seg.len);
+                let rx_ok = seg.rx_buf.as_ref().map_or(false, |b| b.len() >= seg.len);
+                let words_ok = self.byte_order.get() == ByteOrder::LittleEndian || seg.len % 4 == 0;
+                seg.len > 0
+                    && match seg.direction {
+                        //No data goes through the FIFOs, so byte order doesn't matter
+                        SegmentDirection::Dummy => seg.len <= MAX_DUMMY_CYCLES,
+                        SegmentDirection::RxOnly => rx_ok && words_ok,
+                        SegmentDirection::TxOnly => tx_ok && words_ok,
+                        SegmentDirection::Bidirectional => {
+                            tx_ok && rx_ok && words_ok && seg.speed == SegmentSpeed::Standard
+                        }
+                    }
+            })
+    }
+
+    pub fn set_verify_client(&self, client: &'static dyn SpiVerifyClient) {
+        self.verify_client.set(client);
+    }
+
+    /// Run the `write` transaction, then the `read` transaction, and check
+    /// that the data received by the last segment of `read` matches the data
+    /// sent by the last segment of `write`. For example `write` could be a
+    /// flash page program and `read` a read of the same page. Each is run as
+    /// by `transfer_segments()`, with chip select held for the whole of one
+    /// transaction and released between them.
+    ///
+    /// The read is issued as soon as the write has finished, so this is only
+    /// useful for devices that can be read straight away. Anything the
+    /// device needs in between, such as polling a flash's busy status, has
+    /// to be done by the caller with separate transfers instead. Reading the
+    /// data back doubles the time taken, so use `transfer_segments()` when
+    /// no check is wanted.
+    ///
+    /// Returns `INVAL` if either transaction isn't valid, or the last
+    /// segments don't send and receive the same number of bytes. The result
+    /// of the comparison and all buffers are returned through
+    /// `SpiVerifyClient::transfer_verified_done()`.
+    pub fn transfer_segments_verified(
+        &self,
+        write: &'static mut [SpiSegment],
+        read: &'static mut [SpiSegment],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [SpiSegment],
+            &'static mut [SpiSegment],
+        ),
+    > {
+        if !self.is_initialized() {
+            return Err((ErrorCode::OFF, write, read));
+        }
+
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, write, read));
+        }
+
+        let comparable = match (write.last(), read.last()) {
+            (Some(w), Some(r)) => {
+                (w.direction == SegmentDirection::TxOnly
+                    || w.direction == SegmentDirection::Bidirectional)
+                    && (r.direction == SegmentDirection::RxOnly
+                        || r.direction == SegmentDirection::Bidirectional)
+                    && w.len == r.len
+            }
+            _ => false,
+        };
+        if !comparable || !self.segments_valid(write) || !self.segments_valid(read) {
+            return Err((ErrorCode::INVAL, write, read));
+        }
+
+        if let Err((e, write)) = self.transfer_segments(write) {
+            return Err((e, write, read));
+        }
+        self.verify_segments.replace(read);
+        self.verify_phase.set(VerifyPhase::Write);
+
+        Ok(())
+    }
+
+    /// Start the read back once the write has finished, or compare the data
+    /// once the read back has finished.
+    fn continue_verified(
+        &self,
+        segments: &'static mut [SpiSegment],
+        status: Result<(), ErrorCode>,
+    ) {
+        let other = match self.verify_segments.take() {
+            Some(other) => other,
+            None => return,
+        };
+
+        if self.verify_phase.get() == VerifyPhase::Write {
+            let (read, status) = match status {
+                Ok(()) => match self.transfer_segments(other) {
+                    Ok(()) => {
+                        self.verify_segments.replace(segments);
+                        self.verify_phase.set(VerifyPhase::Read);
+                        return;
+                    }
+                    Err((e, other)) => (other, Err(e)),
+                },
+                Err(e) => (other, Err(e)),
+            };
+            self.verify_phase.set(VerifyPhase::Idle);
+            self.verify_client.map(move |client| {
+                client.transfer_verified_done(segments, read, status);
+            });
+        } else {
+            let status = status.and_then(|()| {
+                let w = &other[other.len() - 1];
+                let r = &segments[segments.len() - 1];
+                match (w.tx_buf.as_ref(), r.rx_buf.as_ref()) {
+                    (Some(tx), Some(rx)) if tx[..w.len] == rx[..r.len] => Ok(()),
+                    _ => Err(ErrorCode::FAIL),
+                }
+            });
+            self.verify_phase.set(VerifyPhase::Idle);
+            self.verify_client.map(move |client| {
+                client.transfer_verified_done(other, segments, status);
+            });
+        }
+    }
+
+    /// Issue the next command of the current segment
+    fn start_segment_chunk(&self) {
+        self.segments.map(|segments| {
+            let regs = self.registers;
+            let index = self.segment_index.get();
+            let offset = self.segment_offset.get();
+            let seg = &segments[index];
+            let chunk = cmp::min(seg.len - offset, SPI_HOST_FIFO_BYTES);
+            self.segment_chunk.set(chunk);
+
+            if seg.direction == SegmentDirection::TxOnly
+                || seg.direction == SegmentDirection::Bidirectional
+            {
+                seg.tx_buf.as_ref().map(|tx_buf| {
+                    self.write_tx_fifo(&tx_buf[offset..offset + chunk]);
+                });
             }
 
-            //Hold tx_buf for offset transfer continue
-            self.tx_buf.replace(tx_buf);
+            //Hold CS after this command unless it ends the transaction
+            let last = index == segments.len() - 1 && offset + chunk == seg.len;
+            //IDLE is armed by transfer_segments(), drop any event from before
+            //this command so only its own completion is seen
+            self.clear_event_interrupt();
+            regs.command.write(
+                command::LEN.val((chunk - 1) as u32)
+                    + command::DIRECTION.val(seg.direction as u32)
+                    + command::SPEED.val(seg.speed as u32)
+                    + if last {
+                        command::CSAAT::CLEAR
+                    } else {
+                        command::CSAAT::SET
+                    },
+            );
+        });
 
-            //Set command register to init transfer
-            self.start_transceive();
+        self.enable_interrupts();
+    }
+
+    /// Drain the data received by the last command, then issue the next
+    /// command or finish the transaction.
+    fn continue_segments(&self) {
+        let done = self.segments.map_or(true, |segments| {
+            let index = self.segment_index.get();
+            let offset = self.segment_offset.get();
+            let chunk = self.segment_chunk.get();
+            let seg = &mut segments[index];
+
+            if seg.direction == SegmentDirection::RxOnly
+                || seg.direction == SegmentDirection::Bidirectional
+            {
+                seg.rx_buf.as_mut().map(|rx_buf| {
+                    self.read_rx_fifo(&mut rx_buf[offset..offset + chunk], chunk);
+                });
+            }
+
+            if offset + chunk < seg.len {
+                self.segment_offset.set(offset + chunk);
+                false
+            } else if index + 1 < segments.len() {
+                self.segment_index.set(index + 1);
+                self.segment_offset.set(0);
+                false
+            } else {
+                true
+            }
         });
+
+        if done {
+            self.finish_segments(Ok(()));
+        } else {
+            self.start_segment_chunk();
+        }
     }
 
-    /// Issue a command to start SPI transaction
+    fn finish_segments(&self, status: Result<(), ErrorCode>) {
+        self.registers.event_en.modify(event_en::IDLE::CLEAR);
+        self.clear_spi_busy();
+        self.segments.take().map(|segments| {
+            if self.verify_phase.get() != VerifyPhase::Idle {
+                self.continue_verified(segments, status);
+                return;
+            }
+            self.segment_client.map(|client| {
+                client.transfer_segments_done(segments, status);
+            });
+        });
+    }
+
+    /// Issue a command to transfer the chunk queued by `fill_tx_fifo()`
     /// Currently only Bi-Directional transactions are supported
     fn start_transceive(&self) {
         let regs = self.registers;
-        //8-bits that describe command transfer len (cannot exceed 255)
-        let num_transfer_bytes: u32;
-        //TXQD holds number of 32bit words
-        let txfifo_num_bytes = regs.status.read(status::TXQD) * 4;
+        //COMMAND.LEN holds the number of bytes minus one
+        let num_transfer_bytes = (self.tx_chunk.get() - 1) as u32;
 
-        if txfifo_num_bytes > u8::MAX as u32 {
-            num_transfer_bytes = u8::MAX as u32;
-        } else {
-            num_transfer_bytes = txfifo_num_bytes;
-        }
+        //Arm the events and drop any from before this command, so only its
+        //own completion is seen
+        self.enable_tx_interrupt();
+        self.clear_event_interrupt();
 
-        //Flush all data in TXFIFO and assert CSAAT for all
-        // but the last transfer segment.
+        //Assert CSAAT for all but the last chunk of the transfer
         if self.tx_offset.get() >= self.tx_len.get() {
             regs.command.write(
                 command::LEN.val(num_transfer_bytes)
@@ -317,7 +1260,6 @@ impl SpiHost {
             );
         }
         self.enable_interrupts();
-        self.enable_tx_interrupt();
     }
 
     /// Reset the soft internal state, should be called once
@@ -328,6 +1270,7 @@ impl SpiHost {
         self.rx_len.set(0);
         self.tx_offset.set(0);
         self.rx_offset.set(0);
+        self.tx_chunk.set(0);
 
         debug_assert!(self.tx_buf.is_none());
         debug_assert!(self.rx_buf.is_none());
@@ -343,24 +1286,35 @@ impl SpiHost {
     }
 
     /// Reset SPI Host
-    fn reset_spi_ip(&self) {
+    ///
+    /// Each wait is bounded by `SYNC_POLL_LIMIT` status reads, `FAIL` is
+    /// returned if the controller didn't go idle in that time. The reset is
+    /// released either way.
+    fn reset_spi_ip(&self) -> Result<(), ErrorCode> {
         let regs = self.registers;
         //IP to reset state
         regs.ctrl.modify(ctrl::SW_RST::SET);
 
         //Wait for status ready to be set before continuing
-        while regs.status.is_set(status::ACTIVE) {}
+        let idle = (0..SYNC_POLL_LIMIT).any(|_| !regs.status.is_set(status::ACTIVE));
         //Wait for both FIFOs to completely drain
-        while regs.status.read(status::TXQD) != 0 && regs.status.read(status::RXQD) != 0 {}
+        let drained = idle
+            && (0..SYNC_POLL_LIMIT).any(|_| {
+                regs.status.read(status::TXQD) == 0 && regs.status.read(status::RXQD) == 0
+            });
         //Clear Reset
         regs.ctrl.modify(ctrl::SW_RST::CLEAR);
+
+        if drained {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
     }
 
-    /// Enable both event/err IRQ
+    /// Enable both event/err IRQ. Pending interrupts are left pending, so
+    /// an event raised while they were disabled is still taken.
     fn enable_interrupts(&self) {
-        self.registers
-            .intr_state
-            .write(intr::ERROR::SET + intr::SPI_EVENT::SET);
         self.registers
             .intr_enable
             .modify(intr::ERROR::SET + intr::SPI_EVENT::SET);
@@ -387,10 +1341,11 @@ impl SpiHost {
         regs.intr_state.modify(intr::ERROR::SET);
     }
 
-    /// Clear the event IRQ
+    /// Clear the event IRQ. Only that bit is written, a read-modify-write
+    /// would also clear a pending error.
     fn clear_event_interrupt(&self) {
         let regs = self.registers;
-        regs.intr_state.modify(intr::SPI_EVENT::SET);
+        regs.intr_state.write(intr::SPI_EVENT::SET);
     }
     /// Will generate a `test` interrupt on the error irq
     /// Note: Left to allow debug accessibility
@@ -426,12 +1381,24 @@ impl SpiHost {
 
     fn disable_tx_interrupt(&self) {
         let regs = self.registers;
-        regs.event_en.modify(event_en::TXEMPTY::CLEAR);
+        regs.event_en
+            .modify(event_en::TXEMPTY::CLEAR + event_en::TXWM::CLEAR + event_en::IDLE::CLEAR);
     }
 
+    /// TXEMPTY can fire while the last bytes are still on the wire, IDLE
+    /// signals once the command has actually finished. When coalescing TXWM
+    /// replaces TXEMPTY, see `set_coalescing()`.
     fn enable_tx_interrupt(&self) {
         let regs = self.registers;
-        regs.event_en.modify(event_en::TXEMPTY::SET);
+        if self.coalesce.get() {
+            regs.ctrl
+                .modify(ctrl::TX_WATERMARK.val(self.tx_watermark.get() as u32));
+            regs.event_en
+                .modify(event_en::TXEMPTY::CLEAR + event_en::TXWM::SET + event_en::IDLE::SET);
+        } else {
+            regs.event_en
+                .modify(event_en::TXEMPTY::SET + event_en::TXWM::CLEAR + event_en::IDLE::SET);
+        }
     }
 
     /// Enable required error interrupts
@@ -450,32 +1417,170 @@ impl SpiHost {
 
     fn set_spi_busy(&self) {
         self.busy.set(true);
+        //A new transfer replaces any ready notification still to come
+        self.registers.event_en.modify(event_en::READY::CLEAR);
     }
 
     fn clear_spi_busy(&self) {
         self.busy.set(false);
     }
 
+    /// Apply `cfg` in one go. Every field is checked before anything is
+    /// written, so on error the previous configuration is left untouched.
+    /// Returns `INVAL` for an unimplemented chip select, a rate below
+    /// `min_rate()` or a CS timing above `CSN_TIMING_MAX`, `NOSUPPORT` for a
+    /// rate above `max_rate()` and `BUSY` during a transfer.
+    pub fn configure(&self, cfg: SpiHostConfig) -> Result<(), ErrorCode> {
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if cfg.chip_select as usize >= NUM_CHIP_SELECTS
+            || cfg.rate == 0
+            || !Self::cs_timing_valid(cfg.csn_lead, cfg.csn_trail, cfg.csn_idle)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let scaler = self.calculate_tsck_scaler(cfg.rate)?;
+        let cpol = match cfg.polarity {
+            ClockPolarity::IdleLow => conf_opts::CPOL_0::CLEAR,
+            ClockPolarity::IdleHigh => conf_opts::CPOL_0::SET,
+        };
+        let cpha = match cfg.phase {
+            ClockPhase::SampleLeading => conf_opts::CPHA_0::CLEAR,
+            ClockPhase::SampleTrailing => conf_opts::CPHA_0::SET,
+        };
+
+        self.specify_chip_select(cfg.chip_select)?;
+        self.modify_config(
+            conf_opts::CLKDIV_0.val(scaler as u32)
+                + conf_opts::CSNLEAD_0.val(cfg.csn_lead as u32)
+                + conf_opts::CSNTRAIL_0.val(cfg.csn_trail as u32)
+                + conf_opts::CSNIDLE_0.val(cfg.csn_idle as u32)
+                + cpol
+                + cpha,
+        );
+        self.tsclk[cfg.chip_select as usize].set(cfg.rate);
+
+        Ok(())
+    }
+
+    /// Set the CS timing of the selected chip select, for devices that need
+    /// longer CS setup, hold or idle times. All three are counted in SCK
+    /// half-periods, on top of the minimum the hardware always inserts.
+    ///
+    /// Returns `INVAL` if any value is above `CSN_TIMING_MAX` and `BUSY`
+    /// during a transfer.
+    pub fn set_cs_timing(&self, lead: u8, trail: u8, idle: u8) -> Result<(), ErrorCode> {
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if !Self::cs_timing_valid(lead, trail, idle) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.modify_config(
+            conf_opts::CSNLEAD_0.val(lead as u32)
+                + conf_opts::CSNTRAIL_0.val(trail as u32)
+                + conf_opts::CSNIDLE_0.val(idle as u32),
+        );
+
+        Ok(())
+    }
+
+    /// Returns the `(lead, trail, idle)` CS timing of the selected chip select
+    pub fn get_cs_timing(&self) -> (u8, u8, u8) {
+        let opts = self.config();
+        (
+            opts.read(conf_opts::CSNLEAD_0) as u8,
+            opts.read(conf_opts::CSNTRAIL_0) as u8,
+            opts.read(conf_opts::CSNIDLE_0) as u8,
+        )
+    }
+
+    fn cs_timing_valid(lead: u8, trail: u8, idle: u8) -> bool {
+        lead <= CSN_TIMING_MAX && trail <= CSN_TIMING_MAX && idle <= CSN_TIMING_MAX
+    }
+
     /// Divide a/b and return a value always rounded
     /// up to the nearest integer
     fn div_up(&self, a: usize, b: usize) -> usize {
         (a + (b - 1)) / b
     }
 
+    /// The settings of the selected chip select
+    fn config(&self) -> LocalRegisterCopy<u32, conf_opts::Register> {
+        LocalRegisterCopy::new(self.config[self.chip_select.get() as usize].get())
+    }
+
+    /// Change the settings of the selected chip select. They are written to
+    /// `CONFIGOPTS` straight away unless a transfer is running, so that the
+    /// transfer isn't split across two modes, and are applied before the
+    /// next transfer starts either way.
+    fn modify_config(&self, field: FieldValue<u32, conf_opts::Register>) {
+        let mut config = self.config();
+        config.modify(field);
+        self.config[self.chip_select.get() as usize].set(config.get());
+
+        if !self.is_busy() {
+            self.apply_config(self.chip_select.get() as usize);
+        }
+    }
+
+    /// Write the settings of chip select `cs` to its `CONFIGOPTS`. This is
+    /// done before every transfer, so the configured rate and mode are in
+    /// effect whatever order the setters were called in, and even after a
+    /// reset has cleared the register.
+    fn apply_config(&self, cs: usize) {
+        self.registers.config_opts[cs].set(self.config[cs].get());
+    }
+
+    /// The highest SCK rate the host can run at, half the CPU clock
+    pub fn max_rate(&self) -> u32 {
+        self.cpu_clk / 2
+    }
+
+    /// The lowest SCK rate the host can run at, limited by the width of the
+    /// `CLKDIV` field
+    pub fn min_rate(&self) -> u32 {
+        let max_div = 2 * (conf_opts::CLKDIV_0.mask + 1);
+        self.div_up(self.cpu_clk as usize, max_div as usize) as u32
+    }
+
     /// Calculate the scaler based on a specified tsclk rate
     /// This scaler will pre-scale the cpu_clk and must be <= cpu_clk/2
+    ///
+    /// Returns `NOSUPPORT` above `max_rate()` and `INVAL` below `min_rate()`,
+    /// where the scaler doesn't fit in `CLKDIV`.
     fn calculate_tsck_scaler(&self, rate: u32) -> Result<u16, ErrorCode> {
-        if rate > self.cpu_clk / 2 {
+        if rate > self.max_rate() {
             return Err(ErrorCode::NOSUPPORT);
         }
+        if rate == 0 {
+            return Err(ErrorCode::INVAL);
+        }
         //Divide and truncate
         let mut scaler: u32 = (self.cpu_clk / (2 * rate)) - 1;
 
-        //Increase scaler if the division was not exact, ensuring that it does not overflow
-        //or exceed divider specification where tsck is at most <= Tclk/2
-        if self.cpu_clk % (2 * rate) != 0 && scaler != 0xFF {
+        //Increase scaler if the division was not exact, so that tsck is at
+        //most the requested rate
+        if self.cpu_clk % (2 * rate) != 0 {
             scaler += 1;
         }
+
+        if scaler > conf_opts::CLKDIV_0.mask {
+            return Err(ErrorCode::INVAL);
+        }
         Ok(scaler as u16)
     }
 }
@@ -488,6 +1593,8 @@ impl hil::spi::SpiMaster for SpiHost {
         self.event_enable();
         self.err_enable();
 
+        regs.intr_state
+            .write(intr::ERROR::SET + intr::SPI_EVENT::SET);
         self.enable_interrupts();
 
         self.enable_spi_host();
@@ -498,6 +1605,8 @@ impl hil::spi::SpiMaster for SpiHost {
         // Could be Verilator specific
         regs.tx_data.write(tx_data::DATA.val(0x00));
         assert_eq!(regs.status.read(status::TXQD), 0);
+
+        self.initialized.set(true);
         Ok(())
     }
 
@@ -515,58 +1624,59 @@ impl hil::spi::SpiMaster for SpiHost {
         rx_buf: Option<&'static mut [u8]>,
         len: usize,
     ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
-        debug_assert!(!self.busy.get());
-        debug_assert!(self.tx_buf.is_none());
-        debug_assert!(self.rx_buf.is_none());
         let regs = self.registers;
 
-        if self.is_busy() || regs.status.is_set(status::TXFULL) {
+        if !self.is_initialized() {
+            return Err((ErrorCode::OFF, tx_buf, rx_buf));
+        }
+
+        //A previous transfer is still holding the buffers or the FIFO
+        if self.is_busy() || regs.status.read(status::TXQD) != 0 {
             return Err((ErrorCode::BUSY, tx_buf, rx_buf));
         }
 
+        //Don't trust an earlier error path to have cleaned up, stale
+        //offsets would skip bytes of this transfer
+        self.tx_len.set(0);
+        self.rx_len.set(0);
+        self.tx_offset.set(0);
+        self.rx_offset.set(0);
+        self.tx_chunk.set(0);
+
         if rx_buf.is_none() {
             return Err((ErrorCode::NOMEM, tx_buf, rx_buf));
         }
 
-        self.tx_len.set(cmp::min(len, tx_buf.len()));
+        let tx_len = cmp::min(len, tx_buf.len());
+        if tx_len == 0 {
+            return Err((ErrorCode::SIZE, tx_buf, rx_buf));
+        }
 
-        let mut t_byte: u32;
-        let mut tx_slice: [u8; 4];
-        //We are committing to the transfer now
-        self.set_spi_busy();
+        //Partial words can't be reordered, the padding would go out first
+        if self.byte_order.get() == ByteOrder::BigEndian && tx_len % 4 != 0 {
+            return Err((ErrorCode::INVAL, tx_buf, rx_buf));
+        }
 
-        while !regs.status.is_set(status::TXFULL) && regs.status.read(status::TXQD) < 64 {
-            tx_slice = [0, 0, 0, 0];
-            for n in 0..4 {
-                if self.tx_offset.get() >= self.tx_len.get() {
-                    break;
-                }
-                tx_slice[n] = tx_buf[self.tx_offset.get()];
-                self.tx_offset.set(self.tx_offset.get() + 1);
-            }
-            t_byte = u32::from_le_bytes(tx_slice);
-            regs.tx_data.write(tx_data::DATA.val(t_byte));
+        self.tx_len.set(tx_len);
+        self.apply_config(self.chip_select.get() as usize);
 
-            //Transfer Complete in one-shot
-            if self.tx_offset.get() >= self.tx_len.get() {
-                break;
-            }
+        if let Err(e) = self.fill_tx_fifo(tx_buf) {
+            self.tx_len.set(0);
+            self.tx_offset.set(0);
+            return Err((e, tx_buf, rx_buf));
         }
+        //We are committed to the transfer now
+        self.set_spi_busy();
 
         //Hold tx_buf for offset transfer continue
         self.tx_buf.replace(tx_buf);
 
         //Hold rx_buf for later
-
         rx_buf.map(|rx_buf_t| {
-            self.rx_len
-                .set(cmp::min(self.tx_len.get() as usize, rx_buf_t.len()) as usize);
+            self.rx_len.set(cmp::min(tx_len, rx_buf_t.len()));
             self.rx_buf.replace(rx_buf_t);
         });
 
-        //Set command register to init transfer
-        self.start_transceive();
-
         Ok(())
     }
 
@@ -585,9 +1695,25 @@ impl hil::spi::SpiMaster for SpiHost {
         Err(ErrorCode::FAIL)
     }
 
+    /// Select the chip select used for transfers. `set_rate()`,
+    /// `set_polarity()` and `set_phase()` configure the selected chip
+    /// select only, so each device on the bus keeps its own settings.
     fn specify_chip_select(&self, cs: Self::ChipSelect) -> Result<(), ErrorCode> {
         let regs = self.registers;
 
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if cs as usize >= NUM_CHIP_SELECTS {
+            return Err(ErrorCode::INVAL);
+        }
+
+        //Changing CS part way through would split the transfer
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
         //CSID will index the CONFIGOPTS multi-register
         regs.csid.write(csid_ctrl::CSID.val(cs));
         self.chip_select.set(cs);
@@ -596,36 +1722,44 @@ impl hil::spi::SpiMaster for SpiHost {
     }
 
     fn set_rate(&self, rate: u32) -> Result<u32, ErrorCode> {
-        let regs = self.registers;
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
 
         match self.calculate_tsck_scaler(rate) {
             Ok(scaler) => {
-                regs.config_opts
-                    .modify(conf_opts::CLKDIV_0.val(scaler as u32));
-                self.tsclk.set(rate);
+                self.modify_config(conf_opts::CLKDIV_0.val(scaler as u32));
+                self.tsclk[self.chip_select.get() as usize].set(rate);
                 Ok(rate)
             }
             Err(e) => Err(e),
         }
     }
 
+    /// Returns 0 before `init()`, or if no rate has been set
     fn get_rate(&self) -> u32 {
-        self.tsclk.get()
+        if !self.is_initialized() {
+            return 0;
+        }
+
+        self.tsclk[self.chip_select.get() as usize].get()
     }
 
     fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
-        let regs = self.registers;
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
         match polarity {
-            ClockPolarity::IdleLow => regs.config_opts.modify(conf_opts::CPOL_0::CLEAR),
-            ClockPolarity::IdleHigh => regs.config_opts.modify(conf_opts::CPOL_0::SET),
+            ClockPolarity::IdleLow => self.modify_config(conf_opts::CPOL_0::CLEAR),
+            ClockPolarity::IdleHigh => self.modify_config(conf_opts::CPOL_0::SET),
         };
         Ok(())
     }
 
+    /// Before `init()` this reports the reset value, `IdleLow`
     fn get_polarity(&self) -> ClockPolarity {
-        let regs = self.registers;
-
-        match regs.config_opts.read(conf_opts::CPOL_0) {
+        match self.config().read(conf_opts::CPOL_0) {
             0 => ClockPolarity::IdleLow,
             1 => ClockPolarity::IdleHigh,
             _ => unreachable!(),
@@ -633,18 +1767,20 @@ impl hil::spi::SpiMaster for SpiHost {
     }
 
     fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
-        let regs = self.registers;
+        if !self.is_initialized() {
+            return Err(ErrorCode::OFF);
+        }
+
         match phase {
-            ClockPhase::SampleLeading => regs.config_opts.modify(conf_opts::CPHA_0::CLEAR),
-            ClockPhase::SampleTrailing => regs.config_opts.modify(conf_opts::CPHA_0::SET),
+            ClockPhase::SampleLeading => self.modify_config(conf_opts::CPHA_0::CLEAR),
+            ClockPhase::SampleTrailing => self.modify_config(conf_opts::CPHA_0::SET),
         };
         Ok(())
     }
 
+    /// Before `init()` this reports the reset value, `SampleLeading`
     fn get_phase(&self) -> ClockPhase {
-        let regs = self.registers;
-
-        match regs.config_opts.read(conf_opts::CPHA_0) {
+        match self.config().read(conf_opts::CPHA_0) {
             1 => ClockPhase::SampleTrailing,
             0 => ClockPhase::SampleLeading,
             _ => unreachable!(),
@@ -663,3 +1799,11 @@ impl hil::spi::SpiMaster for SpiHost {
         unimplemented!("spi_host: does not support release low");
     }
 }
+
+impl DynamicDeferredCallClient for SpiHost {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.deferred_call.replace(false) {
+            self.continue_command();
+        }
+    }
+}
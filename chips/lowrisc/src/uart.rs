@@ -1,6 +1,50 @@
 //! UART driver.
+//!
+//! Break signals
+//! -------------
+//!
+//! `send_break()` holds TX low using the TX pin override, while dummy
+//! characters are clocked out of the FIFO to time the break. A break is
+//! therefore a whole number of character times (10 bit periods), at least
+//! one, rounded up from the requested length.
+//!
+//! The receiver reports a break once RX has been held low for 2 character
+//! times (the smallest `RXBLVL` setting), so shorter breaks, such as the
+//! 13 bit LIN break, are only seen as framing errors. Breaks are reported to
+//! the client set with `set_break_client()`.
+//!
+//! 9-bit multidrop frames
+//! ----------------------
+//!
+//! The UART only supports 8 data bits, there is no true 9-bit mode. Multidrop
+//! buses (such as RS-485 with 9-bit addressing) can still be driven by using
+//! the parity bit as the 9th bit: `set_multidrop()` enables parity and
+//! `transmit_9bit_sync()` picks even or odd parity for each frame so that the
+//! parity bit carries the address mark.
+//!
+//! Received frames are checked against even parity, a frame with the address
+//! bit set is the one that doesn't match, which the hardware reports with the
+//! `rx_parity_err` interrupt. As that is a single latched flag rather than a
+//! per-byte status, `receive_9bit_sync()` must be called for each frame before
+//! the next one arrives. If a flagged frame is followed by more data the
+//! address bit can't be recovered and `FAIL` is returned.
+//!
+//! TX and RX share the parity setting, so the bus has to be used half duplex,
+//! as RS-485 is anyway. `transmit_buffer()` doesn't set the parity per byte,
+//! the 9th bit of frames sent with it is undefined while in multidrop mode.
+//!
+//! Changing the baud rate
+//! ----------------------
+//!
+//! `configure()` resets the FIFOs along with the rest of the UART, dropping
+//! anything queued. `set_baud_rate()` only reprograms the divisor, once the
+//! TX FIFO has drained, so bytes already queued go out at the old rate and
+//! the next transmit uses the new one. The achievable rates depend on the
+//! peripheral clock, the divisor is `baud * 2^20 / clock` and must fit in 16
+//! bits.
 
 use core::cell::Cell;
+use core::fmt::Write;
 use kernel::ErrorCode;
 
 use kernel::hil;
@@ -100,20 +144,64 @@ register_bitfields![u32,
     ]
 ];
 
+/// Client for break signals
+pub trait BreakClient {
+    /// A break started with `send_break()` has finished and TX is released
+    fn break_sent(&self);
+
+    /// A break was detected on RX
+    fn break_received(&self);
+}
+
+/// Number of bit periods in a character, start + 8 data + stop
+const BITS_PER_CHAR: u32 = 10;
+/// Largest value of the `NCO` baud rate divisor
+const NCO_MAX: u64 = 0xFFFF;
+
+/// Whether odd parity has to be used for the parity bit of a frame carrying
+/// `data` to be `address`, see the 9-bit multidrop frames documentation.
+pub const fn multidrop_parity_odd(data: u8, address: bool) -> bool {
+    // Even parity sets the bit when `data` has an odd number of ones
+    let even_bit = data.count_ones() % 2 == 1;
+    even_bit != address
+}
+
 pub struct Uart<'a> {
     registers: StaticRef<UartRegisters>,
     clock_frequency: u32,
     tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
     rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    break_client: OptionalCell<&'a dyn BreakClient>,
 
     tx_buffer: TakeCell<'static, [u8]>,
     tx_len: Cell<usize>,
     tx_index: Cell<usize>,
+    tx_aborted: Cell<bool>,
+
+    /// A break is being sent, with this many characters still to queue
+    break_active: Cell<bool>,
+    break_remaining: Cell<u32>,
 
     rx_buffer: TakeCell<'static, [u8]>,
     rx_len: Cell<usize>,
+    rx_aborted: Cell<bool>,
+
+    multidrop: Cell<bool>,
+
+    /// Number of times the RX FIFO has overflowed
+    rx_overruns: Cell<usize>,
 }
 
+/// The bytes `self_test()` sends, alternating and solid bit patterns
+pub const SELF_TEST_PATTERN: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+// Status reads `self_test()` waits for each byte to be received
+const SELF_TEST_POLL_LIMIT: usize = 1_000_000;
+// Status reads `flush_tx()` waits for the TX FIFO to empty, enough for a full
+// FIFO at low baud rates
+const FLUSH_POLL_LIMIT: usize = 10_000_000;
+/// Status reads `receive_9bit_sync()` waits for a frame to arrive
+pub const RECEIVE_POLL_LIMIT: usize = 10_000_000;
+
 #[derive(Copy, Clone)]
 pub struct UartParams {
     pub baud_rate: u32,
@@ -126,15 +214,22 @@ impl<'a> Uart<'a> {
             clock_frequency: clock_frequency,
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
+            break_client: OptionalCell::empty(),
             tx_buffer: TakeCell::empty(),
             tx_len: Cell::new(0),
             tx_index: Cell::new(0),
+            tx_aborted: Cell::new(false),
+            break_active: Cell::new(false),
+            break_remaining: Cell::new(0),
             rx_buffer: TakeCell::empty(),
             rx_len: Cell::new(0),
+            rx_aborted: Cell::new(false),
+            multidrop: Cell::new(false),
+            rx_overruns: Cell::new(0),
         }
     }
 
-    fn set_baud_rate(&self, baud_rate: u32) {
+    fn configure_baud_rate(&self, baud_rate: u32) {
         let regs = self.registers;
         let uart_ctrl_nco = ((baud_rate as u64) << 20) / self.clock_frequency as u64;
 
@@ -178,6 +273,84 @@ impl<'a> Uart<'a> {
         regs.intr_state.write(intr::rx_watermark::SET);
     }
 
+    fn enable_break_interrupt(&self) {
+        let regs = self.registers;
+
+        // Clear any stale break before listening for a new one
+        regs.intr_state.write(intr::rx_break_err::SET);
+        regs.intr_enable.modify(intr::rx_break_err::SET);
+    }
+
+    fn disable_break_interrupt(&self) {
+        let regs = self.registers;
+
+        regs.intr_enable.modify(intr::rx_break_err::CLEAR);
+        regs.intr_state.write(intr::rx_break_err::SET);
+    }
+
+    /// Set the client for breaks sent and received. Break detection is only
+    /// enabled while a client is set.
+    pub fn set_break_client(&self, client: &'a dyn BreakClient) {
+        self.break_client.set(client);
+        self.enable_break_interrupt();
+    }
+
+    pub fn clear_break_client(&self) {
+        self.disable_break_interrupt();
+        self.break_client.clear();
+    }
+
+    /// Hold TX low for at least `duration_bits` bit periods. The length is
+    /// rounded up to a whole number of characters, see the module
+    /// documentation. `break_sent()` is called once TX has been released.
+    ///
+    /// Returns `BUSY` if a transmit or break is in progress, or what was
+    /// already sent doesn't finish, and `INVAL` if `duration_bits` is zero.
+    pub fn send_break(&self, duration_bits: u32) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if duration_bits == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.tx_buffer.is_some() || self.break_active.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Let anything already sent finish before pulling the line low
+        self.flush_tx()?;
+
+        let bits_per_char = self.bits_per_char();
+        let chars = (duration_bits + bits_per_char - 1) / bits_per_char;
+        self.break_active.set(true);
+        self.break_remaining.set(chars);
+
+        regs.ovrd.write(ovrd::txen::SET + ovrd::txval::CLEAR);
+        self.break_progress();
+
+        Ok(())
+    }
+
+    /// Bit periods per character, including the parity bit if enabled
+    fn bits_per_char(&self) -> u32 {
+        if self.registers.ctrl.is_set(ctrl::parity_en) {
+            BITS_PER_CHAR + 1
+        } else {
+            BITS_PER_CHAR
+        }
+    }
+
+    /// Queue dummy characters to time the break, these never reach the pin
+    /// while the override is active.
+    fn break_progress(&self) {
+        let regs = self.registers;
+
+        self.enable_tx_interrupt();
+        while self.break_remaining.get() > 0 && !regs.status.is_set(status::txfull) {
+            regs.wdata.write(wdata::data.val(0));
+            self.break_remaining.set(self.break_remaining.get() - 1);
+        }
+    }
+
     fn tx_progress(&self) {
         let regs = self.registers;
         let idx = self.tx_index.get();
@@ -207,14 +380,63 @@ impl<'a> Uart<'a> {
         }
     }
 
+    /// Write the control, status and interrupt registers to `writer`, for
+    /// the panic output.
+    pub fn dump_state(&self, writer: &mut dyn Write) {
+        let regs = self.registers;
+        let _ = writer.write_fmt(format_args!(
+            " ctrl: {:#010X}  status: {:#010X}  fifo_status: {:#010X}  intr_state: {:#010X}",
+            regs.ctrl.get(),
+            regs.status.get(),
+            regs.fifo_status.get(),
+            regs.intr_state.get()
+        ));
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let intrs = regs.intr_state.extract();
 
+        if intrs.is_set(intr::rx_overflow) {
+            regs.intr_state.write(intr::rx_overflow::SET);
+            self.rx_overruns
+                .set(self.rx_overruns.get().saturating_add(1));
+        }
+
+        if intrs.is_set(intr::rx_break_err) {
+            regs.intr_state.write(intr::rx_break_err::SET);
+            self.break_client.map(|client| client.break_received());
+        }
+
         if intrs.is_set(intr::tx_empty) {
             self.disable_tx_interrupt();
 
-            if self.tx_index.get() == self.tx_len.get() {
+            if self.break_active.get() {
+                if self.break_remaining.get() > 0 {
+                    self.break_progress();
+                } else {
+                    // TX empty fires once the last character leaves the FIFO,
+                    // wait for it to finish shifting out before releasing TX.
+                    // TX is released regardless if it doesn't.
+                    let _ = self.flush_tx();
+                    regs.ovrd.write(ovrd::txen::CLEAR);
+                    self.break_active.set(false);
+                    self.break_client.map(|client| client.break_sent());
+                }
+            } else if self.tx_aborted.get() {
+                // `transmit_abort()` has already stopped the transmit, tx_index
+                // is the number of bytes that actually went out.
+                self.tx_aborted.set(false);
+                self.tx_client.map(|client| {
+                    self.tx_buffer.take().map(|tx_buf| {
+                        client.transmitted_buffer(
+                            tx_buf,
+                            self.tx_index.get(),
+                            Err(ErrorCode::CANCEL),
+                        );
+                    });
+                });
+            } else if self.tx_index.get() == self.tx_len.get() {
                 // We sent everything to the UART hardware, now from an
                 // interrupt callback we can issue the callback.
                 self.tx_client.map(|client| {
@@ -234,6 +456,23 @@ impl<'a> Uart<'a> {
                     let mut len = 0;
                     let mut return_code = Ok(());
 
+                    if self.rx_aborted.replace(false) {
+                        // `receive_abort()` got us here, only return what
+                        // has already arrived.
+                        while len < self.rx_len.get() && !regs.status.is_set(status::rxempty) {
+                            rx_buf[len] = regs.rdata.get() as u8;
+                            len += 1;
+                        }
+
+                        client.received_buffer(
+                            rx_buf,
+                            len,
+                            Err(ErrorCode::CANCEL),
+                            uart::Error::Aborted,
+                        );
+                        return;
+                    }
+
                     for i in 0..self.rx_len.get() {
                         rx_buf[i] = regs.rdata.get() as u8;
                         len = i + 1;
@@ -258,13 +497,254 @@ impl<'a> Uart<'a> {
             regs.wdata.write(wdata::data.val(*b as u32));
         }
     }
+
+    /// Enable or disable 9-bit multidrop frames, using the parity bit as the
+    /// 9th bit. See the module documentation for the limitations.
+    ///
+    /// Returns `BUSY`, with the framing unchanged, if what was already sent
+    /// doesn't finish.
+    pub fn set_multidrop(&self, enabled: bool) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        // Don't change the framing of anything still being sent
+        self.flush_tx()?;
+
+        if enabled {
+            regs.ctrl
+                .modify(ctrl::parity_en::SET + ctrl::parity_odd::CLEAR);
+        } else {
+            regs.ctrl
+                .modify(ctrl::parity_en::CLEAR + ctrl::parity_odd::CLEAR);
+        }
+        regs.intr_state.write(intr::rx_parity_err::SET);
+        self.multidrop.set(enabled);
+
+        Ok(())
+    }
+
+    pub fn is_multidrop(&self) -> bool {
+        self.multidrop.get()
+    }
+
+    /// Send a single 9-bit frame, `address` is the 9th bit. Busy waits until
+    /// the frame has been sent.
+    ///
+    /// Returns `OFF` if multidrop mode isn't enabled and `BUSY` if a transmit
+    /// or break is in progress, or the TX FIFO doesn't empty.
+    pub fn transmit_9bit_sync(&self, data: u8, address: bool) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if !self.multidrop.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.tx_buffer.is_some() || self.break_active.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.flush_tx()?;
+        if multidrop_parity_odd(data, address) {
+            regs.ctrl.modify(ctrl::parity_odd::SET);
+        } else {
+            regs.ctrl.modify(ctrl::parity_odd::CLEAR);
+        }
+
+        regs.wdata.write(wdata::data.val(data as u32));
+        let ret = self.flush_tx();
+
+        // Received frames are always checked against even parity
+        regs.ctrl.modify(ctrl::parity_odd::CLEAR);
+
+        ret
+    }
+
+    /// Busy wait for a single 9-bit frame, returning the data and the 9th
+    /// (address) bit.
+    ///
+    /// Returns `OFF` if multidrop mode isn't enabled, `BUSY` if no frame
+    /// arrives within `RECEIVE_POLL_LIMIT` status reads and `FAIL` if the
+    /// address bit can't be recovered because more data arrived before the
+    /// frame was read, the frame is dropped in that case.
+    pub fn receive_9bit_sync(&self) -> Result<(u8, bool), ErrorCode> {
+        let regs = self.registers;
+
+        if !self.multidrop.get() {
+            return Err(ErrorCode::OFF);
+        }
+
+        if !(0..RECEIVE_POLL_LIMIT).any(|_| !regs.status.is_set(status::rxempty)) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Sample the flag before the FIFO level, so that a later frame can
+        // only ever show up in the level.
+        let mismatch = regs.intr_state.is_set(intr::rx_parity_err);
+        let queued = regs.fifo_status.read(fifo_status::rxlvl);
+        let data = regs.rdata.get() as u8;
+
+        if mismatch {
+            regs.intr_state.write(intr::rx_parity_err::SET);
+        }
+        if regs.status.is_set(status::rxempty) {
+            // Don't leave the watermark interrupt pending for a frame that
+            // has already been read.
+            regs.intr_state.write(intr::rx_watermark::SET);
+        }
+
+        if mismatch && queued > 1 {
+            return Err(ErrorCode::FAIL);
+        }
+
+        // The parity bit matches even parity unless the hardware flagged it
+        let even_bit = data.count_ones() % 2 == 1;
+        Ok((data, even_bit != mismatch))
+    }
+
+    /// Enable or disable system loopback, where everything sent on TX is
+    /// received on RX.
+    pub fn set_system_loopback(&self, enabled: bool) {
+        let regs = self.registers;
+
+        if enabled {
+            regs.ctrl.modify(ctrl::slpbk::SET);
+        } else {
+            regs.ctrl.modify(ctrl::slpbk::CLEAR);
+        }
+    }
+
+    /// Number of bytes waiting in the RX FIFO
+    pub fn rx_fifo_level(&self) -> usize {
+        self.registers.fifo_status.read(fifo_status::rxlvl) as usize
+    }
+
+    /// Number of bytes in the TX FIFO still to be sent
+    pub fn tx_fifo_level(&self) -> usize {
+        self.registers.fifo_status.read(fifo_status::txlvl) as usize
+    }
+
+    /// Number of times a byte has arrived with the RX FIFO full since boot.
+    /// Each overflow drops at least one byte, but several bytes dropped
+    /// before the interrupt is handled only count once.
+    pub fn rx_overrun_count(&self) -> usize {
+        self.rx_overruns.get()
+    }
+
+    /// Change the baud rate without reconfiguring the rest of the UART, for
+    /// protocols that negotiate a faster rate once connected. See the module
+    /// documentation.
+    ///
+    /// Busy waits for anything already in the TX FIFO to be sent at the old
+    /// rate first. Returns `INVAL` if `baud_rate` can't be reached from the
+    /// peripheral clock with the 16 bit `NCO` divisor, and `BUSY` if a
+    /// `transmit_buffer()` or break is in progress, what was already sent
+    /// doesn't finish, or a byte is being received. The rate is unchanged on
+    /// error.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        let nco = ((baud_rate as u64) << 20) / self.clock_frequency as u64;
+        if nco == 0 || nco > NCO_MAX {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.tx_buffer.is_some() || self.break_active.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.flush_tx()?;
+        if !regs.status.is_set(status::rxidle) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        regs.ctrl.modify(ctrl::nco.val(nco as u32));
+
+        Ok(())
+    }
+
+    /// Busy wait until everything in the TX FIFO has been sent
+    ///
+    /// Returns `BUSY` if TX isn't idle after `FLUSH_POLL_LIMIT` status reads.
+    pub fn flush_tx(&self) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+        if (0..FLUSH_POLL_LIMIT).any(|_| regs.status.is_set(status::txidle)) {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    /// Check the TX and RX paths by sending `SELF_TEST_PATTERN` through
+    /// system loopback and reading it back. Busy waits, and restores the
+    /// previous control and interrupt settings before returning.
+    ///
+    /// Must not be called while anything else is using the UART: the RX FIFO
+    /// is reset, so bytes that have been received but not yet read are lost,
+    /// and nothing sent while the test runs reaches the TX pin.
+    ///
+    /// Returns `BUSY` if a transmit, receive or break is in progress or TX
+    /// doesn't go idle, and `FAIL` if a byte doesn't come back, or comes back
+    /// changed.
+    pub fn self_test(&self) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        if self.tx_buffer.is_some() || self.rx_buffer.is_some() || self.break_active.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.flush_tx()?;
+        let ctrl = regs.ctrl.get();
+        let intr_enable = regs.intr_enable.get();
+
+        // Poll for the data rather than taking interrupts for it
+        regs.intr_enable.set(0);
+        regs.ctrl
+            .modify(ctrl::slpbk::SET + ctrl::parity_en::CLEAR + ctrl::parity_odd::CLEAR);
+        regs.fifo_ctrl.modify(fifo_ctrl::rxrst::SET);
+
+        let mut ret = Ok(());
+        for &b in SELF_TEST_PATTERN.iter() {
+            regs.wdata.write(wdata::data.val(b as u32));
+
+            let mut polls = 0;
+            while regs.status.is_set(status::rxempty) && polls < SELF_TEST_POLL_LIMIT {
+                polls += 1;
+            }
+            if regs.status.is_set(status::rxempty) || regs.rdata.get() as u8 != b {
+                ret = Err(ErrorCode::FAIL);
+                break;
+            }
+        }
+
+        ret = ret.and(self.flush_tx());
+        regs.fifo_ctrl.modify(fifo_ctrl::rxrst::SET);
+        regs.intr_state.write(
+            intr::tx_watermark::SET
+                + intr::rx_watermark::SET
+                + intr::tx_empty::SET
+                + intr::rx_timeout::SET,
+        );
+        regs.ctrl.set(ctrl);
+        regs.intr_enable.set(intr_enable);
+
+        ret
+    }
+}
+
+/// Lets `PwrMgr::request_reset()` finish sending console output
+impl crate::pwrmgr::ResetFlush for Uart<'_> {
+    fn flush_before_reset(&self) {
+        let _ = self.flush_tx();
+    }
 }
 
 impl hil::uart::Configure for Uart<'_> {
     fn configure(&self, params: hil::uart::Parameters) -> Result<(), ErrorCode> {
         let regs = self.registers;
         // We can set the baud rate.
-        self.set_baud_rate(params.baud_rate);
+        self.configure_baud_rate(params.baud_rate);
+
+        // Setting the baud rate resets the rest of CTRL
+        if self.multidrop.get() {
+            regs.ctrl.modify(ctrl::parity_en::SET);
+        }
 
         regs.fifo_ctrl
             .write(fifo_ctrl::rxrst::SET + fifo_ctrl::txrst::SET);
@@ -272,6 +752,14 @@ impl hil::uart::Configure for Uart<'_> {
         // Disable all interrupts for now
         regs.intr_enable.set(0 as u32);
 
+        // Always count overruns, whether or not a receive is in progress
+        regs.intr_state.write(intr::rx_overflow::SET);
+        regs.intr_enable.modify(intr::rx_overflow::SET);
+
+        if self.break_client.is_some() {
+            self.enable_break_interrupt();
+        }
+
         Ok(())
     }
 }
@@ -288,7 +776,7 @@ impl<'a> hil::uart::Transmit<'a> for Uart<'a> {
     ) -> Result<(), (ErrorCode, &'static mut [u8])> {
         if tx_len == 0 || tx_len > tx_data.len() {
             Err((ErrorCode::SIZE, tx_data))
-        } else if self.tx_buffer.is_some() {
+        } else if self.tx_buffer.is_some() || self.break_active.get() {
             Err((ErrorCode::BUSY, tx_data))
         } else {
             // Save the buffer so we can keep sending it.
@@ -302,7 +790,31 @@ impl<'a> hil::uart::Transmit<'a> for Uart<'a> {
     }
 
     fn transmit_abort(&self) -> Result<(), ErrorCode> {
-        Err(ErrorCode::FAIL)
+        let regs = self.registers;
+
+        if self.tx_buffer.is_none() || self.tx_aborted.get() {
+            return Ok(());
+        }
+
+        self.disable_tx_interrupt();
+
+        // Drop whatever is still queued in the FIFO. The byte currently in
+        // the shift register isn't affected by the reset, so wait for it to
+        // go out rather than leave a partial byte on the line.
+        let queued = regs.fifo_status.read(fifo_status::txlvl) as usize;
+        regs.fifo_ctrl.modify(fifo_ctrl::txrst::SET);
+        let _ = self.flush_tx();
+
+        self.tx_index
+            .set(self.tx_index.get().saturating_sub(queued));
+        self.tx_aborted.set(true);
+
+        // The buffer is returned from the interrupt handler, trigger the
+        // TX empty interrupt to get there.
+        self.enable_tx_interrupt();
+        regs.intr_test.write(intr::tx_empty::SET);
+
+        Err(ErrorCode::BUSY)
     }
 
     fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
@@ -329,12 +841,27 @@ impl<'a> hil::uart::Receive<'a> for Uart<'a> {
 
         self.rx_buffer.replace(rx_buffer);
         self.rx_len.set(rx_len);
+        self.rx_aborted.set(false);
 
         Ok(())
     }
 
     fn receive_abort(&self) -> Result<(), ErrorCode> {
-        Err(ErrorCode::FAIL)
+        let regs = self.registers;
+
+        if self.rx_buffer.is_none() || self.rx_aborted.get() {
+            return Ok(());
+        }
+
+        self.rx_aborted.set(true);
+
+        // The buffer is returned from the interrupt handler, along with
+        // anything already in the FIFO. Trigger the RX watermark interrupt
+        // to get there.
+        self.enable_rx_interrupt();
+        regs.intr_test.write(intr::rx_watermark::SET);
+
+        Err(ErrorCode::BUSY)
     }
 
     fn receive_word(&self) -> Result<(), ErrorCode> {
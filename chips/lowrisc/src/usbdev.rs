@@ -13,6 +13,20 @@ use kernel::utilities::StaticRef;
 pub const N_ENDPOINTS: usize = 12;
 pub const N_BUFFERS: usize = 32;
 
+/// Frame numbers sent by the host in SOF packets are 11 bits wide
+const FRAME_NUMBER_MASK: u32 = 0x7FF;
+
+/// Client for start-of-frame notifications, see `Usb::set_frame_client()`.
+pub trait FrameClient {
+    /// Called once per SOF packet, every 1 ms at full speed.
+    ///
+    /// `frame` is the frame number extended past the 11 bits the host sends:
+    /// the low 11 bits are the host's frame number and the count keeps
+    /// increasing across the wrap at 2048. If SOF interrupts are serviced
+    /// late, frames that were missed are still counted.
+    fn frame(&self, frame: u32);
+}
+
 register_structs! {
     pub UsbRegisters {
         (0x000 => intr_state: ReadWrite<u32, INTR::Register>),
@@ -60,7 +74,7 @@ register_bitfields![u32,
         DEVICE_ADDRESS OFFSET(16) NUMBITS(7) []
     ],
     USBSTAT [
-        FRAME OFFSET(0) NUMBITS(10) [],
+        FRAME OFFSET(0) NUMBITS(11) [],
         HOST_LOST OFFSET(11) NUMBITS(1) [],
         LINK_STATE OFFSET(12) NUMBITS(2) [],
         SENSE OFFSET(15) NUMBITS(1) [],
@@ -359,6 +373,8 @@ pub struct Usb<'a> {
     state: OptionalCell<State>,
     bufs: Cell<[Buffer; N_BUFFERS]>,
     addr: Cell<u16>,
+    frame_client: OptionalCell<&'a dyn FrameClient>,
+    frame_count: OptionalCell<u32>,
 }
 
 impl<'a> Usb<'a> {
@@ -416,9 +432,48 @@ impl<'a> Usb<'a> {
                 Buffer::new(31),
             ]),
             addr: Cell::new(0),
+            frame_client: OptionalCell::empty(),
+            frame_count: OptionalCell::empty(),
         }
     }
 
+    /// Deliver a callback for every SOF packet received, to let
+    /// isochronous drivers align to frame boundaries.
+    ///
+    /// The SOF interrupt fires every millisecond while the bus is active,
+    /// so it is only enabled while a frame client is set.
+    pub fn set_frame_client(&self, client: &'a dyn FrameClient) {
+        self.frame_count.clear();
+        self.frame_client.set(client);
+        self.registers.intr_enable.modify(INTR::FRAME::SET);
+    }
+
+    /// Stop delivering SOF callbacks and disable the SOF interrupt.
+    pub fn clear_frame_client(&self) {
+        self.registers.intr_enable.modify(INTR::FRAME::CLEAR);
+        self.frame_client.clear();
+    }
+
+    /// The current frame number, as sent in the last SOF packet
+    pub fn frame_number(&self) -> u16 {
+        self.registers.usbstat.read(USBSTAT::FRAME) as u16
+    }
+
+    /// Extend the frame number from the hardware past its 11 bits and pass
+    /// it to the frame client.
+    fn frame_received(&self) {
+        let raw = self.registers.usbstat.read(USBSTAT::FRAME);
+        let count = match self.frame_count.extract() {
+            // The difference modulo 2048 is the number of frames since the
+            // last SOF we saw, which handles the wrap
+            Some(last) => last.wrapping_add(raw.wrapping_sub(last) & FRAME_NUMBER_MASK),
+            None => raw,
+        };
+        self.frame_count.set(count);
+
+        self.frame_client.map(|client| client.frame(count));
+    }
+
     fn get_state(&self) -> State {
         self.state.unwrap_or_panic() // Unwrap fail = get_state: state value is in use
     }
@@ -475,7 +530,11 @@ impl<'a> Usb<'a> {
                 + INTR::RX_CRC_ERR::SET
                 + INTR::RX_PID_ERR::SET
                 + INTR::RX_BITSTUFF_ERR::SET
-                + INTR::FRAME::CLEAR
+                + if self.frame_client.is_some() {
+                    INTR::FRAME::SET
+                } else {
+                    INTR::FRAME::CLEAR
+                }
                 + INTR::CONNECTED::SET,
         );
     }
@@ -750,6 +809,8 @@ impl<'a> Usb<'a> {
         }
 
         if irqs.is_set(INTR::FRAME) {
+            self.frame_received();
+
             for (ep, desc) in self.descriptors.iter().enumerate() {
                 match desc.state.get() {
                     EndpointState::Disabled => {}
@@ -1,27 +1,34 @@
 //! Virtualise the Accel interface to enable multiple users of an underlying
 //! Accel hardware peripheral.
+//!
+//! Operations can be given a timeout with `run_with_timeout()`. If the
+//! operation hasn't completed when it expires the operation is aborted, its
+//! user gets `op_done()` with `Err(ErrorCode::CANCEL)` and the mux is
+//! released so a waiting user can claim it. OTBN itself can't be stopped, so
+//! the waiting user will get `BUSY` until the aborted program finishes.
 
 use crate::otbn::{Client, Otbn};
 use core::cell::Cell;
-use kernel::collections::list::{ListLink, ListNode};
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
-pub struct VirtualMuxAccel<'a> {
-    mux: &'a MuxAccel<'a>,
-    next: ListLink<'a, VirtualMuxAccel<'a>>,
+pub struct VirtualMuxAccel<'a, A: Alarm<'a>> {
+    mux: &'a MuxAccel<'a, A>,
+    next: ListLink<'a, VirtualMuxAccel<'a, A>>,
     client: OptionalCell<&'a dyn Client<'a>>,
     id: u32,
 }
 
-impl<'a> ListNode<'a, VirtualMuxAccel<'a>> for VirtualMuxAccel<'a> {
-    fn next(&self) -> &'a ListLink<VirtualMuxAccel<'a>> {
+impl<'a, A: Alarm<'a>> ListNode<'a, VirtualMuxAccel<'a, A>> for VirtualMuxAccel<'a, A> {
+    fn next(&self) -> &'a ListLink<VirtualMuxAccel<'a, A>> {
         &self.next
     }
 }
 
-impl<'a> VirtualMuxAccel<'a> {
-    pub fn new(mux_accel: &'a MuxAccel<'a>) -> VirtualMuxAccel<'a> {
+impl<'a, A: Alarm<'a>> VirtualMuxAccel<'a, A> {
+    pub fn new(mux_accel: &'a MuxAccel<'a, A>) -> VirtualMuxAccel<'a, A> {
         let id = mux_accel.next_id.get();
         mux_accel.next_id.set(id + 1);
 
@@ -33,10 +40,32 @@ impl<'a> VirtualMuxAccel<'a> {
         }
     }
 
+    /// Bind to the mux so operation callbacks are delivered, should be
+    /// called after `static_init!`
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
     pub fn set_client(&'a self, client: &'a dyn Client<'a>) {
         self.client.set(client);
     }
 
+    /// Whether this user currently holds the mux, from its first load until
+    /// `clear_data()` or a timeout.
+    pub fn is_claimed(&self) -> bool {
+        self.mux.running.get() && self.mux.running_id.get() == self.id
+    }
+
+    /// Whether this user could start an operation now, that is no other
+    /// user holds the mux and OTBN is idle.
+    ///
+    /// This is only a hint. The mux doesn't queue users, so another user can
+    /// still claim OTBN before this one does and the next load or run then
+    /// returns `BUSY`.
+    pub fn is_available(&self) -> bool {
+        (!self.mux.running.get() || self.is_claimed()) && self.mux.accel.is_idle()
+    }
+
     pub fn load_binary(&self, input: &[u8]) -> Result<(), ErrorCode> {
         // Check if any mux is enabled. If it isn't we enable it for us.
         if self.mux.running.get() == false {
@@ -67,17 +96,36 @@ impl<'a> VirtualMuxAccel<'a> {
         &self,
         address: usize,
         output: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.run_with_timeout(address, output, None)
+    }
+
+    /// Like `run()`, but if the operation hasn't completed `timeout_ms`
+    /// milliseconds after it starts it is aborted and `op_done()` is called
+    /// with `Err(ErrorCode::CANCEL)`. A `timeout_ms` of `None` never times
+    /// out.
+    pub fn run_with_timeout(
+        &self,
+        address: usize,
+        output: &'static mut [u8],
+        timeout_ms: Option<u32>,
     ) -> Result<(), (ErrorCode, &'static mut [u8])> {
         // Check if any mux is enabled. If it isn't we enable it for us.
         if self.mux.running.get() == false {
             self.mux.running.set(true);
             self.mux.running_id.set(self.id);
-            self.mux.accel.run(address, output)
-        } else if self.mux.running_id.get() == self.id {
-            self.mux.accel.run(address, output)
-        } else {
-            Err((ErrorCode::BUSY, output))
+        } else if self.mux.running_id.get() != self.id {
+            return Err((ErrorCode::BUSY, output));
+        }
+
+        self.mux.accel.run(address, output)?;
+
+        if let Some(ms) = timeout_ms {
+            let alarm = self.mux.alarm;
+            self.mux.timeout_armed.set(true);
+            alarm.set_alarm(alarm.now(), alarm.ticks_from_ms(ms));
         }
+        Ok(())
     }
 
     /// Disable the Accel hardware and clear the keys and any other sensitive
@@ -85,12 +133,13 @@ impl<'a> VirtualMuxAccel<'a> {
     pub fn clear_data(&self) {
         if self.mux.running_id.get() == self.id {
             self.mux.running.set(false);
+            self.mux.disarm_timeout();
             self.mux.accel.clear_data()
         }
     }
 }
 
-impl<'a> Client<'a> for VirtualMuxAccel<'a> {
+impl<'a, A: Alarm<'a>> Client<'a> for VirtualMuxAccel<'a, A> {
     fn op_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
         self.client
             .map(move |client| client.op_done(result, output));
@@ -101,20 +150,69 @@ impl<'a> Client<'a> for VirtualMuxAccel<'a> {
 /// `VirtualMuxAccel` as the one that has been enabled and running. Until that
 /// Mux calls `clear_data()` it will be the only `VirtualMuxAccel` that can
 /// interact with the underlying device.
-pub struct MuxAccel<'a> {
+///
+/// The mux must be set as the client of both the `Otbn` and `alarm`.
+pub struct MuxAccel<'a, A: Alarm<'a>> {
     accel: &'a Otbn<'a>,
+    alarm: &'a A,
+    users: List<'a, VirtualMuxAccel<'a, A>>,
     running: Cell<bool>,
     running_id: Cell<u32>,
     next_id: Cell<u32>,
+    timeout_armed: Cell<bool>,
 }
 
-impl<'a> MuxAccel<'a> {
-    pub const fn new(accel: &'a Otbn<'a>) -> MuxAccel<'a> {
+impl<'a, A: Alarm<'a>> MuxAccel<'a, A> {
+    pub const fn new(accel: &'a Otbn<'a>, alarm: &'a A) -> MuxAccel<'a, A> {
         MuxAccel {
             accel,
+            alarm,
+            users: List::new(),
             running: Cell::new(false),
             running_id: Cell::new(0),
             next_id: Cell::new(0),
+            timeout_armed: Cell::new(false),
+        }
+    }
+
+    fn disarm_timeout(&self) {
+        if self.timeout_armed.get() {
+            self.timeout_armed.set(false);
+            let _ = self.alarm.disarm();
+        }
+    }
+
+    fn running_user(&self) -> Option<&'a VirtualMuxAccel<'a, A>> {
+        if self.running.get() {
+            self.users
+                .iter()
+                .find(|user| user.id == self.running_id.get())
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Client<'a> for MuxAccel<'a, A> {
+    fn op_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+        self.disarm_timeout();
+        self.running_user()
+            .map(move |user| user.op_done(result, output));
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAccel<'a, A> {
+    fn alarm(&self) {
+        if !self.timeout_armed.get() {
+            return;
+        }
+        self.timeout_armed.set(false);
+
+        // The operation may have completed while the alarm was pending
+        if let Some(output) = self.accel.abort() {
+            let user = self.running_user();
+            self.running.set(false);
+            user.map(move |user| user.op_done(Err(ErrorCode::CANCEL), output));
         }
     }
 }
@@ -90,3 +90,8 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `DebugHistoryCapability` allows the holder to read the retained kernel
+/// debug output. This output can include anything the kernel has printed, so
+/// it should only be given to trusted consoles.
+pub unsafe trait DebugHistoryCapability {}
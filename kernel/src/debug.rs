@@ -50,10 +50,12 @@
 //! ```
 
 use core::cell::Cell;
+use core::cmp;
 use core::fmt::{write, Arguments, Result, Write};
 use core::panic::PanicInfo;
 use core::str;
 
+use crate::capabilities::DebugHistoryCapability;
 use crate::collections::queue::Queue;
 use crate::collections::ring_buffer::RingBuffer;
 use crate::hil;
@@ -509,6 +511,7 @@ impl DebugWriterWrapper {
 impl IoWrite for DebugWriterWrapper {
     fn write(&mut self, bytes: &[u8]) {
         const FULL_MSG: &[u8] = b"\n*** DEBUG BUFFER FULL ***\n";
+        unsafe { DEBUG_HISTORY }.map(|history| history.record(bytes));
         self.dw.map(|dw| {
             dw.internal_buffer.map(|ring_buffer| {
                 let available_len_for_msg =
@@ -540,6 +543,92 @@ impl Write for DebugWriterWrapper {
     }
 }
 
+///////////////////////////////////////////////////////////////////
+// debug history support
+
+/// Retained copy of the most recent kernel debug output.
+///
+/// Unlike the debug writer's internal buffer, which is drained as bytes are
+/// sent to the UART, the history keeps the last `buffer.len()` bytes written
+/// with `debug!()` so they can be replayed later, for example by the process
+/// console's `dmesg` command. Once full, the oldest bytes are overwritten.
+///
+/// Bytes are addressed by their position in the debug output since boot, so
+/// a reader can tell when the bytes it was about to read have been
+/// overwritten.
+pub struct DebugHistory {
+    buffer: TakeCell<'static, [u8]>,
+    /// Number of bytes recorded since boot. The next byte is stored at
+    /// `written % buffer.len()`.
+    written: Cell<usize>,
+}
+
+impl DebugHistory {
+    pub fn new(buffer: &'static mut [u8]) -> Self {
+        Self {
+            buffer: TakeCell::new(buffer),
+            written: Cell::new(0),
+        }
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        self.buffer.map(|buffer| {
+            let len = buffer.len();
+            if len == 0 {
+                return;
+            }
+            let mut written = self.written.get();
+            for &b in bytes {
+                buffer[written % len] = b;
+                written = written.wrapping_add(1);
+            }
+            self.written.set(written);
+        });
+    }
+
+    /// Returns the positions of the oldest byte still retained and of the
+    /// byte after the newest, as `(start, end)`.
+    pub fn span(&self) -> (usize, usize) {
+        let written = self.written.get();
+        let len = self.buffer.map_or(0, |buffer| buffer.len());
+        (written.saturating_sub(len), written)
+    }
+
+    /// Copy retained bytes, oldest first, starting at position `from` into
+    /// `out`.
+    ///
+    /// If the byte at `from` has already been overwritten, copying starts at
+    /// the oldest retained byte instead. Returns the position of the first
+    /// byte copied and the number of bytes copied.
+    pub fn read(&self, from: usize, out: &mut [u8]) -> (usize, usize) {
+        let (start, end) = self.span();
+        let from = cmp::max(from, start);
+        let count = cmp::min(end.saturating_sub(from), out.len());
+        self.buffer.map(|buffer| {
+            let len = buffer.len();
+            for (i, b) in out[..count].iter_mut().enumerate() {
+                *b = buffer[(from + i) % len];
+            }
+        });
+        (from, count)
+    }
+}
+
+static mut DEBUG_HISTORY: Option<&'static DebugHistory> = None;
+
+/// Function used by board main.rs to set a reference to the debug history.
+pub unsafe fn set_debug_history(history: &'static DebugHistory) {
+    DEBUG_HISTORY = Some(history);
+}
+
+/// Get a reference to the debug history, if the board has set one.
+///
+/// The history can contain anything the kernel has printed, so access to it
+/// requires the `DebugHistoryCapability`.
+pub fn debug_history(_capability: &dyn DebugHistoryCapability) -> Option<&'static DebugHistory> {
+    unsafe { DEBUG_HISTORY }
+}
+
 pub fn debug_print(args: Arguments) {
     let writer = unsafe { get_debug_writer() };
 
@@ -696,3 +785,72 @@ pub unsafe fn flush<W: Write + IoWrite>(writer: &mut W) {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::DebugHistory;
+    use std::boxed::Box;
+
+    fn history(len: usize) -> DebugHistory {
+        DebugHistory::new(Box::leak(std::vec![0; len].into_boxed_slice()))
+    }
+
+    #[test]
+    fn history_empty() {
+        let history = history(8);
+        let mut out = [0; 8];
+
+        assert_eq!(history.span(), (0, 0));
+        assert_eq!(history.read(0, &mut out), (0, 0));
+        assert_eq!(history.read(5, &mut out), (5, 0));
+    }
+
+    #[test]
+    fn history_partial() {
+        let history = history(8);
+        let mut out = [0; 8];
+
+        history.record(b"abc");
+        assert_eq!(history.span(), (0, 3));
+        assert_eq!(history.read(0, &mut out), (0, 3));
+        assert_eq!(&out[..3], b"abc");
+
+        assert_eq!(history.read(1, &mut out[..1]), (1, 1));
+        assert_eq!(&out[..1], b"b");
+        assert_eq!(history.read(3, &mut out), (3, 0));
+    }
+
+    #[test]
+    fn history_wrapped() {
+        let history = history(4);
+        let mut out = [0; 8];
+
+        history.record(b"abc");
+        history.record(b"def");
+        assert_eq!(history.span(), (2, 6));
+        assert_eq!(history.read(2, &mut out), (2, 4));
+        assert_eq!(&out[..4], b"cdef");
+
+        // A read that itself wraps around the end of the buffer
+        assert_eq!(history.read(3, &mut out[..2]), (3, 2));
+        assert_eq!(&out[..2], b"de");
+    }
+
+    #[test]
+    fn history_read_overwritten() {
+        let history = history(4);
+        let mut out = [0; 8];
+
+        history.record(b"ab");
+        let (from, count) = history.read(0, &mut out[..1]);
+        assert_eq!((from, count), (0, 1));
+
+        // The reader's next byte is overwritten before it gets to it
+        history.record(b"cdefg");
+        assert_eq!(history.span(), (3, 7));
+        assert_eq!(history.read(from + count, &mut out), (3, 4));
+        assert_eq!(&out[..4], b"defg");
+    }
+}
@@ -829,6 +829,76 @@ mod tests {
         assert_eq!(us, u32::MAX);
     }
 
+    struct Test16MHz32();
+    impl Time for Test16MHz32 {
+        type Frequency = Freq16MHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Self::Ticks {
+            0u32.into()
+        }
+    }
+
+    #[test]
+    fn test_to_ticks32_overflow() {
+        // 268ms is the last value where `ms * freq` fits in a u32
+        let t = Test16MHz32().ticks_from_ms(268);
+        assert_eq!(t.into_u32(), 4_288_000);
+
+        let t = Test16MHz32().ticks_from_ms(269);
+        assert_eq!(t.into_u32(), 4_304_000);
+
+        let t = Test16MHz32().ticks_from_ms(60_000);
+        assert_eq!(t.into_u32(), 960_000_000);
+
+        // 268_435ms is the last value where the ticks fit in a u32
+        let t = Test16MHz32().ticks_from_ms(268_435);
+        assert_eq!(t.into_u32(), 4_294_960_000);
+
+        let t = Test16MHz32().ticks_from_ms(268_436);
+        assert_eq!(t.into_u32(), u32::MAX);
+
+        let t = Test16MHz32().ticks_from_ms(u32::MAX);
+        assert_eq!(t.into_u32(), u32::MAX);
+
+        let t = Test16MHz32().ticks_from_us(268_435_455);
+        assert_eq!(t.into_u32(), 4_294_967_280);
+
+        let t = Test16MHz32().ticks_from_us(268_435_456);
+        assert_eq!(t.into_u32(), u32::MAX);
+
+        let t = Test16MHz32().ticks_from_seconds(268);
+        assert_eq!(t.into_u32(), 4_288_000_000);
+
+        let t = Test16MHz32().ticks_from_seconds(269);
+        assert_eq!(t.into_u32(), u32::MAX);
+    }
+
+    struct Test16MHz64();
+    impl Time for Test16MHz64 {
+        type Frequency = Freq16MHz;
+        type Ticks = Ticks64;
+
+        fn now(&self) -> Self::Ticks {
+            0u32.into()
+        }
+    }
+
+    #[test]
+    fn test_to_ticks64_overflow() {
+        let t = Test16MHz64().ticks_from_ms(268_436);
+        assert_eq!(t.into_u64(), 4_294_976_000);
+
+        let t = Test16MHz64().ticks_from_ms(u32::MAX);
+        assert_eq!(t.into_u64(), 16_000 * u32::MAX as u64);
+
+        let t = Test16MHz64().ticks_from_us(u32::MAX);
+        assert_eq!(t.into_u64(), 16 * u32::MAX as u64);
+
+        let t = Test16MHz64().ticks_from_seconds(u32::MAX);
+        assert_eq!(t.into_u64(), 16_000_000 * u32::MAX as u64);
+    }
+
     #[test]
     fn test_dyn_object() {
         let time: &dyn Time<Frequency = Freq1KHz, Ticks = Ticks24> = &Test1KHz24();